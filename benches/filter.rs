@@ -27,6 +27,7 @@ fn bench_filter_covered(b: &mut Bencher) {
         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions,
+        mcdc: None,
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
@@ -37,6 +38,7 @@ fn bench_filter_covered_no_functions(b: &mut Bencher) {
         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions: FxHashMap::default(),
+        mcdc: None,
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
@@ -62,6 +64,7 @@ fn bench_filter_uncovered_no_lines_executed(b: &mut Bencher) {
         lines: [(1, 0), (2, 0), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions: FxHashMap::default(),
+        mcdc: None,
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
@@ -87,6 +90,7 @@ fn bench_filter_covered_functions_executed(b: &mut Bencher) {
         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions,
+        mcdc: None,
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
@@ -105,6 +109,7 @@ fn bench_filter_covered_toplevel_executed(b: &mut Bencher) {
         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions,
+        mcdc: None,
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
@@ -130,6 +135,7 @@ fn bench_filter_uncovered_functions_not_executed(b: &mut Bencher) {
         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions,
+        mcdc: None,
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
@@ -40,6 +40,7 @@ fn bench_lib_merge_results(b: &mut Bencher) {
         .cloned()
         .collect(),
         functions: functions1,
+        mcdc: None,
     };
 
     let mut functions2: FunctionMap = FxHashMap::default();
@@ -71,6 +72,7 @@ fn bench_lib_merge_results(b: &mut Bencher) {
         .cloned()
         .collect(),
         functions: functions2,
+        mcdc: None,
     };
 
     b.iter(|| black_box(grcov::merge_results(&mut result, result2.clone())));
@@ -107,12 +109,10 @@ fn bench_lib_consumer(b: &mut Bencher) {
                 .spawn(move || {
                     consumer(
                         &working_dir,
-                        None,
                         &result_map,
                         receiver,
-                        false,
-                        false,
-                        None,
+                        &InputCounts::default(),
+                        &ConsumerOptions::default(),
                     );
                 })
                 .unwrap();
@@ -128,6 +128,7 @@ fn bench_lib_consumer(b: &mut Bencher) {
                         stem: "".to_string(),
                         gcno_buf: gcno_buf.clone(),
                         gcda_buf: Vec::new(),
+                        gcno_entry: None,
                     }),
                     name: "".to_string(),
                 }))
@@ -143,3 +144,170 @@ fn bench_lib_consumer(b: &mut Bencher) {
         }
     });
 }
+
+// An archive with 5k small entries, the gcno and gcda files of 2500 objects.
+fn zip_entries(dir: &std::path::Path, gcno: &str, gcda: &str) -> Vec<String> {
+    let zip_path = dir.join("coverage.zip");
+    let gcno = std::fs::read(gcno).unwrap();
+    let gcda = std::fs::read(gcda).unwrap();
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for i in 0..2500 {
+        for (ext, content) in &[("gcno", &gcno), ("gcda", &gcda)] {
+            zip.start_file(format!("dir{}/file.{}", i, ext), options)
+                .unwrap();
+            std::io::Write::write_all(&mut zip, content).unwrap();
+        }
+    }
+    zip.finish().unwrap();
+    vec![zip_path.to_string_lossy().into_owned()]
+}
+
+#[bench]
+fn bench_lib_zip_entries(b: &mut Bencher) {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let paths = zip_entries(tmp_dir.path(), "test/llvm/file.gcno", "test/llvm/file.gcda");
+
+    b.iter(|| {
+        let (sender, receiver) = unbounded();
+        producer(
+            tmp_dir.path(),
+            &paths,
+            &sender,
+            false,
+            true,
+            &DedupInputs::none(),
+            &InputCounts::default(),
+        );
+        let mut zip_readers = ZipReaders::default();
+        while let Ok(Some(work_item)) = receiver.try_recv() {
+            if let ItemType::Entries(entries) = work_item.item {
+                black_box(zip_readers.read_gcno_entries(entries).unwrap());
+            }
+        }
+    });
+}
+
+#[bench]
+fn bench_lib_zip_entries_gcc(b: &mut Bencher) {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let paths = zip_entries(tmp_dir.path(), "test/prova.gcno", "test/prova.gcda");
+    let gcno_path = tmp_dir.path().join("file.gcno");
+
+    b.iter(|| {
+        let (sender, receiver) = unbounded();
+        producer(
+            tmp_dir.path(),
+            &paths,
+            &sender,
+            false,
+            false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
+        );
+        let mut zip_readers = ZipReaders::default();
+        while let Ok(Some(work_item)) = receiver.try_recv() {
+            if let ItemType::GccEntries(entries) = work_item.item {
+                zip_readers
+                    .extract_gcc_entries(&entries, &gcno_path)
+                    .unwrap();
+            }
+        }
+    });
+}
+
+// The names of 100 symbols, each one appearing in 100 compilation units.
+fn repeated_symbols() -> Vec<String> {
+    (0..10_000)
+        .map(|i| {
+            let segment = format!("drop_in_place$LT$alloc..vec..Vec$LT$u{}$GT$$GT$", i % 100);
+            format!(
+                "_ZN4core3ptr{}{}17h0123456789abcdefE",
+                segment.len(),
+                segment
+            )
+        })
+        .collect()
+}
+
+#[bench]
+fn bench_demangle_repeated_symbols(b: &mut Bencher) {
+    let names = repeated_symbols();
+    b.iter(|| {
+        for name in &names {
+            black_box(demangle_name(name));
+        }
+    });
+}
+
+#[bench]
+fn bench_demangle_repeated_symbols_uncached(b: &mut Bencher) {
+    use symbolic_common::Name;
+    use symbolic_demangle::{Demangle, DemangleOptions};
+
+    let names = repeated_symbols();
+    b.iter(|| {
+        for name in &names {
+            black_box(Name::from(name.as_str()).demangle(DemangleOptions::name_only()));
+        }
+    });
+}
+
+// 10k results of 100 source files, e.g. the reports of many test binaries, each one with the
+// lines of a part of the file.
+fn synthetic_results() -> Vec<(String, CovResult)> {
+    (0..10_000u32)
+        .map(|i| {
+            let start = (i / 100 % 8) * 50;
+            let mut functions: FunctionMap = FxHashMap::default();
+            for f in 0..5 {
+                functions.insert(
+                    format!("f{}_{}", start, f),
+                    Function {
+                        start: start + f * 10,
+                        executed: i % 3 == 0,
+                    },
+                );
+            }
+            (
+                format!("src/file{}.rs", i % 100),
+                CovResult {
+                    lines: (start..start + 100)
+                        .map(|line| (line, u64::from(i % 7)))
+                        .collect(),
+                    branches: (start..start + 100)
+                        .step_by(10)
+                        .map(|line| (line, vec![i % 2 == 0, i % 5 == 0]))
+                        .collect(),
+                    functions,
+                    mcdc: None,
+                },
+            )
+        })
+        .collect()
+}
+
+#[bench]
+fn bench_lib_add_results(b: &mut Bencher) {
+    let results = synthetic_results();
+    b.iter(|| {
+        let result_map: SyncCovResultMap = Mutex::new(FxHashMap::default());
+        add_results(results.clone(), &result_map, None, &[]);
+        black_box(result_map)
+    });
+}
+
+// The results of the checked-in lcov report, with each file twice, as in the reports of two
+// binaries covering the same sources.
+#[bench]
+fn bench_lib_merge_results_lcov_fixture(b: &mut Bencher) {
+    let results = parse_lcov(&std::fs::read("./test/prova.info").unwrap(), true).unwrap();
+    b.iter(|| {
+        let mut merged = results.clone();
+        for ((_, result), (_, result2)) in merged.iter_mut().zip(results.clone()) {
+            merge_results(result, result2);
+        }
+        black_box(merged)
+    });
+}
@@ -3,11 +3,12 @@
 extern crate test;
 
 use grcov::{
-    output_activedata_etl, output_covdir, output_lcov, CovResult, Function, FunctionMap,
-    ResultTuple,
+    output_activedata_etl, output_covdir, output_html, output_lcov, parse_lcov, CovResult,
+    Function, FunctionMap, ResultTuple,
 };
 use rustc_hash::FxHashMap;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use tempfile::tempdir;
 use test::{black_box, Bencher};
 
@@ -39,11 +40,21 @@ fn generate_cov_result_iter() -> Vec<ResultTuple> {
                         functions
                     },
                     lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
+                    mcdc: None,
                 },
             )
         })
         .collect::<Vec<_>>()
 }
+// The results of the checked-in lcov report.
+fn lcov_fixture() -> Vec<ResultTuple> {
+    parse_lcov(&fs::read("./test/prova.info").unwrap(), true)
+        .unwrap()
+        .into_iter()
+        .map(|(path, result)| (PathBuf::from(&path), PathBuf::from(path), result))
+        .collect()
+}
+
 #[bench]
 fn bench_output_activedata_etl(b: &mut Bencher) {
     let dir = tempdir().unwrap();
@@ -63,6 +74,7 @@ fn bench_output_covdir(b: &mut Bencher) {
         black_box(output_covdir(
             &generate_cov_result_iter(),
             Some(&dir.path().join("temp")),
+            2,
         ));
     });
 }
@@ -78,3 +90,70 @@ fn bench_output_lcov(b: &mut Bencher) {
         ));
     });
 }
+
+#[bench]
+fn bench_output_covdir_lcov_fixture(b: &mut Bencher) {
+    let dir = tempdir().unwrap();
+    // The lines of the covdir output start at 1.
+    let mut results = lcov_fixture();
+    for (_, _, result) in &mut results {
+        result.lines.remove(&0);
+    }
+    b.iter(|| {
+        black_box(output_covdir(&results, Some(&dir.path().join("temp")), 2));
+    });
+}
+
+#[bench]
+fn bench_output_lcov_lcov_fixture(b: &mut Bencher) {
+    let dir = tempdir().unwrap();
+    let results = lcov_fixture();
+    b.iter(|| {
+        black_box(output_lcov(&results, Some(&dir.path().join("temp")), false));
+    });
+}
+
+// A large synthetic project, with sources of a hundred lines in a few hundred directories.
+fn generate_html_fixture(sources: &Path, files: usize) -> Vec<ResultTuple> {
+    let source: String = (0..100).map(|i| format!("let x{} = {};\n", i, i)).collect();
+    (0..files)
+        .map(|i| {
+            let rel_path = PathBuf::from(format!("src/dir{}/file{}.rs", i % 300, i));
+            let abs_path = sources.join(&rel_path);
+            fs::create_dir_all(abs_path.parent().unwrap()).unwrap();
+            fs::write(&abs_path, &source).unwrap();
+            let result = CovResult {
+                lines: (1..=100).map(|line| (line, u64::from(line % 3))).collect(),
+                branches: [].iter().cloned().collect(),
+                functions: FxHashMap::default(),
+                mcdc: None,
+            };
+            (abs_path, rel_path, result)
+        })
+        .collect()
+}
+
+fn bench_output_html(b: &mut Bencher, num_threads: usize) {
+    let dir = tempdir().unwrap();
+    let results = generate_html_fixture(&dir.path().join("sources"), 2000);
+    b.iter(|| {
+        black_box(output_html(
+            &results,
+            Some(dir.path().join("html").as_path()),
+            num_threads,
+            false,
+            None,
+            2,
+        ));
+    });
+}
+
+#[bench]
+fn bench_output_html_single_thread(b: &mut Bencher) {
+    bench_output_html(b, 1);
+}
+
+#[bench]
+fn bench_output_html_all_cpus(b: &mut Bencher) {
+    bench_output_html(b, num_cpus::get());
+}
@@ -0,0 +1,33 @@
+#![feature(test)]
+#![allow(clippy::unit_arg)]
+extern crate test;
+
+use grcov::{rewrite_paths, CovResult, CovResultMap, RewriteOptions};
+use rustc_hash::FxHashMap;
+use test::{black_box, Bencher};
+
+// 100k source files, none of them matching the 50 --ignore globs, and all of them matching the
+// --keep-only glob, so that all the globs are matched against every path.
+#[bench]
+fn bench_rewrite_paths_globs(b: &mut Bencher) {
+    let to_ignore_dirs: Vec<String> = (0..50).map(|i| format!("**/generated{}/**", i)).collect();
+    let to_ignore_dirs: Vec<_> = to_ignore_dirs.iter().map(String::as_str).collect();
+    b.iter(|| {
+        let mut result_map: CovResultMap = FxHashMap::default();
+        for i in 0..100_000 {
+            result_map.insert(
+                format!("crate{}/src/module{}/file{}.rs", i % 50, i % 700, i),
+                CovResult::default(),
+            );
+        }
+        black_box(rewrite_paths(
+            result_map,
+            RewriteOptions {
+                to_ignore_dirs: &to_ignore_dirs,
+                to_keep_dirs: &["crate*/src/**"],
+                use_ignore_files: false,
+                ..Default::default()
+            },
+        ))
+    });
+}
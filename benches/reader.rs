@@ -2,12 +2,13 @@
 #![allow(clippy::unit_arg)]
 extern crate test;
 
-use grcov::{Gcno, GcovReaderBuf, LittleEndian};
+use grcov::{ArchiveEntry, Gcno, GcnoCache, GcovReaderBuf, LittleEndian};
 use std::path::PathBuf;
 use test::{black_box, Bencher};
 
 const LLVM_READER_GCNO: &[u8] = include_bytes!("../test/llvm/reader.gcno");
 const LLVM_READER_GCDA: &[u8] = include_bytes!("../test/llvm/reader.gcda");
+const LARGE_GCNO: &[u8] = include_bytes!("../test/Unified_cpp_netwerk_base0.gcno");
 
 #[bench]
 fn bench_reader_gcno(b: &mut Bencher) {
@@ -18,6 +19,36 @@ fn bench_reader_gcno(b: &mut Bencher) {
     });
 }
 
+#[bench]
+fn bench_reader_gcno_large(b: &mut Bencher) {
+    b.iter(|| {
+        let mut gcno = Gcno::new();
+        let file = GcovReaderBuf::<LittleEndian>::new("netwerk_base", LARGE_GCNO.to_vec());
+        black_box(gcno.read_gcno(file).unwrap());
+        gcno
+    });
+}
+
+// The same gcno file read from a warm cache of the parsed gcno files.
+#[bench]
+fn bench_reader_gcno_large_cached(b: &mut Bencher) {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let entry = ArchiveEntry::File(tmp_dir.path().join("netwerk_base.gcno"));
+    std::fs::write(tmp_dir.path().join("netwerk_base.gcno"), LARGE_GCNO).unwrap();
+    let cache = GcnoCache::new(&tmp_dir.path().join("cache"));
+    cache
+        .parse(&entry, "netwerk_base", LARGE_GCNO.to_vec())
+        .unwrap();
+
+    b.iter(|| {
+        black_box(
+            cache
+                .parse(&entry, "netwerk_base", LARGE_GCNO.to_vec())
+                .unwrap(),
+        )
+    });
+}
+
 #[bench]
 fn bench_reader_gcda(b: &mut Bencher) {
     let mut gcno = Gcno::new();
@@ -0,0 +1,300 @@
+use rustc_hash::FxHashMap;
+use std::collections::btree_map;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::defs::*;
+use crate::merge_results;
+use crate::parser::parse_lcov;
+
+/// The default directory where the reports of the tagged runs are stored.
+pub const DEFAULT_TAG_CACHE_DIR: &str = ".grcov/tags";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateMode {
+    /// A line is covered if it is covered in any run, and the hit counts are summed up.
+    Union,
+    /// A line is covered only if it is covered in all the runs.
+    Intersection,
+}
+
+impl FromStr for AggregateMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "union" => Self::Union,
+            "intersection" => Self::Intersection,
+            _ => return Err(format!("{} is not a supported aggregation mode", s)),
+        })
+    }
+}
+
+/// Returns the path of the report of the run labelled with the given tag.
+pub fn tag_report_path(cache_dir: &Path, tag: &str) -> Result<PathBuf, String> {
+    if tag.is_empty()
+        || tag.starts_with('.')
+        || !tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c))
+    {
+        return Err(format!(
+            "Invalid tag '{}': only letters, digits and '-', '_', '.', '+' are allowed",
+            tag
+        ));
+    }
+    Ok(cache_dir.join(format!("{}.info", tag)))
+}
+
+/// Keeps the coverage of `result` only where it is also covered in `result2`. The lines which
+/// are missing from one of the results are considered as not covered, and the hit counts are the
+/// lowest ones.
+pub fn intersect_results(result: &mut CovResult, result2: &CovResult) {
+    for (line_no, execution_count) in result.lines.iter_mut() {
+        *execution_count = match result2.lines.get(line_no) {
+            Some(&execution_count2) => (*execution_count).min(execution_count2),
+            None => 0,
+        };
+    }
+    for &line_no in result2.lines.keys() {
+        result.lines.entry(line_no).or_insert(0);
+    }
+
+    for (line_no, taken) in result.branches.iter_mut() {
+        let taken2 = result2.branches.get(line_no);
+        for (i, taken) in taken.iter_mut().enumerate() {
+            *taken &= taken2.and_then(|t| t.get(i)).copied().unwrap_or(false);
+        }
+    }
+    for (&line_no, taken2) in &result2.branches {
+        match result.branches.entry(line_no) {
+            btree_map::Entry::Occupied(c) => {
+                let taken = c.into_mut();
+                if taken2.len() > taken.len() {
+                    taken.resize(taken2.len(), false);
+                }
+            }
+            btree_map::Entry::Vacant(v) => {
+                v.insert(vec![false; taken2.len()]);
+            }
+        };
+    }
+
+    for (name, function) in result.functions.iter_mut() {
+        function.executed &= matches!(result2.functions.get(name), Some(f) if f.executed);
+    }
+    for (name, function) in &result2.functions {
+        result.functions.entry(name.clone()).or_insert(Function {
+            start: function.start,
+            executed: false,
+        });
+    }
+}
+
+/// Combines the coverage results of several runs.
+pub fn aggregate_results(runs: Vec<CovResultMap>, mode: AggregateMode) -> CovResultMap {
+    let mut aggregated: CovResultMap = FxHashMap::default();
+    match mode {
+        AggregateMode::Union => {
+            for run in runs {
+                for (path, result) in run {
+                    match aggregated.get_mut(&path) {
+                        Some(r) => {
+                            merge_results(r, result);
+                        }
+                        None => {
+                            aggregated.insert(path, result);
+                        }
+                    }
+                }
+            }
+        }
+        AggregateMode::Intersection => {
+            let empty = CovResult::default();
+            let paths: Vec<String> = runs.iter().flat_map(|run| run.keys().cloned()).collect();
+            for path in paths {
+                if aggregated.contains_key(&path) {
+                    continue;
+                }
+                let mut results = runs.iter().map(|run| run.get(&path).unwrap_or(&empty));
+                let mut result = results.next().unwrap().clone();
+                for result2 in results {
+                    intersect_results(&mut result, result2);
+                }
+                aggregated.insert(path, result);
+            }
+        }
+    }
+    aggregated
+}
+
+/// Reads the reports of the runs labelled with the given tags, and combines them.
+pub fn aggregate_tags(
+    cache_dir: &Path,
+    tags: &[String],
+    mode: AggregateMode,
+) -> Result<CovResultMap, String> {
+    let mut runs = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let path = tag_report_path(cache_dir, tag)?;
+        let buffer = fs::read(&path).map_err(|e| {
+            format!(
+                "Unable to read the report of tag '{}' ({}): {}",
+                tag,
+                path.display(),
+                e
+            )
+        })?;
+        let results = parse_lcov(&buffer, true)
+            .map_err(|e| format!("Invalid report for tag '{}': {}", tag, e))?;
+        runs.push(results.into_iter().collect());
+    }
+    Ok(aggregate_results(runs, mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(
+        lines: &[(u32, u64)],
+        branches: &[(u32, &[bool])],
+        functions: &[(&str, bool)],
+    ) -> CovResult {
+        CovResult {
+            lines: lines.iter().cloned().collect(),
+            branches: branches
+                .iter()
+                .map(|&(line_no, taken)| (line_no, taken.to_vec()))
+                .collect(),
+            functions: functions
+                .iter()
+                .map(|&(name, executed)| (name.to_string(), Function { start: 1, executed }))
+                .collect(),
+            mcdc: None,
+        }
+    }
+
+    fn runs() -> Vec<CovResultMap> {
+        let mut run1 = FxHashMap::default();
+        run1.insert(
+            "src/lib.rs".to_string(),
+            result(
+                &[(1, 2), (2, 1), (3, 0), (4, 5)],
+                &[(2, &[true, false])],
+                &[("common", true), ("feature_a", true)],
+            ),
+        );
+        run1.insert("src/a.rs".to_string(), result(&[(1, 1)], &[], &[]));
+        let mut run2 = FxHashMap::default();
+        run2.insert(
+            "src/lib.rs".to_string(),
+            result(
+                &[(1, 3), (2, 0), (3, 4), (5, 1)],
+                &[(2, &[true, true])],
+                &[("common", true), ("feature_a", false), ("feature_b", true)],
+            ),
+        );
+        vec![run1, run2]
+    }
+
+    #[test]
+    fn test_aggregate_mode_from_str() {
+        assert_eq!(
+            AggregateMode::from_str("union").unwrap(),
+            AggregateMode::Union
+        );
+        assert_eq!(
+            AggregateMode::from_str("intersection").unwrap(),
+            AggregateMode::Intersection
+        );
+        assert!(AggregateMode::from_str("sum").is_err());
+    }
+
+    #[test]
+    fn test_tag_report_path() {
+        assert_eq!(
+            tag_report_path(Path::new("cache"), "no-default-features").unwrap(),
+            PathBuf::from("cache/no-default-features.info")
+        );
+        assert!(tag_report_path(Path::new("cache"), "").is_err());
+        assert!(tag_report_path(Path::new("cache"), "../evil").is_err());
+        assert!(tag_report_path(Path::new("cache"), "a/b").is_err());
+    }
+
+    #[test]
+    fn test_aggregate_union() {
+        let aggregated = aggregate_results(runs(), AggregateMode::Union);
+        assert_eq!(aggregated.len(), 2);
+        assert_eq!(
+            aggregated["src/lib.rs"],
+            result(
+                &[(1, 5), (2, 1), (3, 4), (4, 5), (5, 1)],
+                &[(2, &[true, true])],
+                &[("common", true), ("feature_a", true), ("feature_b", true)],
+            )
+        );
+        assert_eq!(aggregated["src/a.rs"], result(&[(1, 1)], &[], &[]));
+    }
+
+    #[test]
+    fn test_aggregate_intersection() {
+        let aggregated = aggregate_results(runs(), AggregateMode::Intersection);
+        assert_eq!(aggregated.len(), 2);
+        assert_eq!(
+            aggregated["src/lib.rs"],
+            result(
+                &[(1, 2), (2, 0), (3, 0), (4, 0), (5, 0)],
+                &[(2, &[true, false])],
+                &[("common", true), ("feature_a", false), ("feature_b", false)],
+            )
+        );
+        // The file is missing from the second run, so nothing is covered.
+        assert_eq!(
+            aggregated["src/a.rs"].lines,
+            [(1, 0)].iter().cloned().collect::<LineMap>()
+        );
+    }
+
+    #[test]
+    fn test_aggregate_tags() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        fs::write(
+            tag_report_path(tmp_dir.path(), "a").unwrap(),
+            "SF:src/lib.rs\nDA:1,1\nDA:2,0\nend_of_record\n",
+        )
+        .unwrap();
+        fs::write(
+            tag_report_path(tmp_dir.path(), "b").unwrap(),
+            "SF:src/lib.rs\nDA:1,2\nDA:2,3\nend_of_record\n",
+        )
+        .unwrap();
+        let tags = vec!["a".to_string(), "b".to_string()];
+
+        let union = aggregate_tags(tmp_dir.path(), &tags, AggregateMode::Union).unwrap();
+        assert_eq!(
+            union["src/lib.rs"].lines,
+            [(1, 3), (2, 3)].iter().cloned().collect::<LineMap>()
+        );
+
+        let intersection =
+            aggregate_tags(tmp_dir.path(), &tags, AggregateMode::Intersection).unwrap();
+        assert_eq!(
+            intersection["src/lib.rs"].lines,
+            [(1, 1), (2, 0)].iter().cloned().collect::<LineMap>()
+        );
+
+        let err = aggregate_tags(
+            tmp_dir.path(),
+            &["a".to_string(), "c".to_string()],
+            AggregateMode::Union,
+        )
+        .unwrap_err();
+        assert!(
+            err.starts_with("Unable to read the report of tag 'c'"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use log::error;
+use rayon::prelude::*;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::defs::*;
+use crate::llvm_tools::{
+    exported_binaries, get_cov_path, get_profdata_path, llvm_stderr, profdata_input_list, run,
+    run_with_stdin, ExportOptions,
+};
+use crate::output::get_target_output_writable;
+
+/// Annotates the source files of the results with `llvm-cov show --format=text`, from the
+/// profiles and the binaries they were recorded by, found as for their export: each line is
+/// prefixed by its line number and its execution count. With `show_line_counts_or_regions`, the
+/// lines with several regions are followed by the counts of their regions, and with
+/// `show_expansions` the macros are expanded under the lines using them.
+///
+/// The listings are written one after the other, each of them after a line with the relative
+/// path of its file followed by ':', or with `per_file` in a directory where each file is
+/// written at its relative path followed by `.txt`.
+pub fn output_annotated_source(
+    results: &[ResultTuple],
+    profraw_paths: &[PathBuf],
+    binaries: &ExportOptions,
+    output_path: Option<&Path>,
+    per_file: bool,
+    show_line_counts_or_regions: bool,
+    show_expansions: bool,
+) {
+    if let Err(e) = annotate_sources(
+        results,
+        profraw_paths,
+        binaries,
+        output_path,
+        per_file,
+        show_line_counts_or_regions,
+        show_expansions,
+    ) {
+        error!("Unable to annotate the source files: {}", e);
+    }
+}
+
+fn annotate_sources(
+    results: &[ResultTuple],
+    profraw_paths: &[PathBuf],
+    binaries: &ExportOptions,
+    output_path: Option<&Path>,
+    per_file: bool,
+    show_line_counts_or_regions: bool,
+    show_expansions: bool,
+) -> Result<(), String> {
+    if profraw_paths.is_empty() {
+        return Err("the source files can only be annotated from .profraw files".to_string());
+    }
+    let (binaries, _) = exported_binaries(
+        binaries.binary_path,
+        binaries.executables,
+        binaries.allowed_binaries,
+        false,
+    );
+    if binaries.is_empty() {
+        return Err("no binaries were found, set them with --binary-path".to_string());
+    }
+
+    let tmp_dir = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let profdata_path = tmp_dir.path().join("grcov.profdata");
+    run_with_stdin(
+        get_profdata_path()?,
+        profdata_input_list(profraw_paths),
+        &[
+            "merge".as_ref(),
+            "-f".as_ref(),
+            "-".as_ref(),
+            "-sparse".as_ref(),
+            "-o".as_ref(),
+            profdata_path.as_ref(),
+        ],
+        llvm_stderr(),
+    )?;
+
+    // The first binary is given as is, and the other ones as objects.
+    let mut args: Vec<&OsStr> = vec!["show".as_ref(), binaries[0].as_ref()];
+    for binary in &binaries[1..] {
+        args.extend::<[&OsStr; 2]>(["--object".as_ref(), binary.as_ref()]);
+    }
+    args.extend::<[&OsStr; 3]>([
+        "--instr-profile".as_ref(),
+        profdata_path.as_ref(),
+        "--format=text".as_ref(),
+    ]);
+    if show_line_counts_or_regions {
+        args.push("--show-line-counts-or-regions".as_ref());
+    }
+    if show_expansions {
+        args.push("--show-expansions".as_ref());
+    }
+
+    let cov_tool_path = get_cov_path()?;
+    let listings: Vec<(&Path, Vec<u8>)> = results
+        .par_iter()
+        .filter_map(|(abs_path, rel_path, _)| {
+            let mut args = args.clone();
+            args.push(abs_path.as_ref());
+            match run(&cov_tool_path, &args, llvm_stderr()) {
+                Ok(listing) => Some((rel_path.as_path(), listing)),
+                Err(e) => {
+                    error!("Unable to annotate {}: {}", rel_path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    if per_file {
+        let output_dir =
+            output_path.ok_or("the annotated source files need an output directory")?;
+        for (rel_path, listing) in listings {
+            let mut path = output_dir.join(rel_path).into_os_string();
+            path.push(".txt");
+            let path = PathBuf::from(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            File::create(&path)
+                .and_then(|mut file| file.write_all(&listing))
+                .map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+        }
+    } else {
+        let mut writer = BufWriter::new(get_target_output_writable(output_path));
+        for (rel_path, listing) in listings {
+            writeln!(writer, "{}:", rel_path.display())
+                .and_then(|_| writer.write_all(&listing))
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llvm_tools::test_helpers::instrumented_profraw;
+
+    #[test]
+    fn test_output_annotated_source() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path();
+        let (binary, profraw) = match instrumented_profraw(
+            tmp_path,
+            "fn main() {\n    if std::env::args().count() > 5 {\n        println!(\"Many\");\n    }\n}\n",
+        ) {
+            Some(paths) => paths,
+            None => return,
+        };
+        let source = tmp_path.join("main.rs");
+
+        let results = vec![(source, PathBuf::from("src/main.rs"), CovResult::default())];
+        let output = tmp_path.join("annotated.txt");
+        annotate_sources(
+            &results,
+            std::slice::from_ref(&profraw),
+            &ExportOptions {
+                executables: std::slice::from_ref(&binary),
+                ..Default::default()
+            },
+            Some(&output),
+            false,
+            false,
+            false,
+        )
+        .unwrap();
+        let annotated = fs::read_to_string(&output).unwrap();
+        let lines: Vec<&str> = annotated.lines().collect();
+        assert_eq!(lines[0], "src/main.rs:");
+        // The line number, the execution count and the source of each line, as llvm-cov shows
+        // them.
+        let line = |number: &str| {
+            lines
+                .iter()
+                .find(|line| line.trim_start().starts_with(&format!("{}|", number)))
+                .unwrap()
+                .split('|')
+                .map(str::trim)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(line("1"), vec!["1", "1", "fn main() {"]);
+        assert_eq!(line("3"), vec!["3", "0", "println!(\"Many\");"]);
+
+        let output_dir = tmp_path.join("annotated");
+        annotate_sources(
+            &results,
+            &[profraw],
+            &ExportOptions {
+                executables: &[binary],
+                ..Default::default()
+            },
+            Some(&output_dir),
+            true,
+            true,
+            true,
+        )
+        .unwrap();
+        let annotated = fs::read_to_string(output_dir.join("src/main.rs.txt")).unwrap();
+        assert!(annotated.contains("fn main() {"));
+    }
+}
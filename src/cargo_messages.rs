@@ -0,0 +1,126 @@
+use serde_json::Value;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Extracts the executables built by cargo from its JSON messages, as written by
+/// `cargo test --message-format=json`: the test binaries, and the binaries of the package used by
+/// its integration tests. The other lines, e.g. the output of the tests, are ignored.
+pub fn parse_cargo_executables(messages: &str) -> Vec<PathBuf> {
+    let mut executables: Vec<PathBuf> = messages
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|message| message["reason"] == "compiler-artifact")
+        .filter_map(|message| message["executable"].as_str().map(PathBuf::from))
+        .collect();
+    executables.sort();
+    executables.dedup();
+    executables
+}
+
+/// Reads the executables built by cargo from a file with its JSON messages, or from stdin when
+/// the path is `-`. Their paths are canonicalized, so that they can be compared with the binaries
+/// found in --binary-path, and the ones which don't exist anymore are left out.
+pub fn read_cargo_executables(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let messages = if path == Path::new("-") {
+        let mut messages = String::new();
+        io::stdin().read_to_string(&mut messages).map(|_| messages)
+    } else {
+        fs::read_to_string(path)
+    };
+    let messages = messages.map_err(|e| {
+        format!(
+            "Unable to read the cargo messages from {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    Ok(parse_cargo_executables(&messages)
+        .into_iter()
+        .filter_map(|executable| fs::canonicalize(executable).ok())
+        .collect())
+}
+
+/// Reads the executables to export with --cargo, which must not be empty.
+pub fn discover_cargo_executables(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let executables = read_cargo_executables(path)?;
+    if executables.is_empty() {
+        let source = if path == Path::new("-") {
+            "stdin".to_string()
+        } else {
+            path.display().to_string()
+        };
+        return Err(format!(
+            "No executables found in the cargo messages from {}. They must be the JSON output of \
+             `cargo test --no-run --message-format=json` (with the instrumented build), run since \
+             the last `cargo clean`.",
+            source
+        ));
+    }
+    Ok(executables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGES: &str = r#"{"reason":"compiler-artifact","package_id":"sample 0.1.0 (path+file:///work/sample)","target":{"kind":["lib"],"name":"sample","src_path":"/work/sample/src/lib.rs","test":true},"profile":{"test":false},"filenames":["/work/sample/target/debug/deps/libsample-0123456789abcdef.rlib"],"executable":null,"fresh":false}
+{"reason":"compiler-artifact","package_id":"sample 0.1.0 (path+file:///work/sample)","target":{"kind":["lib"],"name":"sample","src_path":"/work/sample/src/lib.rs","test":true},"profile":{"test":true},"filenames":["/work/sample/target/debug/deps/sample-0123456789abcdef"],"executable":"/work/sample/target/debug/deps/sample-0123456789abcdef","fresh":false}
+{"reason":"compiler-message","package_id":"sample 0.1.0 (path+file:///work/sample)","message":{"rendered":"warning: unused variable"}}
+{"reason":"compiler-artifact","package_id":"sample 0.1.0 (path+file:///work/sample)","target":{"kind":["test"],"name":"integration","src_path":"/work/sample/tests/integration.rs","test":true},"profile":{"test":true},"filenames":["/work/sample/target/debug/deps/integration-fedcba9876543210"],"executable":"/work/sample/target/debug/deps/integration-fedcba9876543210","fresh":true}
+{"reason":"build-finished","success":true}
+
+running 1 test
+test tests::it_works ... ok
+"#;
+
+    #[test]
+    fn test_parse_cargo_executables() {
+        assert_eq!(
+            parse_cargo_executables(MESSAGES),
+            vec![
+                PathBuf::from("/work/sample/target/debug/deps/integration-fedcba9876543210"),
+                PathBuf::from("/work/sample/target/debug/deps/sample-0123456789abcdef"),
+            ]
+        );
+        assert!(parse_cargo_executables("not json\n").is_empty());
+    }
+
+    #[test]
+    fn test_read_cargo_executables() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let executable = tmp_dir.path().join("sample-0123456789abcdef");
+        fs::write(&executable, "").unwrap();
+        let missing = tmp_dir.path().join("missing-0123456789abcdef");
+        let messages_path = tmp_dir.path().join("cargo.json");
+        fs::write(
+            &messages_path,
+            [&executable, &missing]
+                .iter()
+                .map(|path| {
+                    serde_json::json!({"reason": "compiler-artifact", "executable": path})
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            read_cargo_executables(&messages_path).unwrap(),
+            vec![fs::canonicalize(&executable).unwrap()]
+        );
+        assert!(read_cargo_executables(&tmp_dir.path().join("missing.json")).is_err());
+    }
+
+    #[test]
+    fn test_discover_cargo_executables() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let messages_path = tmp_dir.path().join("cargo.json");
+        fs::write(&messages_path, MESSAGES).unwrap();
+        // The executables of the messages don't exist anymore.
+        let err = discover_cargo_executables(&messages_path).unwrap_err();
+        assert!(err.contains("No executables found"));
+        assert!(err.contains("cargo test --no-run --message-format=json"));
+    }
+}
@@ -0,0 +1,112 @@
+use serde_json::Value;
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+/// The name of the profraws written by the tests run by `grcov test`, unique for each process
+/// (`%p`) and binary (`%m`).
+pub const TEST_PROFRAW_PATTERN: &str = "grcov-%p-%m.profraw";
+
+/// Adds the flags enabling the source-based coverage to the existing RUSTFLAGS.
+pub fn coverage_rustflags(rustflags: Option<OsString>) -> OsString {
+    let mut rustflags = rustflags.unwrap_or_default();
+    if !rustflags.is_empty() {
+        rustflags.push(" ");
+    }
+    rustflags.push("-Cinstrument-coverage");
+    rustflags
+}
+
+/// Returns the line of the stdout of cargo to show as is, i.e. the output of the tests, or None
+/// for the JSON messages of cargo, of which only the rendered diagnostics are shown.
+pub fn cargo_line_output(line: &str) -> Option<&str> {
+    match serde_json::from_str::<Value>(line) {
+        Ok(message) if message.get("reason").is_some() => None,
+        _ => Some(line),
+    }
+}
+
+fn rendered_diagnostic(line: &str) -> Option<String> {
+    let message: Value = serde_json::from_str(line).ok()?;
+    if message["reason"] != "compiler-message" {
+        return None;
+    }
+    message["message"]["rendered"].as_str().map(str::to_string)
+}
+
+/// Runs `cargo test` with the source-based coverage enabled, the profraws being written to
+/// `profraw_dir`, and writes the JSON messages of cargo to `messages_path` so that the executables
+/// it built can be exported.
+pub fn run_cargo_test(
+    cargo_args: &[OsString],
+    profraw_dir: &Path,
+    messages_path: &Path,
+) -> Result<ExitStatus, String> {
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut command = Command::new(cargo);
+    command
+        .arg("test")
+        .arg("--message-format=json-diagnostic-rendered-ansi")
+        .args(cargo_args)
+        .env("RUSTFLAGS", coverage_rustflags(env::var_os("RUSTFLAGS")))
+        .env("LLVM_PROFILE_FILE", profraw_dir.join(TEST_PROFRAW_PATTERN))
+        .stdout(Stdio::piped());
+
+    let mut messages = File::create(messages_path)
+        .map_err(|e| format!("Unable to create {}: {}", messages_path.display(), e))?;
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Failed to execute {:?}\n{}", command, e))?;
+    let stdout = child.stdout.take().unwrap();
+    for line in BufReader::new(stdout).lines() {
+        let line = line.map_err(|e| format!("Failed to read the output of cargo: {}", e))?;
+        if let Some(output) = cargo_line_output(&line) {
+            println!("{}", output);
+        } else if let Some(rendered) = rendered_diagnostic(&line) {
+            eprint!("{}", rendered);
+        }
+        writeln!(messages, "{}", line)
+            .map_err(|e| format!("Unable to write {}: {}", messages_path.display(), e))?;
+    }
+
+    child
+        .wait()
+        .map_err(|e| format!("Failed to execute {:?}\n{}", command, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage_rustflags() {
+        assert_eq!(coverage_rustflags(None), "-Cinstrument-coverage");
+        assert_eq!(
+            coverage_rustflags(Some("-Copt-level=1".into())),
+            "-Copt-level=1 -Cinstrument-coverage"
+        );
+    }
+
+    #[test]
+    fn test_cargo_line_output() {
+        assert_eq!(
+            cargo_line_output(r#"{"reason":"compiler-artifact","executable":null}"#),
+            None
+        );
+        assert_eq!(
+            cargo_line_output("test tests::it_works ... ok"),
+            Some("test tests::it_works ... ok")
+        );
+        // The JSON printed by the tests themselves is shown.
+        assert_eq!(cargo_line_output(r#"{"a": 1}"#), Some(r#"{"a": 1}"#));
+        assert_eq!(
+            rendered_diagnostic(
+                r#"{"reason":"compiler-message","message":{"rendered":"warning: unused\n"}}"#
+            ),
+            Some("warning: unused\n".to_string())
+        );
+    }
+}
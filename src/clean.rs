@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::path_rewriting::home_dir;
+
+/// The kinds of coverage artifacts removed by `grcov clean`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Profraw,
+    Gcda,
+    Gcno,
+    /// The profiles merged by grcov.
+    Intermediate,
+}
+
+/// Returns the kind of a coverage artifact, or None for the other files.
+pub fn artifact_kind(path: &Path) -> Option<ArtifactKind> {
+    let file_name = path.file_name()?.to_str()?;
+    if file_name == "grcov.profdata" {
+        return Some(ArtifactKind::Intermediate);
+    }
+    match path.extension()?.to_str()? {
+        "profraw" => Some(ArtifactKind::Profraw),
+        "gcda" => Some(ArtifactKind::Gcda),
+        "gcno" => Some(ArtifactKind::Gcno),
+        _ => None,
+    }
+}
+
+/// Refuses to clean the root directory or the home directory, where the artifacts of unrelated
+/// projects would be removed too.
+pub fn check_clean_root(path: &Path) -> Result<(), String> {
+    let path =
+        fs::canonicalize(path).map_err(|e| format!("Unable to clean {}: {}", path.display(), e))?;
+    let is_home = home_dir()
+        .and_then(|home| fs::canonicalize(home).ok())
+        .is_some_and(|home| home == path);
+    if path.parent().is_none() || is_home {
+        Err(format!(
+            "Refusing to clean {}, use --force to clean it anyway",
+            path.display()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Lists the artifacts of the given kinds in the directories, or the given files when they are
+/// artifacts. The symbolic links to directories aren't followed, so that only the files in the
+/// directories are listed.
+pub fn find_artifacts(paths: &[PathBuf], kinds: &[ArtifactKind]) -> Vec<PathBuf> {
+    let is_removed = |path: &Path| artifact_kind(path).is_some_and(|kind| kinds.contains(&kind));
+    let mut artifacts = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            if path.is_file() && is_removed(path) {
+                artifacts.push(path.clone());
+            }
+            continue;
+        }
+        for entry in WalkDir::new(path).into_iter().filter_map(Result::ok) {
+            if !entry.file_type().is_dir() && is_removed(entry.path()) {
+                artifacts.push(entry.into_path());
+            }
+        }
+    }
+    artifacts.sort();
+    artifacts.dedup();
+    artifacts
+}
+
+/// Removes the artifacts, and returns the errors of the ones which couldn't be removed.
+pub fn remove_artifacts(artifacts: &[PathBuf]) -> Vec<String> {
+    artifacts
+        .iter()
+        .filter_map(|artifact| {
+            fs::remove_file(artifact)
+                .err()
+                .map(|e| format!("Unable to remove {}: {}", artifact.display(), e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_artifact_kind() {
+        assert_eq!(
+            artifact_kind(Path::new("target/default_1.profraw")),
+            Some(ArtifactKind::Profraw)
+        );
+        assert_eq!(
+            artifact_kind(Path::new("obj/main.gcda")),
+            Some(ArtifactKind::Gcda)
+        );
+        assert_eq!(
+            artifact_kind(Path::new("obj/main.gcno")),
+            Some(ArtifactKind::Gcno)
+        );
+        assert_eq!(
+            artifact_kind(Path::new("/tmp/0/grcov.profdata")),
+            Some(ArtifactKind::Intermediate)
+        );
+        assert_eq!(artifact_kind(Path::new("merged.profdata")), None);
+        assert_eq!(artifact_kind(Path::new("src/main.rs")), None);
+    }
+
+    #[test]
+    fn test_check_clean_root() {
+        assert!(check_clean_root(Path::new("/")).is_err());
+        if let Some(home) = home_dir().filter(|home| home.is_dir()) {
+            assert!(check_clean_root(&home).is_err());
+        }
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        assert!(check_clean_root(tmp_dir.path()).is_ok());
+        assert!(check_clean_root(&tmp_dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_find_and_remove_artifacts() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let dir = tmp_dir.path().join("target");
+        let outside = tmp_dir.path().join("outside");
+        fs::create_dir_all(dir.join("deps")).unwrap();
+        fs::create_dir(&outside).unwrap();
+        for file in &[
+            "default_1.profraw",
+            "deps/main.gcda",
+            "deps/main.gcno",
+            "deps/main.o",
+            "merged.profdata",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        fs::write(outside.join("other.profraw"), "").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&outside, dir.join("link")).unwrap();
+
+        let kinds = [ArtifactKind::Profraw, ArtifactKind::Gcda];
+        let artifacts = find_artifacts(std::slice::from_ref(&dir), &kinds);
+        assert_eq!(
+            artifacts,
+            vec![dir.join("default_1.profraw"), dir.join("deps/main.gcda")]
+        );
+
+        assert!(remove_artifacts(&artifacts).is_empty());
+        assert!(!dir.join("default_1.profraw").exists());
+        assert!(!dir.join("deps/main.gcda").exists());
+        assert!(dir.join("deps/main.gcno").exists());
+        assert!(dir.join("merged.profdata").exists());
+        assert!(outside.join("other.profraw").exists());
+
+        assert_eq!(remove_artifacts(&artifacts).len(), 2);
+    }
+}
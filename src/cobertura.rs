@@ -7,17 +7,15 @@ use rustc_hash::FxHashMap;
 use std::io::{BufWriter, Cursor, Write};
 use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
-use symbolic_common::Name;
-use symbolic_demangle::{Demangle, DemangleOptions};
 
+use crate::demangle_cache::demangle_name;
 use crate::output::get_target_output_writable;
+use crate::summary::coverage_ratio_or;
 
 macro_rules! demangle {
-    ($name: expr, $demangle: expr, $options: expr) => {{
+    ($name: expr, $demangle: expr) => {{
         if $demangle {
-            Name::from($name)
-                .demangle($options)
-                .unwrap_or_else(|| $name.clone())
+            demangle_name($name).unwrap_or_else(|| $name.clone())
         } else {
             $name.clone()
         }
@@ -89,18 +87,14 @@ impl CoverageStats {
     }
 
     fn line_rate(&self) -> f64 {
-        if self.lines_valid > 0.0 {
-            self.lines_covered / self.lines_valid
-        } else {
-            0.0
-        }
+        coverage_ratio_or(self.lines_covered as u64, self.lines_valid as u64, 0.0)
     }
     fn branch_rate(&self) -> f64 {
-        if self.branches_valid > 0.0 {
-            self.branches_covered / self.branches_valid
-        } else {
-            0.0
-        }
+        coverage_ratio_or(
+            self.branches_covered as u64,
+            self.branches_valid as u64,
+            0.0,
+        )
     }
 }
 
@@ -228,12 +222,7 @@ impl ToString for ConditionType {
     }
 }
 
-fn get_coverage(
-    results: &[ResultTuple],
-    sources: Vec<String>,
-    demangle: bool,
-    demangle_options: DemangleOptions,
-) -> Coverage {
+fn get_coverage(results: &[ResultTuple], sources: Vec<String>, demangle: bool) -> Coverage {
     let packages: Vec<Package> = results
         .iter()
         .map(|(_, rel_path, result)| {
@@ -296,7 +285,7 @@ fn get_coverage(
                         .collect();
 
                     Method {
-                        name: demangle!(name, demangle, demangle_options),
+                        name: demangle!(name, demangle),
                         signature: String::new(),
                         lines,
                     }
@@ -331,12 +320,11 @@ pub fn output_cobertura(
     output_file: Option<&Path>,
     demangle: bool,
 ) {
-    let demangle_options = DemangleOptions::name_only();
     let sources = vec![source_dir
         .unwrap_or_else(|| Path::new("."))
         .display()
         .to_string()];
-    let coverage = get_coverage(results, sources, demangle, demangle_options);
+    let coverage = get_coverage(results, sources, demangle);
 
     let mut writer = Writer::new_with_indent(Cursor::new(vec![]), b' ', 4);
     writer
@@ -611,6 +599,7 @@ mod tests {
                     );
                     map
                 },
+                mcdc: None,
             },
             Result::Test => CovResult {
                 /* main.rs
@@ -688,6 +677,7 @@ mod tests {
                     );
                     map
                 },
+                mcdc: None,
             },
         }
     }
@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The name of the configuration file looked up in the working directory.
+pub const CONFIG_FILE_NAME: &str = "grcov.toml";
+
+/// The options which can be set in a configuration file. The keys are the names of the
+/// corresponding command line options.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConfigFile {
+    pub output_types: Option<Vec<String>>,
+    pub output_path: Option<PathBuf>,
+    pub output_config_file: Option<PathBuf>,
+    pub source_dir: Option<PathBuf>,
+    pub prefix_dir: Option<PathBuf>,
+    pub remap_path_prefixes: Option<Vec<String>>,
+    pub strip_filename_prefixes: Option<Vec<PathBuf>>,
+    pub auto_strip_prefix: Option<bool>,
+    pub add_filename_prefix: Option<PathBuf>,
+    pub strip_components: Option<usize>,
+    pub repo_relative: Option<bool>,
+    pub source_snapshot: Option<PathBuf>,
+    pub binary_path: Option<PathBuf>,
+    pub llvm_path: Option<PathBuf>,
+    pub capture_llvm_stderr: Option<bool>,
+    pub llvm_stderr_to_file: Option<PathBuf>,
+    pub path_mapping: Option<PathBuf>,
+    pub ignore_not_existing: Option<bool>,
+    pub ignore_missing_inputs: Option<bool>,
+    pub ignore: Option<Vec<String>>,
+    pub keep_only: Option<Vec<String>>,
+    pub skip_dir: Option<Vec<String>>,
+    pub no_grcovignore: Option<bool>,
+    pub strip_system_paths: Option<bool>,
+    pub system_path_prefixes: Option<Vec<PathBuf>>,
+    pub branch: Option<bool>,
+    pub mcdc: Option<bool>,
+    pub wasm: Option<bool>,
+    pub export_cache_dir: Option<PathBuf>,
+    pub no_export_cache: Option<bool>,
+    pub in_memory_profdata: Option<bool>,
+    pub fail_on_binary_mismatch: Option<bool>,
+    pub filter: Option<String>,
+    pub llvm: Option<bool>,
+    pub cache_dir: Option<PathBuf>,
+    pub no_cache: Option<bool>,
+    pub token: Option<String>,
+    pub commit_sha: Option<String>,
+    pub service_name: Option<String>,
+    pub service_number: Option<String>,
+    pub service_job_id: Option<String>,
+    pub service_pull_request: Option<String>,
+    pub parallel: Option<bool>,
+    pub threads: Option<usize>,
+    pub precision: Option<usize>,
+    pub output_encoding: Option<String>,
+    pub show_line_counts_or_regions: Option<bool>,
+    pub show_expansions: Option<bool>,
+    pub guess_directory_when_missing: Option<bool>,
+    pub vcs_branch: Option<String>,
+    pub log: Option<PathBuf>,
+    pub log_level: Option<String>,
+    pub log_format: Option<String>,
+    pub excl_line: Option<String>,
+    pub excl_start: Option<String>,
+    pub excl_stop: Option<String>,
+    pub excl_br_line: Option<String>,
+    pub excl_br_start: Option<String>,
+    pub excl_br_stop: Option<String>,
+    pub minimum_line_length: Option<usize>,
+    pub exclude_unreachable: Option<bool>,
+    pub unreachable_macro: Option<Vec<String>>,
+    pub include_fn: Option<String>,
+    pub exclude_fn: Option<String>,
+    pub exclude_synthesized_fn: Option<bool>,
+    pub excl_generated: Option<String>,
+    pub no_demangle: Option<bool>,
+    pub stage_profraws: Option<bool>,
+    pub sort_lcov: Option<bool>,
+    pub skip_unprofiled_binaries: Option<bool>,
+    pub cargo_messages: Option<PathBuf>,
+    pub cargo: Option<PathBuf>,
+    pub baseline_lcov: Option<Vec<PathBuf>>,
+    pub trend_file: Option<PathBuf>,
+    pub tag: Option<String>,
+    pub tag_cache_dir: Option<PathBuf>,
+    pub fail_under_line: Option<f64>,
+    pub fail_under_branch: Option<f64>,
+    pub fail_under_function: Option<f64>,
+    pub no_path_warning: Option<bool>,
+    pub fail_under: Option<Vec<String>>,
+    pub merge_mode: Option<Vec<String>>,
+    pub fail_empty_files: Option<bool>,
+    pub threshold_report: Option<PathBuf>,
+    pub report_ignored: Option<PathBuf>,
+    pub source_index: Option<PathBuf>,
+    pub max_memory: Option<usize>,
+    pub min_lines_in_file: Option<usize>,
+    pub depfile: Option<PathBuf>,
+    pub diagnostics_dump: Option<PathBuf>,
+    pub version_file: Option<PathBuf>,
+    pub dry_run: Option<bool>,
+    pub dry_run_format: Option<String>,
+    pub dedup_profraws: Option<bool>,
+    pub dedup_inputs: Option<String>,
+    pub watch: Option<bool>,
+    pub watch_debounce: Option<u64>,
+    pub serve: Option<String>,
+    pub quiet: Option<bool>,
+    pub print_summary: Option<bool>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(toml::de::Error),
+    UnknownKey(String, Option<String>),
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> ConfigError {
+        ConfigError::Parse(err)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref path, ref err) => {
+                write!(f, "Unable to read {}: {}", path.display(), err)
+            }
+            ConfigError::Parse(ref err) => write!(f, "Invalid configuration file: {}", err),
+            ConfigError::UnknownKey(ref key, Some(ref suggestion)) => write!(
+                f,
+                "Unknown configuration key '{}', did you mean '{}'?",
+                key, suggestion
+            ),
+            ConfigError::UnknownKey(ref key, None) => {
+                write!(f, "Unknown configuration key '{}'", key)
+            }
+        }
+    }
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut distances: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = distances[0];
+        distances[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = distances[j + 1];
+            distances[j + 1] = substitution.min(distances[j] + 1).min(previous + 1);
+        }
+    }
+    distances[b.len()]
+}
+
+impl ConfigFile {
+    fn keys() -> Vec<String> {
+        match serde_json::to_value(ConfigFile::default()) {
+            Ok(serde_json::Value::Object(map)) => map.keys().cloned().collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn closest_key(key: &str) -> Option<String> {
+        ConfigFile::keys()
+            .into_iter()
+            .map(|k| (levenshtein(key, &k), k))
+            .filter(|(distance, _)| *distance <= 3)
+            .min()
+            .map(|(_, k)| k)
+    }
+
+    pub fn parse(buffer: &str) -> Result<ConfigFile, ConfigError> {
+        let table: toml::value::Table = toml::from_str(buffer)?;
+        let keys = ConfigFile::keys();
+        if let Some(key) = table.keys().find(|key| !keys.contains(key)) {
+            return Err(ConfigError::UnknownKey(
+                key.to_string(),
+                ConfigFile::closest_key(key),
+            ));
+        }
+        Ok(toml::Value::Table(table).try_into()?)
+    }
+
+    /// Loads the configuration from the given file, or from grcov.toml in the working directory
+    /// if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Option<ConfigFile>, ConfigError> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(CONFIG_FILE_NAME).is_file() => Path::new(CONFIG_FILE_NAME),
+            None => return Ok(None),
+        };
+        let buffer =
+            fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        ConfigFile::parse(&buffer).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = ConfigFile::parse(
+            r#"
+            output-types = ["html", "lcov"]
+            output-path = "target/coverage"
+            source-dir = "."
+            binary-path = "target/debug"
+            ignore = ["/*", "target/*"]
+            branch = true
+            threads = 4
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            ConfigFile {
+                output_types: Some(vec!["html".to_string(), "lcov".to_string()]),
+                output_path: Some(PathBuf::from("target/coverage")),
+                source_dir: Some(PathBuf::from(".")),
+                binary_path: Some(PathBuf::from("target/debug")),
+                ignore: Some(vec!["/*".to_string(), "target/*".to_string()]),
+                branch: Some(true),
+                threads: Some(4),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_config_empty() {
+        assert_eq!(ConfigFile::parse("").unwrap(), ConfigFile::default());
+    }
+
+    #[test]
+    fn test_parse_config_unknown_key() {
+        let err = ConfigFile::parse("ignore-not-exist = true").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown configuration key 'ignore-not-exist', did you mean 'ignore-not-existing'?"
+        );
+
+        let err = ConfigFile::parse("keep_only = [\"src/*\"]").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown configuration key 'keep_only', did you mean 'keep-only'?"
+        );
+
+        let err = ConfigFile::parse("coverage-threshold = 80").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown configuration key 'coverage-threshold'"
+        );
+    }
+
+    #[test]
+    fn test_parse_config_invalid_value() {
+        let err = ConfigFile::parse("branch = \"yes\"").unwrap_err();
+        assert!(matches!(err, ConfigError::Parse(_)));
+        assert!(err.to_string().starts_with("Invalid configuration file:"));
+    }
+
+    #[test]
+    fn test_load_config_missing_file() {
+        let err = ConfigFile::load(Some(Path::new("test/missing.toml"))).unwrap_err();
+        assert!(matches!(err, ConfigError::Io(_, _)));
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("branch", "branch"), 0);
+        assert_eq!(levenshtein("brnch", "branch"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+}
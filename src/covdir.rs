@@ -1,7 +1,7 @@
 use serde_json::{json, map::Map};
-use std::collections::BTreeMap;
 
 pub use crate::defs::*;
+use crate::summary::coverage_pct_or;
 
 impl CDStats {
     pub fn new(total: usize, covered: usize, precision: usize) -> Self {
@@ -28,23 +28,19 @@ impl CDStats {
     }
 
     pub fn get_percent(x: usize, y: usize, precision: usize) -> f64 {
-        if y != 0 {
-            // This function calculates the coverage percentage with rounded decimal points up to `precision`.
-            // However the `serdes_json` will determine the final format of `coveragePercent` in the report.
-            // If `precision` is 0, then `coveragePercent` output will still have 1 (null) decimal place, i.e. 98.321... -> 98.0.
-            // If `coveragePercent` has multiple trailing zeros, they will be truncated to 1 decimal place i.e 98.0000... -> 98.0.
-            // These limitation are considered good enough behavior for covdir report, for an improved output
-            // a custom serdes_json serializer for `f64` would have to be written.
-            f64::round(x as f64 / (y as f64) * f64::powi(10.0, precision as i32 + 2))
-                / f64::powi(10.0, precision as i32)
-        } else {
-            0.0
-        }
+        // This function calculates the coverage percentage with rounded decimal points up to `precision`.
+        // However the `serdes_json` will determine the final format of `coveragePercent` in the report.
+        // If `precision` is 0, then `coveragePercent` output will still have 1 (null) decimal place, i.e. 98.321... -> 98.0.
+        // If `coveragePercent` has multiple trailing zeros, they will be truncated to 1 decimal place i.e 98.0000... -> 98.0.
+        // These limitation are considered good enough behavior for covdir report, for an improved output
+        // a custom serdes_json serializer for `f64` would have to be written.
+        f64::round(coverage_pct_or(x as u64, y as u64, 0.0) * f64::powi(10.0, precision as i32))
+            / f64::powi(10.0, precision as i32)
     }
 }
 
 impl CDFileStats {
-    pub fn new(name: String, coverage: BTreeMap<u32, u64>, precision: usize) -> Self {
+    pub fn new(name: String, coverage: &LineMap, precision: usize) -> Self {
         let (total, covered, lines) = Self::get_coverage(coverage);
         Self {
             name,
@@ -53,9 +49,9 @@ impl CDFileStats {
         }
     }
 
-    fn get_coverage(coverage: BTreeMap<u32, u64>) -> (usize, usize, Vec<i64>) {
+    fn get_coverage(coverage: &LineMap) -> (usize, usize, Vec<i64>) {
         let mut covered = 0;
-        let last_line = *coverage.keys().last().unwrap_or(&0) as usize;
+        let last_line = coverage.last_line().unwrap_or(0) as usize;
         let total = coverage.len();
         let mut lines: Vec<i64> = vec![-1; last_line];
         for (line_num, line_count) in coverage.iter() {
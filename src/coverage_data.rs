@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::defs::*;
+use crate::output::write_lcov_record;
+use crate::path_utils::{filter_results_by_path, map_result_paths};
+use crate::{merge_results, parse_lcov_reader, ParserError};
+
+/// The coverage of the source files, by their paths, for the users of grcov as a library which
+/// read and write lcov reports. Writing it to lcov and reading it back gives the same coverage.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CoverageData {
+    pub files: BTreeMap<String, CovResult>,
+}
+
+impl CoverageData {
+    /// Reads an lcov report, with its branches. The records of the same source file are merged,
+    /// summing their execution counts whatever the --merge-mode of the CLI.
+    pub fn read_lcov<R: BufRead>(reader: R) -> Result<Self, ParserError> {
+        let mut files: BTreeMap<String, CovResult> = BTreeMap::new();
+        for (path, result) in parse_lcov_reader(reader, true)? {
+            match files.get_mut(&path) {
+                Some(file_result) => {
+                    merge_results(file_result, result);
+                }
+                None => {
+                    files.insert(path, result);
+                }
+            }
+        }
+        Ok(CoverageData { files })
+    }
+
+    /// Writes an lcov report, with a record for each source file, as the lcov output does.
+    pub fn write_lcov<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"TN:\n")?;
+        for (path, result) in &self.files {
+            write_lcov_record(writer, path, result, false)?;
+        }
+        Ok(())
+    }
+
+    /// Only keeps the source files whose path matches the predicate, as filter_results_by_path
+    /// does for the CLI.
+    pub fn filter_by_path<F>(self, predicate: F) -> Self
+    where
+        F: Fn(&Path) -> bool,
+    {
+        Self::from_results(filter_results_by_path(self.into_results(), predicate))
+    }
+
+    /// Transforms the paths of the source files, e.g. with strip_filename_prefix, as
+    /// map_result_paths does for the CLI. The results of the files transformed to the same path
+    /// are merged, summing their execution counts.
+    pub fn map_paths<F>(self, f: F) -> Self
+    where
+        F: Fn(PathBuf) -> PathBuf,
+    {
+        Self::from_results(map_result_paths(self.into_results(), f))
+    }
+
+    // The coverage of the results, by their relative paths. The results of the same path are
+    // merged.
+    fn from_results(results: Vec<ResultTuple>) -> Self {
+        let mut files: BTreeMap<String, CovResult> = BTreeMap::new();
+        for (_, rel_path, result) in results {
+            let path = rel_path.to_string_lossy().into_owned();
+            match files.get_mut(&path) {
+                Some(file_result) => {
+                    merge_results(file_result, result);
+                }
+                None => {
+                    files.insert(path, result);
+                }
+            }
+        }
+        CoverageData { files }
+    }
+
+    /// The results of the source files, sorted by path, as the outputs take them. The paths of
+    /// the report are both the absolute and the relative paths of the files.
+    pub fn into_results(self) -> Vec<ResultTuple> {
+        self.files
+            .into_iter()
+            .map(|(path, result)| (PathBuf::from(&path), PathBuf::from(path), result))
+            .collect()
+    }
+}
+
+/// Reads an lcov tracefile into the results of its source files, so that an existing report can
+/// be converted to the other output formats.
+pub fn lcov_file_to_results(path: &Path) -> Result<Vec<ResultTuple>, ParserError> {
+    let file = File::open(path)?;
+    Ok(CoverageData::read_lcov(BufReader::new(file))?.into_results())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{results_to_markdown, strip_filename_prefix, total_stats};
+    use proptest::collection::{btree_map, hash_map, vec};
+    use proptest::prelude::*;
+    use rustc_hash::FxHashSet;
+    use std::fs;
+
+    fn cov_result() -> impl Strategy<Value = CovResult> {
+        (
+            btree_map(any::<u32>(), any::<u64>(), 0..20),
+            btree_map(any::<u32>(), vec(any::<bool>(), 1..5), 0..5),
+            hash_map(
+                "[A-Za-z_][A-Za-z0-9_:<>, ]{0,20}",
+                (any::<u32>(), any::<bool>()),
+                0..5,
+            ),
+        )
+            .prop_map(|(lines, branches, functions)| CovResult {
+                lines: lines.into(),
+                branches,
+                functions: functions
+                    .into_iter()
+                    .map(|(name, (start, executed))| (name, Function { start, executed }))
+                    .collect(),
+                mcdc: None,
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn test_filter_and_map_paths(
+            files in btree_map("(src|tests)(/[a-z]{1,4}){1,3}\\.rs", cov_result(), 0..10)
+        ) {
+            let data = CoverageData { files };
+            let kept = data.clone().filter_by_path(|path| path.starts_with("src/"));
+            prop_assert!(kept.files.keys().all(|path| path.starts_with("src/")));
+            prop_assert_eq!(
+                kept.files.len(),
+                data.files.keys().filter(|path| path.starts_with("src/")).count()
+            );
+
+            let stripped = kept
+                .clone()
+                .map_paths(|path| path.strip_prefix("src/").unwrap().to_owned());
+            prop_assert_eq!(stripped.files.len(), kept.files.len());
+            for (path, result) in &kept.files {
+                prop_assert_eq!(&stripped.files[&path["src/".len()..]], result);
+            }
+            prop_assert_eq!(data.clone().map_paths(|path| path), data.clone());
+            let prefixes = [PathBuf::from("src")];
+            prop_assert_eq!(
+                kept.map_paths(|path| strip_filename_prefix(path, &prefixes)),
+                stripped
+            );
+
+            // The files mapped to the same path are merged.
+            let merged = data.clone().map_paths(|_| PathBuf::from("all.rs"));
+            prop_assert_eq!(merged.files.len(), usize::from(!data.files.is_empty()));
+            let lines: FxHashSet<u32> = data
+                .files
+                .values()
+                .flat_map(|result| result.lines.keys().cloned())
+                .collect();
+            let merged_lines = merged.files.values().map(|result| result.lines.len()).sum::<usize>();
+            prop_assert_eq!(merged_lines, lines.len());
+        }
+
+        #[test]
+        fn test_lcov_round_trip(
+            files in btree_map("[a-z]{1,8}(/[a-z0-9_.-]{1,8}){0,3}\\.rs", cov_result(), 0..5)
+        ) {
+            let data = CoverageData { files };
+            let mut lcov = Vec::new();
+            data.write_lcov(&mut lcov).unwrap();
+            prop_assert_eq!(CoverageData::read_lcov(&lcov[..]).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_read_lcov() {
+        let info = fs::read("test/prova.info").unwrap();
+        let data = CoverageData::read_lcov(&info[..]).unwrap();
+        assert!(data
+            .files
+            .contains_key("resource://gre/components/MainProcessSingleton.js"));
+
+        let mut lcov = Vec::new();
+        data.write_lcov(&mut lcov).unwrap();
+        assert_eq!(CoverageData::read_lcov(&lcov[..]).unwrap(), data);
+
+        assert!(CoverageData::read_lcov(&b"SF:src/lib.rs\nDA:1"[..]).is_err());
+    }
+
+    #[test]
+    fn test_lcov_file_to_results() {
+        let results = lcov_file_to_results(Path::new("test/prova.info")).unwrap();
+        let data = CoverageData::read_lcov(&fs::read("test/prova.info").unwrap()[..]).unwrap();
+        assert_eq!(results.len(), data.files.len());
+        for ((abs_path, rel_path, result), (path, expected)) in results.iter().zip(&data.files) {
+            assert_eq!(abs_path, Path::new(path));
+            assert_eq!(rel_path, Path::new(path));
+            assert_eq!(result, expected);
+        }
+
+        let summary = results_to_markdown(&results);
+        let total = total_stats(&results);
+        let total_row = summary
+            .lines()
+            .find(|line| line.contains("**Total**"))
+            .unwrap();
+        assert!(total_row.contains(&format!("{}/{}", total.covered_lines, total.total_lines)));
+        assert!(total.total_lines > 0);
+        assert!(summary.contains("resource://gre/components/MainProcessSingleton.js"));
+
+        assert!(matches!(
+            lcov_file_to_results(Path::new("test/missing.info")),
+            Err(ParserError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,345 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::defs::*;
+use crate::summary::plural;
+use crate::{merge_results, parse_jacoco_xml_report, parse_lcov, parse_llvm_cov_json};
+
+/// The format of the report of `grcov diff`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffFormat {
+    Text,
+    Json,
+    Html,
+}
+
+impl FromStr for DiffFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(DiffFormat::Text),
+            "json" => Ok(DiffFormat::Json),
+            "html" => Ok(DiffFormat::Html),
+            _ => Err(format!("{} is not a supported diff format", s)),
+        }
+    }
+}
+
+/// Reads a coverage report, in the format given by its extension: JaCoCo XML (`.xml`), llvm-cov
+/// or gcov JSON (`.json`), or else lcov. The results of the same file are merged.
+pub fn read_coverage_report(path: &Path) -> Result<BTreeMap<String, CovResult>, String> {
+    let buffer = fs::read(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let results = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("xml") => parse_jacoco_xml_report(BufReader::new(Cursor::new(buffer))),
+        Some("json") => parse_llvm_cov_json(&buffer, true, false),
+        _ => parse_lcov(&buffer, true),
+    }
+    .map_err(|e| format!("Invalid coverage report {}: {}", path.display(), e))?;
+
+    let mut merged: BTreeMap<String, CovResult> = BTreeMap::new();
+    for (path, result) in results {
+        match merged.get_mut(&path) {
+            Some(merged_result) => {
+                merge_results(merged_result, result);
+            }
+            None => {
+                merged.insert(path, result);
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// The lines of a source file whose coverage changed between two reports.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct FileDiff {
+    pub path: String,
+    /// The lines covered before, and not anymore.
+    pub regressions: Vec<u32>,
+    /// The lines which weren't covered before, and are now.
+    pub improvements: Vec<u32>,
+    /// The instrumented lines which weren't instrumented before, e.g. added code.
+    pub added: Vec<u32>,
+}
+
+/// The changes of the coverage between two reports, for the files with changes.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct CoverageDiff {
+    pub files: Vec<FileDiff>,
+}
+
+// Writes the lines as ranges, e.g. "3-5, 9".
+fn line_ranges(lines: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &line in lines {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == line => *end = line,
+            _ => ranges.push((line, line)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+impl CoverageDiff {
+    /// Compares the line coverage of each source file of `after` with the one of `before`.
+    pub fn new(
+        before: &BTreeMap<String, CovResult>,
+        after: &BTreeMap<String, CovResult>,
+    ) -> CoverageDiff {
+        let no_lines = LineMap::new();
+        let files = after
+            .iter()
+            .map(|(path, result)| {
+                let before_lines = before.get(path).map_or(&no_lines, |result| &result.lines);
+                let mut diff = FileDiff {
+                    path: path.clone(),
+                    ..FileDiff::default()
+                };
+                for (&line, &count) in &result.lines {
+                    match before_lines.get(&line) {
+                        Some(&before_count) if before_count > 0 && count == 0 => {
+                            diff.regressions.push(line)
+                        }
+                        Some(&before_count) if before_count == 0 && count > 0 => {
+                            diff.improvements.push(line)
+                        }
+                        Some(_) => {}
+                        None => diff.added.push(line),
+                    }
+                }
+                diff
+            })
+            .filter(|diff| {
+                !diff.regressions.is_empty()
+                    || !diff.improvements.is_empty()
+                    || !diff.added.is_empty()
+            })
+            .collect();
+        CoverageDiff { files }
+    }
+
+    pub fn has_regressions(&self) -> bool {
+        self.files.iter().any(|file| !file.regressions.is_empty())
+    }
+
+    fn count(&self, lines: impl Fn(&FileDiff) -> &Vec<u32>) -> usize {
+        self.files.iter().map(|file| lines(file).len()).sum()
+    }
+
+    /// Lists the changes of each file, with the regressions in red and the improvements in green
+    /// with `color`.
+    pub fn to_text(&self, color: bool) -> String {
+        let paint = |color_code: &str, text: String| {
+            if color {
+                format!("{}{}{}", color_code, text, RESET)
+            } else {
+                text
+            }
+        };
+        let mut text = String::new();
+        for file in &self.files {
+            writeln!(text, "{}", file.path).unwrap();
+            if !file.regressions.is_empty() {
+                let line = format!("  regressed: {}", line_ranges(&file.regressions));
+                writeln!(text, "{}", paint(RED, line)).unwrap();
+            }
+            if !file.improvements.is_empty() {
+                let line = format!("  improved:  {}", line_ranges(&file.improvements));
+                writeln!(text, "{}", paint(GREEN, line)).unwrap();
+            }
+            if !file.added.is_empty() {
+                writeln!(text, "  added:     {}", line_ranges(&file.added)).unwrap();
+            }
+        }
+        writeln!(
+            text,
+            "{} regressed, {} improved and {} added in {}",
+            plural(self.count(|file| &file.regressions), "line", "lines"),
+            self.count(|file| &file.improvements),
+            self.count(|file| &file.added),
+            plural(self.files.len(), "file", "files")
+        )
+        .unwrap();
+        text
+    }
+
+    /// Writes a standalone HTML page, with a table of the changed lines of each file.
+    pub fn to_html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Coverage diff</title>\n<style>\n\
+             body { font-family: sans-serif; }\n\
+             table { border-collapse: collapse; margin-bottom: 1em; }\n\
+             td, th { border: 1px solid #ccc; padding: 2px 8px; text-align: left; }\n\
+             .regression { background: #f8d0d0; color: #a00000; }\n\
+             .improvement { background: #d0f0d0; color: #006000; }\n\
+             .added { background: #e8eef8; }\n\
+             </style>\n</head>\n<body>\n<h1>Coverage diff</h1>\n",
+        );
+        writeln!(
+            html,
+            "<p><span class=\"regression\">{} regressed</span>, \
+             <span class=\"improvement\">{} improved</span> and \
+             <span class=\"added\">{} added</span> in {} files.</p>",
+            plural(self.count(|file| &file.regressions), "line", "lines"),
+            self.count(|file| &file.improvements),
+            self.count(|file| &file.added),
+            plural(self.files.len(), "file", "files")
+        )
+        .unwrap();
+        for file in &self.files {
+            writeln!(html, "<h2>{}</h2>", tera::escape_html(&file.path)).unwrap();
+            html.push_str("<table>\n<tr><th>Lines</th><th>Change</th></tr>\n");
+            for (lines, class, change) in [
+                (
+                    &file.regressions,
+                    "regression",
+                    "covered before, not anymore",
+                ),
+                (&file.improvements, "improvement", "covered now"),
+                (&file.added, "added", "new"),
+            ] {
+                if !lines.is_empty() {
+                    writeln!(
+                        html,
+                        "<tr class=\"{}\"><td>{}</td><td>{}</td></tr>",
+                        class,
+                        line_ranges(lines),
+                        change
+                    )
+                    .unwrap();
+                }
+            }
+            html.push_str("</table>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    pub fn format(&self, format: DiffFormat, color: bool) -> String {
+        match format {
+            DiffFormat::Text => self.to_text(color),
+            DiffFormat::Json => serde_json::to_string_pretty(self).unwrap(),
+            DiffFormat::Html => self.to_html(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(lines: &[(u32, u64)]) -> CovResult {
+        CovResult {
+            lines: lines.iter().cloned().collect(),
+            branches: BTreeMap::new(),
+            functions: FunctionMap::default(),
+            mcdc: None,
+        }
+    }
+
+    fn reports() -> (BTreeMap<String, CovResult>, BTreeMap<String, CovResult>) {
+        let before = vec![
+            (
+                "src/lib.rs".to_string(),
+                result(&[(1, 1), (2, 1), (3, 1), (4, 0), (5, 1)]),
+            ),
+            ("src/same.rs".to_string(), result(&[(1, 1)])),
+            ("src/removed.rs".to_string(), result(&[(1, 1)])),
+        ]
+        .into_iter()
+        .collect();
+        let after = vec![
+            (
+                "src/lib.rs".to_string(),
+                result(&[(1, 1), (2, 0), (3, 0), (4, 2), (5, 3), (7, 0)]),
+            ),
+            ("src/same.rs".to_string(), result(&[(1, 4)])),
+            ("src/new.rs".to_string(), result(&[(1, 1), (2, 0)])),
+        ]
+        .into_iter()
+        .collect();
+        (before, after)
+    }
+
+    #[test]
+    fn test_coverage_diff() {
+        let (before, after) = reports();
+        let diff = CoverageDiff::new(&before, &after);
+        assert_eq!(
+            diff.files,
+            vec![
+                FileDiff {
+                    path: "src/lib.rs".to_string(),
+                    regressions: vec![2, 3],
+                    improvements: vec![4],
+                    added: vec![7],
+                },
+                FileDiff {
+                    path: "src/new.rs".to_string(),
+                    regressions: vec![],
+                    improvements: vec![],
+                    added: vec![1, 2],
+                },
+            ]
+        );
+        assert!(diff.has_regressions());
+        assert!(!CoverageDiff::new(&after, &after).has_regressions());
+
+        assert_eq!(
+            diff.format(DiffFormat::Text, false),
+            "src/lib.rs\n  regressed: 2-3\n  improved:  4\n  added:     7\n\
+             src/new.rs\n  added:     1-2\n\
+             2 lines regressed, 1 improved and 3 added in 2 files\n"
+        );
+        assert!(diff
+            .format(DiffFormat::Text, true)
+            .contains("\x1b[31m  regressed: 2-3\x1b[0m"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&diff.format(DiffFormat::Json, false)).unwrap();
+        assert_eq!(json["files"][0]["regressions"], serde_json::json!([2, 3]));
+
+        let html = diff.format(DiffFormat::Html, false);
+        assert!(html.contains("<tr class=\"regression\"><td>2-3</td>"));
+        assert!(html.contains("<h2>src&#x2F;new.rs</h2>"));
+    }
+
+    #[test]
+    fn test_read_coverage_report() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let path = tmp_dir.path().join("lcov.info");
+        fs::write(
+            &path,
+            "SF:src/lib.rs\nDA:1,1\nDA:2,0\nend_of_record\nSF:src/lib.rs\nDA:2,3\nend_of_record\n",
+        )
+        .unwrap();
+        let results = read_coverage_report(&path).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results["src/lib.rs"].lines,
+            [(1, 1), (2, 3)].iter().cloned().collect()
+        );
+        assert!(read_coverage_report(&tmp_dir.path().join("missing.info")).is_err());
+    }
+}
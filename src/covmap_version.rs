@@ -0,0 +1,162 @@
+//! The version of the LLVM coverage mapping format of the binaries, checked against the versions
+//! supported by the installed llvm-cov (see https://llvm.org/docs/CoverageMappingFormat.html).
+
+use object::{Object, ObjectSection};
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+use crate::raw_coverage::COVMAP_SECTIONS;
+use crate::wasm::{is_wasm_module, wasm_custom_sections};
+
+// The size of the header of a coverage mapping record: the number of function records, the size
+// of the file names, the size of the coverage mapping and the version.
+const HEADER_SIZE: usize = 16;
+// The version from which the function records are stored in `__llvm_covfun`, so that each record
+// of `__llvm_covmap` only has the file names after its header.
+const VERSION_4: u32 = 4;
+
+/// Returns the version of the coverage mapping of a binary, counted from 1 as in LLVM (e.g. 6 for
+/// `CovMapVersion::Version6`), or None when the binary has no coverage mapping. The highest
+/// version is returned when its records have different versions.
+pub fn covmap_version(binary: &[u8]) -> Result<Option<u32>, String> {
+    if is_wasm_module(binary) {
+        // The WebAssembly modules are little-endian.
+        let sections = wasm_custom_sections(binary)?;
+        return Ok(sections
+            .into_iter()
+            .filter(|(name, _)| COVMAP_SECTIONS.contains(name))
+            .filter_map(|(_, data)| records_version(data, u32::from_le_bytes))
+            .max());
+    }
+
+    let obj = object::File::parse(binary).map_err(|e| format!("Invalid binary: {}", e))?;
+    let read_u32 = if obj.is_little_endian() {
+        u32::from_le_bytes
+    } else {
+        u32::from_be_bytes
+    };
+
+    let mut version = None;
+    for section in obj.sections() {
+        if !matches!(section.name(), Ok(name) if COVMAP_SECTIONS.contains(&name)) {
+            continue;
+        }
+        let data = section
+            .data()
+            .map_err(|e| format!("Invalid coverage mapping: {}", e))?;
+        version = version.max(records_version(data, read_u32));
+    }
+    Ok(version)
+}
+
+// Returns the highest version of the records of a coverage mapping section.
+fn records_version(data: &[u8], read_u32: fn([u8; 4]) -> u32) -> Option<u32> {
+    let read_u32 = |bytes: &[u8]| read_u32(bytes.try_into().unwrap());
+    let mut version = None;
+    let mut offset = 0;
+    while offset + HEADER_SIZE <= data.len() {
+        let filenames_size = read_u32(&data[offset + 4..offset + 8]) as usize;
+        // The versions are encoded starting from 0.
+        let record_version = read_u32(&data[offset + 12..offset + 16]) + 1;
+        version = version.max(Some(record_version));
+        if record_version < VERSION_4 {
+            // The size of the older records depends on their function records, and all the
+            // records of a binary have the same version anyway.
+            break;
+        }
+        offset = (offset + HEADER_SIZE + filenames_size + 7) & !7;
+    }
+    version
+}
+
+/// Returns the highest coverage mapping version supported by a major version of LLVM.
+pub fn max_covmap_version(llvm_major: u32) -> u32 {
+    match llvm_major {
+        18.. => 7,
+        13..=17 => 6,
+        12 => 5,
+        11 => 4,
+        8..=10 => 3,
+        5..=7 => 2,
+        _ => 1,
+    }
+}
+
+/// Parses the major version of LLVM from the output of `llvm-cov --version`, e.g. 17 from
+/// "LLVM version 17.0.6-rust-1.75.0-stable".
+pub fn parse_llvm_major_version(output: &str) -> Option<u32> {
+    let version = output.split("LLVM version ").nth(1)?;
+    version
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Checks that the coverage mapping of a binary can be read by the llvm-cov of the given major
+/// version, and returns its version if it has one.
+pub fn check_covmap_version(binary_path: &Path, llvm_major: u32) -> Result<Option<u32>, String> {
+    let binary = fs::read(binary_path)
+        .map_err(|e| format!("Unable to read {}: {}", binary_path.display(), e))?;
+    let version = covmap_version(&binary)?;
+    match version {
+        Some(version) if version > max_covmap_version(llvm_major) => Err(format!(
+            "The coverage mapping of {} is in version {} of the format, while llvm-cov {} only \
+             supports up to version {}. Use the llvm-tools of the toolchain which built it, e.g. \
+             with `rustup component add llvm-tools-preview` or --llvm-path.",
+            binary_path.display(),
+            version,
+            llvm_major,
+            max_covmap_version(llvm_major)
+        )),
+        _ => Ok(version),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_covmap_version() {
+        let sample = fs::read("test/raw_coverage/sample.o").unwrap();
+        assert_eq!(covmap_version(&sample).unwrap(), Some(7));
+        let no_coverage = fs::read("test/raw_coverage/no_coverage.o").unwrap();
+        assert_eq!(covmap_version(&no_coverage).unwrap(), None);
+        assert!(covmap_version(b"not a binary").is_err());
+
+        // A record of version 7 (encoded as 6), with 8 bytes of file names.
+        let mut record = [0u8; 24];
+        record[4] = 8;
+        record[12] = 6;
+        let module = crate::wasm::tests::wasm_module(&[("__llvm_covmap", &record)]);
+        assert_eq!(covmap_version(&module).unwrap(), Some(7));
+        let module = crate::wasm::tests::wasm_module(&[]);
+        assert_eq!(covmap_version(&module).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_llvm_major_version() {
+        let output = "LLVM (http://llvm.org/):\n  LLVM version 17.0.6-rust-1.75.0-stable\n  Optimized build.\n";
+        assert_eq!(parse_llvm_major_version(output), Some(17));
+        assert_eq!(
+            parse_llvm_major_version("Ubuntu LLVM version 14.0.0\n"),
+            Some(14)
+        );
+        assert_eq!(parse_llvm_major_version("llvm-cov 1.0"), None);
+    }
+
+    #[test]
+    fn test_check_covmap_version() {
+        let sample = Path::new("test/raw_coverage/sample.o");
+        assert_eq!(check_covmap_version(sample, 18).unwrap(), Some(7));
+        let err = check_covmap_version(sample, 17).unwrap_err();
+        assert!(err.contains("version 7"));
+        assert!(err.contains("llvm-cov 17 only supports up to version 6"));
+        assert_eq!(
+            check_covmap_version(Path::new("test/raw_coverage/no_coverage.o"), 10).unwrap(),
+            None
+        );
+    }
+}
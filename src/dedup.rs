@@ -0,0 +1,144 @@
+use md5::{Digest, Md5};
+use rustc_hash::FxHashMap;
+use std::str::FromStr;
+
+use crate::producer::InputKind;
+
+/// The kinds of input files whose copies with the same content are skipped, set with
+/// --dedup-inputs. The `.profraw` files are deduplicated by default, as the same profile is never
+/// meant to be counted twice, while the lcov files are not, as merging the same report several
+/// times can be intended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DedupInputs(Vec<InputKind>);
+
+impl DedupInputs {
+    pub fn none() -> Self {
+        DedupInputs(Vec::new())
+    }
+
+    pub fn contains(&self, kind: InputKind) -> bool {
+        self.0.contains(&kind)
+    }
+
+    pub fn add(&mut self, kind: InputKind) {
+        if !self.contains(kind) {
+            self.0.push(kind);
+        }
+    }
+}
+
+impl Default for DedupInputs {
+    fn default() -> Self {
+        DedupInputs(vec![InputKind::Profraw])
+    }
+}
+
+impl FromStr for DedupInputs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut kinds = DedupInputs::none();
+        if s == "none" {
+            return Ok(kinds);
+        }
+        for kind in s.split(',') {
+            kinds.add(match kind.trim() {
+                "profraw" => InputKind::Profraw,
+                "gcda" => InputKind::Gcda,
+                "info" => InputKind::Info,
+                "jacoco-xml" => InputKind::JacocoXml,
+                kind => {
+                    return Err(format!(
+                        "{} can't be deduplicated, the kinds are profraw, gcda, info and jacoco-xml (or none)",
+                        kind
+                    ))
+                }
+            });
+        }
+        Ok(kinds)
+    }
+}
+
+/// The contents of the inputs seen by the producer, by their size and hash, to find the copies of
+/// the same file, e.g. the artifacts of a CI job copied in several directories.
+#[derive(Debug, Default)]
+pub struct SeenInputs(FxHashMap<(InputKind, String, usize, [u8; 16]), String>);
+
+impl SeenInputs {
+    /// Returns the name of the first input of this kind with the same content and `key` (e.g. the
+    /// stem of a gcda file, whose counts only mean something for its gcno), or records this one.
+    /// The empty inputs have nothing to count, and are never duplicates.
+    pub fn first_with_content(
+        &mut self,
+        kind: InputKind,
+        key: &str,
+        content: &[u8],
+        name: &str,
+    ) -> Option<String> {
+        if content.is_empty() {
+            return None;
+        }
+        let hash = (
+            kind,
+            key.to_string(),
+            content.len(),
+            Md5::digest(content).into(),
+        );
+        match self.0.get(&hash) {
+            Some(first) => Some(first.clone()),
+            None => {
+                self.0.insert(hash, name.to_string());
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup_inputs() {
+        let kinds: DedupInputs = "profraw, info".parse().unwrap();
+        assert!(kinds.contains(InputKind::Profraw));
+        assert!(kinds.contains(InputKind::Info));
+        assert!(!kinds.contains(InputKind::Gcda));
+        assert_eq!("none".parse::<DedupInputs>().unwrap(), DedupInputs::none());
+        assert_eq!(
+            "profraw".parse::<DedupInputs>().unwrap(),
+            DedupInputs::default()
+        );
+        assert!("gcno".parse::<DedupInputs>().is_err());
+    }
+
+    #[test]
+    fn test_seen_inputs() {
+        let mut seen = SeenInputs::default();
+        let info = b"SF:src/lib.rs\nDA:1,1\nend_of_record\n";
+        assert_eq!(
+            seen.first_with_content(InputKind::Info, "", info, "job1/lcov.info"),
+            None
+        );
+        assert_eq!(
+            seen.first_with_content(InputKind::Info, "", info, "job2/lcov.info"),
+            Some("job1/lcov.info".to_string())
+        );
+        assert_eq!(
+            seen.first_with_content(InputKind::Gcda, "", info, "job2/lcov.gcda"),
+            None
+        );
+        assert_eq!(
+            seen.first_with_content(InputKind::Gcda, "other", info, "job2/other.gcda"),
+            None
+        );
+        assert_eq!(
+            seen.first_with_content(InputKind::Info, "", b"", "job1/empty.info"),
+            None
+        );
+        assert_eq!(
+            seen.first_with_content(InputKind::Info, "", b"", "job2/empty.info"),
+            None
+        );
+    }
+}
@@ -4,21 +4,51 @@ use serde::ser::{Serialize, Serializer};
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
+use std::ops::Deref;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Mutex;
 
+pub use crate::line_map::LineMap;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Function {
     pub start: u32,
     pub executed: bool,
 }
 
+/// An MC/DC decision, with whether the independence pair of each of its conditions is covered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct McdcRecord {
+    pub line: u32,
+    pub conditions: Vec<bool>,
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct CovResult {
-    pub lines: BTreeMap<u32, u64>,
+    pub lines: LineMap,
     pub branches: BTreeMap<u32, Vec<bool>>,
     pub functions: FunctionMap,
+    /// The MC/DC decisions of the file, when its coverage has MC/DC records.
+    pub mcdc: Option<Vec<McdcRecord>>,
+}
+
+impl CovResult {
+    /// Keeps the MC/DC decisions whose line is given a new line by `remap`, at this line.
+    pub fn remap_mcdc(&mut self, mut remap: impl FnMut(u32) -> Option<u32>) {
+        if let Some(mcdc) = &mut self.mcdc {
+            mcdc.retain_mut(|record| match remap(record.line) {
+                Some(line) => {
+                    record.line = line;
+                    true
+                }
+                None => false,
+            });
+            if mcdc.is_empty() {
+                self.mcdc = None;
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -34,14 +64,70 @@ pub struct GcnoBuffers {
     pub stem: String,
     pub gcno_buf: Vec<u8>,
     pub gcda_buf: Vec<Vec<u8>>,
+    /// Where the gcno file was read from, for its parsed content to be cached.
+    pub gcno_entry: Option<ArchiveEntry>,
+}
+
+/// Where an input file is: a file of a zip archive, or a file on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveEntry {
+    Zip(PathBuf, String),
+    File(PathBuf),
+}
+
+/// The gcno file of a stem and its gcda files in the LLVM format, given by where they are, for
+/// the consumers to read them rather than the producer.
+#[derive(Debug)]
+pub struct GcnoEntries {
+    pub stem: String,
+    pub gcno: ArchiveEntry,
+    pub gcdas: Vec<ArchiveEntry>,
+}
+
+/// The gcno file of a stem and one of its gcda files in the GCC format, given by where they are,
+/// for the consumers to extract them and run gcov on them rather than the producer.
+#[derive(Debug)]
+pub struct GccEntries {
+    pub stem: String,
+    pub gcno: ArchiveEntry,
+    pub gcda: Option<ArchiveEntry>,
+}
+
+/// The content of an input file, read in memory, or mapped when the file is big.
+#[derive(Debug)]
+pub enum FileContent {
+    Buffer(Vec<u8>),
+    Mapped(memmap2::Mmap),
+}
+
+impl Deref for FileContent {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileContent::Buffer(buffer) => buffer,
+            FileContent::Mapped(mmap) => mmap,
+        }
+    }
+}
+
+impl AsRef<[u8]> for FileContent {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
 }
 
 #[derive(Debug)]
 pub enum ItemType {
     Path((String, PathBuf)),
     Paths(Vec<PathBuf>),
-    Content(Vec<u8>),
+    Content(FileContent),
+    /// A big file of a zip archive, given by the path of the archive and its name there, which is
+    /// read as it's parsed rather than in memory.
+    ZipEntry((PathBuf, String)),
     Buffers(GcnoBuffers),
+    Entries(GcnoEntries),
+    GccEntries(GccEntries),
 }
 
 #[derive(Debug)]
@@ -147,6 +233,6 @@ impl<'a> Serialize for StringOrRef<'a> {
 }
 
 pub struct JacocoReport {
-    pub lines: BTreeMap<u32, u64>,
+    pub lines: LineMap,
     pub branches: BTreeMap<u32, Vec<bool>>,
 }
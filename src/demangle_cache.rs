@@ -0,0 +1,57 @@
+use once_cell::sync::Lazy;
+use rustc_hash::{FxHashMap, FxHasher};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use symbolic_common::Name;
+use symbolic_demangle::{Demangle, DemangleOptions};
+
+// The cache is split in shards, each with its own lock, for the outputs written in parallel.
+const SHARDS: usize = 16;
+// The names kept by each shard, so that the cache doesn't grow without limit with the symbols of
+// a huge project: a full shard is cleared.
+const SHARD_CAPACITY: usize = 4096;
+
+type Shard = Mutex<FxHashMap<String, Option<String>>>;
+
+static CACHE: Lazy<Vec<Shard>> = Lazy::new(|| (0..SHARDS).map(|_| Shard::default()).collect());
+
+fn shard(name: &str) -> &'static Shard {
+    let mut hasher = FxHasher::default();
+    name.hash(&mut hasher);
+    &CACHE[hasher.finish() as usize % SHARDS]
+}
+
+/// Demangles a symbol name, or returns None when it isn't mangled. The same symbols appear in
+/// many compilation units (e.g. the monomorphized generics), so each one is only demangled once
+/// per run.
+pub fn demangle_name(name: &str) -> Option<String> {
+    let shard = shard(name);
+    if let Some(demangled) = shard.lock().unwrap().get(name) {
+        return demangled.clone();
+    }
+    // The name is demangled without the lock, as it's the slow part.
+    let demangled = Name::from(name).demangle(DemangleOptions::name_only());
+    let mut shard = shard.lock().unwrap();
+    if shard.len() >= SHARD_CAPACITY {
+        shard.clear();
+    }
+    shard.insert(name.to_string(), demangled.clone());
+    demangled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demangle_name() {
+        let mangled = "_ZN9quick_xml6reader15Reader$LT$B$GT$10read_event17h4f1c3b56a7b8e9d0E";
+        let expected = Some("quick_xml::reader::Reader<B>::read_event".to_string());
+        assert_eq!(demangle_name(mangled), expected);
+        assert!(shard(mangled).lock().unwrap().contains_key(mangled));
+        assert_eq!(demangle_name(mangled), expected);
+
+        assert_eq!(demangle_name("main"), None);
+        assert_eq!(demangle_name("main"), None);
+    }
+}
@@ -0,0 +1,194 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::llvm_tools::{is_binary, split_binary_paths, walk_binary_dir};
+
+// The extensions of the files read by the producer in the input directories.
+const INPUT_EXTENSIONS: &[&str] = &["gcno", "gcda", "profraw", "profdata", "info", "xml"];
+
+/// Escapes a path for a Makefile rule: the spaces and '#' are escaped with a backslash, and '$'
+/// is doubled.
+pub fn escape_make_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            ' ' | '\t' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '$' => escaped.push_str("$$"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Produces a Makefile rule making the targets depend on the given files, one per line.
+pub fn depfile_rule(targets: &[PathBuf], dependencies: &[PathBuf]) -> String {
+    let mut rule = targets
+        .iter()
+        .map(|target| escape_make_path(target))
+        .collect::<Vec<_>>()
+        .join(" ");
+    rule.push(':');
+    for dependency in dependencies {
+        rule.push_str(" \\\n  ");
+        rule.push_str(&escape_make_path(dependency));
+    }
+    rule.push('\n');
+    rule
+}
+
+/// Iterates over the files which can be read from the input paths: the files and archives given
+/// directly, and the coverage files in the directories, which are walked lazily.
+pub fn input_files(paths: &[String]) -> impl Iterator<Item = PathBuf> + '_ {
+    paths.iter().flat_map(|path| {
+        let path = Path::new(path);
+        let (file, dir) = if path.is_dir() {
+            (None, Some(WalkDir::new(path)))
+        } else {
+            (Some(path.to_path_buf()), None)
+        };
+        let dir_files = dir
+            .into_iter()
+            .flat_map(|dir| dir.into_iter().filter_map(Result::ok))
+            .filter(|entry| {
+                let entry_path = entry.path();
+                let is_input = entry_path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| INPUT_EXTENSIONS.contains(&ext))
+                    || entry_path.file_name() == Some("linked-files-map.json".as_ref());
+                is_input && entry.file_type().is_file()
+            })
+            .map(|entry| entry.into_path());
+        file.into_iter().chain(dir_files)
+    })
+}
+
+/// Lists the files which can be read from the input paths: the files and archives given
+/// directly, and the coverage files in the directories.
+pub fn find_input_files(paths: &[String]) -> Vec<PathBuf> {
+    input_files(paths).collect()
+}
+
+/// Lists the binaries whose coverage mapping is read for the source-based coverage, like
+/// profraws_to_lcov does.
+pub fn find_binaries(binary_path: &Path) -> Vec<PathBuf> {
+    let mut binaries = Vec::new();
+    for binary_path in split_binary_paths(binary_path) {
+        if binary_path.is_file() {
+            binaries.push(binary_path);
+            continue;
+        }
+        binaries.extend(
+            walk_binary_dir(&binary_path)
+                .filter_map(Result::ok)
+                .filter(|entry| {
+                    is_binary(entry.path()) && entry.metadata().is_ok_and(|m| m.len() > 0)
+                })
+                .map(|entry| entry.into_path()),
+        );
+    }
+    binaries
+}
+
+/// Writes a Makefile dependency file making the outputs depend on all the files read by grcov.
+pub fn write_depfile(
+    path: &Path,
+    targets: &[PathBuf],
+    mut dependencies: Vec<PathBuf>,
+) -> Result<(), String> {
+    dependencies.sort();
+    dependencies.dedup();
+    fs::write(path, depfile_rule(targets, &dependencies)).map_err(|e| {
+        format!(
+            "Unable to write the dependency file {}: {}",
+            path.display(),
+            e
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_make_path() {
+        assert_eq!(escape_make_path(Path::new("src/lib.rs")), "src/lib.rs");
+        assert_eq!(
+            escape_make_path(Path::new("my dir/#1/$HOME.rs")),
+            "my\\ dir/\\#1/$$HOME.rs"
+        );
+    }
+
+    #[test]
+    fn test_depfile_rule() {
+        assert_eq!(
+            depfile_rule(
+                &[PathBuf::from("output.lcov")],
+                &[
+                    PathBuf::from("default_1.profraw"),
+                    PathBuf::from("src/my file.rs"),
+                ],
+            ),
+            "output.lcov: \\\n  default_1.profraw \\\n  src/my\\ file.rs\n"
+        );
+    }
+
+    #[test]
+    fn test_find_input_files() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let dir = tmp_dir.path().join("target");
+        fs::create_dir_all(dir.join("deps")).unwrap();
+        for file in &[
+            "default_1.profraw",
+            "deps/main.gcda",
+            "deps/main.gcno",
+            "deps/main.d",
+            "linked-files-map.json",
+            "other.json",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        let mut files = find_input_files(&[
+            dir.to_string_lossy().into_owned(),
+            "coverage.zip".to_string(),
+        ]);
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                dir.join("default_1.profraw"),
+                dir.join("deps/main.gcda"),
+                dir.join("deps/main.gcno"),
+                dir.join("linked-files-map.json"),
+                PathBuf::from("coverage.zip"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_depfile() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let path = tmp_dir.path().join("lcov.info.d");
+        write_depfile(
+            &path,
+            &[PathBuf::from("lcov.info")],
+            vec![
+                PathBuf::from("src/main.rs"),
+                PathBuf::from("a.profraw"),
+                PathBuf::from("src/main.rs"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "lcov.info: \\\n  a.profraw \\\n  src/main.rs\n"
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::borrow::Borrow;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::defs::*;
+use crate::html;
+use crate::llvm_tools::{get_cov_path, get_profdata_path};
+use crate::trend::TrendCoverage;
+use crate::version_info::{command_version, rustc_command};
+
+// The flags whose values are secrets, replaced in the dump.
+const SECRET_FLAGS: &[&str] = &["--token"];
+const REDACTED: &str = "<redacted>";
+
+/// The size and the modification time of an input file, in the diagnostics dump.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileMetadata {
+    pub path: PathBuf,
+    pub size: Option<u64>,
+    /// The modification time, in seconds since the Unix epoch.
+    pub modified: Option<u64>,
+}
+
+impl FileMetadata {
+    pub fn new(path: &Path) -> Self {
+        let metadata = fs::metadata(path).ok();
+        FileMetadata {
+            path: path.to_path_buf(),
+            size: metadata.as_ref().map(|metadata| metadata.len()),
+            modified: metadata
+                .and_then(|metadata| metadata.modified().ok())
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs()),
+        }
+    }
+}
+
+/// Replaces the values of the flags which are secrets, e.g. the Coveralls token, given either as
+/// `--token VALUE` or `--token=VALUE`.
+pub fn redact_args(args: &[String]) -> Vec<String> {
+    let mut redacted = Vec::with_capacity(args.len());
+    let mut is_secret = false;
+    for arg in args {
+        if is_secret {
+            redacted.push(REDACTED.to_string());
+            is_secret = false;
+            continue;
+        }
+        match arg.split_once('=') {
+            Some((flag, _)) if SECRET_FLAGS.contains(&flag) => {
+                redacted.push(format!("{}={}", flag, REDACTED));
+            }
+            _ => {
+                is_secret = SECRET_FLAGS.contains(&arg.as_str());
+                redacted.push(arg.clone());
+            }
+        }
+    }
+    redacted
+}
+
+/// Summarizes the coverage of the results, as in the trend file.
+pub fn coverage_summary(results: impl IntoIterator<Item = impl Borrow<ResultTuple>>) -> Value {
+    let mut stats = HtmlStats::default();
+    let mut files = 0;
+    for item in results {
+        stats.add(&html::get_stats(&item.borrow().2));
+        files += 1;
+    }
+    json!({
+        "files": files,
+        "coverage": TrendCoverage::from(&stats),
+    })
+}
+
+/// Builds the diagnostics to attach to bug reports: the versions of grcov and of the tools it
+/// uses, the platform, the input files, the command line (with the secrets redacted) and a
+/// summary of the coverage.
+pub fn diagnostics_json(
+    args: &[String],
+    profraws: &[PathBuf],
+    binaries: &[PathBuf],
+    results: impl IntoIterator<Item = impl Borrow<ResultTuple>>,
+) -> Value {
+    let rustc = rustc_command();
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let metadata = |paths: &[PathBuf]| -> Vec<FileMetadata> {
+        paths.iter().map(|path| FileMetadata::new(path)).collect()
+    };
+    json!({
+        "grcov_version": env!("CARGO_PKG_VERSION"),
+        "rustc_version": command_version(rustc),
+        "cargo_version": command_version(cargo),
+        "llvm_cov_version": get_cov_path().ok().and_then(command_version),
+        "llvm_profdata_version": get_profdata_path().ok().and_then(command_version),
+        "os": env::consts::OS,
+        "arch": env::consts::ARCH,
+        "args": redact_args(args),
+        "profraws": metadata(profraws),
+        "binaries": metadata(binaries),
+        "summary": coverage_summary(results),
+    })
+}
+
+/// Writes the diagnostics dump to a file.
+pub fn write_diagnostics(path: &Path, diagnostics: &Value) -> Result<(), String> {
+    let dump = serde_json::to_string_pretty(diagnostics).unwrap();
+    fs::write(path, dump).map_err(|e| {
+        format!(
+            "Unable to write the diagnostics to {}: {}",
+            path.display(),
+            e
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_redact_args() {
+        assert_eq!(
+            redact_args(&args(&[
+                "grcov",
+                ".",
+                "--token",
+                "secret",
+                "-t",
+                "coveralls",
+                "--token=secret",
+                "--commit-sha",
+                "abc",
+            ])),
+            args(&[
+                "grcov",
+                ".",
+                "--token",
+                "<redacted>",
+                "-t",
+                "coveralls",
+                "--token=<redacted>",
+                "--commit-sha",
+                "abc",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_json() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let profraw = tmp_dir.path().join("default.profraw");
+        fs::write(&profraw, "12345").unwrap();
+        let missing = tmp_dir.path().join("missing");
+        let result = CovResult {
+            lines: vec![(1, 1), (2, 0)].into_iter().collect(),
+            branches: BTreeMap::new(),
+            functions: Default::default(),
+            mcdc: None,
+        };
+        let results = vec![(
+            PathBuf::from("/work/src/main.rs"),
+            PathBuf::from("src/main.rs"),
+            result,
+        )];
+
+        let diagnostics = diagnostics_json(
+            &args(&["grcov", "--token", "secret"]),
+            std::slice::from_ref(&profraw),
+            &[missing],
+            &results,
+        );
+        assert_eq!(diagnostics["grcov_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(diagnostics["os"], env::consts::OS);
+        assert_eq!(
+            diagnostics["args"],
+            json!(["grcov", "--token", "<redacted>"])
+        );
+        assert_eq!(diagnostics["profraws"][0]["size"], 5);
+        assert!(diagnostics["profraws"][0]["modified"].is_u64());
+        assert!(diagnostics["binaries"][0]["size"].is_null());
+        assert_eq!(diagnostics["summary"]["files"], 1);
+        assert_eq!(diagnostics["summary"]["coverage"]["covered_lines"], 1);
+        assert_eq!(diagnostics["summary"]["coverage"]["total_lines"], 2);
+        assert!(!diagnostics.to_string().contains("secret"));
+
+        let path = tmp_dir.path().join("diagnostics.json");
+        write_diagnostics(&path, &diagnostics).unwrap();
+        let written: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written, diagnostics);
+    }
+}
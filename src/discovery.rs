@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::llvm_tools::walk_binary_path;
+
+// Lists the profraw files in a directory and its subdirectories, sorted.
+fn find_profraws(dir: &Path) -> Vec<PathBuf> {
+    let mut profraws: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(|entry| entry.into_path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "profraw"))
+        .collect();
+    profraws.sort();
+    profraws
+}
+
+/// Lists the binaries of `binary_root` (a binary or a directory, or a list of them like a
+/// --binary-path) and the profraw files of `profraw_root`, walking both trees in parallel. The
+/// binaries can then be given as the executables of profraws_to_lcov, without a binary path, so
+/// that it doesn't walk them again.
+pub fn discover_binaries_and_profraws(
+    binary_root: &Path,
+    profraw_root: &Path,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    rayon::join(
+        || walk_binary_path(binary_root, false),
+        || find_profraws(profraw_root),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_discover_binaries_and_profraws() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let profraw_root = tmp_dir.path().join("profraws");
+        fs::create_dir_all(profraw_root.join("nested")).unwrap();
+        for name in &[
+            "b.profraw",
+            "nested/a.profraw",
+            "default.profdata",
+            "lcov.info",
+        ] {
+            fs::write(profraw_root.join(name), "").unwrap();
+        }
+
+        let binary_root = tmp_dir.path().join("target");
+        fs::create_dir_all(&binary_root).unwrap();
+        fs::write(binary_root.join("main.rs"), "fn main() {}").unwrap();
+        // The binaries are recognized by their header.
+        let binary = binary_root.join("binary");
+        let mut elf = b"\x7fELF".to_vec();
+        elf.resize(64, 0);
+        fs::write(&binary, elf).unwrap();
+
+        let (binaries, profraws) = discover_binaries_and_profraws(&binary_root, &profraw_root);
+        assert_eq!(binaries, vec![binary]);
+        assert_eq!(
+            profraws,
+            vec![
+                profraw_root.join("b.profraw"),
+                profraw_root.join("nested/a.profraw")
+            ]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use log::warn;
+use serde::Serialize;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::path_rewriting::{PathAction, PathExplanation};
+use crate::producer::DiscoveredInput;
+use crate::raw_coverage::covmap_filenames;
+
+/// The format of the --dry-run report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DryRunFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for DryRunFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(DryRunFormat::Text),
+            "json" => Ok(DryRunFormat::Json),
+            _ => Err(format!("{} is not a supported dry run format", s)),
+        }
+    }
+}
+
+/// What grcov would process, as listed by --dry-run: the input files with their type, the
+/// binaries passed to llvm-cov, and what the path rewriting does with the source files.
+#[derive(Debug, Default, Serialize)]
+pub struct DryRun {
+    pub inputs: Vec<DiscoveredInput>,
+    pub binaries: Vec<PathBuf>,
+    /// The binaries left out as they aren't in the cargo messages.
+    pub binaries_skipped: usize,
+    pub paths: Vec<PathExplanation>,
+}
+
+impl DryRun {
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        writeln!(text, "Input files ({}):", self.inputs.len()).unwrap();
+        for input in &self.inputs {
+            writeln!(
+                text,
+                "  {:<16} {} (in {})",
+                input.kind.to_string(),
+                input.path,
+                input.archive
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            text,
+            "Binaries passed to llvm-cov ({}):",
+            self.binaries.len()
+        )
+        .unwrap();
+        for binary in &self.binaries {
+            writeln!(text, "  {}", binary.display()).unwrap();
+        }
+        if self.binaries_skipped > 0 {
+            writeln!(
+                text,
+                "  ({} more not in the cargo messages)",
+                self.binaries_skipped
+            )
+            .unwrap();
+        }
+
+        writeln!(text, "Source files ({}):", self.paths.len()).unwrap();
+        for explanation in &self.paths {
+            match &explanation.action {
+                PathAction::Kept => writeln!(text, "  kept      {}", explanation.path),
+                PathAction::Remapped { rel_path, .. } => writeln!(
+                    text,
+                    "  remapped  {} -> {}",
+                    explanation.path,
+                    rel_path.display()
+                ),
+                PathAction::Skipped { reason } => {
+                    writeln!(text, "  skipped   {} ({})", explanation.path, reason)
+                }
+            }
+            .unwrap();
+        }
+        text
+    }
+
+    pub fn format(&self, format: DryRunFormat) -> String {
+        match format {
+            DryRunFormat::Text => self.to_text(),
+            DryRunFormat::Json => serde_json::to_string_pretty(self).unwrap(),
+        }
+    }
+}
+
+/// Lists the source files in the coverage mapping of the binaries. The binaries which can't be
+/// read are skipped with a warning.
+pub fn scan_binary_source_paths(binaries: &[PathBuf]) -> Vec<String> {
+    let mut source_paths = Vec::new();
+    for binary in binaries {
+        let filenames = fs::read(binary)
+            .map_err(|e| e.to_string())
+            .and_then(|buffer| covmap_filenames(&buffer).map_err(|e| e.to_string()));
+        match filenames {
+            Ok(filenames) => source_paths.extend(filenames),
+            Err(e) => warn!(
+                path = binary.display().to_string(), reason = e.as_str();
+                "Unable to list the source files of {}: {}", binary.display(), e
+            ),
+        }
+    }
+    source_paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path_rewriting::SkipReason;
+    use crate::producer::InputKind;
+
+    #[test]
+    fn test_scan_binary_source_paths() {
+        let source_paths = scan_binary_source_paths(&[
+            PathBuf::from("test/raw_coverage/sample.o"),
+            PathBuf::from("test/missing.o"),
+        ]);
+        assert_eq!(source_paths.len(), 1);
+        assert!(source_paths[0].ends_with("sample.rs"), "{:?}", source_paths);
+    }
+
+    #[test]
+    fn test_dry_run_format() {
+        let dry_run = DryRun {
+            inputs: vec![DiscoveredInput {
+                archive: "plain files".to_string(),
+                path: "/work/default.profraw".to_string(),
+                kind: InputKind::Profraw,
+            }],
+            binaries: vec![PathBuf::from("target/debug/app")],
+            binaries_skipped: 0,
+            paths: vec![
+                PathExplanation {
+                    path: "/build/src/main.rs".to_string(),
+                    action: PathAction::Remapped {
+                        abs_path: PathBuf::from("/work/src/main.rs"),
+                        rel_path: PathBuf::from("src/main.rs"),
+                    },
+                },
+                PathExplanation {
+                    path: "tests/it.rs".to_string(),
+                    action: PathAction::Skipped {
+                        reason: SkipReason::Ignored("tests/*".to_string()),
+                    },
+                },
+            ],
+        };
+        assert_eq!(
+            dry_run.format(DryRunFormat::Text),
+            "Input files (1):\n  \
+             profraw          /work/default.profraw (in plain files)\n\
+             Binaries passed to llvm-cov (1):\n  \
+             target/debug/app\n\
+             Source files (2):\n  \
+             remapped  /build/src/main.rs -> src/main.rs\n  \
+             skipped   tests/it.rs (matches the --ignore glob 'tests/*')\n"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&dry_run.format(DryRunFormat::Json)).unwrap();
+        assert_eq!(json["inputs"][0]["kind"], "profraw");
+        assert_eq!(json["paths"][0]["action"], "remapped");
+        assert_eq!(json["paths"][0]["rel_path"], "src/main.rs");
+        assert_eq!(json["paths"][1]["action"], "skipped");
+        assert_eq!(json["paths"][1]["reason"]["ignored"], "tests/*");
+    }
+}
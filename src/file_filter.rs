@@ -1,12 +1,30 @@
 use regex::Regex;
-use std::path::Path;
+use rustc_hash::FxHashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum FilterType {
     Line(u32),
     Branch(u32),
     Both(u32),
 }
 
+/// Matches the lines calling the macros of code which is never reached, for --exclude-unreachable:
+/// `unreachable!` and the `macros` (e.g. `unimplemented`), and the panics of the internal errors,
+/// i.e. with a message starting with "internal".
+pub fn unreachable_regex(macros: &[impl AsRef<str>]) -> Regex {
+    let macros: Vec<String> = std::iter::once("unreachable")
+        .chain(macros.iter().map(AsRef::as_ref))
+        .map(regex::escape)
+        .collect();
+    Regex::new(&format!(
+        r#"\b(?:{})!\s*[(\[{{]|\bpanic!\s*\(\s*"internal"#,
+        macros.join("|")
+    ))
+    .unwrap()
+}
+
 #[derive(Default)]
 pub struct FileFilter {
     excl_line: Option<Regex>,
@@ -15,9 +33,15 @@ pub struct FileFilter {
     excl_br_line: Option<Regex>,
     excl_br_start: Option<Regex>,
     excl_br_stop: Option<Regex>,
+    min_line_length: usize,
+    excl_unreachable: Option<Regex>,
+    // The filters of the files already read, as several source paths can be rewritten to the
+    // same file.
+    cache: Mutex<FxHashMap<PathBuf, Vec<FilterType>>>,
 }
 
 impl FileFilter {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         excl_line: Option<Regex>,
         excl_start: Option<Regex>,
@@ -25,6 +49,8 @@ impl FileFilter {
         excl_br_line: Option<Regex>,
         excl_br_start: Option<Regex>,
         excl_br_stop: Option<Regex>,
+        min_line_length: usize,
+        excl_unreachable: Option<Regex>,
     ) -> Self {
         Self {
             excl_line,
@@ -33,18 +59,50 @@ impl FileFilter {
             excl_br_line,
             excl_br_start,
             excl_br_stop,
+            min_line_length,
+            excl_unreachable,
+            cache: Mutex::default(),
         }
     }
 
+    // Whether a line is excluded on its own, for containing the marker or a macro which is never
+    // reached, or for having fewer characters than --minimum-line-length once trimmed (e.g. a
+    // lone brace).
+    fn is_excluded_line(&self, line: &str) -> bool {
+        self.excl_line.as_ref().is_some_and(|f| f.is_match(line))
+            || self
+                .excl_unreachable
+                .as_ref()
+                .is_some_and(|f| f.is_match(line))
+            || line.trim().chars().count() < self.min_line_length
+    }
+
+    /// Returns the lines of a source file excluded from the line or branch coverage, reading
+    /// each file once.
     pub fn create(&self, file: &Path) -> Vec<FilterType> {
         if self.excl_line.is_none()
             && self.excl_start.is_none()
             && self.excl_br_line.is_none()
             && self.excl_br_start.is_none()
+            && self.min_line_length == 0
+            && self.excl_unreachable.is_none()
         {
             return Vec::new();
         }
 
+        if let Some(filters) = self.cache.lock().unwrap().get(file) {
+            return filters.clone();
+        }
+        // The file is read without the lock, so that the files are read in parallel.
+        let filters = self.filters(file);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(file.to_path_buf(), filters.clone());
+        filters
+    }
+
+    fn filters(&self, file: &Path) -> Vec<FilterType> {
         let file = std::fs::read_to_string(file);
         let file = if let Ok(file) = file {
             file
@@ -114,12 +172,12 @@ impl FileFilter {
                     // Single line exclusion. If single line exclusions occur
                     // inside a region they are meaningless (would be applied
                     // anway), so they are lower priority.
-                    if self.excl_line.as_ref().map_or(false, |f| f.is_match(line)) {
+                    if self.is_excluded_line(line) {
                         Some(FilterType::Both(number))
                     } else {
                         Some(FilterType::Branch(number))
                     }
-                } else if self.excl_line.as_ref().map_or(false, |f| f.is_match(line)) {
+                } else if self.is_excluded_line(line) {
                     Some(FilterType::Line(number))
                 } else {
                     None
@@ -128,3 +186,76 @@ impl FileFilter {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_file_filter_reads_files_once() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let path = tmp_dir.path().join("excl.c");
+        fs::copy("test/lcov_excl/excl.c", &path).unwrap();
+        let filter = FileFilter::new(
+            Some(Regex::new("LCOV_EXCL_LINE").unwrap()),
+            Some(Regex::new("LCOV_EXCL_START").unwrap()),
+            Some(Regex::new("LCOV_EXCL_STOP").unwrap()),
+            Some(Regex::new("LCOV_EXCL_BR_LINE").unwrap()),
+            None,
+            None,
+            0,
+            None,
+        );
+        let expected = vec![
+            FilterType::Line(3),
+            FilterType::Line(5),
+            FilterType::Line(6),
+            FilterType::Line(7),
+            FilterType::Line(8),
+            FilterType::Branch(10),
+        ];
+        assert_eq!(filter.create(&path), expected);
+
+        // The filters of the file are kept once it's read, even when it changes.
+        fs::write(&path, "int x; // LCOV_EXCL_LINE\n").unwrap();
+        assert_eq!(filter.create(&path), expected);
+        assert_eq!(
+            FileFilter::new(
+                Some(Regex::new("LCOV_EXCL_LINE").unwrap()),
+                None,
+                None,
+                None,
+                None,
+                None,
+                0,
+                None
+            )
+            .create(&path),
+            vec![FilterType::Line(1)]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_regex() {
+        let regex = unreachable_regex(&["unimplemented", "bug"]);
+        for line in &[
+            "        _ => unreachable!(),",
+            "    None => std::unreachable!(\"no value\"),",
+            "    unimplemented!()",
+            "    bug! { \"parser\" }",
+            "    panic!(\"internal error: {}\", e)",
+        ] {
+            assert!(regex.is_match(line), "{}", line);
+        }
+        for line in &[
+            "    panic!(\"invalid input\")",
+            "    my_unreachable!()",
+            "    let unreachable = 1;",
+            "    todo!()",
+        ] {
+            assert!(!regex.is_match(line), "{}", line);
+        }
+        assert!(!unreachable_regex(&[] as &[&str]).is_match("unimplemented!()"));
+    }
+}
@@ -46,6 +46,7 @@ mod tests {
             lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions,
+            mcdc: None,
         };
 
         assert!(is_covered(&result));
@@ -57,6 +58,7 @@ mod tests {
             lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions: FxHashMap::default(),
+            mcdc: None,
         };
 
         assert!(is_covered(&result));
@@ -83,6 +85,7 @@ mod tests {
             lines: [(1, 0), (2, 0), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions: FxHashMap::default(),
+            mcdc: None,
         };
 
         assert!(!is_covered(&result));
@@ -109,6 +112,7 @@ mod tests {
             lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions,
+            mcdc: None,
         };
 
         assert!(is_covered(&result));
@@ -128,6 +132,7 @@ mod tests {
             lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions,
+            mcdc: None,
         };
 
         assert!(is_covered(&result));
@@ -154,6 +159,7 @@ mod tests {
             lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions,
+            mcdc: None,
         };
 
         assert!(!is_covered(&result));
@@ -0,0 +1,306 @@
+use regex::Regex;
+use rustc_hash::FxHashSet;
+use std::collections::BTreeMap;
+
+use crate::defs::*;
+use crate::demangle_cache::demangle_name;
+
+/// Filters the functions of the results by their demangled names, along with their lines.
+#[derive(Default)]
+pub struct FunctionFilter {
+    include_fn: Option<Regex>,
+    exclude_fn: Option<Regex>,
+    exclude_synthesized: bool,
+}
+
+// The methods of the traits which are usually derived, as they appear at the end of the demangled
+// names of their implementations, e.g. `<sample::Point as core::fmt::Debug>::fmt`.
+const DERIVED_METHODS: &[&str] = &[
+    " as core::fmt::Debug>::fmt",
+    " as core::clone::Clone>::clone",
+    " as core::clone::Clone>::clone_from",
+    " as core::cmp::PartialEq>::eq",
+    " as core::cmp::PartialEq>::ne",
+    " as core::cmp::Eq>::assert_receiver_is_total_eq",
+    " as core::cmp::PartialOrd>::partial_cmp",
+    " as core::cmp::Ord>::cmp",
+    " as core::hash::Hash>::hash",
+    " as core::default::Default>::default",
+];
+
+/// Checks whether a function is synthesized by the compiler, from its demangled name: a closure
+/// (`{{closure}}`, or `{closure#0}` with the v0 mangling) or the implementation of a method of a
+/// derivable trait, e.g. `Debug::fmt`. The implementations written by hand can't be told apart
+/// from the derived ones.
+pub fn is_synthesized_function(demangled_name: &str) -> bool {
+    demangled_name.contains("{closure")
+        || DERIVED_METHODS
+            .iter()
+            .any(|method| demangled_name.ends_with(method))
+}
+
+fn demangled_name(name: &str) -> String {
+    demangle_name(name).unwrap_or_else(|| name.to_string())
+}
+
+impl FunctionFilter {
+    /// With `exclude_synthesized`, the functions synthesized by the compiler are also removed
+    /// from the results, but not their lines.
+    pub fn new(
+        include_fn: Option<Regex>,
+        exclude_fn: Option<Regex>,
+        exclude_synthesized: bool,
+    ) -> Self {
+        Self {
+            include_fn,
+            exclude_fn,
+            exclude_synthesized,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include_fn.is_none() && self.exclude_fn.is_none() && !self.exclude_synthesized
+    }
+
+    /// Checks whether a function is excluded: its demangled name doesn't match the include
+    /// regex, or matches the exclude regex.
+    pub fn is_excluded(&self, name: &str) -> bool {
+        let name = demangled_name(name);
+        self.include_fn
+            .as_ref()
+            .is_some_and(|include_fn| !include_fn.is_match(&name))
+            || self
+                .exclude_fn
+                .as_ref()
+                .is_some_and(|exclude_fn| exclude_fn.is_match(&name))
+    }
+
+    /// Removes the excluded functions from a result, along with their lines and branches.
+    ///
+    /// The records only give the first line of the functions, so a function is assumed to span
+    /// the lines up to the next function. The functions starting on the same line (e.g. the
+    /// instances of a generic function) share their lines, which are only removed when all of
+    /// them are excluded. The lines before the first function are always kept.
+    pub fn apply(&self, result: &mut CovResult) {
+        if self.exclude_synthesized {
+            result
+                .functions
+                .retain(|name, _| !is_synthesized_function(&demangled_name(name)));
+        }
+
+        // Whether all the functions starting on each line are excluded.
+        let mut starts: BTreeMap<u32, bool> = BTreeMap::new();
+        let mut excluded_names = FxHashSet::default();
+        for (name, function) in &result.functions {
+            let excluded = self.is_excluded(name);
+            if excluded {
+                excluded_names.insert(name.clone());
+            }
+            *starts.entry(function.start).or_insert(true) &= excluded;
+        }
+        if excluded_names.is_empty() {
+            return;
+        }
+
+        let is_excluded_line = |line: &u32| {
+            starts
+                .range(..=*line)
+                .next_back()
+                .is_some_and(|(_, &excluded)| excluded)
+        };
+        result.lines.retain(|line, _| !is_excluded_line(line));
+        result.branches.retain(|line, _| !is_excluded_line(line));
+        result.remap_mcdc(|line| Some(line).filter(|line| !is_excluded_line(line)));
+        result
+            .functions
+            .retain(|name, _| !excluded_names.contains(name));
+    }
+}
+
+/// Removes the functions excluded by the filter, and the files which have no lines left.
+pub fn filter_functions(results: Vec<ResultTuple>, filter: &FunctionFilter) -> Vec<ResultTuple> {
+    results
+        .into_iter()
+        .filter_map(|(abs_path, rel_path, mut result)| {
+            filter.apply(&mut result);
+            if result.lines.is_empty() && result.functions.is_empty() {
+                None
+            } else {
+                Some((abs_path, rel_path, result))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const DEBUG_FMT: &str =
+        "_ZN50_$LT$sample..Point$u20$as$u20$core..fmt..Debug$GT$3fmt17h0123456789abcdefE";
+    const NEW: &str = "_ZN6sample5Point3new17h0123456789abcdefE";
+
+    fn function(start: u32, executed: bool) -> Function {
+        Function { start, executed }
+    }
+
+    fn sample_result() -> CovResult {
+        let mut functions = FunctionMap::default();
+        functions.insert(NEW.to_string(), function(3, true));
+        functions.insert(DEBUG_FMT.to_string(), function(8, false));
+        // Another instance of a generic function, starting on the same line.
+        functions.insert("sample::convert::<u8>".to_string(), function(12, true));
+        functions.insert("sample::convert::<u16>".to_string(), function(12, false));
+        CovResult {
+            lines: vec![(1, 1), (3, 1), (4, 1), (8, 0), (9, 0), (12, 1), (13, 1)]
+                .into_iter()
+                .collect(),
+            branches: vec![(9, vec![false, false]), (13, vec![true, false])]
+                .into_iter()
+                .collect(),
+            functions,
+            mcdc: None,
+        }
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let filter = FunctionFilter::new(
+            None,
+            Some(Regex::new("as core::fmt::Debug>").unwrap()),
+            false,
+        );
+        assert!(filter.is_excluded(DEBUG_FMT));
+        assert!(!filter.is_excluded(NEW));
+
+        let filter =
+            FunctionFilter::new(Some(Regex::new("^sample::Point::").unwrap()), None, false);
+        assert!(!filter.is_excluded(NEW));
+        assert!(filter.is_excluded(DEBUG_FMT));
+    }
+
+    #[test]
+    fn test_apply_exclude_fn() {
+        let filter = FunctionFilter::new(None, Some(Regex::new("fmt::Debug").unwrap()), false);
+        let mut result = sample_result();
+        filter.apply(&mut result);
+        assert_eq!(
+            result.lines,
+            vec![(1, 1), (3, 1), (4, 1), (12, 1), (13, 1)]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(
+            result.branches,
+            vec![(13, vec![true, false])].into_iter().collect()
+        );
+        assert!(!result.functions.contains_key(DEBUG_FMT));
+        assert_eq!(result.functions.len(), 3);
+    }
+
+    #[test]
+    fn test_apply_shared_lines() {
+        // The lines of the generic function are kept as long as one of its instances is.
+        let filter = FunctionFilter::new(None, Some(Regex::new("<u16>").unwrap()), false);
+        let mut result = sample_result();
+        filter.apply(&mut result);
+        assert_eq!(result.lines.len(), 7);
+        assert_eq!(result.functions.len(), 3);
+
+        let filter =
+            FunctionFilter::new(None, Some(Regex::new("^sample::convert").unwrap()), false);
+        let mut result = sample_result();
+        filter.apply(&mut result);
+        assert_eq!(
+            result.lines,
+            vec![(1, 1), (3, 1), (4, 1), (8, 0), (9, 0)]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(result.functions.len(), 2);
+    }
+
+    #[test]
+    fn test_is_synthesized_function() {
+        assert!(is_synthesized_function(&demangled_name(DEBUG_FMT)));
+        assert!(!is_synthesized_function(&demangled_name(NEW)));
+        assert!(is_synthesized_function(&demangled_name(
+            "_ZN6sample4main28_$u7b$$u7b$closure$u7d$$u7d$17h0123456789abcdefE"
+        )));
+        assert!(is_synthesized_function("sample::main::{closure#0}"));
+        assert!(is_synthesized_function(
+            "<sample::Point as core::cmp::PartialEq>::eq"
+        ));
+        assert!(!is_synthesized_function(
+            "<sample::Point as core::fmt::Display>::fmt"
+        ));
+    }
+
+    #[test]
+    fn test_apply_exclude_synthesized() {
+        let mut functions = FunctionMap::default();
+        functions.insert(NEW.to_string(), function(3, true));
+        functions.insert(DEBUG_FMT.to_string(), function(8, false));
+        let result = CovResult {
+            lines: vec![(3, 1), (4, 1), (8, 0), (9, 0)].into_iter().collect(),
+            branches: BTreeMap::new(),
+            functions,
+            mcdc: None,
+        };
+        let results = filter_functions(
+            vec![(
+                PathBuf::from("/src/main.rs"),
+                PathBuf::from("main.rs"),
+                result,
+            )],
+            &FunctionFilter::new(None, None, true),
+        );
+
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let lcov_path = tmp_dir.path().join("lcov.info");
+        crate::output_lcov(&results, Some(&lcov_path), false);
+        let lcov = std::fs::read_to_string(&lcov_path).unwrap();
+        assert!(lcov.contains("FNF:1\n"), "{}", lcov);
+        assert!(lcov.contains("FNH:1\n"), "{}", lcov);
+        // The lines of the Debug impl are still reported.
+        assert!(lcov.contains("LF:4\n"), "{}", lcov);
+    }
+
+    #[test]
+    fn test_filter_functions() {
+        let filter = FunctionFilter::new(
+            Some(Regex::new("^sample::Point::new$").unwrap()),
+            None,
+            false,
+        );
+        let mut only_debug = CovResult::default();
+        only_debug
+            .functions
+            .insert(DEBUG_FMT.to_string(), function(1, true));
+        only_debug.lines.insert(1, 1);
+        let results = filter_functions(
+            vec![
+                (
+                    PathBuf::from("/src/main.rs"),
+                    PathBuf::from("main.rs"),
+                    sample_result(),
+                ),
+                (
+                    PathBuf::from("/src/fmt.rs"),
+                    PathBuf::from("fmt.rs"),
+                    only_debug,
+                ),
+            ],
+            &filter,
+        );
+        assert_eq!(results.len(), 1);
+        let (_, rel_path, result) = &results[0];
+        assert_eq!(rel_path, &PathBuf::from("main.rs"));
+        assert_eq!(
+            result.lines,
+            vec![(1, 1), (3, 1), (4, 1)].into_iter().collect()
+        );
+        assert_eq!(result.functions.len(), 1);
+    }
+}
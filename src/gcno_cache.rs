@@ -0,0 +1,161 @@
+//! The cache of the parsed gcno files of --cache-dir: the gcno files only change when the code is
+//! recompiled, so they are parsed once and read back on the next runs, which only parse the
+//! counters of the gcda files.
+
+use log::debug;
+use md5::{Digest, Md5};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::defs::ArchiveEntry;
+use crate::output::write_atomically;
+use crate::reader::{Gcno, GcovError};
+
+// The header of the cached files. The version of grcov is part of it, as the layout of the parsed
+// gcno files may change from one version to the next.
+fn cache_header() -> String {
+    format!("grcov gcno cache {}\n", env!("CARGO_PKG_VERSION"))
+}
+
+/// A directory where the parsed gcno files are stored, under the hash of their path, size and
+/// modification time.
+pub struct GcnoCache {
+    dir: PathBuf,
+}
+
+impl GcnoCache {
+    pub fn new(dir: &Path) -> Self {
+        GcnoCache {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    // The path of the cached gcno file, named after the hash of the path, size and modification
+    // time of the file it was read from, the zip archive for a file of a zip archive. None when
+    // the file can't be read.
+    fn cache_path(&self, entry: &ArchiveEntry) -> Option<PathBuf> {
+        let (path, name) = match entry {
+            ArchiveEntry::Zip(zip_path, name) => (zip_path, Some(name)),
+            ArchiveEntry::File(path) => (path, None),
+        };
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        let mut hasher = Md5::new();
+        hasher.update(fs::canonicalize(path).ok()?.to_string_lossy().as_bytes());
+        if let Some(name) = name {
+            hasher.update(b"\0");
+            hasher.update(name.as_bytes());
+        }
+        hasher.update(metadata.len().to_le_bytes());
+        hasher.update(modified.as_nanos().to_le_bytes());
+        Some(self.dir.join(format!("{:x}.gcno", hasher.finalize())))
+    }
+
+    // Reads a cached gcno file. The files written by another version of grcov, or which can't be
+    // deserialized, are missing ones.
+    fn load(&self, cache_path: &Path) -> Option<Gcno> {
+        let buffer = fs::read(cache_path).ok()?;
+        let header = cache_header();
+        if !buffer.starts_with(header.as_bytes()) {
+            debug!("{:?} isn't a gcno cache of this version", cache_path);
+            return None;
+        }
+        bincode::deserialize(&buffer[header.len()..])
+            .map_err(|e| debug!("Failed to read the cached gcno {:?}: {}", cache_path, e))
+            .ok()
+    }
+
+    // Stores a parsed gcno file, atomically so that a concurrent run never reads it partially
+    // written.
+    fn store(&self, cache_path: &Path, gcno: &Gcno) -> io::Result<()> {
+        let mut buffer = cache_header().into_bytes();
+        bincode::serialize_into(&mut buffer, gcno).map_err(io::Error::other)?;
+        fs::create_dir_all(&self.dir)?;
+        write_atomically(cache_path, &buffer)
+    }
+
+    /// Returns the parsed gcno file read from `entry`, from the cache when it didn't change, or
+    /// parsed from its content and stored in the cache otherwise.
+    pub fn parse(
+        &self,
+        entry: &ArchiveEntry,
+        stem: &str,
+        gcno_buf: Vec<u8>,
+    ) -> Result<Gcno, GcovError> {
+        let cache_path = match self.cache_path(entry) {
+            Some(cache_path) => cache_path,
+            None => return Gcno::parse(stem, gcno_buf),
+        };
+        if let Some(gcno) = self.load(&cache_path) {
+            return Ok(gcno);
+        }
+        let gcno = Gcno::parse(stem, gcno_buf)?;
+        if let Err(e) = self.store(&cache_path, &gcno) {
+            debug!("Failed to cache the gcno {:?}: {}", cache_path, e);
+        }
+        Ok(gcno)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compute(gcno: Gcno) -> Vec<(String, crate::defs::CovResult)> {
+        let gcda = fs::read("test/llvm/reader.gcda").unwrap();
+        gcno.compute_counters("reader", vec![gcda], true).unwrap()
+    }
+
+    #[test]
+    fn test_gcno_cache() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let gcno_path = tmp_dir.path().join("reader.gcno");
+        fs::copy("test/llvm/reader.gcno", &gcno_path).unwrap();
+        let gcno_buf = fs::read(&gcno_path).unwrap();
+        let entry = ArchiveEntry::File(gcno_path.clone());
+        let cache = GcnoCache::new(&tmp_dir.path().join("cache"));
+        let expected = compute(Gcno::parse("reader", gcno_buf.clone()).unwrap());
+
+        assert_eq!(
+            compute(cache.parse(&entry, "reader", gcno_buf.clone()).unwrap()),
+            expected
+        );
+        let cache_path = cache.cache_path(&entry).unwrap();
+        assert!(cache_path.exists());
+        // The cached gcno is read instead of the content of the file.
+        assert_eq!(
+            compute(cache.parse(&entry, "reader", Vec::new()).unwrap()),
+            expected
+        );
+
+        // A corrupted cached file, or one of another version, is parsed again and replaced.
+        for content in [
+            &b"garbage"[..],
+            &b"grcov gcno cache 0.0.0\n"[..],
+            &cache_header().into_bytes()[..],
+        ] {
+            fs::write(&cache_path, content).unwrap();
+            assert_eq!(
+                compute(cache.parse(&entry, "reader", gcno_buf.clone()).unwrap()),
+                expected
+            );
+            assert_eq!(compute(cache.load(&cache_path).unwrap()), expected);
+        }
+
+        // A modified gcno file is another entry of the cache.
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .open(&gcno_path)
+            .unwrap();
+        file.set_len(gcno_buf.len() as u64 - 1).unwrap();
+        assert_ne!(cache.cache_path(&entry).unwrap(), cache_path);
+        let zip_entry = ArchiveEntry::Zip(gcno_path, "reader.gcno".to_string());
+        assert_ne!(cache.cache_path(&zip_entry), cache.cache_path(&entry));
+        assert_eq!(
+            cache.cache_path(&ArchiveEntry::File(tmp_dir.path().join("missing.gcno"))),
+            None
+        );
+    }
+}
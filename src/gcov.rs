@@ -43,12 +43,19 @@ pub fn run_gcov(
     } else {
         &mut command
     };
-    let status = command
+    let command = command
         .arg(gcno_path)
         .arg("-i") // Generate intermediate gcov format, faster to parse.
         .current_dir(working_dir);
 
-    let output = if let Ok(output) = status.output() {
+    check_gcov_output(gcno_path, command)
+}
+
+// Runs gcov and checks that it succeeded. The outputs are read with Command::output, which reads
+// stdout and stderr at the same time: gcov can write a lot on both for a large file, and would
+// hang when the pipe of one of them is full while the other one is read.
+fn check_gcov_output(gcno_path: &Path, command: &mut Command) -> Result<(), GcovError> {
+    let output = if let Ok(output) = command.output() {
         output
     } else {
         return Err(GcovError::ProcessFailure);
@@ -108,6 +115,27 @@ fn parse_version(gcov_output: &str) -> Version {
 mod tests {
     use super::*;
 
+    #[cfg(unix)]
+    #[test]
+    fn test_check_gcov_output_large_outputs() {
+        // 10 MB on stderr first, then on stdout, far more than the pipes can hold.
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg("yes e | head -c 10485760 >&2; yes o | head -c 10485760; exit 1");
+        match check_gcov_output(Path::new("big.gcno"), &mut command) {
+            Err(GcovError::Failure((path, stdout, stderr))) => {
+                assert_eq!(path, "big.gcno");
+                assert_eq!(stdout.len(), 10485760);
+                assert!(stdout.starts_with("o\no\n") && !stdout.contains('e'));
+                assert_eq!(stderr.len(), 10485760);
+                assert!(stderr.starts_with("e\ne\n") && !stderr.contains('o'));
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+        assert!(check_gcov_output(Path::new("a.gcno"), Command::new("true").arg("a.gcno")).is_ok());
+    }
+
     #[test]
     fn test_parse_version() {
         assert_eq!(
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::value::{from_value, to_value, Value};
 use std::borrow::Cow;
@@ -7,10 +8,11 @@ use std::collections::{btree_map, BTreeMap};
 use std::fs::{self, File};
 use std::io::{BufReader, Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
 use tera::try_get_value;
 
 use crate::defs::*;
+use crate::output::{write_atomically, AtomicFile};
+use crate::summary::coverage_pct;
 
 impl HtmlStats {
     #[inline(always)]
@@ -24,6 +26,43 @@ impl HtmlStats {
     }
 }
 
+impl HtmlGlobalStats {
+    /// Adds the stats of a source file to the ones of its directory and to the global ones.
+    pub fn add_file(&mut self, rel_path: &Path, stats: &HtmlStats) {
+        let parent = rel_path.parent().unwrap().to_str().unwrap().to_string();
+        let file_name = rel_path.file_name().unwrap().to_str().unwrap().to_string();
+        let fs = HtmlFileStats {
+            stats: stats.clone(),
+        };
+        self.stats.add(stats);
+        let entry = self.dirs.entry(parent);
+        match entry {
+            btree_map::Entry::Occupied(ds) => {
+                let ds = ds.into_mut();
+                ds.stats.add(stats);
+                ds.files.insert(file_name, fs);
+            }
+            btree_map::Entry::Vacant(v) => {
+                let mut files = BTreeMap::new();
+                files.insert(file_name, fs);
+                v.insert(HtmlDirStats {
+                    files,
+                    stats: stats.clone(),
+                });
+            }
+        };
+    }
+}
+
+/// The pages of the source files written by a consumer, with the stats of the source files to
+/// build the indexes from once all the pages are written, and the errors of the pages which
+/// couldn't be written.
+#[derive(Debug, Default)]
+pub struct HtmlPages {
+    pub files: Vec<(PathBuf, HtmlStats)>,
+    pub errors: Vec<String>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Config {
     hi_limit: f64,
@@ -179,7 +218,7 @@ fn add_html_ext(path: &Path) -> PathBuf {
     }
 }
 
-fn get_stats(result: &CovResult) -> HtmlStats {
+pub(crate) fn get_stats(result: &CovResult) -> HtmlStats {
     let total_lines = result.lines.len();
     let covered_lines = result.lines.values().filter(|x| **x > 0).count();
     let total_funs = result.functions.len();
@@ -202,14 +241,10 @@ fn get_stats(result: &CovResult) -> HtmlStats {
 }
 
 #[inline(always)]
-fn get_percentage_of_covered_lines(covered_lines: usize, total_lines: usize) -> f64 {
-    if total_lines != 0 {
-        covered_lines as f64 / total_lines as f64 * 100.0
-    } else {
-        // If the file is empty (no lines) then the coverage
-        // must be 100% (0% means "bad" which is not the case).
-        100.0
-    }
+pub fn get_percentage_of_covered_lines(covered_lines: usize, total_lines: usize) -> f64 {
+    // If the file is empty (no lines) then the coverage
+    // must be 100% (0% means "bad" which is not the case).
+    coverage_pct(covered_lines as u64, total_lines as u64)
 }
 
 fn percent(args: &HashMap<String, Value>) -> tera::Result<Value> {
@@ -232,32 +267,6 @@ fn get_base(rel_path: &Path) -> String {
     "../".repeat(count)
 }
 
-fn get_dirs_result(global: Arc<Mutex<HtmlGlobalStats>>, rel_path: &Path, stats: &HtmlStats) {
-    let parent = rel_path.parent().unwrap().to_str().unwrap().to_string();
-    let file_name = rel_path.file_name().unwrap().to_str().unwrap().to_string();
-    let fs = HtmlFileStats {
-        stats: stats.clone(),
-    };
-    let mut global = global.lock().unwrap();
-    global.stats.add(stats);
-    let entry = global.dirs.entry(parent);
-    match entry {
-        btree_map::Entry::Occupied(ds) => {
-            let ds = ds.into_mut();
-            ds.stats.add(stats);
-            ds.files.insert(file_name, fs);
-        }
-        btree_map::Entry::Vacant(v) => {
-            let mut files = BTreeMap::new();
-            files.insert(file_name, fs);
-            v.insert(HtmlDirStats {
-                files,
-                stats: stats.clone(),
-            });
-        }
-    };
-}
-
 use tera::{Context, Tera};
 
 fn make_context() -> Context {
@@ -268,6 +277,24 @@ fn make_context() -> Context {
     ctx
 }
 
+// Renders a page, and writes it once rendered, so that a page which fails to render doesn't
+// replace the previous one.
+fn write_page(
+    tera: &Tera,
+    template: &str,
+    ctx: &Context,
+    output_file: &Path,
+) -> Result<(), String> {
+    let out = tera
+        .render(template, ctx)
+        .map_err(|e| format!("Cannot render the file {:?}: {}", output_file, e))?;
+    create_parent(output_file);
+    write_atomically(output_file, out.as_bytes())
+        .map_err(|e| format!("Cannot write the file {:?}: {}", output_file, e))
+}
+
+/// Writes the top level index, and the index of each directory, in parallel. Returns the errors
+/// of the pages which couldn't be written.
 pub fn gen_index(
     tera: &Tera,
     global: &HtmlGlobalStats,
@@ -275,16 +302,8 @@ pub fn gen_index(
     output: &Path,
     branch_enabled: bool,
     precision: usize,
-) {
+) -> Vec<String> {
     let output_file = output.join("index.html");
-    create_parent(&output_file);
-    let mut output_stream = match File::create(&output_file) {
-        Err(_) => {
-            eprintln!("Cannot create file {:?}", output_file);
-            return;
-        }
-        Ok(f) => f,
-    };
 
     let mut ctx = make_context();
     let empty: &[&str] = &[];
@@ -297,24 +316,29 @@ pub fn gen_index(
     ctx.insert("kind", "Directory");
     ctx.insert("branch_enabled", &branch_enabled);
 
-    let out = tera.render("index.html", &ctx).unwrap();
-
-    if output_stream.write_all(out.as_bytes()).is_err() {
-        eprintln!("Cannot write the file {:?}", output_file);
-        return;
-    }
-
-    for (dir_name, dir_stats) in global.dirs.iter() {
-        gen_dir_index(
-            tera,
-            dir_name,
-            dir_stats,
-            conf,
-            output,
-            branch_enabled,
-            precision,
-        );
-    }
+    let mut errors: Vec<String> = write_page(tera, "index.html", &ctx, &output_file)
+        .err()
+        .into_iter()
+        .collect();
+    errors.extend(
+        global
+            .dirs
+            .par_iter()
+            .filter_map(|(dir_name, dir_stats)| {
+                gen_dir_index(
+                    tera,
+                    dir_name,
+                    dir_stats,
+                    conf,
+                    output,
+                    branch_enabled,
+                    precision,
+                )
+                .err()
+            })
+            .collect::<Vec<_>>(),
+    );
+    errors
 }
 
 pub fn gen_dir_index(
@@ -325,19 +349,11 @@ pub fn gen_dir_index(
     output: &Path,
     branch_enabled: bool,
     precision: usize,
-) {
+) -> Result<(), String> {
     let index = Path::new(dir_name).join("index.html");
     let layers = index.components().count() - 1;
     let prefix = "../".repeat(layers) + "index.html";
     let output_file = output.join(index);
-    create_parent(&output_file);
-    let mut output = match File::create(&output_file) {
-        Err(_) => {
-            eprintln!("Cannot create file {:?}", output_file);
-            return;
-        }
-        Ok(f) => f,
-    };
 
     let mut ctx = make_context();
     ctx.insert("date", &conf.date);
@@ -350,13 +366,12 @@ pub fn gen_dir_index(
     ctx.insert("branch_enabled", &branch_enabled);
     ctx.insert("precision", &precision);
 
-    let out = tera.render("index.html", &ctx).unwrap();
-
-    if output.write_all(out.as_bytes()).is_err() {
-        eprintln!("Cannot write the file {:?}", output_file);
-    }
+    write_page(tera, "index.html", &ctx, &output_file)
 }
 
+// Writes the page of a source file. The stats of the source files which can't be read, e.g.
+// removed since the coverage was collected, are left out of the indexes, as they have no page.
+#[allow(clippy::too_many_arguments)]
 fn gen_html(
     tera: &Tera,
     path: &Path,
@@ -364,7 +379,7 @@ fn gen_html(
     conf: &Config,
     output: &Path,
     rel_path: &Path,
-    global: Arc<Mutex<HtmlGlobalStats>>,
+    pages: &mut HtmlPages,
     branch_enabled: bool,
     precision: usize,
 ) {
@@ -381,17 +396,37 @@ fn gen_html(
     };
 
     let stats = get_stats(result);
-    get_dirs_result(global, rel_path, &stats);
+    if let Err(e) = gen_file_page(
+        tera,
+        &mut f,
+        path,
+        result,
+        &stats,
+        conf,
+        output,
+        rel_path,
+        branch_enabled,
+        precision,
+    ) {
+        pages.errors.push(e);
+    }
+    pages.files.push((rel_path.to_path_buf(), stats));
+}
 
+#[allow(clippy::too_many_arguments)]
+fn gen_file_page(
+    tera: &Tera,
+    f: &mut File,
+    path: &Path,
+    result: &CovResult,
+    stats: &HtmlStats,
+    conf: &Config,
+    output: &Path,
+    rel_path: &Path,
+    branch_enabled: bool,
+    precision: usize,
+) -> Result<(), String> {
     let output_file = output.join(add_html_ext(rel_path));
-    create_parent(&output_file);
-    let mut output = match File::create(&output_file) {
-        Err(_) => {
-            eprintln!("Cannot create file {:?}", output_file);
-            return;
-        }
-        Ok(f) => f,
-    };
     let base_url = get_base(rel_path);
     let filename = rel_path.file_name().unwrap().to_str().unwrap();
     let parent = rel_path.parent().unwrap().to_str().unwrap().to_string();
@@ -409,15 +444,13 @@ fn gen_html(
             ("./index.html", parent.as_str()),
         ],
     );
-    ctx.insert("stats", &stats);
+    ctx.insert("stats", stats);
     ctx.insert("branch_enabled", &branch_enabled);
     ctx.insert("precision", &precision);
 
     let mut file_buf = Vec::new();
-    if let Err(e) = f.read_to_end(&mut file_buf) {
-        eprintln!("Failed to read {}: {}", path.display(), e);
-        return;
-    }
+    f.read_to_end(&mut file_buf)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
 
     let file_utf8 = String::from_utf8_lossy(&file_buf);
     if matches!(&file_utf8, Cow::Owned(_)) {
@@ -445,22 +478,21 @@ fn gen_html(
 
     ctx.insert("items", &items);
 
-    let out = tera.render("file.html", &ctx).unwrap();
-
-    if output.write_all(out.as_bytes()).is_err() {
-        eprintln!("Cannot write the file {:?}", output_file);
-    }
+    write_page(tera, "file.html", &ctx, &output_file)
 }
 
+/// Writes the pages of the source files received, until the end of the jobs. The pages are
+/// independent, so several consumers write them in parallel, in any order; the indexes are built
+/// from the returned stats once they are all done.
 pub fn consumer_html(
     tera: &Tera,
     receiver: HtmlJobReceiver,
-    global: Arc<Mutex<HtmlGlobalStats>>,
     output: &Path,
     conf: Config,
     branch_enabled: bool,
     precision: usize,
-) {
+) -> HtmlPages {
+    let mut pages = HtmlPages::default();
     while let Ok(job) = receiver.recv() {
         if job.is_none() {
             break;
@@ -473,11 +505,12 @@ pub fn consumer_html(
             &conf,
             output,
             &job.rel_path,
-            global.clone(),
+            &mut pages,
             branch_enabled,
             precision,
         );
     }
+    pages
 }
 
 /// Different available styles to render badges with [`gen_badge`].
@@ -532,7 +565,7 @@ impl BadgeStyle {
 pub fn gen_badge(tera: &Tera, stats: &HtmlStats, conf: &Config, output: &Path, style: BadgeStyle) {
     let output_file = output.join(style.path());
     create_parent(&output_file);
-    let mut output_stream = match File::create(&output_file) {
+    let mut output_stream = match AtomicFile::create(&output_file) {
         Err(_) => {
             eprintln!("Cannot create file {:?}", output_file);
             return;
@@ -580,7 +613,7 @@ pub fn gen_coverage_json(stats: &HtmlStats, conf: &Config, output: &Path, precis
 
     let output_file = output.join("coverage.json");
     create_parent(&output_file);
-    let mut output_stream = match File::create(&output_file) {
+    let mut output_stream = match AtomicFile::create(&output_file) {
         Err(_) => {
             eprintln!("Cannot create file {:?}", output_file);
             return;
@@ -613,7 +646,8 @@ pub fn gen_coverage_json(stats: &HtmlStats, conf: &Config, output: &Path, precis
 
 #[cfg(test)]
 mod tests {
-    use super::get_percentage_of_covered_lines;
+    use super::*;
+    use crossbeam_channel::unbounded;
 
     #[test]
     fn test_get_percentage_of_covered_lines() {
@@ -623,4 +657,55 @@ mod tests {
         assert_eq!(get_percentage_of_covered_lines(0, 0), 100.0);
         assert_eq!(get_percentage_of_covered_lines(5, 0), 100.0);
     }
+
+    #[test]
+    fn test_consumer_html() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let sources = tmp_dir.path().join("sources");
+        let output = tmp_dir.path().join("html");
+        fs::create_dir_all(sources.join("src/b")).unwrap();
+        for path in &["src/a.rs", "src/b/c.rs", "src/b/d.rs"] {
+            fs::write(sources.join(path), "fn main() {\n}\n").unwrap();
+        }
+        // The page of d.rs can't replace a directory which isn't empty, which shouldn't stop the
+        // other ones.
+        fs::create_dir_all(output.join("src/b/d.rs.html/dir")).unwrap();
+
+        let (sender, receiver) = unbounded();
+        for path in &["src/a.rs", "src/b/c.rs", "src/b/d.rs", "src/missing.rs"] {
+            sender
+                .send(Some(HtmlItem {
+                    abs_path: sources.join(path),
+                    rel_path: PathBuf::from(path),
+                    result: CovResult {
+                        lines: [(1, 1), (2, 0)].iter().cloned().collect(),
+                        ..CovResult::default()
+                    },
+                }))
+                .unwrap();
+        }
+        sender.send(None).unwrap();
+
+        let (tera, config) = get_config(None);
+        let pages = consumer_html(&tera, receiver, &output, config.clone(), false, 2);
+        assert_eq!(pages.errors.len(), 1);
+        assert!(pages.errors[0].contains("d.rs.html"));
+        assert!(output.join("src/a.rs.html").exists());
+        assert!(output.join("src/b/c.rs.html").exists());
+
+        // The source files which couldn't be read have no stats, the ones whose page couldn't be
+        // written still count.
+        let mut global = HtmlGlobalStats::default();
+        for (rel_path, stats) in &pages.files {
+            global.add_file(rel_path, stats);
+        }
+        assert_eq!(global.stats.total_lines, 6);
+        assert_eq!(global.stats.covered_lines, 3);
+        assert_eq!(global.dirs["src/b"].files.len(), 2);
+
+        assert!(gen_index(&tera, &global, &config, &output, false, 2).is_empty());
+        assert!(output.join("index.html").exists());
+        assert!(output.join("src/index.html").exists());
+        assert!(output.join("src/b/index.html").exists());
+    }
 }
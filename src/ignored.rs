@@ -0,0 +1,166 @@
+use rustc_hash::FxHashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::defs::*;
+use crate::path_rewriting::SkipReason;
+
+/// The filter which excluded a source file from the report, in the --report-ignored file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IgnoredReason {
+    /// An --ignore glob, a .grcovignore file or --excl-generated.
+    ExclFile,
+    /// --exclude-fn or --include-fn, which removed all the functions of the file.
+    ExclFn,
+    /// --keep-only, --strip-system-paths or --ignore-not-existing, which keep the files of the
+    /// source directory.
+    SourceDirFilter,
+    /// --min-lines-in-file, for a file without any instrumented lines.
+    ZeroLines,
+    /// --min-lines-in-file.
+    MinLinesFilter,
+}
+
+impl fmt::Display for IgnoredReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IgnoredReason::ExclFile => "ExclFile",
+            IgnoredReason::ExclFn => "ExclFn",
+            IgnoredReason::SourceDirFilter => "SourceDirFilter",
+            IgnoredReason::ZeroLines => "ZeroLines",
+            IgnoredReason::MinLinesFilter => "MinLinesFilter",
+        })
+    }
+}
+
+impl From<&SkipReason> for IgnoredReason {
+    fn from(reason: &SkipReason) -> IgnoredReason {
+        match reason {
+            SkipReason::Ignored(_) | SkipReason::Grcovignore => IgnoredReason::ExclFile,
+            SkipReason::SystemPath(_)
+            | SkipReason::NotNormalizable
+            | SkipReason::NotKept
+            | SkipReason::NotExisting => IgnoredReason::SourceDirFilter,
+        }
+    }
+}
+
+/// The source files of the coverage data excluded by the filters, for --report-ignored.
+#[derive(Debug, Default)]
+pub struct IgnoredFiles(Vec<(IgnoredReason, String)>);
+
+impl IgnoredFiles {
+    /// Adds the files skipped by the path rewriting.
+    pub fn add_skipped(&mut self, skipped: &[(String, SkipReason)]) {
+        self.0.extend(
+            skipped
+                .iter()
+                .map(|(path, reason)| (IgnoredReason::from(reason), path.clone())),
+        );
+    }
+
+    /// Applies a filter to the results, and adds the files it removed, with the reason given by
+    /// `reason` for their result before the filter.
+    pub fn track(
+        &mut self,
+        results: Vec<ResultTuple>,
+        filter: impl FnOnce(Vec<ResultTuple>) -> Vec<ResultTuple>,
+        reason: impl Fn(&CovResult) -> IgnoredReason,
+    ) -> Vec<ResultTuple> {
+        let before: Vec<(PathBuf, IgnoredReason)> = results
+            .iter()
+            .map(|(_, rel_path, result)| (rel_path.clone(), reason(result)))
+            .collect();
+        let results = filter(results);
+        let kept: FxHashSet<&PathBuf> = results.iter().map(|(_, rel_path, _)| rel_path).collect();
+        self.0.extend(
+            before
+                .into_iter()
+                .filter(|(rel_path, _)| !kept.contains(rel_path))
+                .map(|(rel_path, reason)| (reason, rel_path.to_string_lossy().into_owned())),
+        );
+        results
+    }
+
+    /// Lists the excluded files, one per line, as `REASON\tPATH`.
+    pub fn to_text(&self) -> String {
+        let mut entries = self.0.clone();
+        entries.sort();
+        entries
+            .iter()
+            .map(|(reason, path)| format!("{}\t{}\n", reason, path))
+            .collect()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_text()).map_err(|e| {
+            format!(
+                "Unable to write the ignored files to {}: {}",
+                path.display(),
+                e
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn result_tuple(rel_path: &str, lines: &[(u32, u64)]) -> ResultTuple {
+        (
+            PathBuf::from("/work").join(rel_path),
+            PathBuf::from(rel_path),
+            CovResult {
+                lines: lines.iter().cloned().collect(),
+                branches: BTreeMap::new(),
+                functions: Default::default(),
+                mcdc: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_ignored_files() {
+        let mut ignored = IgnoredFiles::default();
+        ignored.add_skipped(&[
+            (
+                "/work/tests/it.rs".to_string(),
+                SkipReason::Ignored("tests/*".to_string()),
+            ),
+            (
+                "/usr/lib/rustlib/src/lib.rs".to_string(),
+                SkipReason::SystemPath(PathBuf::from("/usr/lib/rustlib")),
+            ),
+        ]);
+
+        let results = vec![
+            result_tuple("src/lib.rs", &[(1, 1), (2, 0), (3, 1)]),
+            result_tuple("src/mod.rs", &[(1, 1)]),
+            result_tuple("src/empty.rs", &[]),
+        ];
+        let results = ignored.track(
+            results,
+            |results| crate::filter_min_lines_in_file(results, 2),
+            |result| {
+                if result.lines.is_empty() {
+                    IgnoredReason::ZeroLines
+                } else {
+                    IgnoredReason::MinLinesFilter
+                }
+            },
+        );
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].1, PathBuf::from("src/lib.rs"));
+
+        assert_eq!(
+            ignored.to_text(),
+            "ExclFile\t/work/tests/it.rs\n\
+             SourceDirFilter\t/usr/lib/rustlib/src/lib.rs\n\
+             ZeroLines\tsrc/empty.rs\n\
+             MinLinesFilter\tsrc/mod.rs\n"
+        );
+    }
+}
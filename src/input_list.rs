@@ -0,0 +1,278 @@
+use globset::GlobBuilder;
+use log::warn;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// The characters which make an input path a glob pattern.
+const GLOB_METACHARACTERS: &[char] = &['*', '?', '[', '{'];
+
+/// Parses a list of input paths, one per line. The blank lines and the comments starting with
+/// `#` are ignored, and the relative paths are resolved against `base_dir`. Each path comes with
+/// its line number.
+pub fn parse_input_list(list: &str, base_dir: &Path) -> Vec<(usize, PathBuf)> {
+    list.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            Some((i + 1, base_dir.join(line)))
+        })
+        .collect()
+}
+
+/// Reads the input paths from a list file, as given with --input-list or `@file`. The paths which
+/// don't exist are errors, or warnings which leave them out with `ignore_missing`.
+pub fn read_input_list(path: &Path, ignore_missing: bool) -> Result<Vec<String>, String> {
+    let list = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read the input list {}: {}", path.display(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut inputs = Vec::new();
+    let mut errors = Vec::new();
+    for (line, input) in parse_input_list(&list, base_dir) {
+        if input.exists() {
+            inputs.push(input.to_string_lossy().into_owned());
+            continue;
+        }
+        let message = format!(
+            "{}:{}: {} doesn't exist",
+            path.display(),
+            line,
+            input.display()
+        );
+        if ignore_missing {
+            warn!("{}", message);
+        } else {
+            errors.push(message);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+    Ok(inputs)
+}
+
+/// Returns whether an input path is a glob pattern, e.g. `target/**/*.profraw` given by a shell
+/// which doesn't expand the globs. The paths which exist are never patterns.
+pub fn is_glob_pattern(path: &str) -> bool {
+    path.contains(GLOB_METACHARACTERS) && !Path::new(path).exists()
+}
+
+/// Lists the paths matching a glob pattern, sorted. `*` and `?` don't match the path separators,
+/// while `**` matches any number of directories.
+pub fn expand_glob(pattern: &str) -> Result<Vec<String>, String> {
+    let glob = GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| format!("Invalid glob pattern {}: {}", pattern, e))?
+        .compile_matcher();
+
+    // Only the directory before the first component with a glob is walked.
+    let base: PathBuf = Path::new(pattern)
+        .components()
+        .take_while(|component| {
+            !component
+                .as_os_str()
+                .to_string_lossy()
+                .contains(GLOB_METACHARACTERS)
+        })
+        .collect();
+    let is_relative = base.as_os_str().is_empty();
+    let root = if is_relative { Path::new(".") } else { &base };
+
+    let mut matches = Vec::new();
+    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
+        let path = if is_relative {
+            entry
+                .path()
+                .strip_prefix(".")
+                .unwrap_or_else(|_| entry.path())
+        } else {
+            entry.path()
+        };
+        if glob.is_match(path) {
+            matches.push(path.to_string_lossy().into_owned());
+        }
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+/// Replaces the `@file` input paths with the paths listed in the file, and the glob patterns with
+/// the paths they match, and adds the ones of the --input-list file. A pattern matching nothing
+/// is only an error when there are no inputs at all.
+pub fn expand_input_paths(
+    paths: &[String],
+    input_list: Option<&Path>,
+    ignore_missing: bool,
+) -> Result<Vec<String>, String> {
+    let mut inputs = Vec::new();
+    let mut unmatched_patterns = Vec::new();
+    for path in paths {
+        if let Some(list) = path.strip_prefix('@') {
+            inputs.extend(read_input_list(&PathBuf::from(list), ignore_missing)?);
+        } else if is_glob_pattern(path) {
+            let matches = expand_glob(path)?;
+            if matches.is_empty() {
+                unmatched_patterns.push(path.as_str());
+            }
+            inputs.extend(matches);
+        } else {
+            inputs.push(path.clone());
+        }
+    }
+    if let Some(input_list) = input_list {
+        inputs.extend(read_input_list(input_list, ignore_missing)?);
+    }
+
+    if !unmatched_patterns.is_empty() {
+        let message = format!("No input files match {}", unmatched_patterns.join(", "));
+        if inputs.is_empty() {
+            return Err(message);
+        }
+        warn!("{}", message);
+    }
+    Ok(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_input_list() {
+        let list = "# The profiles of the unit tests.\nunit.profraw\n\n  /abs/it.profraw  \n";
+        assert_eq!(
+            parse_input_list(list, Path::new("build")),
+            vec![
+                (2, PathBuf::from("build/unit.profraw")),
+                (4, PathBuf::from("/abs/it.profraw")),
+            ]
+        );
+    }
+
+    // Creates a tree of profraw files, as written by the tests of a workspace.
+    fn profraw_tree() -> tempfile::TempDir {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        for path in &[
+            "target/a1.profraw",
+            "target/a2.profraw",
+            "target/b1.profraw",
+            "target/deps/nested/a3.profraw",
+            "target/deps/a1.profdata",
+        ] {
+            let path = tmp_dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        tmp_dir
+    }
+
+    #[test]
+    fn test_expand_glob() {
+        let tmp_dir = profraw_tree();
+        let root = tmp_dir.path();
+        let pattern = |pattern: &str| root.join(pattern).to_string_lossy().into_owned();
+        let paths = |paths: &[&str]| -> Vec<String> {
+            paths
+                .iter()
+                .map(|path| root.join(path).to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert!(is_glob_pattern(&pattern("target/**/*.profraw")));
+        assert!(!is_glob_pattern("test/prova.info"));
+        assert_eq!(
+            expand_glob(&pattern("target/**/*.profraw")).unwrap(),
+            paths(&[
+                "target/a1.profraw",
+                "target/a2.profraw",
+                "target/b1.profraw",
+                "target/deps/nested/a3.profraw",
+            ])
+        );
+        assert_eq!(
+            expand_glob(&pattern("target/*.profraw")).unwrap(),
+            paths(&[
+                "target/a1.profraw",
+                "target/a2.profraw",
+                "target/b1.profraw"
+            ])
+        );
+        assert_eq!(
+            expand_glob(&pattern("target/a?.profraw")).unwrap(),
+            paths(&["target/a1.profraw", "target/a2.profraw"])
+        );
+        assert_eq!(
+            expand_glob(&pattern("target/[ab]1.*")).unwrap(),
+            paths(&["target/a1.profraw", "target/b1.profraw"])
+        );
+        assert!(expand_glob(&pattern("target/[a.profraw")).is_err());
+    }
+
+    #[test]
+    fn test_expand_input_paths_globs() {
+        let tmp_dir = profraw_tree();
+        let pattern = |pattern: &str| tmp_dir.path().join(pattern).to_string_lossy().into_owned();
+
+        let inputs = expand_input_paths(
+            &[
+                "test/prova.info".to_string(),
+                pattern("target/deps/**/*.prof*"),
+                pattern("target/*.missing"),
+            ],
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            inputs,
+            vec![
+                "test/prova.info".to_string(),
+                pattern("target/deps/a1.profdata"),
+                pattern("target/deps/nested/a3.profraw"),
+            ]
+        );
+
+        let err = expand_input_paths(&[pattern("target/*.missing")], None, false).unwrap_err();
+        assert_eq!(
+            err,
+            format!("No input files match {}", pattern("target/*.missing"))
+        );
+    }
+
+    #[test]
+    fn test_read_input_list() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let profraw = tmp_dir.path().join("default.profraw");
+        fs::write(&profraw, "").unwrap();
+        let list_path = tmp_dir.path().join("inputs.txt");
+        fs::write(&list_path, "default.profraw\n# Removed.\nmissing.profraw\n").unwrap();
+
+        let err = read_input_list(&list_path, false).unwrap_err();
+        assert_eq!(
+            err,
+            format!(
+                "{}:3: {} doesn't exist",
+                list_path.display(),
+                tmp_dir.path().join("missing.profraw").display()
+            )
+        );
+        assert_eq!(
+            read_input_list(&list_path, true).unwrap(),
+            vec![profraw.to_string_lossy().into_owned()]
+        );
+
+        let at_list = format!("@{}", list_path.display());
+        assert_eq!(
+            expand_input_paths(&["test/prova.info".to_string(), at_list], None, true).unwrap(),
+            vec![
+                "test/prova.info".to_string(),
+                profraw.to_string_lossy().into_owned()
+            ]
+        );
+    }
+}
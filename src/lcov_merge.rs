@@ -0,0 +1,86 @@
+//! The side channel of the merge of the lcov exports of the binaries: the index of the binaries
+//! contributing to each source file, for --source-index.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::defs::*;
+use crate::merged_path;
+
+/// The binaries whose coverage includes each source file, for --source-index. The source files
+/// are the ones the results are merged under, before the path rewriting.
+#[derive(Debug, Default)]
+pub struct SourceIndex(Mutex<BTreeMap<String, BTreeSet<PathBuf>>>);
+
+impl SourceIndex {
+    /// Records that the coverage exported for `binary` has results for the given source files.
+    pub fn add(
+        &self,
+        binary: &Path,
+        results: &[(impl AsRef<str>, CovResult)],
+        source_dir: Option<&Path>,
+    ) {
+        let mut index = self.0.lock().unwrap();
+        for (path, _) in results {
+            index
+                .entry(merged_path(path.as_ref().to_string(), source_dir))
+                .or_default()
+                .insert(binary.to_path_buf());
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(&*self.0.lock().unwrap()).unwrap()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, serde_json::to_string_pretty(&self.to_json()).unwrap()).map_err(|e| {
+            format!(
+                "Unable to write the source index to {}: {}",
+                path.display(),
+                e
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_source_index() {
+        let results = |paths: &[&str]| -> Vec<(String, CovResult)> {
+            paths
+                .iter()
+                .map(|path| (path.to_string(), CovResult::default()))
+                .collect()
+        };
+        let index = SourceIndex::default();
+        index.add(
+            Path::new("target/debug/deps/a-1234"),
+            &results(&["/work/src/lib.rs", "/work/src/a.rs"]),
+            None,
+        );
+        index.add(
+            Path::new("target/debug/deps/b-5678"),
+            &results(&["/work/src/lib.rs"]),
+            None,
+        );
+        index.add(
+            Path::new("target/debug/deps/a-1234"),
+            &results(&["/work/src/lib.rs"]),
+            None,
+        );
+        assert_eq!(
+            index.to_json(),
+            json!({
+                "/work/src/a.rs": ["target/debug/deps/a-1234"],
+                "/work/src/lib.rs": ["target/debug/deps/a-1234", "target/debug/deps/b-5678"],
+            })
+        );
+    }
+}
@@ -1,5 +1,4 @@
 #![recursion_limit = "1024"]
-#![allow(clippy::too_many_arguments)]
 
 mod defs;
 pub use crate::defs::*;
@@ -24,12 +23,21 @@ mod symlink;
 mod path_rewriting;
 pub use crate::path_rewriting::*;
 
+mod path_utils;
+pub use crate::path_utils::*;
+
+mod path_interner;
+pub use crate::path_interner::*;
+
 mod output;
 pub use crate::output::*;
 
 mod cobertura;
 pub use crate::cobertura::*;
 
+mod sonar;
+pub use crate::sonar::*;
+
 mod reader;
 pub use crate::reader::*;
 
@@ -38,92 +46,325 @@ pub use crate::covdir::*;
 
 pub mod html;
 
+mod raw_coverage;
+pub use crate::raw_coverage::*;
+
+mod config;
+pub use crate::config::*;
+
+mod aggregate;
+pub use crate::aggregate::*;
+
+mod threshold;
+pub use crate::threshold::*;
+
+mod trend;
+pub use crate::trend::*;
+
+mod watch;
+pub use crate::watch::*;
+
+mod serve;
+pub use crate::serve::*;
+
+mod depfile;
+pub use crate::depfile::*;
+
+mod clean;
+pub use crate::clean::*;
+
+mod cargo_messages;
+pub use crate::cargo_messages::*;
+
+mod cargo_test;
+pub use crate::cargo_test::*;
+
 mod file_filter;
 pub use crate::file_filter::*;
 
-use log::{error, warn};
-use std::fs;
-use std::io::{BufReader, Cursor};
+mod function_filter;
+pub use crate::function_filter::*;
+
+mod progress;
+pub use crate::progress::*;
+
+mod log_format;
+pub use crate::log_format::*;
+
+mod line_remap;
+pub use crate::line_remap::*;
+
+mod covmap_version;
+pub use crate::covmap_version::*;
+
+mod wasm;
+pub use crate::wasm::*;
+
+mod rollup;
+pub use crate::rollup::*;
+
+mod diagnostics;
+pub use crate::diagnostics::*;
+
+mod input_list;
+pub use crate::input_list::*;
+
+mod threads;
+pub use crate::threads::*;
+
+mod dry_run;
+pub use crate::dry_run::*;
+
+mod ignored;
+pub use crate::ignored::*;
+
+mod summary;
+pub use crate::summary::*;
+
+mod lcov_merge;
+pub use crate::lcov_merge::*;
+
+mod spill;
+pub use crate::spill::*;
+
+mod coverage_diff;
+pub use crate::coverage_diff::*;
+
+mod dedup;
+pub use crate::dedup::*;
+
+mod coverage_data;
+pub use crate::coverage_data::*;
+
+mod discovery;
+pub use crate::discovery::*;
+
+mod demangle_cache;
+pub use crate::demangle_cache::*;
+
+mod line_map;
+
+mod version_info;
+pub use crate::version_info::*;
+
+mod annotated_source;
+pub use crate::annotated_source::*;
+
+mod merge_mode;
+pub use crate::merge_mode::*;
+
+mod gcno_cache;
+pub use crate::gcno_cache::*;
+
+use log::{debug, error, trace, warn};
+use regex::Regex;
+use rustc_hash::FxHashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor};
 use std::{
-    collections::{btree_map, hash_map},
-    path::Path,
+    collections::{btree_map, hash_map, BTreeMap},
+    fmt, mem,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 use walkdir::WalkDir;
 
-// Merge results, without caring about duplicate lines (they will be removed at the end).
-pub fn merge_results(result: &mut CovResult, result2: CovResult) -> bool {
-    let mut warn_overflow = false;
-    for (&line_no, &execution_count) in &result2.lines {
-        match result.lines.entry(line_no) {
-            btree_map::Entry::Occupied(c) => {
-                let v = c.get().checked_add(execution_count).unwrap_or_else(|| {
-                    warn_overflow = true;
-                    std::u64::MAX
-                });
-
-                *c.into_mut() = v;
-            }
+// Whether the keys of two maps don't overlap, so that one can be appended to the other.
+fn disjoint_keys<V>(a: &BTreeMap<u32, V>, b: &BTreeMap<u32, V>) -> bool {
+    match (a.keys().next(), a.keys().next_back()) {
+        (Some(a_first), Some(a_last)) => match (b.keys().next(), b.keys().next_back()) {
+            (Some(b_first), Some(b_last)) => a_last < b_first || b_last < a_first,
+            _ => true,
+        },
+        _ => true,
+    }
+}
+
+// Moves the entries of `from` to `into`, merging the values of the lines of both with `merge`.
+// The maps are swapped first when `from` is the biggest one, so `merge` mustn't depend on the
+// order of its arguments.
+fn merge_maps<V>(
+    into: &mut BTreeMap<u32, V>,
+    mut from: BTreeMap<u32, V>,
+    mut merge: impl FnMut(&mut V, V),
+) {
+    if into.len() < from.len() {
+        mem::swap(into, &mut from);
+    }
+    // Appending rebuilds the map, which is only worth it when it isn't much bigger.
+    if from.len() * 4 >= into.len() && disjoint_keys(into, &from) {
+        into.append(&mut from);
+        return;
+    }
+    for (line_no, value) in from {
+        match into.entry(line_no) {
+            btree_map::Entry::Occupied(c) => merge(c.into_mut(), value),
             btree_map::Entry::Vacant(v) => {
-                v.insert(execution_count);
+                v.insert(value);
             }
         };
     }
+}
 
-    for (line_no, taken) in result2.branches {
-        match result.branches.entry(line_no) {
-            btree_map::Entry::Occupied(c) => {
-                let v = c.into_mut();
-                for (x, y) in taken.iter().zip(v.iter_mut()) {
-                    *y |= x;
-                }
-                let l = v.len();
-                if taken.len() > l {
-                    v.extend(&taken[l..]);
+// The start line and the demangled name of a function, which are the same for all its copies.
+fn function_instance(name: &str, function: &Function) -> (u32, String) {
+    (
+        function.start,
+        demangle_name(name).unwrap_or_else(|| name.to_string()),
+    )
+}
+
+// Merge results, without caring about duplicate lines (they will be removed at the end).
+// The execution counts of a line are summed, whatever the order of the results, so a line hit by
+// one binary and not by another one is covered (the sum saturates, and the overflow is reported in
+// the returned value). A branch is taken, and a function executed, if it is in any of them (a
+// function of `result2` being the one of `result` at the same line with the same demangled name).
+// The maps of `result2` are moved into the ones of `result`, or instead of them when they're empty.
+pub fn merge_results(result: &mut CovResult, result2: CovResult) -> bool {
+    merge_results_with_mode(result, result2, MergeMode::Sum)
+}
+
+/// Like merge_results, merging the execution counts of the lines with the given mode: the highest
+/// one is kept with MergeMode::Max.
+pub fn merge_results_with_mode(
+    result: &mut CovResult,
+    result2: CovResult,
+    mode: MergeMode,
+) -> bool {
+    if result.lines.is_empty()
+        && result.branches.is_empty()
+        && result.functions.is_empty()
+        && result.mcdc.is_none()
+    {
+        *result = result2;
+        return false;
+    }
+
+    let mut warn_overflow = false;
+    result.lines.merge(result2.lines, |count, count2| {
+        *count = match mode {
+            MergeMode::Sum => count.checked_add(count2).unwrap_or_else(|| {
+                warn_overflow = true;
+                std::u64::MAX
+            }),
+            MergeMode::Max => (*count).max(count2),
+        };
+    });
+
+    merge_maps(&mut result.branches, result2.branches, |v, taken| {
+        for (x, y) in taken.iter().zip(v.iter_mut()) {
+            *y |= x;
+        }
+        let l = v.len();
+        if taken.len() > l {
+            v.extend(&taken[l..]);
+        }
+    });
+
+    // The conditions of a decision are covered if they are in any of them, the decisions being
+    // matched by their line and number of conditions, in their order.
+    if let Some(mcdc2) = result2.mcdc {
+        let mcdc = result.mcdc.get_or_insert_with(Vec::new);
+        let mut seen: FxHashMap<(u32, usize), usize> = FxHashMap::default();
+        for record2 in mcdc2 {
+            let key = (record2.line, record2.conditions.len());
+            let nth = seen.entry(key).or_insert(0);
+            let matching = mcdc
+                .iter_mut()
+                .filter(|record| (record.line, record.conditions.len()) == key)
+                .nth(*nth);
+            *nth += 1;
+            match matching {
+                Some(record) => {
+                    for (covered, covered2) in record.conditions.iter_mut().zip(record2.conditions)
+                    {
+                        *covered |= covered2;
+                    }
                 }
+                None => mcdc.push(record2),
             }
-            btree_map::Entry::Vacant(v) => {
-                v.insert(taken);
-            }
-        };
+        }
+        mcdc.sort_by_key(|record| record.line);
     }
 
-    for (name, function) in result2.functions {
-        match result.functions.entry(name) {
-            hash_map::Entry::Occupied(f) => f.into_mut().executed |= function.executed,
-            hash_map::Entry::Vacant(v) => {
-                v.insert(function);
+    // The functions aren't swapped, as the start line of the first result is kept.
+    if result.functions.is_empty() {
+        result.functions = result2.functions;
+    } else {
+        // The functions of result, by their line and demangled name, only built for the first
+        // function missing from it.
+        let mut instances: Option<FxHashMap<(u32, String), String>> = None;
+        for (name, function) in result2.functions {
+            if let Some(f) = result.functions.get_mut(&name) {
+                f.executed |= function.executed;
+                continue;
             }
-        };
+            // The copies of an inline or generic function instantiated by several binaries have
+            // different hashes in their mangled names, but they are the same function.
+            let functions = &result.functions;
+            let instances = instances.get_or_insert_with(|| {
+                functions
+                    .iter()
+                    .map(|(name, f)| (function_instance(name, f), name.clone()))
+                    .collect()
+            });
+            let instance = function_instance(&name, &function);
+            match instances.get(&instance) {
+                Some(first) => {
+                    result.functions.get_mut(first).unwrap().executed |= function.executed
+                }
+                None => {
+                    instances.insert(instance, name.clone());
+                    result.functions.insert(name, function);
+                }
+            }
+        }
     }
 
     warn_overflow
 }
 
-fn add_results(
+/// Returns the path the results of a source file are merged under.
+pub fn merged_path(path: String, source_dir: Option<&Path>) -> String {
+    match source_dir {
+        Some(source_dir) => {
+            // the goal here is to be able to merge results for paths like foo/./bar and foo/bar
+            if let Ok(p) = canonicalize_path(source_dir.join(&path)) {
+                String::from(p.to_str().unwrap())
+            } else {
+                path
+            }
+        }
+        None => path,
+    }
+}
+
+// The map keeps the path of the first result of each file, and drops the others. The paths of all
+// the parsers are normalized first, so that `c:\src\lib.rs` and `C:/src/lib.rs` are merged. The
+// results are merged with the mode of the merge rules matching their path. Returns an estimate of
+// the memory added to the map, for --max-memory.
+pub fn add_results(
     results: Vec<(String, CovResult)>,
     result_map: &SyncCovResultMap,
     source_dir: Option<&Path>,
-) {
+    merge_rules: &[MergeRule],
+) -> usize {
     let mut map = result_map.lock().unwrap();
     let mut warn_overflow = false;
+    let mut added = 0;
     for result in results.into_iter() {
-        let path = match source_dir {
-            Some(source_dir) => {
-                // the goal here is to be able to merge results for paths like foo/./bar and foo/bar
-                if let Ok(p) = canonicalize_path(source_dir.join(&result.0)) {
-                    String::from(p.to_str().unwrap())
-                } else {
-                    result.0
-                }
-            }
-            None => result.0,
-        };
+        let path = merged_path(normalize_source_path(result.0), source_dir);
+        let mode = merge_mode(merge_rules, &path);
         let entry = map.entry(path);
         match entry {
             hash_map::Entry::Occupied(obj) => {
-                warn_overflow |= merge_results(obj.into_mut(), result.1);
+                let prev = obj.into_mut();
+                let size = entries_size(prev);
+                warn_overflow |= merge_results_with_mode(prev, result.1, mode);
+                added += entries_size(prev).saturating_sub(size);
             }
             hash_map::Entry::Vacant(v) => {
+                added += estimated_entry_size(v.key(), &result.1);
                 v.insert(result.1);
             }
         };
@@ -132,6 +373,183 @@ fn add_results(
     if warn_overflow {
         warn!("Execution count overflow detected.");
     }
+    added
+}
+
+/// Like add_results, for the results of one input with interned paths: the path of each file is
+/// normalized once, and only copied into the map by the first result of the file.
+pub fn add_interned_results(
+    results: InternedResults,
+    result_map: &SyncCovResultMap,
+    source_dir: Option<&Path>,
+    merge_rules: &[MergeRule],
+) -> usize {
+    let mut merged_paths: FxHashMap<Arc<str>, String> = FxHashMap::default();
+    let mut map = result_map.lock().unwrap();
+    let mut warn_overflow = false;
+    let mut added = 0;
+    for (path, result) in results {
+        let path = merged_paths.entry(path).or_insert_with_key(|path| {
+            merged_path(normalize_source_path(path.to_string()), source_dir)
+        });
+        let mode = merge_mode(merge_rules, path);
+        match map.get_mut(path.as_str()) {
+            Some(prev) => {
+                let size = entries_size(prev);
+                warn_overflow |= merge_results_with_mode(prev, result, mode);
+                added += entries_size(prev).saturating_sub(size);
+            }
+            None => {
+                added += estimated_entry_size(path, &result);
+                map.insert(path.clone(), result);
+            }
+        }
+    }
+
+    if warn_overflow {
+        warn!("Execution count overflow detected.");
+    }
+    added
+}
+
+/// Turns the results of a baseline report into results where everything is instrumented but
+/// not covered, so that adding them to the other results only adds the missing lines, branches
+/// and functions, like genhtml's --baseline-file.
+pub fn baseline_results(results: Vec<(String, CovResult)>) -> Vec<(String, CovResult)> {
+    results
+        .into_iter()
+        .map(|(path, mut result)| {
+            for execution_count in result.lines.values_mut() {
+                *execution_count = 0;
+            }
+            for taken in result.branches.values_mut() {
+                taken.iter_mut().for_each(|taken| *taken = false);
+            }
+            for function in result.functions.values_mut() {
+                function.executed = false;
+            }
+            (path, result)
+        })
+        .collect()
+}
+
+/// Removes the files with fewer than `min_lines` instrumented lines (e.g. files with only
+/// re-exports), so that they don't skew the coverage percentages.
+pub fn filter_min_lines_in_file(results: Vec<ResultTuple>, min_lines: usize) -> Vec<ResultTuple> {
+    let (kept, excluded): (Vec<_>, Vec<_>) = results
+        .into_iter()
+        .partition(|(_, _, result)| result.lines.len() >= min_lines);
+    if !excluded.is_empty() {
+        debug!(
+            "Excluded {} files with fewer than {} instrumented lines",
+            excluded.len(),
+            min_lines
+        );
+        for (_, rel_path, result) in &excluded {
+            trace!(
+                "Excluded {} with {} instrumented lines",
+                rel_path.display(),
+                result.lines.len()
+            );
+        }
+    }
+    kept
+}
+
+/// The marker of the generated files excluded by --excl-generated, when no regex is given.
+pub const DEFAULT_GENERATED_MARKER: &str = r"@generated|Code generated by";
+
+// The number of lines at the beginning of a source file searched for the generated marker.
+const GENERATED_MARKER_LINES: usize = 5;
+
+/// Returns whether one of the first lines of a source file matches the marker of the generated
+/// files, e.g. `// @generated`. The files which can't be read aren't considered as generated.
+pub fn is_generated_file(path: &Path, marker: &Regex) -> bool {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    BufReader::new(file)
+        .lines()
+        .take(GENERATED_MARKER_LINES)
+        .map_while(Result::ok)
+        .any(|line| marker.is_match(&line))
+}
+
+/// Removes the files written by code generators, whose first lines match the marker.
+pub fn filter_generated_files(results: Vec<ResultTuple>, marker: &Regex) -> Vec<ResultTuple> {
+    let count = results.len();
+    let kept = filter_results_by_path(results, |abs_path| {
+        let generated = is_generated_file(abs_path, marker);
+        if generated {
+            trace!("Excluded the generated file {}", abs_path.display());
+        }
+        !generated
+    });
+    if kept.len() < count {
+        debug!("Excluded {} generated files", count - kept.len());
+    }
+    kept
+}
+
+// The number of missing files listed in the warning about missing source files.
+const MISSING_SOURCE_FILES_EXAMPLES: usize = 5;
+
+/// The warning about the source files of the results which don't exist on this machine.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MissingSourceFiles {
+    pub missing: usize,
+    pub total: usize,
+    /// The first missing files, listed in the warning.
+    pub examples: Vec<String>,
+}
+
+impl fmt::Display for MissingSourceFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} of the {} source files in the coverage data don't exist on this machine, e.g.:",
+            self.missing, self.total
+        )?;
+        for path in &self.examples {
+            writeln!(f, "    {}", path)?;
+        }
+        write!(f, "The coverage data was probably generated on another machine: use --prefix-dir or --path-mapping to rewrite the paths, or --no-path-warning to disable this warning.")
+    }
+}
+
+/// Returns a warning if most of the source files of the results don't exist, which usually
+/// means that the coverage data was generated on another machine.
+pub fn missing_source_files_warning(
+    results: &[(impl AsRef<str>, CovResult)],
+    source_dir: Option<&Path>,
+) -> Option<MissingSourceFiles> {
+    let missing: Vec<&str> = results
+        .iter()
+        .map(|(path, _)| path.as_ref())
+        .filter(|path| {
+            let path = Path::new(path);
+            let path = match source_dir {
+                Some(source_dir) if path.is_relative() => source_dir.join(path),
+                _ => path.to_path_buf(),
+            };
+            !path.exists()
+        })
+        .collect();
+
+    if missing.len() * 2 <= results.len() {
+        return None;
+    }
+
+    Some(MissingSourceFiles {
+        missing: missing.len(),
+        total: results.len(),
+        examples: missing
+            .iter()
+            .take(MISSING_SOURCE_FILES_EXAMPLES)
+            .map(|path| path.to_string())
+            .collect(),
+    })
 }
 
 fn rename_single_files(results: &mut [(String, CovResult)], stem: &str) {
@@ -147,6 +565,92 @@ fn rename_single_files(results: &mut [(String, CovResult)], stem: &str) {
     }
 }
 
+// Checks that a gcda file is complete. The empty or truncated ones are skipped with a warning,
+// so that the lines of their gcno are reported as not executed, rather than the whole file being
+// lost with a parse error.
+fn is_truncated_gcda(buf: &[u8], path: &str, input_counts: &InputCounts) -> bool {
+    match gcda_truncation(buf) {
+        Some(offset) => {
+            input_counts.add_skipped(1);
+            if buf.is_empty() {
+                warn!(path = path; "Skipping {}: the gcda file is empty", path);
+            } else {
+                warn!(
+                    path = path, offset = offset;
+                    "Skipping {}: the gcda file is truncated at byte {}", path, offset
+                );
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+// Where a consumer extracts the gcno file of a stem from a zip archive: in its working directory,
+// where gcov writes its outputs under the name of the gcno file.
+fn extracted_gcno_path(working_dir: &Path, stem: &str) -> PathBuf {
+    let name = Path::new(stem).file_name().unwrap_or_default();
+    working_dir.join(format!("{}.gcno", name.to_string_lossy()))
+}
+
+// A gcno file extracted by a consumer, and its gcda file, which are removed when dropped, before
+// the outputs of gcov are read from the working directory.
+struct ExtractedPair(PathBuf);
+
+impl Drop for ExtractedPair {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+        let _ = fs::remove_file(self.0.with_extension("gcda"));
+    }
+}
+
+// Copies a gcno file to a temporary directory, where gcov reads it alone as there is no gcda file
+// next to it, so that the lines of a truncated gcda file are reported as not executed. The gcda
+// file, which may be the one of the user, is left as it is.
+fn copy_gcno_alone(gcno_path: &Path) -> io::Result<(tempfile::TempDir, PathBuf)> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join(gcno_path.file_name().unwrap());
+    fs::copy(gcno_path, &path)?;
+    Ok((dir, path))
+}
+
+// Computes the counters of a gcno file in the LLVM format and its gcda files.
+fn compute_gcno(
+    buffers: GcnoBuffers,
+    branch_enabled: bool,
+    guess_directory: bool,
+    gcno_cache: Option<&GcnoCache>,
+    input_counts: &InputCounts,
+) -> Vec<(String, CovResult)> {
+    let GcnoBuffers {
+        stem,
+        gcno_buf,
+        mut gcda_buf,
+        gcno_entry,
+    } = buffers;
+    let gcda_path = format!("{}.gcda", stem);
+    gcda_buf.retain(|gcda_buf| !is_truncated_gcda(gcda_buf, &gcda_path, input_counts));
+    // The gcno file is read from the cache when it didn't change.
+    let gcno = match (gcno_cache, &gcno_entry) {
+        (Some(gcno_cache), Some(entry)) => gcno_cache.parse(entry, &stem, gcno_buf),
+        _ => Gcno::parse(&stem, gcno_buf),
+    };
+    match gcno.and_then(|gcno| gcno.compute_counters(&stem, gcda_buf, branch_enabled)) {
+        Ok(mut r) => {
+            if guess_directory {
+                rename_single_files(&mut r, &stem);
+            }
+            r
+        }
+        Err(e) => {
+            // Just print the error, don't panic and continue
+            input_counts.add_parse_error();
+            error!("Error in computing counters: {}", e);
+            Vec::new()
+        }
+    }
+}
+
 // Some versions of GCC, because of a bug, generate multiple gcov files for each
 // gcno, so we have to support this case too for the time being.
 #[derive(PartialEq, Eq)]
@@ -157,42 +661,132 @@ enum GcovType {
 }
 
 macro_rules! try_parse {
-    ($v:expr, $f:expr) => {
+    ($v:expr, $f:expr, $input_counts:expr) => {
         match $v {
             Ok(val) => val,
             Err(err) => {
-                error!("Error parsing file {}: {}", $f, err);
+                $input_counts.add_parse_error();
+                error!(
+                    path = $f.as_str(), reason = err.to_string();
+                    "Error parsing file {}: {}", $f, err
+                );
                 continue;
             }
         }
     };
 }
 
+/// The options of the consumers, which are the same for all of them.
+#[derive(Default)]
+pub struct ConsumerOptions<'a> {
+    pub source_dir: Option<&'a Path>,
+    pub branch_enabled: bool,
+    pub mcdc_enabled: bool,
+    pub guess_directory: bool,
+    pub binary_path: Option<&'a Path>,
+    pub cargo_executables: &'a [PathBuf],
+    pub stage_profraws: bool,
+    pub path_warning: bool,
+    pub skip_unprofiled_binaries: bool,
+    pub allowed_binaries: Option<&'a [PathBuf]>,
+    pub wasm: bool,
+    pub export_cache_dir: Option<&'a Path>,
+    pub gcno_cache: Option<&'a GcnoCache>,
+    pub in_memory_profdata: bool,
+    pub fail_on_binary_mismatch: bool,
+    pub source_index: Option<&'a SourceIndex>,
+    pub merge_rules: &'a [MergeRule],
+    pub merge_spill: Option<&'a MergeSpill>,
+}
+
+/// Parses the work items received from the producer until it's finished, with its own working
+/// directory, and merges their results into the result map.
 pub fn consumer(
     working_dir: &Path,
-    source_dir: Option<&Path>,
     result_map: &SyncCovResultMap,
     receiver: JobReceiver,
-    branch_enabled: bool,
-    guess_directory: bool,
-    binary_path: Option<&Path>,
+    input_counts: &InputCounts,
+    options: &ConsumerOptions,
 ) {
+    let &ConsumerOptions {
+        source_dir,
+        branch_enabled,
+        mcdc_enabled,
+        guess_directory,
+        binary_path,
+        cargo_executables,
+        stage_profraws,
+        path_warning,
+        skip_unprofiled_binaries,
+        allowed_binaries,
+        wasm,
+        export_cache_dir,
+        gcno_cache,
+        in_memory_profdata,
+        fail_on_binary_mismatch,
+        source_index,
+        merge_rules,
+        merge_spill,
+    } = options;
     let mut gcov_type = GcovType::Unknown;
+    let mut zip_readers = ZipReaders::default();
 
     while let Ok(work_item) = receiver.recv() {
         if work_item.is_none() {
             break;
         }
         let work_item = work_item.unwrap();
+        let _parsed = ParsedFiles(work_item_files(&work_item));
+        let mut interner = PathInterner::default();
         let new_results = match work_item.format {
             ItemFormat::Gcno => {
-                match work_item.item {
+                // The GCC files of zip archives are extracted here rather than by the producer.
+                let mut extracted = None;
+                let item = match work_item.item {
+                    ItemType::GccEntries(entries) => {
+                        let gcno_path = extracted_gcno_path(working_dir, &entries.stem);
+                        let pair = ExtractedPair(gcno_path.clone());
+                        if let Err(e) = zip_readers.extract_gcc_entries(&entries, &gcno_path) {
+                            input_counts.add_parse_error();
+                            error!("Failed to extract {}.gcno: {}", entries.stem, e);
+                            continue;
+                        }
+                        extracted = Some(pair);
+                        ItemType::Path((entries.stem, gcno_path))
+                    }
+                    item => item,
+                };
+                match item {
                     ItemType::Path((stem, gcno_path)) => {
                         // GCC
+                        let gcda_path = gcno_path.with_extension("gcda");
+                        // The gcno of a truncated gcda file is read from a directory of its own,
+                        // which is removed with it once gcov ran.
+                        let mut _gcno_alone_dir = None;
+                        let mut gcno_path = gcno_path;
+                        if let Ok(gcda_buf) = fs::read(&gcda_path) {
+                            let name = Path::new(&work_item.name).join(format!("{}.gcda", stem));
+                            if is_truncated_gcda(&gcda_buf, &name.to_string_lossy(), input_counts) {
+                                match copy_gcno_alone(&gcno_path) {
+                                    Ok((dir, path)) => {
+                                        _gcno_alone_dir = Some(dir);
+                                        gcno_path = path;
+                                    }
+                                    Err(e) => {
+                                        input_counts.add_parse_error();
+                                        error!("Failed to copy {}: {}", gcno_path.display(), e);
+                                        continue;
+                                    }
+                                }
+                            }
+                        }
                         if let Err(e) = run_gcov(&gcno_path, branch_enabled, working_dir) {
+                            input_counts.add_parse_error();
                             error!("Error when running gcov: {}", e);
                             continue;
                         };
+                        // The outputs of gcov are all the other files of the working directory.
+                        drop(extracted);
                         let gcov_ext = get_gcov_output_ext();
                         let gcov_path =
                             gcno_path.file_name().unwrap().to_str().unwrap().to_string() + gcov_ext;
@@ -214,7 +808,8 @@ pub fn consumer(
                                 } else {
                                     panic!("Invalid gcov extension: {}", gcov_ext);
                                 },
-                                work_item.name
+                                work_item.name,
+                                input_counts
                             );
                             fs::remove_file(gcov_path).unwrap();
                             new_results
@@ -231,7 +826,8 @@ pub fn consumer(
                                     } else {
                                         parse_gcov(gcov_path)
                                     },
-                                    work_item.name
+                                    work_item.name,
+                                    input_counts
                                 ));
 
                                 fs::remove_file(gcov_path).unwrap();
@@ -243,26 +839,34 @@ pub fn consumer(
                         if guess_directory {
                             rename_single_files(&mut new_results, &stem);
                         }
-                        new_results
+                        interner.intern_results(new_results)
                     }
                     ItemType::Buffers(buffers) => {
                         // LLVM
-                        match Gcno::compute(
-                            &buffers.stem,
-                            buffers.gcno_buf,
-                            buffers.gcda_buf,
+                        interner.intern_results(compute_gcno(
+                            buffers,
                             branch_enabled,
-                        ) {
-                            Ok(mut r) => {
-                                if guess_directory {
-                                    rename_single_files(&mut r, &buffers.stem);
-                                }
-                                r
-                            }
+                            guess_directory,
+                            gcno_cache,
+                            input_counts,
+                        ))
+                    }
+                    ItemType::Entries(entries) => {
+                        // LLVM, with the files of zip archives read here rather than by the
+                        // producer.
+                        let stem = entries.stem.clone();
+                        match zip_readers.read_gcno_entries(entries) {
+                            Ok(buffers) => interner.intern_results(compute_gcno(
+                                buffers,
+                                branch_enabled,
+                                guess_directory,
+                                gcno_cache,
+                                input_counts,
+                            )),
                             Err(e) => {
-                                // Just print the error, don't panic and continue
-                                error!("Error in computing counters: {}", e);
-                                Vec::new()
+                                input_counts.add_parse_error();
+                                error!("Failed to read {}.gcno: {}", stem, e);
+                                continue;
                             }
                         }
                     }
@@ -270,37 +874,100 @@ pub fn consumer(
                         error!("Invalid content type");
                         continue;
                     }
-                    ItemType::Paths(_) => {
+                    ItemType::Paths(_) | ItemType::ZipEntry(_) | ItemType::GccEntries(_) => {
                         error!("Invalid content type");
                         continue;
                     }
                 }
             }
             ItemFormat::Profraw => {
-                if binary_path.is_none() {
+                if binary_path.is_none() && cargo_executables.is_empty() {
+                    input_counts.add_skipped(work_item_files(&work_item) as usize);
                     error!("The path to the compiled binary must be given as an argument when source-based coverage is used");
                     continue;
                 }
 
                 if let ItemType::Paths(profraw_paths) = work_item.item {
-                    match llvm_tools::profraws_to_lcov(
+                    // The branches are read from the JSON export, as the lcov export of llvm-cov
+                    // misses the BRDA records of some kinds of branches, and so are the MC/DC
+                    // records, which only the JSON export has.
+                    let json_export = mcdc_enabled
+                        || branch_enabled
+                        || profraw_paths
+                            .iter()
+                            .any(|path| llvm_tools::profraw_has_mcdc(path));
+                    match llvm_tools::profraws_to_lcov_with_stats(
                         profraw_paths.as_slice(),
-                        binary_path.as_ref().unwrap(),
                         working_dir,
+                        &ExportOptions {
+                            binary_path,
+                            executables: cargo_executables,
+                            stage: stage_profraws,
+                            // The exports are parsed, and the report sorted when it is written.
+                            sort: false,
+                            skip_unprofiled_binaries,
+                            allowed_binaries,
+                            json: json_export,
+                            wasm,
+                            export_cache_dir,
+                            in_memory_profdata,
+                            fail_on_binary_mismatch,
+                        },
                     ) {
-                        Ok(lcovs) => {
-                            let mut new_results: Vec<(String, CovResult)> = Vec::new();
+                        Ok((lcovs, stats)) => {
+                            debug!(
+                                "Merged the profraws in {:?} and exported {} binaries in {:?} ({} cached, {} skipped)",
+                                stats.merge_time,
+                                stats.binaries_processed,
+                                stats.export_time,
+                                stats.binaries_cached,
+                                stats.binaries_skipped
+                            );
+                            input_counts.add_skipped(stats.binaries_skipped);
+                            // The source files of the binaries share the same interned paths, e.g.
+                            // the ones of a library linked into all the test binaries.
+                            let mut new_results = InternedResults::new();
 
-                            for lcov in lcovs {
-                                new_results.append(&mut try_parse!(
-                                    parse_lcov(lcov, branch_enabled),
-                                    work_item.name
-                                ));
+                            for (lcov, binary) in lcovs.into_iter().zip(&stats.exported) {
+                                let mut results = if json_export {
+                                    interner.intern_results(try_parse!(
+                                        parse_llvm_cov_json(&lcov, branch_enabled, mcdc_enabled),
+                                        work_item.name,
+                                        input_counts
+                                    ))
+                                } else {
+                                    try_parse!(
+                                        parse_lcov_interned(&lcov, branch_enabled, &mut interner),
+                                        work_item.name,
+                                        input_counts
+                                    )
+                                };
+                                if let Some(source_index) = source_index {
+                                    source_index.add(binary, &results, source_dir);
+                                }
+                                new_results.append(&mut results);
+                            }
+
+                            if path_warning {
+                                if let Some(warning) =
+                                    missing_source_files_warning(&new_results, source_dir)
+                                {
+                                    suspend_progress(|| {
+                                        warn!(
+                                            missing = warning.missing, total = warning.total;
+                                            "{}", warning
+                                        )
+                                    });
+                                }
                             }
 
                             new_results
                         }
                         Err(e) => {
+                            input_counts.add_parse_error();
+                            if let llvm_tools::LlvmToolError::UnprofiledBinaries(_) = e {
+                                input_counts.add_mismatched_profile();
+                            }
                             error!("Error while executing llvm tools: {}", e);
                             continue;
                         }
@@ -313,11 +980,30 @@ pub fn consumer(
             ItemFormat::Info | ItemFormat::JacocoXml => {
                 if let ItemType::Content(content) = work_item.item {
                     if work_item.format == ItemFormat::Info {
-                        try_parse!(parse_lcov(content, branch_enabled), work_item.name)
+                        try_parse!(
+                            parse_lcov_interned(&content, branch_enabled, &mut interner),
+                            work_item.name,
+                            input_counts
+                        )
                     } else {
                         let buffer = BufReader::new(Cursor::new(content));
-                        try_parse!(parse_jacoco_xml_report(buffer), work_item.name)
+                        interner.intern_results(try_parse!(
+                            parse_jacoco_xml_report(buffer),
+                            work_item.name,
+                            input_counts
+                        ))
                     }
+                } else if let ItemType::ZipEntry((zip_path, name)) = work_item.item {
+                    let is_info = work_item.format == ItemFormat::Info;
+                    let parsed = parse_zip_entry(&zip_path, &name, |file| {
+                        if is_info {
+                            parse_lcov_reader_interned(file, branch_enabled, &mut interner)
+                        } else {
+                            parse_jacoco_xml_report(BufReader::new(file))
+                                .map(|results| interner.intern_results(results))
+                        }
+                    });
+                    try_parse!(parsed, work_item.name, input_counts)
                 } else {
                     error!("Invalid content type");
                     continue;
@@ -325,18 +1011,180 @@ pub fn consumer(
             }
         };
 
-        add_results(new_results, result_map, source_dir);
+        let added = add_interned_results(new_results, result_map, source_dir, merge_rules);
+        if let Some(merge_spill) = merge_spill {
+            merge_spill
+                .add(result_map, added)
+                .expect("Failed to spill the results to a temporary file");
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::collection::{btree_map, hash_map, vec};
+    use proptest::prelude::*;
     use rustc_hash::FxHashMap;
     use std::fs::File;
     use std::io::Read;
+    use std::path::PathBuf;
     use std::sync::{Arc, Mutex};
 
+    #[test]
+    fn test_filter_min_lines_in_file() {
+        let result = |lines: u32| CovResult {
+            lines: (1..=lines).map(|line| (line, 1)).collect(),
+            branches: Default::default(),
+            functions: Default::default(),
+            mcdc: None,
+        };
+        let results = vec![
+            (
+                PathBuf::from("src/lib.rs"),
+                PathBuf::from("src/lib.rs"),
+                result(2),
+            ),
+            (
+                PathBuf::from("src/run.rs"),
+                PathBuf::from("src/run.rs"),
+                result(3),
+            ),
+            (
+                PathBuf::from("src/big.rs"),
+                PathBuf::from("src/big.rs"),
+                result(10),
+            ),
+        ];
+
+        let kept: Vec<PathBuf> = filter_min_lines_in_file(results, 3)
+            .into_iter()
+            .map(|(_, rel_path, _)| rel_path)
+            .collect();
+        assert_eq!(
+            kept,
+            vec![PathBuf::from("src/run.rs"), PathBuf::from("src/big.rs")]
+        );
+    }
+
+    #[test]
+    fn test_filter_generated_files() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let generated = tmp_dir.path().join("generated.rs");
+        fs::write(
+            &generated,
+            "// @generated by build.rs\npub const A: u32 = 1;\n",
+        )
+        .unwrap();
+        let handwritten = tmp_dir.path().join("main.rs");
+        fs::write(&handwritten, "// See generated.rs\nfn main() {}\n").unwrap();
+        let lcov = format!(
+            "SF:{}\nDA:2,1\nend_of_record\nSF:{}\nDA:2,0\nend_of_record\n",
+            generated.display(),
+            handwritten.display()
+        );
+        let results: Vec<ResultTuple> = parse_lcov(lcov.as_bytes(), false)
+            .unwrap()
+            .into_iter()
+            .map(|(path, result)| (PathBuf::from(&path), PathBuf::from(&path), result))
+            .collect();
+
+        let marker = Regex::new(DEFAULT_GENERATED_MARKER).unwrap();
+        let kept = filter_generated_files(results, &marker);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].0, handwritten);
+        assert!(!is_generated_file(
+            &tmp_dir.path().join("missing.rs"),
+            &marker
+        ));
+    }
+
+    #[test]
+    fn test_baseline_results() {
+        let result_map: Arc<SyncCovResultMap> = Arc::new(Mutex::new(FxHashMap::default()));
+        let results = parse_lcov(
+            b"SF:src/lib.rs\nFN:1,run\nFNDA:1,run\nDA:1,1\nDA:2,3\nend_of_record\n",
+            true,
+        )
+        .unwrap();
+        add_results(results, &result_map, None, &[]);
+
+        // The baseline was generated on another machine, with more tests and more files.
+        let baseline = parse_lcov(
+            b"SF:src/lib.rs\n\
+              FN:1,run\nFN:5,unused\nFNDA:4,run\nFNDA:2,unused\n\
+              DA:1,4\nDA:2,0\nDA:5,2\nDA:6,2\n\
+              BRDA:6,0,0,1\nBRDA:6,0,1,1\n\
+              end_of_record\n\
+              SF:src/integration.rs\nDA:1,7\nend_of_record\n",
+            true,
+        )
+        .unwrap();
+        add_results(baseline_results(baseline), &result_map, None, &[]);
+
+        let result_map = result_map.lock().unwrap();
+        let lib = &result_map["src/lib.rs"];
+        // The counts only come from the regular inputs.
+        assert_eq!(
+            lib.lines,
+            [(1, 1), (2, 3), (5, 0), (6, 0)].iter().cloned().collect()
+        );
+        assert_eq!(
+            lib.branches,
+            [(6, vec![false, false])].iter().cloned().collect()
+        );
+        assert!(lib.functions["run"].executed);
+        assert!(!lib.functions["unused"].executed);
+        // The files only present in the baseline are not covered.
+        assert_eq!(
+            result_map["src/integration.rs"].lines,
+            [(1, 0)].iter().cloned().collect()
+        );
+    }
+
+    #[test]
+    fn test_missing_source_files_warning() {
+        let results = |paths: &[&str]| -> Vec<(String, CovResult)> {
+            paths
+                .iter()
+                .map(|path| (path.to_string(), CovResult::default()))
+                .collect()
+        };
+
+        // Half of the files exist.
+        assert_eq!(
+            missing_source_files_warning(
+                &results(&["src/lib.rs", "/home/ci-runner/build/src/foo.rs"]),
+                None,
+            ),
+            None
+        );
+        // The relative paths are relative to the source directory.
+        assert_eq!(
+            missing_source_files_warning(
+                &results(&["lib.rs", "defs.rs", "/home/ci-runner/build/src/foo.rs"]),
+                Some(Path::new("src")),
+            ),
+            None
+        );
+
+        let paths: Vec<String> = (0..7)
+            .map(|i| format!("/home/ci-runner/build/src/{}.rs", i))
+            .collect();
+        let mut paths: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+        paths.push("src/lib.rs");
+        let warning = missing_source_files_warning(&results(&paths), None).unwrap();
+        assert_eq!((warning.missing, warning.total), (7, 8));
+        let warning = warning.to_string();
+        assert!(warning.starts_with(
+            "7 of the 8 source files in the coverage data don't exist on this machine, e.g.:\n    /home/ci-runner/build/src/0.rs\n"
+        ));
+        // Only a few examples are listed.
+        assert!(warning.contains("/home/ci-runner/build/src/4.rs"));
+        assert!(!warning.contains("/home/ci-runner/build/src/5.rs"));
+        assert!(warning.contains("--no-path-warning"));
+    }
+
     #[test]
     fn test_merge_results() {
         let mut functions1: FunctionMap = FxHashMap::default();
@@ -365,6 +1213,7 @@ mod tests {
             .cloned()
             .collect(),
             functions: functions1,
+            mcdc: None,
         };
         let mut functions2: FunctionMap = FxHashMap::default();
         functions2.insert(
@@ -395,6 +1244,7 @@ mod tests {
             .cloned()
             .collect(),
             functions: functions2,
+            mcdc: None,
         };
 
         merge_results(&mut result, result2);
@@ -427,13 +1277,406 @@ mod tests {
         assert!(func.executed);
     }
 
+    #[test]
+    fn test_merge_results_sums_counts() {
+        let hit = b"SF:src/lib.rs\nDA:5,3\nend_of_record\n";
+        let not_hit = b"SF:src/lib.rs\nDA:5,0\nend_of_record\n";
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        for (i, (first, second)) in vec![(not_hit, hit), (hit, not_hit)].into_iter().enumerate() {
+            let (path, mut result) = parse_lcov(first, false).unwrap().pop().unwrap();
+            let (_, result2) = parse_lcov(second, false).unwrap().pop().unwrap();
+            assert!(!merge_results(&mut result, result2));
+
+            let lcov = tmp_dir.path().join(format!("{}.info", i));
+            let results = vec![(PathBuf::from(&path), PathBuf::from(&path), result)];
+            output_lcov(&results, Some(&lcov), false);
+            let lcov = fs::read_to_string(lcov).unwrap();
+            assert!(lcov.contains("DA:5,3\n"));
+            assert!(!lcov.contains("DA:5,0"));
+        }
+
+        let mut result = CovResult {
+            lines: [(5, u64::MAX)].iter().cloned().collect(),
+            ..CovResult::default()
+        };
+        let result2 = CovResult {
+            lines: [(5, 3)].iter().cloned().collect(),
+            ..CovResult::default()
+        };
+        assert!(merge_results(&mut result, result2));
+        assert_eq!(result.lines[&5], u64::MAX);
+    }
+
+    #[test]
+    fn test_merge_results_dedups_function_instances() {
+        // The same generic function, instantiated by two binaries, with different hashes.
+        let binary1 = b"SF:src/stack.rs\nFN:3,_ZN3lib5stack4push17h0123456789abcdefE\n\
+                        FNDA:0,_ZN3lib5stack4push17h0123456789abcdefE\nDA:3,0\nend_of_record\n";
+        let binary2 = b"SF:src/stack.rs\nFN:3,_ZN3lib5stack4push17hfedcba9876543210E\n\
+                        FNDA:1,_ZN3lib5stack4push17hfedcba9876543210E\n\
+                        FN:9,_ZN3lib5stack3pop17hfedcba9876543210E\n\
+                        FNDA:0,_ZN3lib5stack3pop17hfedcba9876543210E\nDA:3,1\nDA:9,0\n\
+                        end_of_record\n";
+        let result_map: SyncCovResultMap = Mutex::new(FxHashMap::default());
+        add_results(parse_lcov(binary1, false).unwrap(), &result_map, None, &[]);
+        add_results(parse_lcov(binary2, false).unwrap(), &result_map, None, &[]);
+        let result = result_map
+            .into_inner()
+            .unwrap()
+            .remove("src/stack.rs")
+            .unwrap();
+        assert_eq!(result.functions.len(), 2);
+        assert!(result.functions["_ZN3lib5stack4push17h0123456789abcdefE"].executed);
+
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let lcov = tmp_dir.path().join("lcov.info");
+        let path = PathBuf::from("src/stack.rs");
+        output_lcov(&[(path.clone(), path, result)], Some(&lcov), false);
+        let lcov = fs::read_to_string(lcov).unwrap();
+        assert!(lcov.contains("FNF:2\nFNH:1\n"));
+
+        // The functions with the same name at other lines are other functions.
+        let mut result = parse_lcov(binary1, false).unwrap().pop().unwrap().1;
+        let mut moved = parse_lcov(binary2, false).unwrap().pop().unwrap().1;
+        moved
+            .functions
+            .get_mut("_ZN3lib5stack4push17hfedcba9876543210E")
+            .unwrap()
+            .start = 4;
+        merge_results(&mut result, moved);
+        assert_eq!(result.functions.len(), 3);
+    }
+
+    #[test]
+    fn test_merge_results_mcdc() {
+        let decision = |line, conditions: &[bool]| McdcRecord {
+            line,
+            conditions: conditions.to_vec(),
+        };
+        let mut result = CovResult {
+            lines: [(2, 1)].iter().cloned().collect(),
+            mcdc: Some(vec![
+                decision(2, &[true, false]),
+                decision(2, &[false, false]),
+            ]),
+            ..CovResult::default()
+        };
+        let result2 = CovResult {
+            lines: [(2, 1)].iter().cloned().collect(),
+            mcdc: Some(vec![
+                decision(1, &[true]),
+                decision(2, &[false, false]),
+                decision(2, &[false, true]),
+            ]),
+            ..CovResult::default()
+        };
+        merge_results(&mut result, result2);
+        // The decisions of a line are matched in their order.
+        assert_eq!(
+            result.mcdc,
+            Some(vec![
+                decision(1, &[true]),
+                decision(2, &[true, false]),
+                decision(2, &[false, true]),
+            ])
+        );
+
+        let mut result = CovResult {
+            lines: [(2, 1)].iter().cloned().collect(),
+            ..CovResult::default()
+        };
+        let result2 = CovResult {
+            mcdc: Some(vec![decision(2, &[true])]),
+            ..CovResult::default()
+        };
+        merge_results(&mut result, result2);
+        assert_eq!(result.mcdc, Some(vec![decision(2, &[true])]));
+    }
+
+    // The lines are taken in a small range, for the results to share some of them, or to be
+    // disjoint ranges appended to each other.
+    fn cov_result() -> impl Strategy<Value = CovResult> {
+        (
+            btree_map(0u32..64, any::<u64>(), 0..20),
+            btree_map(0u32..64, vec(any::<bool>(), 1..5), 0..10),
+            hash_map("f[0-9]", (0u32..64, any::<bool>()), 0..5),
+        )
+            .prop_map(|(lines, branches, functions)| CovResult {
+                lines: lines.into(),
+                branches,
+                functions: functions
+                    .into_iter()
+                    .map(|(name, (start, executed))| (name, Function { start, executed }))
+                    .collect(),
+                mcdc: None,
+            })
+    }
+
+    // Merges the results line by line, as a reference for the merge of merge_results.
+    fn merge_lines(results: &[&CovResult]) -> (BTreeMap<u32, u64>, BTreeMap<u32, Vec<bool>>) {
+        let mut lines = BTreeMap::new();
+        let mut branches: BTreeMap<u32, Vec<bool>> = BTreeMap::new();
+        for result in results {
+            for (&line_no, &count) in &result.lines {
+                let total: &mut u64 = lines.entry(line_no).or_default();
+                *total = total.saturating_add(count);
+            }
+            for (&line_no, taken) in &result.branches {
+                let merged = branches.entry(line_no).or_default();
+                for (i, &taken) in taken.iter().enumerate() {
+                    match merged.get_mut(i) {
+                        Some(merged) => *merged |= taken,
+                        None => merged.push(taken),
+                    }
+                }
+            }
+        }
+        (lines, branches)
+    }
+
+    proptest! {
+        #[test]
+        fn test_merge_results_commutes(a in cov_result(), b in cov_result()) {
+            let mut ab = a.clone();
+            merge_results(&mut ab, b.clone());
+            let mut ba = b.clone();
+            merge_results(&mut ba, a.clone());
+
+            let (lines, branches) = merge_lines(&[&a, &b]);
+            let lines = LineMap::from(lines);
+            prop_assert_eq!(&ab.lines, &lines);
+            prop_assert_eq!(&ba.lines, &lines);
+            prop_assert_eq!(&ab.branches, &branches);
+            prop_assert_eq!(&ba.branches, &branches);
+
+            let executed = |result: &CovResult| -> BTreeMap<String, bool> {
+                result
+                    .functions
+                    .iter()
+                    .map(|(name, function)| (name.clone(), function.executed))
+                    .collect()
+            };
+            prop_assert_eq!(executed(&ab), executed(&ba));
+            for (name, function) in &ab.functions {
+                let first = a.functions.get(name).or_else(|| b.functions.get(name)).unwrap();
+                prop_assert_eq!(function.start, first.start);
+            }
+        }
+    }
+
+    #[test]
+    fn test_add_interned_results_stores_each_path_once() {
+        // The records of 10 files, each of them repeated for 1000 compilation units.
+        let mut lcov = String::new();
+        for unit in 0..1000 {
+            for file in 0..10 {
+                lcov.push_str(&format!(
+                    "SF:/work/project/src/module{}/file{}.rs\nDA:{},1\nend_of_record\n",
+                    file, file, unit
+                ));
+            }
+        }
+        let results = parse_lcov(lcov.as_bytes(), false).unwrap();
+        let owned_bytes: usize = results.iter().map(|(path, _)| path.len()).sum();
+
+        // The parsed records share the paths of their files.
+        let mut interner = PathInterner::default();
+        let interned = parse_lcov_interned(lcov.as_bytes(), false, &mut interner).unwrap();
+        assert_eq!(interned.len(), 10000);
+        assert_eq!(interner.len(), 10);
+        assert_eq!(interner.path_bytes() * 1000, owned_bytes);
+        assert!(Arc::ptr_eq(&interned[0].0, &interned[10].0));
+
+        let result_map: SyncCovResultMap = Mutex::new(FxHashMap::default());
+        add_interned_results(interned, &result_map, None, &[]);
+        let result_map = result_map.into_inner().unwrap();
+        let expected: SyncCovResultMap = Mutex::new(FxHashMap::default());
+        add_results(results, &expected, None, &[]);
+        assert_eq!(result_map, expected.into_inner().unwrap());
+        assert_eq!(result_map.len(), 10);
+        assert!(result_map.values().all(|result| result.lines.len() == 1000));
+        let merged_bytes: usize = result_map.keys().map(|path| path.len()).sum();
+        assert_eq!(merged_bytes, interner.path_bytes());
+    }
+
+    #[test]
+    fn test_compute_gcno_skips_truncated_gcda() {
+        let buffers = |gcdas: &[&str]| GcnoBuffers {
+            stem: "reader".to_string(),
+            gcno_buf: fs::read("test/llvm/reader.gcno").unwrap(),
+            gcda_buf: gcdas
+                .iter()
+                .map(|gcda| fs::read(format!("test/llvm/{}", gcda)).unwrap())
+                .collect(),
+            gcno_entry: None,
+        };
+        let input_counts = InputCounts::default();
+        let gcno_only = compute_gcno(buffers(&[]), false, false, None, &input_counts);
+        assert!(!gcno_only.is_empty());
+        let executed = compute_gcno(buffers(&["reader.gcda"]), false, false, None, &input_counts);
+        assert_ne!(executed, gcno_only);
+        assert_eq!(input_counts.skipped(), 0);
+
+        // The lines of the gcno are still reported, as not executed.
+        let results = compute_gcno(
+            buffers(&["reader_empty.gcda", "reader_truncated.gcda"]),
+            false,
+            false,
+            None,
+            &input_counts,
+        );
+        assert_eq!(results, gcno_only);
+        assert_eq!(input_counts.skipped(), 2);
+        assert_eq!(input_counts.parse_errors(), 0);
+
+        // The complete gcda files are still read.
+        let results = compute_gcno(
+            buffers(&["reader_truncated.gcda", "reader.gcda"]),
+            false,
+            false,
+            None,
+            &input_counts,
+        );
+        assert_eq!(results, executed);
+        assert_eq!(input_counts.skipped(), 3);
+    }
+
+    #[test]
+    fn test_consumer_gcov_keeps_truncated_gcda() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path();
+        fs::write(
+            tmp_path.join("main.c"),
+            "int main(void) {\n  return 0;\n}\n",
+        )
+        .unwrap();
+        let gcc = |args: &[&str]| {
+            std::process::Command::new("gcc")
+                .args(args)
+                .current_dir(tmp_path)
+                .status()
+                .is_ok_and(|status| status.success())
+        };
+        // This needs gcc, and the gcov matching it.
+        if !gcc(&["--coverage", "-O0", "-c", "main.c"]) || !gcc(&["--coverage", "main.o"]) {
+            return;
+        }
+        let gcda_path = tmp_path.join("main.gcda");
+        fs::write(&gcda_path, b"").unwrap();
+
+        let working_dir = tmp_path.join("work");
+        fs::create_dir(&working_dir).unwrap();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        sender
+            .send(Some(WorkItem {
+                format: ItemFormat::Gcno,
+                item: ItemType::Path(("main".to_string(), tmp_path.join("main.gcno"))),
+                name: String::new(),
+            }))
+            .unwrap();
+        sender.send(None).unwrap();
+        let result_map: SyncCovResultMap = Mutex::new(FxHashMap::default());
+        let input_counts = InputCounts::default();
+        consumer(
+            &working_dir,
+            &result_map,
+            receiver,
+            &input_counts,
+            &ConsumerOptions::default(),
+        );
+
+        // The lines of the gcno are reported as not executed, and the gcda file is left as it is.
+        assert_eq!(input_counts.skipped(), 1);
+        assert_eq!(input_counts.parse_errors(), 0);
+        let result_map = result_map.into_inner().unwrap();
+        let (_, result) = result_map
+            .iter()
+            .find(|(path, _)| path.ends_with("main.c"))
+            .unwrap();
+        assert!(!result.lines.is_empty());
+        assert!(result.lines.values().all(|&count| count == 0));
+        assert_eq!(fs::read(&gcda_path).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_consumer_extracts_gcc_entries() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path();
+        fs::write(
+            tmp_path.join("main.c"),
+            "int main(void) {\n  return 0;\n}\n",
+        )
+        .unwrap();
+        let run = |program: &str, args: &[&str]| {
+            std::process::Command::new(program)
+                .args(args)
+                .current_dir(tmp_path)
+                .status()
+                .is_ok_and(|status| status.success())
+        };
+        // This needs gcc, and the gcov matching it.
+        if !run("gcc", &["--coverage", "-O0", "-c", "main.c"])
+            || !run("gcc", &["--coverage", "-o", "main", "main.o"])
+            || !run("./main", &[])
+        {
+            return;
+        }
+        let zip_path = tmp_path.join("coverage.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for ext in &["gcno", "gcda"] {
+            zip.start_file(format!("sub/main.{}", ext), Default::default())
+                .unwrap();
+            std::io::Write::write_all(
+                &mut zip,
+                &fs::read(tmp_path.join(format!("main.{}", ext))).unwrap(),
+            )
+            .unwrap();
+        }
+        zip.finish().unwrap();
+
+        let working_dir = tmp_path.join("work");
+        fs::create_dir(&working_dir).unwrap();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        sender
+            .send(Some(WorkItem {
+                format: ItemFormat::Gcno,
+                item: ItemType::GccEntries(GccEntries {
+                    stem: "sub/main".to_string(),
+                    gcno: ArchiveEntry::Zip(zip_path.clone(), "sub/main.gcno".to_string()),
+                    gcda: Some(ArchiveEntry::Zip(zip_path, "sub/main.gcda".to_string())),
+                }),
+                name: String::new(),
+            }))
+            .unwrap();
+        sender.send(None).unwrap();
+        let result_map: SyncCovResultMap = Mutex::new(FxHashMap::default());
+        let input_counts = InputCounts::default();
+        consumer(
+            &working_dir,
+            &result_map,
+            receiver,
+            &input_counts,
+            &ConsumerOptions::default(),
+        );
+
+        // The pair is extracted in the working directory, and removed once gcov has run.
+        assert_eq!(input_counts.parse_errors(), 0);
+        let result_map = result_map.into_inner().unwrap();
+        let (_, result) = result_map
+            .iter()
+            .find(|(path, _)| path.ends_with("main.c"))
+            .unwrap();
+        assert!(result.lines.values().any(|&count| count > 0));
+        assert_eq!(fs::read_dir(&working_dir).unwrap().count(), 0);
+    }
+
     #[test]
     fn test_merge_relative_path() {
         let mut f = File::open("./test/relative_path/relative_path.info")
             .expect("Failed to open lcov file");
         let mut buf = Vec::new();
         f.read_to_end(&mut buf).unwrap();
-        let results = parse_lcov(buf, false).unwrap();
+        let results = parse_lcov(&buf, false).unwrap();
         let result_map: Arc<SyncCovResultMap> = Arc::new(Mutex::new(
             FxHashMap::with_capacity_and_hasher(1, Default::default()),
         ));
@@ -441,6 +1684,7 @@ mod tests {
             results,
             &result_map,
             Some(Path::new("./test/relative_path")),
+            &[],
         );
         let result_map = Arc::try_unwrap(result_map).unwrap().into_inner().unwrap();
 
@@ -467,11 +1711,11 @@ mod tests {
             .expect("Failed to open lcov file");
         let mut buf = Vec::new();
         f.read_to_end(&mut buf).unwrap();
-        let results = parse_lcov(buf, false).unwrap();
+        let results = parse_lcov(&buf, false).unwrap();
         let result_map: Arc<SyncCovResultMap> = Arc::new(Mutex::new(
             FxHashMap::with_capacity_and_hasher(3, Default::default()),
         ));
-        add_results(results, &result_map, None);
+        add_results(results, &result_map, None, &[]);
         let result_map = Arc::try_unwrap(result_map).unwrap().into_inner().unwrap();
 
         assert!(result_map.len() == 3);
@@ -0,0 +1,384 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter::{FromIterator, Zip};
+use std::ops::Index;
+use std::slice;
+use std::vec;
+
+/// The execution counts of the lines of a source file, by line number.
+///
+/// The lines are kept sorted in two vectors, one with the line numbers and one with their counts,
+/// instead of the nodes of a map: the lines of a file are mostly added in order by the parsers,
+/// iterated in order by the outputs and merged with the lines of the same file, which are all
+/// linear on vectors, and a line only takes the 12 bytes of its number and count.
+#[derive(Default, Clone, PartialEq, Eq, Hash)]
+pub struct LineMap {
+    lines: Vec<u32>,
+    counts: Vec<u64>,
+}
+
+pub type Iter<'a> = Zip<slice::Iter<'a, u32>, slice::Iter<'a, u64>>;
+pub type IterMut<'a> = Zip<slice::Iter<'a, u32>, slice::IterMut<'a, u64>>;
+pub type IntoIter = Zip<vec::IntoIter<u32>, vec::IntoIter<u64>>;
+
+/// A line of a LineMap, which may not be in it yet.
+pub struct Entry<'a> {
+    map: &'a mut LineMap,
+    line: u32,
+    index: Result<usize, usize>,
+}
+
+impl<'a> Entry<'a> {
+    pub fn or_insert(self, count: u64) -> &'a mut u64 {
+        let index = match self.index {
+            Ok(index) => index,
+            Err(index) => {
+                self.map.lines.insert(index, self.line);
+                self.map.counts.insert(index, count);
+                index
+            }
+        };
+        &mut self.map.counts[index]
+    }
+
+    pub fn or_default(self) -> &'a mut u64 {
+        self.or_insert(0)
+    }
+}
+
+impl LineMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        LineMap {
+            lines: Vec::with_capacity(capacity),
+            counts: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    // Finds a line, checking the last one first, as the lines are mostly added in order.
+    fn search(&self, line: u32) -> Result<usize, usize> {
+        match self.lines.last() {
+            None => Err(0),
+            Some(&last) if last < line => Err(self.lines.len()),
+            Some(&last) if last == line => Ok(self.lines.len() - 1),
+            Some(_) => self.lines.binary_search(&line),
+        }
+    }
+
+    pub fn get(&self, line: &u32) -> Option<&u64> {
+        self.search(*line).ok().map(|index| &self.counts[index])
+    }
+
+    pub fn get_mut(&mut self, line: &u32) -> Option<&mut u64> {
+        match self.search(*line) {
+            Ok(index) => Some(&mut self.counts[index]),
+            Err(_) => None,
+        }
+    }
+
+    pub fn contains_key(&self, line: &u32) -> bool {
+        self.search(*line).is_ok()
+    }
+
+    /// Sets the count of a line, returning its previous count.
+    pub fn insert(&mut self, line: u32, count: u64) -> Option<u64> {
+        match self.search(line) {
+            Ok(index) => Some(std::mem::replace(&mut self.counts[index], count)),
+            Err(index) => {
+                self.lines.insert(index, line);
+                self.counts.insert(index, count);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, line: &u32) -> Option<u64> {
+        match self.search(*line) {
+            Ok(index) => {
+                self.lines.remove(index);
+                Some(self.counts.remove(index))
+            }
+            Err(_) => None,
+        }
+    }
+
+    pub fn entry(&mut self, line: u32) -> Entry<'_> {
+        let index = self.search(line);
+        Entry {
+            map: self,
+            line,
+            index,
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        self.lines.iter().zip(self.counts.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_> {
+        self.lines.iter().zip(self.counts.iter_mut())
+    }
+
+    pub fn keys(&self) -> slice::Iter<'_, u32> {
+        self.lines.iter()
+    }
+
+    pub fn values(&self) -> slice::Iter<'_, u64> {
+        self.counts.iter()
+    }
+
+    pub fn values_mut(&mut self) -> slice::IterMut<'_, u64> {
+        self.counts.iter_mut()
+    }
+
+    pub fn first_line(&self) -> Option<u32> {
+        self.lines.first().cloned()
+    }
+
+    pub fn last_line(&self) -> Option<u32> {
+        self.lines.last().cloned()
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&u32, &mut u64) -> bool) {
+        let mut kept = 0;
+        for index in 0..self.lines.len() {
+            if keep(&self.lines[index], &mut self.counts[index]) {
+                self.lines.swap(kept, index);
+                self.counts.swap(kept, index);
+                kept += 1;
+            }
+        }
+        self.lines.truncate(kept);
+        self.counts.truncate(kept);
+    }
+
+    /// Moves the lines of `other` to this map, merging the counts of the lines of both with
+    /// `merge`. The maps are swapped first when this one is empty, or appended when all the
+    /// lines of one come before the ones of the other, so `merge` mustn't depend on the order of
+    /// its arguments.
+    pub fn merge(&mut self, mut other: LineMap, mut merge: impl FnMut(&mut u64, u64)) {
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = other;
+            return;
+        }
+        if self.lines.last() < other.lines.first() {
+            self.lines.append(&mut other.lines);
+            self.counts.append(&mut other.counts);
+            return;
+        }
+        if other.lines.last() < self.lines.first() {
+            other.lines.append(&mut self.lines);
+            other.counts.append(&mut self.counts);
+            *self = other;
+            return;
+        }
+
+        let mut merged = LineMap::with_capacity(self.len() + other.len());
+        let mut lines = self.lines.iter().zip(self.counts.iter()).peekable();
+        let mut other_lines = other.lines.into_iter().zip(other.counts).peekable();
+        loop {
+            let (line, count) = match (lines.peek(), other_lines.peek()) {
+                (Some(&(&line, &count)), Some(&(other_line, other_count))) => {
+                    if line < other_line {
+                        lines.next();
+                        (line, count)
+                    } else if other_line < line {
+                        other_lines.next();
+                        (other_line, other_count)
+                    } else {
+                        lines.next();
+                        other_lines.next();
+                        let mut count = count;
+                        merge(&mut count, other_count);
+                        (line, count)
+                    }
+                }
+                (Some(&(&line, &count)), None) => {
+                    lines.next();
+                    (line, count)
+                }
+                (None, Some(&(other_line, other_count))) => {
+                    other_lines.next();
+                    (other_line, other_count)
+                }
+                (None, None) => break,
+            };
+            merged.lines.push(line);
+            merged.counts.push(count);
+        }
+        *self = merged;
+    }
+
+    // Sorts the lines added out of order, keeping the last count of the lines added several
+    // times, as for the inserts in a map.
+    fn sort(&mut self) {
+        if self.lines.windows(2).all(|pair| pair[0] < pair[1]) {
+            return;
+        }
+        let mut entries: Vec<(u32, u64)> =
+            self.lines.drain(..).zip(self.counts.drain(..)).collect();
+        entries.sort_by_key(|&(line, _)| line);
+        for (line, count) in entries {
+            if self.lines.last() == Some(&line) {
+                *self.counts.last_mut().unwrap() = count;
+            } else {
+                self.lines.push(line);
+                self.counts.push(count);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for LineMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Index<&u32> for LineMap {
+    type Output = u64;
+
+    fn index(&self, line: &u32) -> &u64 {
+        self.get(line).expect("no entry found for line")
+    }
+}
+
+impl Extend<(u32, u64)> for LineMap {
+    fn extend<I: IntoIterator<Item = (u32, u64)>>(&mut self, iter: I) {
+        for (line, count) in iter {
+            self.lines.push(line);
+            self.counts.push(count);
+        }
+        self.sort();
+    }
+}
+
+impl FromIterator<(u32, u64)> for LineMap {
+    fn from_iter<I: IntoIterator<Item = (u32, u64)>>(iter: I) -> Self {
+        let mut map = LineMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl From<BTreeMap<u32, u64>> for LineMap {
+    fn from(lines: BTreeMap<u32, u64>) -> Self {
+        let mut map = LineMap::with_capacity(lines.len());
+        for (line, count) in lines {
+            map.lines.push(line);
+            map.counts.push(count);
+        }
+        map
+    }
+}
+
+impl IntoIterator for LineMap {
+    type Item = (u32, u64);
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        self.lines.into_iter().zip(self.counts)
+    }
+}
+
+impl<'a> IntoIterator for &'a LineMap {
+    type Item = (&'a u32, &'a u64);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut LineMap {
+    type Item = (&'a u32, &'a mut u64);
+    type IntoIter = IterMut<'a>;
+
+    fn into_iter(self) -> IterMut<'a> {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::{btree_map, vec};
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_line_map_as_btree_map(
+            inserts in vec((0u32..50, 0u64..1000), 0..50),
+            removes in vec(0u32..50, 0..10),
+            other in btree_map(0u32..100, 0u64..1000, 0..50),
+        ) {
+            let mut map = LineMap::new();
+            let mut expected = BTreeMap::new();
+            for &(line, count) in &inserts {
+                prop_assert_eq!(map.insert(line, count), expected.insert(line, count));
+            }
+            for line in &removes {
+                prop_assert_eq!(map.remove(line), expected.remove(line));
+            }
+            *map.entry(60).or_insert(1) += 1;
+            *expected.entry(60).or_insert(1) += 1;
+            prop_assert_eq!(map.iter().collect::<Vec<_>>(), expected.iter().collect::<Vec<_>>());
+            prop_assert_eq!(map.len(), expected.len());
+            prop_assert_eq!(map.get(&3), expected.get(&3));
+            prop_assert_eq!(
+                inserts.iter().cloned().collect::<LineMap>(),
+                LineMap::from(inserts.iter().cloned().collect::<BTreeMap<_, _>>())
+            );
+
+            for (&line, &count) in &other {
+                *expected.entry(line).or_insert(0) += count;
+            }
+            map.merge(other.into(), |count, other_count| *count += other_count);
+            prop_assert_eq!(map, LineMap::from(expected));
+        }
+    }
+
+    #[test]
+    fn test_line_map_merge() {
+        let lines = |lines: &[(u32, u64)]| lines.iter().cloned().collect::<LineMap>();
+        let sum = |count: &mut u64, count2: u64| *count += count2;
+        type Lines = &'static [(u32, u64)];
+        let cases: &[(Lines, Lines, Lines)] = &[
+            (&[], &[(1, 1)], &[(1, 1)]),
+            (&[(1, 1)], &[], &[(1, 1)]),
+            (&[(1, 1)], &[(2, 2)], &[(1, 1), (2, 2)]),
+            (&[(2, 2)], &[(1, 1)], &[(1, 1), (2, 2)]),
+            (
+                &[(1, 1), (3, 3)],
+                &[(2, 2), (3, 1)],
+                &[(1, 1), (2, 2), (3, 4)],
+            ),
+        ];
+        for (a, b, expected) in cases {
+            let mut merged = lines(a);
+            merged.merge(lines(b), sum);
+            assert_eq!(merged, lines(expected));
+        }
+
+        let mut map = lines(&[(3, 0), (1, 2), (2, 1), (1, 4)]);
+        assert_eq!(map, lines(&[(1, 4), (2, 1), (3, 0)]));
+        map.retain(|_, count| *count > 0);
+        assert_eq!(map, lines(&[(1, 4), (2, 1)]));
+        assert_eq!(map[&2], 1);
+        assert_eq!(format!("{:?}", map), "{1: 4, 2: 1}");
+    }
+}
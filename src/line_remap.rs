@@ -0,0 +1,173 @@
+use std::fs;
+use std::path::Path;
+
+use crate::defs::*;
+
+/// Maps the lines of a source file as it was when the coverage was recorded to the lines of its
+/// current version, e.g. after it was reformatted. The i-th element is the new number of the
+/// line i + 1, or None if the line was deleted or changed.
+pub fn line_mapping(snapshot: &str, current: &str) -> Vec<Option<u32>> {
+    let snapshot: Vec<&str> = snapshot.lines().collect();
+    let current: Vec<&str> = current.lines().collect();
+    let mut mapping = Vec::new();
+    let mut current_line = 0;
+    for change in diff::slice(&snapshot, &current) {
+        match change {
+            diff::Result::Left(_) => mapping.push(None),
+            diff::Result::Both(_, _) => {
+                current_line += 1;
+                mapping.push(Some(current_line));
+            }
+            diff::Result::Right(_) => current_line += 1,
+        }
+    }
+    mapping
+}
+
+fn remap_line(mapping: &[Option<u32>], line: u32) -> Option<u32> {
+    mapping
+        .get(line.checked_sub(1)? as usize)
+        .copied()
+        .flatten()
+}
+
+/// Moves the lines, branches and functions of a result to their current line numbers. The lines
+/// which were deleted or changed are dropped, as their coverage is unknown, while the functions
+/// starting on them are moved to the next line which was kept.
+pub fn remap_result(result: &mut CovResult, mapping: &[Option<u32>]) {
+    result.lines = std::mem::take(&mut result.lines)
+        .into_iter()
+        .filter_map(|(line, count)| Some((remap_line(mapping, line)?, count)))
+        .collect();
+    result.branches = std::mem::take(&mut result.branches)
+        .into_iter()
+        .filter_map(|(line, taken)| Some((remap_line(mapping, line)?, taken)))
+        .collect();
+    result.remap_mcdc(|line| remap_line(mapping, line));
+    result.functions.retain(|_, function| {
+        let start = (function.start.max(1)..=mapping.len() as u32)
+            .find_map(|line| remap_line(mapping, line));
+        match start {
+            Some(start) => {
+                function.start = start;
+                true
+            }
+            None => false,
+        }
+    });
+}
+
+/// Remaps the line numbers of the results whose source file changed since the coverage was
+/// recorded, by diffing the copy of the file in the snapshot directory (at the same relative
+/// path) with its current version. The files missing from the snapshot are left as they are.
+pub fn remap_to_snapshot(mut results: Vec<ResultTuple>, snapshot_dir: &Path) -> Vec<ResultTuple> {
+    for (abs_path, rel_path, result) in &mut results {
+        let snapshot = match fs::read_to_string(snapshot_dir.join(&*rel_path)) {
+            Ok(snapshot) => snapshot,
+            Err(_) => continue,
+        };
+        let current = match fs::read_to_string(&*abs_path) {
+            Ok(current) => current,
+            Err(_) => continue,
+        };
+        if snapshot != current {
+            remap_result(result, &line_mapping(&snapshot, &current));
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const SNAPSHOT: &str =
+        "fn main() {\n    let a = 1;\n    let b = 2;\n    println!(\"{}\", a + b);\n}\n";
+
+    fn result() -> CovResult {
+        let mut result = CovResult {
+            lines: vec![(1, 1), (2, 1), (3, 1), (4, 1)].into_iter().collect(),
+            branches: vec![(4, vec![true, false])].into_iter().collect(),
+            functions: Default::default(),
+            mcdc: Some(vec![McdcRecord {
+                line: 4,
+                conditions: vec![true, false],
+            }]),
+        };
+        result.functions.insert(
+            "main".to_string(),
+            Function {
+                start: 1,
+                executed: true,
+            },
+        );
+        result
+    }
+
+    #[test]
+    fn test_line_mapping_insertion() {
+        let current = "fn main() {\n    let a = 1;\n    // The sum.\n    let b = 2;\n    println!(\"{}\", a + b);\n}\n";
+        assert_eq!(
+            line_mapping(SNAPSHOT, current),
+            vec![Some(1), Some(2), Some(4), Some(5), Some(6)]
+        );
+
+        let mut result = result();
+        remap_result(&mut result, &line_mapping(SNAPSHOT, current));
+        // The lines after the inserted one are shifted by one.
+        assert_eq!(
+            result.lines,
+            vec![(1, 1), (2, 1), (4, 1), (5, 1)].into_iter().collect()
+        );
+        assert_eq!(
+            result.branches,
+            vec![(5, vec![true, false])].into_iter().collect()
+        );
+        assert_eq!(result.mcdc.unwrap()[0].line, 5);
+        assert_eq!(result.functions["main"].start, 1);
+    }
+
+    #[test]
+    fn test_line_mapping_deletion() {
+        let current = "fn main() {\n    let a = 1;\n    println!(\"{}\", a + 2);\n}\n";
+        let mapping = line_mapping(SNAPSHOT, current);
+        assert_eq!(mapping, vec![Some(1), Some(2), None, None, Some(4)]);
+
+        let mut result = result();
+        result.functions.get_mut("main").unwrap().start = 3;
+        remap_result(&mut result, &mapping);
+        assert_eq!(result.lines, vec![(1, 1), (2, 1)].into_iter().collect());
+        assert!(result.branches.is_empty());
+        assert_eq!(result.mcdc, None);
+        // The function starting on a changed line is moved to the next line which was kept.
+        assert_eq!(result.functions["main"].start, 4);
+    }
+
+    #[test]
+    fn test_remap_to_snapshot() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let snapshot_dir = tmp_dir.path().join("snapshot");
+        fs::create_dir_all(snapshot_dir.join("src")).unwrap();
+        fs::write(snapshot_dir.join("src/main.rs"), SNAPSHOT).unwrap();
+        let main_path = tmp_dir.path().join("main.rs");
+        fs::write(&main_path, format!("// Formatted.\n{}", SNAPSHOT)).unwrap();
+        let lib_path = tmp_dir.path().join("lib.rs");
+        fs::write(&lib_path, "").unwrap();
+
+        let results = remap_to_snapshot(
+            vec![
+                (main_path, PathBuf::from("src/main.rs"), result()),
+                (lib_path, PathBuf::from("src/lib.rs"), result()),
+            ],
+            &snapshot_dir,
+        );
+        assert_eq!(
+            results[0].2.lines,
+            vec![(2, 1), (3, 1), (4, 1), (5, 1)].into_iter().collect()
+        );
+        assert_eq!(results[0].2.functions["main"].start, 2);
+        // The files missing from the snapshot are left as they are.
+        assert_eq!(results[1].2, result());
+    }
+}
@@ -1,17 +1,126 @@
 use cargo_binutils::Tool;
+use flate2::read::GzDecoder;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use md5::{Digest, Md5};
+use object::read::ReadCache;
+use object::Object;
 use once_cell::sync::OnceCell;
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::convert::TryInto;
 use std::env::consts::EXE_SUFFIX;
 use std::ffi::OsStr;
-use std::fs;
-use std::io::Write;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-use log::warn;
-use walkdir::WalkDir;
+use log::{debug, error, warn};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::covmap_version::{check_covmap_version, parse_llvm_major_version};
+use crate::output::write_atomically;
+use crate::wasm::{check_wasm_support, is_wasm, WASM_EXPORT_ARCH};
 
 pub static LLVM_PATH: OnceCell<PathBuf> = OnceCell::new();
 
+/// What is done with the stderr of the llvm tools, when set.
+pub static LLVM_STDERR: OnceCell<StderrConfig> = OnceCell::new();
+
+/// The directories of a target directory which never contain instrumented binaries, and are
+/// always skipped when looking for the binaries.
+pub const DEFAULT_SKIP_DIRS: &[&str] = &[".fingerprint", "incremental", ".cargo-lock"];
+
+/// The names of the entries which are skipped, with their whole subtree, when looking for the
+/// binaries in a directory, when set. Otherwise, only the DEFAULT_SKIP_DIRS are.
+pub static SKIP_DIRS: OnceCell<GlobSet> = OnceCell::new();
+
+/// Compiles the globs of the names of the entries skipped when looking for the binaries, along
+/// with the DEFAULT_SKIP_DIRS.
+pub fn skip_dirs_globset(patterns: &[impl AsRef<str>]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    let patterns = DEFAULT_SKIP_DIRS
+        .iter()
+        .cloned()
+        .chain(patterns.iter().map(AsRef::as_ref));
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).map_err(|e| format!("Invalid --skip-dir: {}", e))?);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Walks a directory of binaries, without descending in the directories matching the SKIP_DIRS.
+pub fn walk_binary_dir(dir: &Path) -> impl Iterator<Item = walkdir::Result<DirEntry>> {
+    let skip_dirs = SKIP_DIRS.get_or_init(|| skip_dirs_globset(&[] as &[&str]).unwrap());
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(move |entry| entry.depth() == 0 || !skip_dirs.is_match(entry.file_name()))
+}
+
+/// What is done with the stderr of the llvm tools. It is always part of the error when a tool
+/// fails.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum StderrConfig {
+    /// The stderr of the successful invocations is discarded.
+    #[default]
+    Discard,
+    /// The stderr is logged, at the debug level for the successful invocations and at the error
+    /// level for the failed ones.
+    Log,
+    /// The stderr of all the invocations is appended to the file.
+    File(PathBuf),
+    /// The stderr is both logged and appended to the file.
+    LogAndFile(PathBuf),
+}
+
+impl StderrConfig {
+    pub fn new(capture: bool, file: Option<PathBuf>) -> Self {
+        match (capture, file) {
+            (false, None) => StderrConfig::Discard,
+            (true, None) => StderrConfig::Log,
+            (false, Some(file)) => StderrConfig::File(file),
+            (true, Some(file)) => StderrConfig::LogAndFile(file),
+        }
+    }
+
+    fn report(&self, command: &Command, success: bool, stderr: &[u8]) {
+        if stderr.is_empty() {
+            return;
+        }
+        let stderr = String::from_utf8_lossy(stderr);
+        if let StderrConfig::Log | StderrConfig::LogAndFile(_) = self {
+            if success {
+                debug!("Stderr of {:?}:\n{}", command, stderr);
+            } else {
+                error!("Stderr of {:?}:\n{}", command, stderr);
+            }
+        }
+        if let StderrConfig::File(path) | StderrConfig::LogAndFile(path) = self {
+            // Written at once, so that the outputs of the tools run in parallel don't get mixed.
+            let entry = format!("$ {:?}\n{}\n", command, stderr.trim_end());
+            let written = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(entry.as_bytes()));
+            if let Err(e) = written {
+                warn!(
+                    "Unable to write the stderr of the llvm tools to {:?}: {}",
+                    path, e
+                );
+            }
+        }
+    }
+}
+
+/// Returns the configuration of the stderr of the llvm tools, discarding it by default.
+pub fn llvm_stderr() -> &'static StderrConfig {
+    static DISCARD: StderrConfig = StderrConfig::Discard;
+    LLVM_STDERR.get().unwrap_or(&DISCARD)
+}
+
 pub fn is_binary(path: impl AsRef<Path>) -> bool {
     if let Ok(oty) = infer::get_from_path(path) {
         if let Some("dll" | "exe" | "elf" | "mach") = oty.map(|x| x.extension()) {
@@ -25,6 +134,7 @@ pub fn run_with_stdin(
     cmd: impl AsRef<OsStr>,
     stdin: impl AsRef<str>,
     args: &[&OsStr],
+    stderr: &StderrConfig,
 ) -> Result<Vec<u8>, String> {
     let mut command = Command::new(cmd.as_ref());
     let err_fn = |e| format!("Failed to execute {:?}\n{}", cmd.as_ref(), e);
@@ -32,16 +142,21 @@ pub fn run_with_stdin(
     command
         .args(args)
         .stdin(Stdio::piped())
-        .stdout(Stdio::piped());
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
     let mut child = command.spawn().map_err(err_fn)?;
-    child
-        .stdin
-        .as_mut()
-        .unwrap()
-        .write_all(stdin.as_ref().as_bytes())
-        .map_err(err_fn)?;
-
-    let output = child.wait_with_output().map_err(err_fn)?;
+    // The input is written by another thread while the outputs are read, as the tool can fill the
+    // pipe of its stdout or stderr before reading all its input, and then wait for them to be read.
+    let mut child_stdin = child.stdin.take().unwrap();
+    let stdin = stdin.as_ref().as_bytes();
+    let (written, output) = std::thread::scope(|scope| {
+        let writer = scope.spawn(move || child_stdin.write_all(stdin));
+        let output = child.wait_with_output();
+        (writer.join().unwrap(), output)
+    });
+
+    let output = output.map_err(err_fn)?;
+    stderr.report(&command, output.status.success(), &output.stderr);
     if !output.status.success() {
         return Err(format!(
             "Failure while running {:?}\n{}",
@@ -49,17 +164,23 @@ pub fn run_with_stdin(
             String::from_utf8_lossy(&output.stderr)
         ));
     }
+    written.map_err(err_fn)?;
 
     Ok(output.stdout)
 }
 
-pub fn run(cmd: impl AsRef<OsStr>, args: &[&OsStr]) -> Result<Vec<u8>, String> {
+pub fn run(
+    cmd: impl AsRef<OsStr>,
+    args: &[&OsStr],
+    stderr: &StderrConfig,
+) -> Result<Vec<u8>, String> {
     let mut command = Command::new(cmd);
     command.args(args);
 
     let output = command
         .output()
         .map_err(|e| format!("Failed to execute {:?}\n{}", command, e))?;
+    stderr.report(&command, output.status.success(), &output.stderr);
 
     if !output.status.success() {
         return Err(format!(
@@ -72,109 +193,2021 @@ pub fn run(cmd: impl AsRef<OsStr>, args: &[&OsStr]) -> Result<Vec<u8>, String> {
     Ok(output.stdout)
 }
 
-pub fn profraws_to_lcov(
+// The number of times a profraw is read again when it changed while it was being copied.
+const SNAPSHOT_ATTEMPTS: usize = 5;
+
+/// Copies a profraw which may still be updated by a running process, as in continuous mode
+/// (`%c` in LLVM_PROFILE_FILE), where the counters are mmap'd and incremented in place.
+///
+/// The file is read at once and only written out when its size didn't change in the meantime,
+/// so that llvm-profdata never sees a file which is being resized. The counters are not read
+/// atomically: the snapshot can be slightly behind the process for some of them.
+pub fn snapshot_profraw(profraw_path: &Path, snapshot_path: &Path) -> Result<(), String> {
+    let len = |path| fs::metadata(path).map(|metadata| metadata.len());
+    let read_err = |e| format!("Failed to read {:?}\n{}", profraw_path, e);
+    for _ in 0..SNAPSHOT_ATTEMPTS {
+        let len_before = len(profraw_path).map_err(read_err)?;
+        let buffer = fs::read(profraw_path).map_err(read_err)?;
+        let len_after = len(profraw_path).map_err(read_err)?;
+        if len_before != len_after || buffer.len() as u64 != len_after {
+            continue;
+        }
+        if buffer.is_empty() {
+            return Err(format!(
+                "{:?} is empty, the process writing it may not have started yet",
+                profraw_path
+            ));
+        }
+        return fs::write(snapshot_path, buffer).map_err(|e| {
+            format!(
+                "Failed to copy {:?} to {:?}\n{}",
+                profraw_path, snapshot_path, e
+            )
+        });
+    }
+    Err(format!(
+        "{:?} kept changing while it was being copied",
+        profraw_path
+    ))
+}
+
+/// Copies the profraw files into a local directory, in parallel.
+///
+/// llvm-profdata opens its inputs serially, which is slow when they live on a networked
+/// filesystem, so it's faster to copy them all at once and then merge the local copies. The
+/// copies are snapshots, so this is also the way to merge the profraws of running processes.
+pub fn stage_profraws(
     profraw_paths: &[PathBuf],
-    binary_path: &Path,
-    working_dir: &Path,
-) -> Result<Vec<Vec<u8>>, String> {
-    let profdata_path = working_dir.join("grcov.profdata");
+    staging_dir: &Path,
+) -> Result<Vec<PathBuf>, String> {
+    fs::create_dir_all(staging_dir)
+        .map_err(|e| format!("Failed to create directory {:?}\n{}", staging_dir, e))?;
 
-    let args = vec![
-        "merge".as_ref(),
-        "-f".as_ref(),
-        "-".as_ref(),
-        "-sparse".as_ref(),
-        "-o".as_ref(),
-        profdata_path.as_ref(),
-    ];
+    profraw_paths
+        .par_iter()
+        .enumerate()
+        .map(|(i, profraw_path)| {
+            // Prefix the index to avoid collisions between files with the same name coming
+            // from different directories.
+            let file_name = profraw_path.file_name().unwrap_or_default();
+            let staged_path = staging_dir.join(format!("{}_{}", i, file_name.to_string_lossy()));
+            snapshot_profraw(profraw_path, &staged_path)?;
+            Ok(staged_path)
+        })
+        .collect()
+}
+
+/// Returns whether a profile is compressed: a gzipped `.profraw` file (`.gz`), or a tarball of
+/// profiles (`.tar.gz` or `.tgz`).
+pub fn is_compressed_profile(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.ends_with(".gz") || name.ends_with(".tgz")
+}
+
+// The magic number of the raw profiles of a 64-bit target, and the offset of their number of
+// bitmap bytes, which the headers have from version 9 on.
+const PROFRAW_MAGIC: u64 = 0xff6c_7072_6f66_7281;
+const PROFRAW_BITMAP_BYTES_OFFSET: usize = 56;
+
+/// Returns whether a raw profile has MC/DC bitmaps, i.e. it was written by a binary built with
+/// the MC/DC instrumentation. The compressed profiles, and the files which can't be read or aren't
+/// raw profiles, have none.
+pub fn profraw_has_mcdc(path: &Path) -> bool {
+    let mut header = [0; PROFRAW_BITMAP_BYTES_OFFSET + 8];
+    if File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_err()
+    {
+        return false;
+    }
+    let field = |offset: usize| u64::from_le_bytes(header[offset..offset + 8].try_into().unwrap());
+    field(0) == PROFRAW_MAGIC && field(8) as u32 >= 9 && field(PROFRAW_BITMAP_BYTES_OFFSET) != 0
+}
+
+fn is_tarball(name: &str) -> bool {
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// Decompresses the compressed profiles into a directory: the gzipped files, and the `.profraw`
+/// and `.profdata` files of the tarballs. The other paths are kept as they are.
+pub fn decompress_profraws(
+    profraw_paths: &[PathBuf],
+    decompressed_dir: &Path,
+) -> Result<Vec<PathBuf>, String> {
+    fs::create_dir_all(decompressed_dir)
+        .map_err(|e| format!("Failed to create directory {:?}\n{}", decompressed_dir, e))?;
+
+    let mut paths = Vec::with_capacity(profraw_paths.len());
+    for (i, profraw_path) in profraw_paths.iter().enumerate() {
+        if !is_compressed_profile(profraw_path) {
+            paths.push(profraw_path.clone());
+            continue;
+        }
+        let error = |e: io::Error| format!("Failed to decompress {:?}\n{}", profraw_path, e);
+        let file = File::open(profraw_path).map_err(error)?;
+        let decoder = GzDecoder::new(BufReader::new(file));
+        let name = profraw_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+
+        if is_tarball(&name) {
+            let mut archive = tar::Archive::new(decoder);
+            for (j, entry) in archive.entries().map_err(error)?.enumerate() {
+                let mut entry = entry.map_err(error)?;
+                let entry_path = entry.path().map_err(error)?.into_owned();
+                let is_profile = matches!(
+                    entry_path.extension().and_then(OsStr::to_str),
+                    Some("profraw") | Some("profdata")
+                );
+                if !entry.header().entry_type().is_file() || !is_profile {
+                    continue;
+                }
+                // Prefix the indices, as in stage_profraws.
+                let file_name = entry_path.file_name().unwrap_or_default();
+                let path =
+                    decompressed_dir.join(format!("{}_{}_{}", i, j, file_name.to_string_lossy()));
+                io::copy(&mut entry, &mut File::create(&path).map_err(error)?).map_err(error)?;
+                paths.push(path);
+            }
+        } else {
+            let file_name = name.strip_suffix(".gz").unwrap_or(&name);
+            let file_name = if file_name.ends_with(".profraw") || file_name.ends_with(".profdata") {
+                file_name.to_string()
+            } else {
+                format!("{}.profraw", file_name)
+            };
+            let path = decompressed_dir.join(format!("{}_{}", i, file_name));
+            let mut decoder = decoder;
+            io::copy(&mut decoder, &mut File::create(&path).map_err(error)?).map_err(error)?;
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+fn lcov_line_number(line: &str) -> u32 {
+    line.split([':', ','])
+        .nth(1)
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(0)
+}
+
+fn sort_lcov_block(block: &mut [&str]) {
+    let fn_lines: FxHashMap<&str, u32> = block
+        .iter()
+        .filter(|line| line.starts_with("FN:"))
+        .filter_map(|line| {
+            let (number, name) = line["FN:".len()..].split_once(',')?;
+            Some((name, number.parse().ok()?))
+        })
+        .collect();
+
+    // Only sort the runs of consecutive records of the same kind, so that
+    // the overall structure of the block is preserved.
+    let mut start = 0;
+    while start < block.len() {
+        let prefix = block[start].split(':').next().unwrap_or_default();
+        let end = start
+            + block[start..]
+                .iter()
+                .take_while(|line| line.split(':').next() == Some(prefix))
+                .count();
+        let run = &mut block[start..end];
+        match prefix {
+            "FN" | "DA" | "BRDA" => run.sort_by_key(|line| lcov_line_number(line)),
+            "FNDA" => run.sort_by_key(|line| {
+                let name = line
+                    .split_once(',')
+                    .map(|(_, name)| name)
+                    .unwrap_or_default();
+                (fn_lines.get(name).copied().unwrap_or(u32::MAX), name)
+            }),
+            _ => {}
+        }
+        start = end;
+    }
+}
+
+/// Sorts the records of a lcov file, so that its content doesn't depend on the order in which
+/// llvm-cov emitted it: `SF:` blocks are sorted by path and `FN:`, `FNDA:`, `BRDA:` and `DA:`
+/// records by line number within each block.
+pub fn sort_lcov(lcov: &[u8]) -> Vec<u8> {
+    let lcov = String::from_utf8_lossy(lcov);
+    let mut header = Vec::new();
+    let mut blocks: Vec<Vec<&str>> = Vec::new();
+    let mut current = Vec::new();
+
+    for line in lcov.lines() {
+        current.push(line);
+        if line == "end_of_record" {
+            blocks.push(std::mem::take(&mut current));
+        } else if blocks.is_empty() && line.starts_with("TN:") && current.len() == 1 {
+            header.append(&mut current);
+        }
+    }
+
+    for block in &mut blocks {
+        sort_lcov_block(block);
+    }
+    blocks.sort_by_key(|block| {
+        block
+            .iter()
+            .find_map(|line| line.strip_prefix("SF:"))
+            .unwrap_or_default()
+            .to_string()
+    });
+
+    let mut result = String::with_capacity(lcov.len());
+    for line in header
+        .into_iter()
+        .chain(blocks.into_iter().flatten())
+        .chain(current)
+    {
+        result.push_str(line);
+        result.push('\n');
+    }
+    result.into_bytes()
+}
 
-    let stdin_paths: String = profraw_paths.iter().fold("".into(), |mut a, x| {
+// The list of input files given to llvm-profdata. Each path is preceded by its weight, as
+// otherwise llvm-profdata would split the paths containing a comma and skip the ones starting
+// with '#'.
+pub(crate) fn profdata_input_list(profraw_paths: &[PathBuf]) -> String {
+    profraw_paths.iter().fold("".into(), |mut a, x| {
+        a.push_str("1,");
         a.push_str(x.to_string_lossy().as_ref());
         a.push('\n');
         a
-    });
+    })
+}
+
+const PROFILE_FILE_HINT: &str =
+    "Did your tests produce coverage data? Check that LLVM_PROFILE_FILE is set correctly.";
+
+/// The errors of the export of the coverage by profraws_to_lcov.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LlvmToolError {
+    /// No .profraw files were given.
+    NoProfraws,
+    /// None of the given .profraw files exist.
+    MissingProfraws(Vec<PathBuf>),
+    /// An llvm tool can't be found or failed.
+    Tool(String),
+    /// The profiles record the binary IDs of none of the binaries, they were given the binaries
+    /// of another build.
+    UnprofiledBinaries(Vec<PathBuf>),
+}
+
+impl From<String> for LlvmToolError {
+    fn from(err: String) -> LlvmToolError {
+        LlvmToolError::Tool(err)
+    }
+}
 
-    get_profdata_path().and_then(|p| run_with_stdin(p, &stdin_paths, &args))?;
+impl fmt::Display for LlvmToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlvmToolError::NoProfraws => {
+                write!(f, "No profraw files were provided. {}", PROFILE_FILE_HINT)
+            }
+            LlvmToolError::MissingProfraws(paths) => {
+                let paths: Vec<String> = paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                write!(
+                    f,
+                    "None of the profraw files exist ({}). {}",
+                    paths.join(", "),
+                    PROFILE_FILE_HINT
+                )
+            }
+            LlvmToolError::Tool(err) => f.write_str(err),
+            LlvmToolError::UnprofiledBinaries(binaries) => {
+                let binaries: Vec<String> = binaries
+                    .iter()
+                    .map(|binary| binary.display().to_string())
+                    .collect();
+                write!(
+                    f,
+                    "The profiles have no counters for any of the binaries ({}). Are they the \
+                     binaries of another build? Check the --binary-path.",
+                    binaries.join(", ")
+                )
+            }
+        }
+    }
+}
 
+/// The time spent in the llvm tools by profraws_to_lcov, e.g. to account for the cost of the
+/// coverage in CI.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CoverageStats {
+    /// The time spent merging the profraws with llvm-profdata, staging them included.
+    pub merge_time: Duration,
+    /// The time spent exporting the coverage of the binaries with llvm-cov.
+    pub export_time: Duration,
+    /// The number of binaries llvm-cov was run for, whether it succeeded or not.
+    pub binaries_processed: usize,
+    /// The number of binaries whose export was read from the export cache, without running
+    /// llvm-cov.
+    pub binaries_cached: usize,
+    /// The number of binaries skipped, as they weren't in the allowed binaries, or with
+    /// --skip-unprofiled-binaries.
+    pub binaries_skipped: usize,
+    /// The binaries whose coverage was exported, in the order of the outputs.
+    pub exported: Vec<PathBuf>,
+}
+
+// Lists the binaries of a directory, and its WebAssembly modules with `wasm`.
+fn walk_binaries(binary_path: &Path, wasm: bool) -> Vec<PathBuf> {
     let metadata = fs::metadata(binary_path)
         .unwrap_or_else(|e| panic!("Failed to open directory '{:?}': {:?}.", binary_path, e));
 
-    let binaries = if metadata.is_file() {
+    if metadata.is_file() {
         vec![binary_path.to_owned()]
     } else {
         let mut paths = vec![];
 
-        for entry in WalkDir::new(binary_path) {
+        for entry in walk_binary_dir(binary_path) {
             let entry =
                 entry.unwrap_or_else(|_| panic!("Failed to open directory '{:?}'.", binary_path));
 
-            if is_binary(entry.path()) && entry.metadata().unwrap().len() > 0 {
+            let is_module = wasm && is_wasm(entry.path());
+            if (is_binary(entry.path()) || is_module) && entry.metadata().unwrap().len() > 0 {
                 paths.push(entry.into_path());
             }
         }
 
         paths
+    }
+}
+
+/// Returns the paths of a --binary-path: the path itself when it exists, or else the paths of the
+/// list it is, separated like in the PATH variable (by ':', or ';' on Windows).
+pub fn split_binary_paths(binary_path: &Path) -> Vec<PathBuf> {
+    if binary_path.exists() {
+        return vec![binary_path.to_path_buf()];
+    }
+    let paths: Vec<PathBuf> = std::env::split_paths(binary_path.as_os_str())
+        .filter(|path| !path.as_os_str().is_empty())
+        .collect();
+    if paths.is_empty() {
+        vec![binary_path.to_path_buf()]
+    } else {
+        paths
+    }
+}
+
+// Lists the binaries of several binaries or directories. The binaries found in several of them,
+// e.g. through a symlink, are only listed once.
+fn walk_binary_paths(binary_paths: &[&Path], wasm: bool) -> Vec<PathBuf> {
+    let mut found = FxHashSet::default();
+    binary_paths
+        .iter()
+        .flat_map(|binary_path| walk_binaries(binary_path, wasm))
+        .filter(|binary| found.insert(fs::canonicalize(binary).unwrap_or_else(|_| binary.clone())))
+        .collect()
+}
+
+/// Lists the binaries found in a --binary-path (a binary or a directory, or a list of them), and
+/// its WebAssembly modules with `wasm`.
+pub fn walk_binary_path(binary_path: &Path, wasm: bool) -> Vec<PathBuf> {
+    let binary_paths = split_binary_paths(binary_path);
+    let binary_paths: Vec<&Path> = binary_paths.iter().map(PathBuf::as_path).collect();
+    walk_binary_paths(&binary_paths, wasm)
+}
+
+/// Adds the `executables` to the binaries found in a --binary-path, and only keeps the
+/// `allowed_binaries` if they are given. Also returns the number of binaries which aren't allowed.
+pub fn select_binaries(
+    mut binaries: Vec<PathBuf>,
+    executables: &[PathBuf],
+    allowed_binaries: Option<&[PathBuf]>,
+) -> (Vec<PathBuf>, usize) {
+    for executable in executables {
+        let is_found =
+            |binary: &PathBuf| fs::canonicalize(binary).is_ok_and(|binary| &binary == executable);
+        if !binaries.iter().any(is_found) {
+            binaries.push(executable.clone());
+        }
+    }
+
+    match allowed_binaries {
+        Some(allowed_binaries) => {
+            let count = binaries.len();
+            let binaries: Vec<PathBuf> = binaries
+                .into_iter()
+                .filter(|binary| {
+                    fs::canonicalize(binary).is_ok_and(|binary| allowed_binaries.contains(&binary))
+                })
+                .collect();
+            let skipped = count - binaries.len();
+            (binaries, skipped)
+        }
+        None => (binaries, 0),
+    }
+}
+
+/// Lists the binaries whose coverage is exported: the ones found in `binary_path` (a binary or a
+/// directory, or a list of them) and the `executables`, only keeping the `allowed_binaries` if they are given. Also
+/// returns the number of binaries which aren't allowed.
+pub fn exported_binaries(
+    binary_path: Option<&Path>,
+    executables: &[PathBuf],
+    allowed_binaries: Option<&[PathBuf]>,
+    wasm: bool,
+) -> (Vec<PathBuf>, usize) {
+    let binaries = match binary_path {
+        Some(binary_path) => walk_binary_path(binary_path, wasm),
+        None => Vec::new(),
+    };
+    select_binaries(binaries, executables, allowed_binaries)
+}
+
+// The path of the cached export of a binary in the export cache directory, named after the hash
+// of the merged profile, of the path, size and modification time of the binary, and of the format
+// of the export. None when the binary can't be read.
+fn export_cache_path(
+    cache_dir: &Path,
+    profdata_hash: &[u8],
+    binary: &Path,
+    json: bool,
+) -> Option<PathBuf> {
+    let metadata = fs::metadata(binary).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let mut hasher = Md5::new();
+    hasher.update(profdata_hash);
+    hasher.update(fs::canonicalize(binary).ok()?.to_string_lossy().as_bytes());
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(modified.as_nanos().to_le_bytes());
+    let extension = if json { "json" } else { "info" };
+    Some(cache_dir.join(format!("{:x}.{}", hasher.finalize(), extension)))
+}
+
+// Stores an export in the cache, atomically so that a concurrent run never reads it partially
+// written.
+fn write_cached_export(cache_path: &Path, export: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(cache_path.parent().unwrap())?;
+    write_atomically(cache_path, export)
+}
+
+/// The options of the export of the coverage of the binaries by the llvm tools.
+#[derive(Default)]
+pub struct ExportOptions<'a> {
+    pub binary_path: Option<&'a Path>,
+    pub executables: &'a [PathBuf],
+    pub stage: bool,
+    pub sort: bool,
+    pub skip_unprofiled_binaries: bool,
+    pub allowed_binaries: Option<&'a [PathBuf]>,
+    /// Exports the binaries in JSON instead, the only format with the MC/DC records, and with the
+    /// branches of all kinds (the lcov export misses some of them).
+    pub json: bool,
+    /// Exports the WebAssembly modules too.
+    pub wasm: bool,
+    /// Stores the exports there, and reads them back instead of running llvm-cov when neither the
+    /// merged profile nor the binary changed.
+    pub export_cache_dir: Option<&'a Path>,
+    /// Merges the profiles in a memfd instead of a file of the working directory, when the
+    /// platform has them.
+    pub in_memory_profdata: bool,
+    /// Fails when the profiles record none of the binaries, instead of only warning.
+    pub fail_on_binary_mismatch: bool,
+}
+
+/// Exports the coverage of the binaries found in `binary_path` (a binary or a directory), and of
+/// the `executables`, e.g. the ones built by cargo.
+pub fn profraws_to_lcov(
+    profraw_paths: &[PathBuf],
+    working_dir: &Path,
+    options: &ExportOptions,
+) -> Result<Vec<Vec<u8>>, LlvmToolError> {
+    profraws_to_lcov_with_stats(profraw_paths, working_dir, options).map(|(lcovs, _)| lcovs)
+}
+
+/// Like profraws_to_lcov, for the binaries found in several `binary_paths` (binaries or
+/// directories), e.g. split artifact directories. The binaries found in several of them are only
+/// exported once.
+pub fn profraws_to_lcov_multi(
+    profraw_paths: &[PathBuf],
+    binary_paths: &[&Path],
+    working_dir: &Path,
+    options: &ExportOptions,
+) -> Result<Vec<Vec<u8>>, LlvmToolError> {
+    let mut binaries = walk_binary_paths(binary_paths, false);
+    binaries.extend(options.executables.iter().cloned());
+    let mut found = FxHashSet::default();
+    let binaries: Vec<PathBuf> = binaries
+        .into_iter()
+        .map(|binary| fs::canonicalize(&binary).unwrap_or(binary))
+        .filter(|binary| found.insert(binary.clone()))
+        .collect();
+    profraws_to_lcov(
+        profraw_paths,
+        working_dir,
+        &ExportOptions {
+            binary_path: None,
+            executables: &binaries,
+            ..*options
+        },
+    )
+}
+
+// Creates an anonymous file in memory, and returns it with the path of its descriptor. The
+// descriptor is inherited by the llvm tools, so that they can open the same path.
+#[cfg(target_os = "linux")]
+fn create_memfd(name: &str) -> io::Result<(File, PathBuf)> {
+    use std::ffi::CString;
+    use std::os::unix::io::FromRawFd;
+
+    let name = CString::new(name)?;
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let file = unsafe { File::from_raw_fd(fd) };
+    Ok((file, PathBuf::from(format!("/proc/self/fd/{}", fd))))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn create_memfd(_name: &str) -> io::Result<(File, PathBuf)> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "memfd is only supported on Linux",
+    ))
+}
+
+/// Like profraws_to_lcov, but also returns the time spent in the llvm tools.
+pub fn profraws_to_lcov_with_stats(
+    profraw_paths: &[PathBuf],
+    working_dir: &Path,
+    options: &ExportOptions,
+) -> Result<(Vec<Vec<u8>>, CoverageStats), LlvmToolError> {
+    let &ExportOptions {
+        binary_path,
+        executables,
+        stage,
+        sort,
+        skip_unprofiled_binaries,
+        allowed_binaries,
+        json,
+        wasm,
+        export_cache_dir,
+        in_memory_profdata,
+        fail_on_binary_mismatch,
+    } = options;
+    if profraw_paths.is_empty() {
+        return Err(LlvmToolError::NoProfraws);
+    }
+    if !profraw_paths.iter().any(|path| path.exists()) {
+        return Err(LlvmToolError::MissingProfraws(profraw_paths.to_vec()));
+    }
+
+    let mut stats = CoverageStats::default();
+    let merge_start = Instant::now();
+    // The memfd is kept open until the binaries are exported.
+    let memfd = if in_memory_profdata {
+        create_memfd("grcov.profdata")
+            .map_err(|e| debug!("Merging the profiles in a file, without a memfd: {}", e))
+            .ok()
+    } else {
+        None
+    };
+    let profdata_path = match &memfd {
+        Some((_, path)) => path.clone(),
+        None => working_dir.join("grcov.profdata"),
+    };
+
+    // The compressed profiles are decompressed in the working directory, and removed once merged.
+    let decompressed_dir = working_dir.join("decompressed");
+    let decompressed_paths;
+    let is_compressed = profraw_paths.iter().any(|path| is_compressed_profile(path));
+    let profraw_paths = if is_compressed {
+        decompressed_paths = decompress_profraws(profraw_paths, &decompressed_dir)?;
+        if decompressed_paths.is_empty() {
+            return Err(LlvmToolError::NoProfraws);
+        }
+        decompressed_paths.as_slice()
+    } else {
+        profraw_paths
+    };
+
+    let staged_paths;
+    let profraw_paths = if stage {
+        staged_paths = stage_profraws(profraw_paths, &working_dir.join("profraws"))?;
+        staged_paths.as_slice()
+    } else {
+        profraw_paths
+    };
+
+    let args = vec![
+        "merge".as_ref(),
+        "-f".as_ref(),
+        "-".as_ref(),
+        "-sparse".as_ref(),
+        "-o".as_ref(),
+        profdata_path.as_ref(),
+    ];
+
+    let merged = get_profdata_path()
+        .and_then(|p| run_with_stdin(p, profdata_input_list(profraw_paths), &args, llvm_stderr()));
+    if is_compressed {
+        let _ = fs::remove_dir_all(&decompressed_dir);
+    }
+    merged?;
+    stats.merge_time = merge_start.elapsed();
+
+    // llvm-cov can't export anything from an empty profile, the coverage is just empty.
+    if fs::metadata(&profdata_path).is_ok_and(|metadata| metadata.len() == 0) {
+        warn!(
+            "The merged profile {:?} is empty: no instrumented code ran (e.g. the tests panicked \
+             before running any), so there is no coverage to export.",
+            profdata_path
+        );
+        return Ok((Vec::new(), stats));
+    }
+
+    let export_start = Instant::now();
+    let (binaries, binaries_skipped) =
+        exported_binaries(binary_path, executables, allowed_binaries, wasm);
+    stats.binaries_skipped += binaries_skipped;
+    // Listing the binary IDs of the profile runs llvm-profdata again, so only when they're used.
+    let profile_ids = if skip_unprofiled_binaries || fail_on_binary_mismatch {
+        profile_binary_ids(&profdata_path)
+    } else {
+        None
+    };
+    if let Some(profile_ids) = &profile_ids {
+        if fail_on_binary_mismatch {
+            check_profiled_binaries(&binaries, profile_ids)?;
+        }
+    }
+    let binaries = match (skip_unprofiled_binaries, &profile_ids) {
+        (true, Some(profile_ids)) => {
+            let count = binaries.len();
+            let binaries = skip_unprofiled(binaries, profile_ids);
+            stats.binaries_skipped += count - binaries.len();
+            binaries
+        }
+        _ => binaries,
     };
 
     let mut results = vec![];
     let cov_tool_path = get_cov_path()?;
+    let profdata_hash = match export_cache_dir {
+        Some(_) => fs::read(&profdata_path)
+            .ok()
+            .map(|profdata| Md5::digest(profdata).to_vec()),
+        None => None,
+    };
+    // The version of llvm-cov, to check that it can read the coverage mapping of the binaries.
+    // It is only run once a binary isn't in the export cache.
+    let mut llvm_major = None;
 
     for binary in binaries {
-        let args = [
-            "export".as_ref(),
-            binary.as_ref(),
+        let add_result = |results: &mut Vec<Vec<u8>>, result: Vec<u8>| {
+            results.push(if sort && !json {
+                sort_lcov(&result)
+            } else {
+                result
+            });
+        };
+
+        let cache_path = export_cache_dir.zip(profdata_hash.as_deref()).and_then(
+            |(cache_dir, profdata_hash)| export_cache_path(cache_dir, profdata_hash, &binary, json),
+        );
+        if let Some(cached) = cache_path.as_ref().and_then(|path| fs::read(path).ok()) {
+            debug!("Reading the export of {:?} from the cache", binary);
+            add_result(&mut results, cached);
+            stats.binaries_cached += 1;
+            stats.exported.push(binary);
+            continue;
+        }
+
+        let llvm_major = *llvm_major.get_or_insert_with(|| {
+            let llvm_major = run(&cov_tool_path, &["--version".as_ref()], llvm_stderr())
+                .ok()
+                .and_then(|output| parse_llvm_major_version(&String::from_utf8_lossy(&output)));
+            debug!("llvm-cov major version: {:?}", llvm_major);
+            llvm_major
+        });
+        if let Some(llvm_major) = llvm_major {
+            match check_covmap_version(&binary, llvm_major) {
+                Ok(Some(version)) => {
+                    debug!("Coverage mapping version of {:?}: {}", binary, version)
+                }
+                Ok(None) => {}
+                Err(err_str) => {
+                    error!(
+                        path = binary.display().to_string(), reason = err_str.as_str();
+                        "Skipping binary {:?}: {}", binary, err_str
+                    );
+                    stats.binaries_skipped += 1;
+                    continue;
+                }
+            }
+        }
+        let is_module = is_wasm(&binary);
+        if is_module {
+            if let Err(err_str) = check_wasm_support(&binary, llvm_major) {
+                error!(
+                    path = binary.display().to_string(), reason = err_str.as_str();
+                    "Skipping binary {:?}: {}", binary, err_str
+                );
+                stats.binaries_skipped += 1;
+                continue;
+            }
+        }
+        stats.binaries_processed += 1;
+        let mut args: Vec<&OsStr> = vec!["export".as_ref()];
+        if is_module {
+            args.extend::<[&OsStr; 4]>([
+                "--object".as_ref(),
+                binary.as_ref(),
+                "--arch".as_ref(),
+                WASM_EXPORT_ARCH.as_ref(),
+            ]);
+        } else {
+            args.push(binary.as_ref());
+        }
+        args.extend::<[&OsStr; 4]>([
             "--instr-profile".as_ref(),
             profdata_path.as_ref(),
             "--format".as_ref(),
-            "lcov".as_ref(),
-        ];
+            if json { "text" } else { "lcov" }.as_ref(),
+        ]);
 
-        match run(&cov_tool_path, &args) {
-            Ok(result) => results.push(result),
+        match run(&cov_tool_path, &args, llvm_stderr()) {
+            Ok(result) => {
+                if let Some(cache_path) = &cache_path {
+                    if let Err(e) = write_cached_export(cache_path, &result) {
+                        warn!("Failed to cache the export of {:?}: {}", binary, e);
+                    }
+                }
+                add_result(&mut results, result);
+                stats.exported.push(binary);
+            }
             Err(err_str) => warn!(
+                path = binary.display().to_string(), reason = err_str.as_str();
                 "Suppressing error returned by llvm-cov tool for binary {:?}\n{}",
                 binary, err_str
             ),
         }
     }
+    stats.export_time = export_start.elapsed();
 
-    Ok(results)
+    Ok((results, stats))
 }
 
-fn get_profdata_path() -> Result<PathBuf, String> {
-    let path = if let Some(mut path) = LLVM_PATH.get().cloned() {
-        path.push(format!("llvm-profdata{}", EXE_SUFFIX));
-        path
-    } else {
-        Tool::Profdata.path().map_err(|x| x.to_string())?
-    };
+/// Returns the build ID of a binary, in hexadecimal, as llvm-profdata shows it.
+pub fn binary_id(binary_path: &Path) -> Option<String> {
+    // Only the headers and the note of the build ID are read, not the whole binary.
+    let cache = ReadCache::new(File::open(binary_path).ok()?);
+    let obj = object::File::parse(&cache).ok()?;
+    let build_id = obj.build_id().ok()??;
+    Some(
+        build_id
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+    )
+}
 
-    if !path.exists() {
-        Err(String::from("We couldn't find llvm-profdata. Try installing the llvm-tools component with `rustup component add llvm-tools-preview` or specifying the --llvm-path option."))
-    } else {
-        Ok(path)
+/// Parses the binary IDs listed by `llvm-profdata show --binary-ids`.
+pub fn parse_binary_ids(output: &str) -> FxHashSet<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("Binary IDs:"))
+        .skip(1)
+        .map(str::trim)
+        .take_while(|line| !line.is_empty() && line.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// The binary IDs recorded in the profile, or None when it records none (e.g. with old LLVM
+// versions, or on platforms where the binaries have no build ID).
+fn profile_binary_ids(profdata_path: &Path) -> Option<FxHashSet<String>> {
+    let args = [
+        "show".as_ref(),
+        "--binary-ids".as_ref(),
+        profdata_path.as_ref(),
+    ];
+    match get_profdata_path().and_then(|p| run(p, &args, llvm_stderr())) {
+        Ok(output) => Some(parse_binary_ids(&String::from_utf8_lossy(&output)))
+            .filter(|binary_ids| !binary_ids.is_empty()),
+        Err(e) => {
+            warn!("Unable to list the binary IDs of the profile: {}", e);
+            None
+        }
     }
 }
 
-fn get_cov_path() -> Result<PathBuf, String> {
-    let path = if let Some(mut path) = LLVM_PATH.get().cloned() {
-        path.push(format!("llvm-cov{}", EXE_SUFFIX));
-        path
-    } else {
-        Tool::Cov.path().map_err(|x| x.to_string())?
+// Fails when all the binaries have a build ID, and none of them is recorded in the profile, which
+// almost always means that the profiles and the binaries come from different builds. The binaries
+// without a build ID could be the ones which were run.
+fn check_profiled_binaries(
+    binaries: &[PathBuf],
+    binary_ids: &FxHashSet<String>,
+) -> Result<(), LlvmToolError> {
+    let mut ids = binaries.iter().map(|binary| binary_id(binary));
+    if binaries.is_empty() || !ids.all(|id| id.is_some_and(|id| !binary_ids.contains(&id))) {
+        return Ok(());
+    }
+    Err(LlvmToolError::UnprofiledBinaries(binaries.to_vec()))
+}
+
+// Only keeps the binaries whose build ID is recorded in the profile, as the others have no
+// counters in it, so that llvm-cov isn't run for them. The binaries without a build ID are kept.
+fn skip_unprofiled(binaries: Vec<PathBuf>, binary_ids: &FxHashSet<String>) -> Vec<PathBuf> {
+    let (profiled, unprofiled): (Vec<PathBuf>, Vec<PathBuf>) =
+        binaries.into_iter().partition(|binary| {
+            binary_id(binary).is_none_or(|binary_id| binary_ids.contains(&binary_id))
+        });
+    for binary in &unprofiled {
+        debug!(
+            "Skipping {:?}, which has no counters in the profile",
+            binary
+        );
+    }
+    profiled
+}
+
+// Looks for the given llvm tool in the LLVM bin directory, if specified, or in the llvm-tools
+// component of the Rust toolchain otherwise.
+fn get_tool_path(tool: Tool, llvm_path: Option<&Path>) -> Result<PathBuf, String> {
+    let name = format!("llvm-{}", tool.name());
+    let not_found = |reason: String| {
+        format!(
+            "We couldn't find {}{}. Try installing the llvm-tools component with `rustup component add llvm-tools-preview` or specifying the --llvm-path option.",
+            name, reason
+        )
+    };
+
+    let path = match llvm_path {
+        Some(llvm_path) => llvm_path.join(format!("{}{}", name, EXE_SUFFIX)),
+        None => tool.path().map_err(|e| not_found(format!(" ({})", e)))?,
     };
 
     if !path.exists() {
-        Err(String::from("We couldn't find llvm-cov. Try installing the llvm-tools component with `rustup component add llvm-tools-preview` or specifying the --llvm-path option."))
+        Err(not_found(String::new()))
     } else {
         Ok(path)
     }
 }
 
+pub(crate) fn get_profdata_path() -> Result<PathBuf, String> {
+    get_tool_path(Tool::Profdata, LLVM_PATH.get().map(PathBuf::as_path))
+}
+
+pub(crate) fn get_cov_path() -> Result<PathBuf, String> {
+    get_tool_path(Tool::Cov, LLVM_PATH.get().map(PathBuf::as_path))
+}
+
+/// The programs built with source-based coverage, and run, by the tests.
+#[cfg(test)]
+pub(crate) mod test_helpers {
+    use super::*;
+
+    pub(crate) const HELLO_WORLD: &str = "fn main() {\n    println!(\"Hello\");\n}\n";
+
+    // Whether the llvm tools matching the Rust compiler, which the tests building programs need,
+    // are there.
+    pub(crate) fn has_llvm_tools() -> bool {
+        get_profdata_path().is_ok() && get_cov_path().is_ok()
+    }
+
+    // Builds the given program with source-based coverage in the given directory.
+    pub(crate) fn build_instrumented(dir: &Path, source: &str, rustc_args: &[&str]) -> PathBuf {
+        fs::write(dir.join("main.rs"), source).unwrap();
+        let binary_path = dir.join(format!("main{}", EXE_SUFFIX));
+        let status = Command::new("rustc")
+            .arg("-Cinstrument-coverage")
+            .args(rustc_args)
+            .arg("-o")
+            .arg(&binary_path)
+            .arg("main.rs")
+            .current_dir(dir)
+            .status()
+            .expect("Failed to build");
+        assert!(status.success());
+        binary_path
+    }
+
+    // Runs an instrumented binary, which writes its profile to `profraw_path`.
+    pub(crate) fn run_instrumented(binary_path: &Path, profraw_path: &Path) {
+        let status = Command::new(binary_path)
+            .env("LLVM_PROFILE_FILE", profraw_path)
+            .stdout(Stdio::null())
+            .status()
+            .expect("Failed to run");
+        assert!(status.success());
+    }
+
+    // Builds the given program in `dir` and runs it once, returning the paths of the binary and
+    // of its profile, or None without the llvm tools.
+    pub(crate) fn instrumented_profraw(dir: &Path, source: &str) -> Option<(PathBuf, PathBuf)> {
+        if !has_llvm_tools() {
+            return None;
+        }
+        let binary_path = build_instrumented(dir, source, &[]);
+        let profraw_path = dir.join("default.profraw");
+        run_instrumented(&binary_path, &profraw_path);
+        Some((binary_path, profraw_path))
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::test_helpers::*;
     use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
     use std::fs;
+    use std::io::{Seek, SeekFrom};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_stage_profraws() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+
+        let remote_dir = tmp_path.join("remote");
+        fs::create_dir_all(remote_dir.join("a")).unwrap();
+        fs::create_dir_all(remote_dir.join("b")).unwrap();
+        let profraws = vec![
+            remote_dir.join("a").join("default.profraw"),
+            remote_dir.join("b").join("default.profraw"),
+        ];
+        fs::write(&profraws[0], b"first").unwrap();
+        fs::write(&profraws[1], b"second").unwrap();
+
+        let staging_dir = tmp_path.join("staging");
+        let staged = stage_profraws(&profraws, &staging_dir).unwrap();
+
+        assert_eq!(staged.len(), 2);
+        for (original, staged) in profraws.iter().zip(&staged) {
+            assert!(staged.starts_with(&staging_dir));
+            assert_eq!(fs::read(original).unwrap(), fs::read(staged).unwrap());
+        }
+        assert_ne!(staged[0], staged[1]);
+    }
+
+    #[test]
+    fn test_stage_profraws_missing_file() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+
+        let result = stage_profraws(
+            &[tmp_path.join("missing.profraw")],
+            &tmp_path.join("staging"),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_profraws_to_lcov_merges_staged_profraws() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+
+        let profraw = tmp_path.join("default.profraw");
+        fs::write(&profraw, b"not a real profraw").unwrap();
+
+        // The merge itself fails (or llvm-profdata is missing), but the staging happens first.
+        let _ = profraws_to_lcov(
+            &[profraw],
+            &tmp_path,
+            &ExportOptions {
+                binary_path: Some(tmp_path.as_path()),
+                stage: true,
+                ..Default::default()
+            },
+        );
+
+        let staged = tmp_path.join("profraws").join("0_default.profraw");
+        assert_eq!(fs::read(staged).unwrap(), b"not a real profraw");
+    }
+
+    #[test]
+    fn test_profdata_input_list() {
+        assert_eq!(
+            profdata_input_list(&[
+                PathBuf::from("/tmp/a b/default.profraw"),
+                PathBuf::from("/tmp/x,y/#1 (copy) é.profraw"),
+            ]),
+            "1,/tmp/a b/default.profraw\n1,/tmp/x,y/#1 (copy) é.profraw\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_special_characters() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let dir = tmp_dir.path().join("with space (1) ünïcödé");
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("file, #2.txt");
+        fs::write(&path, b"content").unwrap();
+
+        let stderr = StderrConfig::Discard;
+        assert_eq!(run("cat", &[path.as_ref()], &stderr).unwrap(), b"content");
+        assert_eq!(
+            run_with_stdin("cat", "from stdin", &["-".as_ref(), path.as_ref()], &stderr).unwrap(),
+            b"from stdincontent"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_stderr_to_file() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let stderr_path = tmp_dir.path().join("llvm-stderr.log");
+        let stderr = StderrConfig::new(false, Some(stderr_path.clone()));
+        assert_eq!(stderr, StderrConfig::File(stderr_path.clone()));
+
+        let script = |script: &'static str| ["-c".as_ref(), script.as_ref()];
+        assert_eq!(
+            run("sh", &script("echo out; echo 'first warning' >&2"), &stderr).unwrap(),
+            b"out\n"
+        );
+        // Nothing is written for the invocations without stderr.
+        run("sh", &script("echo out"), &stderr).unwrap();
+        let err = run_with_stdin(
+            "sh",
+            "",
+            &script("echo 'second error' >&2; exit 1"),
+            &stderr,
+        )
+        .unwrap_err();
+        assert!(err.contains("second error"));
+
+        let log = fs::read_to_string(&stderr_path).unwrap();
+        assert_eq!(log.matches("$ ").count(), 2);
+        let first = log.find("first warning").unwrap();
+        assert!(log.find("second error").unwrap() > first);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_large_outputs() {
+        // 10 MB on stderr first, then on stdout, far more than the pipes can hold, and only then
+        // the input is read, when there is one.
+        let outputs = "head -c 10485760 /dev/zero >&2; head -c 10485760 /dev/zero";
+        let script = format!("{}; wc -c", outputs);
+        let args = ["-c".as_ref(), script.as_ref()];
+        let stderr = StderrConfig::Discard;
+
+        let read_input = |output: &[u8]| {
+            assert!(output[..10485760].iter().all(|&byte| byte == 0));
+            String::from_utf8_lossy(&output[10485760..])
+                .trim()
+                .to_string()
+        };
+        let output = run("sh", &["-c".as_ref(), outputs.as_ref()], &stderr).unwrap();
+        assert_eq!(read_input(&output), "");
+        let input = "a".repeat(10485760);
+        let output = run_with_stdin("sh", &input, &args, &stderr).unwrap();
+        assert_eq!(read_input(&output), "10485760");
+    }
+
+    #[test]
+    fn test_stage_profraws_special_characters() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let profraw = tmp_dir.path().join("with space (1) ünï,cödé.profraw");
+        fs::write(&profraw, b"profraw").unwrap();
+
+        let staging_dir = tmp_dir.path().join("staging dir (é)");
+        let staged = stage_profraws(&[profraw], &staging_dir).unwrap();
+        assert_eq!(
+            staged,
+            vec![staging_dir.join("0_with space (1) ünï,cödé.profraw")]
+        );
+        assert_eq!(fs::read(&staged[0]).unwrap(), b"profraw");
+    }
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *content).unwrap();
+        }
+        gzip(&builder.into_inner().unwrap())
+    }
+
+    #[test]
+    fn test_decompress_profraws() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path();
+        let plain = tmp_path.join("plain.profraw");
+        fs::write(&plain, b"plain").unwrap();
+        fs::write(tmp_path.join("run.profraw.gz"), gzip(b"gzipped")).unwrap();
+        fs::write(
+            tmp_path.join("runs.tar.gz"),
+            tarball(&[
+                ("ci/job1/default.profraw", b"first"),
+                ("ci/README", b"not a profile"),
+                ("ci/job2/default.profraw", b"second"),
+            ]),
+        )
+        .unwrap();
+
+        let compressed = vec![
+            plain.clone(),
+            tmp_path.join("run.profraw.gz"),
+            tmp_path.join("runs.tar.gz"),
+        ];
+        assert!(!is_compressed_profile(&compressed[0]));
+        assert!(is_compressed_profile(&compressed[1]));
+        assert!(is_compressed_profile(Path::new("runs.tgz")));
+
+        let dir = tmp_path.join("decompressed");
+        let paths = decompress_profraws(&compressed, &dir).unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                plain,
+                dir.join("1_run.profraw"),
+                dir.join("2_0_default.profraw"),
+                dir.join("2_2_default.profraw"),
+            ]
+        );
+        let contents: Vec<Vec<u8>> = paths[1..]
+            .iter()
+            .map(|path| fs::read(path).unwrap())
+            .collect();
+        assert_eq!(contents, [&b"gzipped"[..], b"first", b"second"]);
+
+        fs::write(tmp_path.join("corrupt.profraw.gz"), b"not gzipped").unwrap();
+        assert!(decompress_profraws(&[tmp_path.join("corrupt.profraw.gz")], &dir).is_err());
+    }
+
+    #[test]
+    fn test_profraws_to_lcov_compressed() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+        let (binary_path, profraw_path) = match instrumented_profraw(&tmp_path, HELLO_WORLD) {
+            Some(paths) => paths,
+            None => return,
+        };
+        let profraw = fs::read(&profraw_path).unwrap();
+        fs::write(tmp_path.join("default.profraw.gz"), gzip(&profraw)).unwrap();
+        fs::write(
+            tmp_path.join("profraws.tgz"),
+            tarball(&[("default.profraw", &profraw)]),
+        )
+        .unwrap();
+
+        let line_hits = |profraw_paths: &[PathBuf]| {
+            let lcovs = profraws_to_lcov(
+                profraw_paths,
+                &tmp_path,
+                &ExportOptions {
+                    binary_path: Some(binary_path.as_path()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let lcov = String::from_utf8_lossy(&lcovs[0]).into_owned();
+            lcov.lines()
+                .find(|line| line.starts_with("DA:2,"))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(line_hits(&[tmp_path.join("default.profraw.gz")]), "DA:2,1");
+        assert_eq!(
+            line_hits(&[
+                tmp_path.join("default.profraw.gz"),
+                tmp_path.join("profraws.tgz")
+            ]),
+            "DA:2,2"
+        );
+        assert!(!tmp_path.join("decompressed").exists());
+    }
+
+    #[test]
+    fn test_profraws_to_lcov_export_cache() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+        let (binary_path, profraw_path) = match instrumented_profraw(&tmp_path, HELLO_WORLD) {
+            Some(paths) => paths,
+            None => return,
+        };
+
+        let cache_dir = tmp_path.join("cache");
+        let export = |profraw_paths: &[PathBuf]| {
+            profraws_to_lcov_with_stats(
+                profraw_paths,
+                &tmp_path,
+                &ExportOptions {
+                    binary_path: Some(binary_path.as_path()),
+                    export_cache_dir: Some(&cache_dir),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        let profraws = std::slice::from_ref(&profraw_path);
+        let (lcovs, stats) = export(profraws);
+        assert_eq!((stats.binaries_processed, stats.binaries_cached), (1, 0));
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 1);
+
+        // llvm-cov isn't run again for the same profile and binary.
+        let (cached_lcovs, stats) = export(profraws);
+        assert_eq!((stats.binaries_processed, stats.binaries_cached), (0, 1));
+        assert_eq!(stats.exported, vec![binary_path.clone()]);
+        assert_eq!(cached_lcovs, lcovs);
+
+        // Another profile, or a rebuilt binary, is exported again.
+        let (lcovs, stats) = export(&[profraw_path.clone(), profraw_path.clone()]);
+        assert_eq!((stats.binaries_processed, stats.binaries_cached), (1, 0));
+        assert!(String::from_utf8_lossy(&lcovs[0])
+            .lines()
+            .any(|line| line == "DA:2,2"));
+        let modified = fs::metadata(&binary_path).unwrap().modified().unwrap();
+        File::options()
+            .write(true)
+            .open(&binary_path)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        let (_, stats) = export(profraws);
+        assert_eq!((stats.binaries_processed, stats.binaries_cached), (1, 0));
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_profraws_to_lcov_special_characters() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().join("with space (1) ünï,cödé");
+        fs::create_dir(&tmp_path).unwrap();
+
+        let (binary_path, default_profraw_path) = match instrumented_profraw(&tmp_path, HELLO_WORLD)
+        {
+            Some(paths) => paths,
+            None => return,
+        };
+        let profraw_path = tmp_path.join("#1, run (a).profraw");
+        fs::rename(default_profraw_path, &profraw_path).unwrap();
+
+        for &stage in &[false, true] {
+            let lcovs = profraws_to_lcov(
+                std::slice::from_ref(&profraw_path),
+                &tmp_path,
+                &ExportOptions {
+                    binary_path: Some(binary_path.as_path()),
+                    stage,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert!(tmp_path.join("grcov.profdata").is_file());
+            assert_eq!(lcovs.len(), 1);
+            let output_lcov = String::from_utf8_lossy(&lcovs[0]);
+            assert!(output_lcov.lines().any(|line| line == "DA:2,1"));
+        }
+    }
+
+    #[test]
+    fn test_snapshot_profraw_live_updates() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let profraw = tmp_dir.path().join("default.profraw");
+        let snapshot = tmp_dir.path().join("snapshot.profraw");
+        fs::write(&profraw, vec![0u8; 4096]).unwrap();
+
+        // Increment the counters in place, like a process in continuous mode does.
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let profraw = profraw.clone();
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let mut file = fs::OpenOptions::new().write(true).open(&profraw).unwrap();
+                let mut counter = 0u64;
+                while !stop.load(Ordering::Relaxed) {
+                    counter += 1;
+                    file.seek(SeekFrom::Start((counter % 512) * 8)).unwrap();
+                    file.write_all(&counter.to_le_bytes()).unwrap();
+                }
+            })
+        };
+
+        for _ in 0..20 {
+            snapshot_profraw(&profraw, &snapshot).unwrap();
+            assert_eq!(fs::metadata(&snapshot).unwrap().len(), 4096);
+        }
+        stop.store(true, Ordering::Relaxed);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_profraw_not_ready() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let profraw = tmp_dir.path().join("default.profraw");
+        let snapshot = tmp_dir.path().join("snapshot.profraw");
+        fs::write(&profraw, b"").unwrap();
+
+        let err = snapshot_profraw(&profraw, &snapshot).unwrap_err();
+        assert!(err.contains("is empty"), "{}", err);
+        assert!(!snapshot.exists());
+    }
+
+    #[test]
+    fn test_profraws_to_lcov_continuous_mode() {
+        if !has_llvm_tools() {
+            return;
+        }
+
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+
+        // Continuous mode needs the counters to be relocated on Linux.
+        let rustc_args: &[&str] = if cfg!(target_os = "linux") {
+            &["-Cllvm-args=-runtime-counter-relocation"]
+        } else {
+            &[]
+        };
+        let binary_path = build_instrumented(
+            &tmp_path,
+            "use std::io::BufRead;\n\
+             fn main() {\n\
+             \x20   for line in std::io::stdin().lock().lines() {\n\
+             \x20       println!(\"{}\", line.unwrap());\n\
+             \x20   }\n\
+             }\n",
+            rustc_args,
+        );
+
+        let profraw_path = tmp_path.join("default.profraw");
+        let mut child = Command::new(&binary_path)
+            .env("LLVM_PROFILE_FILE", tmp_path.join("%cdefault.profraw"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Failed to run");
+        let mut stdin = child.stdin.take().unwrap();
+
+        let line_hits = || {
+            let lcovs = profraws_to_lcov(
+                std::slice::from_ref(&profraw_path),
+                &tmp_path,
+                &ExportOptions {
+                    binary_path: Some(binary_path.as_path()),
+                    stage: true,
+                    ..Default::default()
+                },
+            )
+            .ok()?;
+            String::from_utf8_lossy(lcovs.first()?)
+                .lines()
+                .find_map(|line| line.strip_prefix("DA:4,").map(|hits| hits.to_string()))
+        };
+        // The process handles its input asynchronously, so wait for the counters to be updated.
+        let wait_for_hits = |hits: &str| {
+            for _ in 0..100 {
+                if line_hits().as_deref() == Some(hits) {
+                    return true;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            false
+        };
+
+        // The process is still running, and the counters keep growing.
+        stdin.write_all(b"hit\nhit\n").unwrap();
+        assert!(wait_for_hits("2"));
+        stdin.write_all(b"hit\nhit\nhit\n").unwrap();
+        assert!(wait_for_hits("5"));
+
+        drop(stdin);
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_profraws_to_lcov_multiple_profdata() {
+        if !has_llvm_tools() {
+            return;
+        }
+        let profdata_path = get_profdata_path().unwrap();
+
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+
+        let binary_path = build_instrumented(&tmp_path, HELLO_WORLD, &[]);
+
+        // Two runs, each one stored in its own indexed profile.
+        let mut profdata_paths = Vec::new();
+        for name in &["instrumented", "sanitizer"] {
+            let profraw_path = tmp_path.join(format!("{}.profraw", name));
+            run_instrumented(&binary_path, &profraw_path);
+
+            let path = tmp_path.join(format!("{}.profdata", name));
+            run(
+                &profdata_path,
+                &[
+                    "merge".as_ref(),
+                    profraw_path.as_ref(),
+                    "-o".as_ref(),
+                    path.as_ref(),
+                ],
+                &StderrConfig::Discard,
+            )
+            .unwrap();
+            profdata_paths.push(path);
+        }
+
+        let lcovs = profraws_to_lcov(
+            &profdata_paths,
+            &tmp_path,
+            &ExportOptions {
+                binary_path: Some(binary_path.as_path()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(lcovs.len(), 1);
+        let output_lcov = String::from_utf8_lossy(&lcovs[0]);
+        assert!(output_lcov.lines().any(|line| line == "DA:1,2"));
+        assert!(output_lcov.lines().any(|line| line == "DA:2,2"));
+    }
+
+    #[test]
+    fn test_profraws_to_lcov_multi() {
+        if !has_llvm_tools() {
+            return;
+        }
+
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+        let mut profraw_paths = Vec::new();
+        for name in &["first", "second"] {
+            let dir = tmp_path.join(name);
+            fs::create_dir(&dir).unwrap();
+            let binary_path = build_instrumented(
+                &dir,
+                &format!("fn main() {{\n    println!(\"{}\");\n}}\n", name),
+                &["--crate-name", name],
+            );
+            let profraw_path = tmp_path.join(format!("{}.profraw", name));
+            run_instrumented(&binary_path, &profraw_path);
+            profraw_paths.push(profraw_path);
+        }
+        let first = tmp_path.join("first");
+        let second = tmp_path.join("second");
+
+        // The binaries of a directory given twice are only exported once.
+        let lcovs = profraws_to_lcov_multi(
+            &profraw_paths,
+            &[&first, &second, &first],
+            &tmp_path,
+            &ExportOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(lcovs.len(), 2);
+        let source_files: Vec<String> = lcovs
+            .iter()
+            .flat_map(|lcov| {
+                String::from_utf8_lossy(lcov)
+                    .lines()
+                    .filter(|line| line.starts_with("SF:"))
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert!(source_files.iter().any(|line| line.contains("first")));
+        assert!(source_files.iter().any(|line| line.contains("second")));
+
+        // The same directories, as a --binary-path list.
+        let binary_path_list = std::env::join_paths([&first, &second]).unwrap();
+        let lcovs = profraws_to_lcov(
+            &profraw_paths,
+            &tmp_path,
+            &ExportOptions {
+                binary_path: Some(Path::new(&binary_path_list)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(lcovs.len(), 2);
+    }
+
+    #[test]
+    fn test_split_binary_paths() {
+        assert_eq!(
+            split_binary_paths(Path::new("test")),
+            vec![PathBuf::from("test")]
+        );
+        let list = std::env::join_paths(["target/a", "target/b"]).unwrap();
+        assert_eq!(
+            split_binary_paths(Path::new(&list)),
+            vec![PathBuf::from("target/a"), PathBuf::from("target/b")]
+        );
+        assert_eq!(
+            split_binary_paths(Path::new("target/missing")),
+            vec![PathBuf::from("target/missing")]
+        );
+    }
+
+    #[test]
+    fn test_walk_binary_path_skip_dirs() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let target = tmp_dir.path().join("debug");
+        let mut elf = b"\x7fELF".to_vec();
+        elf.resize(64, 0);
+        for dir in &[
+            "deps",
+            ".fingerprint/lib-1",
+            "incremental/lib-2/s-3",
+            "build/lib-4",
+        ] {
+            fs::create_dir_all(target.join(dir)).unwrap();
+            fs::write(target.join(dir).join("binary"), &elf).unwrap();
+        }
+        let mut binaries = walk_binary_path(&target, false);
+        binaries.sort();
+        assert_eq!(
+            binaries,
+            vec![
+                target.join("build/lib-4/binary"),
+                target.join("deps/binary")
+            ]
+        );
+        // The binary path itself is walked even when its name matches.
+        assert_eq!(
+            walk_binary_path(&target.join("incremental"), false),
+            vec![target.join("incremental/lib-2/s-3/binary")]
+        );
+
+        let skip_dirs = skip_dirs_globset(&["build", "*-1"]).unwrap();
+        for (name, skipped) in &[
+            (".fingerprint", true),
+            ("incremental", true),
+            (".cargo-lock", true),
+            ("build", true),
+            ("lib-1", true),
+            ("deps", false),
+            ("lib-4", false),
+        ] {
+            assert_eq!(skip_dirs.is_match(name), *skipped, "{}", name);
+        }
+        assert!(skip_dirs_globset(&["[deps"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_binary_ids() {
+        let output = "Instrumentation level: Front-end\nTotal functions: 1\nTotal count: 1\nBinary IDs: \n61BDEEC51A3472DACD1FD4135A3D19A2A7EA534B\n0fd4e2c83b0a5a8a\n";
+        let binary_ids = parse_binary_ids(output);
+        assert_eq!(binary_ids.len(), 2);
+        assert!(binary_ids.contains("61bdeec51a3472dacd1fd4135a3d19a2a7ea534b"));
+        assert!(binary_ids.contains("0fd4e2c83b0a5a8a"));
+
+        assert!(parse_binary_ids("Total functions: 1\nTotal count: 1\n").is_empty());
+    }
+
+    #[test]
+    fn test_profraws_to_lcov_binary_mismatch() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+        for name in &["run", "other"] {
+            fs::create_dir_all(tmp_path.join(name)).unwrap();
+        }
+        let (run_binary, profraw_path) =
+            match instrumented_profraw(&tmp_path.join("run"), HELLO_WORLD) {
+                Some(paths) => paths,
+                None => return,
+            };
+        let other_binary = build_instrumented(
+            &tmp_path.join("other"),
+            "fn main() {\n    println!(\"Other\");\n}\n",
+            &["--crate-name", "other"],
+        );
+        // The profiles only record the binary IDs on the platforms where binaries have one.
+        if binary_id(&run_binary).is_none() {
+            return;
+        }
+
+        // The profiles of one binary, exported with a completely different one.
+        let export = |binary: &Path, fail_on_binary_mismatch| {
+            profraws_to_lcov_with_stats(
+                std::slice::from_ref(&profraw_path),
+                &tmp_path,
+                &ExportOptions {
+                    binary_path: Some(binary),
+                    fail_on_binary_mismatch,
+                    ..Default::default()
+                },
+            )
+        };
+        assert_eq!(
+            export(&tmp_path.join("other"), true).unwrap_err(),
+            LlvmToolError::UnprofiledBinaries(vec![other_binary])
+        );
+        // Only a warning without the option.
+        let (lcovs, stats) = export(&tmp_path.join("other"), false).unwrap();
+        assert_eq!(lcovs.len(), 1);
+        assert_eq!(stats.binaries_processed, 1);
+        // A single binary of the profiles is enough.
+        assert!(export(&tmp_path, true).is_ok());
+    }
+
+    #[test]
+    fn test_profraws_to_lcov_skip_unprofiled_binaries() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+        let binaries_dir = tmp_path.join("binaries");
+        for name in &["run", "unrelated"] {
+            fs::create_dir_all(binaries_dir.join(name)).unwrap();
+        }
+        let (run_binary, profraw_path) =
+            match instrumented_profraw(&binaries_dir.join("run"), HELLO_WORLD) {
+                Some(paths) => paths,
+                None => return,
+            };
+        let unrelated_binary = build_instrumented(
+            &binaries_dir.join("unrelated"),
+            "fn main() {\n    println!(\"Unrelated\");\n}\n",
+            // Otherwise its main function would have the same name as the one of the binary run.
+            &["--crate-name", "unrelated"],
+        );
+        // The profiles only record the binary IDs on the platforms where binaries have one.
+        if binary_id(&run_binary).is_none() {
+            return;
+        }
+        assert_ne!(binary_id(&run_binary), binary_id(&unrelated_binary));
+
+        let profraws = std::slice::from_ref(&profraw_path);
+        let (lcovs, stats) = profraws_to_lcov_with_stats(
+            profraws,
+            &tmp_path,
+            &ExportOptions {
+                binary_path: Some(binaries_dir.as_path()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(lcovs.len(), 2);
+        assert_eq!(stats.binaries_processed, 2);
+        assert_eq!(stats.binaries_skipped, 0);
+
+        let (lcovs, stats) = profraws_to_lcov_with_stats(
+            profraws,
+            &tmp_path,
+            &ExportOptions {
+                binary_path: Some(binaries_dir.as_path()),
+                skip_unprofiled_binaries: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(lcovs.len(), 1);
+        assert_eq!(stats.binaries_processed, 1);
+        assert_eq!(stats.binaries_skipped, 1);
+        assert_eq!(stats.exported, vec![run_binary.clone()]);
+        assert!(stats.merge_time > Duration::ZERO);
+        assert!(stats.export_time > Duration::ZERO);
+        let output_lcov = String::from_utf8_lossy(&lcovs[0]);
+        assert!(output_lcov.contains("run"));
+        assert!(!output_lcov.contains("unrelated"));
+        assert!(output_lcov.lines().any(|line| line == "DA:2,1"));
+
+        // Only the allowed binaries are exported, e.g. the ones built by cargo test.
+        let allowed_binaries = [fs::canonicalize(&unrelated_binary).unwrap()];
+        let (lcovs, stats) = profraws_to_lcov_with_stats(
+            profraws,
+            &tmp_path,
+            &ExportOptions {
+                binary_path: Some(binaries_dir.as_path()),
+                allowed_binaries: Some(&allowed_binaries),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(lcovs.len(), 1);
+        assert!(String::from_utf8_lossy(&lcovs[0]).contains("unrelated"));
+        assert_eq!(stats.binaries_processed, 1);
+        assert_eq!(stats.binaries_skipped, 1);
+
+        // The executables are exported without walking a binary path, once each.
+        let executables = [fs::canonicalize(&run_binary).unwrap()];
+        for binary_path in [None, Some(run_binary.as_path())] {
+            let (lcovs, stats) = profraws_to_lcov_with_stats(
+                profraws,
+                &tmp_path,
+                &ExportOptions {
+                    binary_path,
+                    executables: &executables,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(lcovs.len(), 1);
+            assert!(String::from_utf8_lossy(&lcovs[0]).contains("run"));
+            assert_eq!(stats.binaries_processed, 1);
+        }
+    }
+
+    #[test]
+    fn test_profraw_has_mcdc() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let header = |magic: u64, version: u64, bitmap_bytes: u64| {
+            let mut header = vec![0; 96];
+            header[..8].copy_from_slice(&magic.to_le_bytes());
+            header[8..16].copy_from_slice(&version.to_le_bytes());
+            header[56..64].copy_from_slice(&bitmap_bytes.to_le_bytes());
+            header
+        };
+        let has_mcdc = |name: &str, content: &[u8]| {
+            let path = tmp_dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            profraw_has_mcdc(&path)
+        };
+
+        assert!(has_mcdc("mcdc.profraw", &header(PROFRAW_MAGIC, 10, 8)));
+        // The high bits of the version are flags.
+        assert!(has_mcdc(
+            "flags.profraw",
+            &header(PROFRAW_MAGIC, 1 << 56 | 9, 1)
+        ));
+        assert!(!has_mcdc(
+            "no_bitmap.profraw",
+            &header(PROFRAW_MAGIC, 10, 0)
+        ));
+        // The headers before version 9 have no bitmaps, the field is another one.
+        assert!(!has_mcdc("v8.profraw", &header(PROFRAW_MAGIC, 8, 8)));
+        assert!(!has_mcdc("not_profraw.profraw", &header(0, 10, 8)));
+        assert!(!has_mcdc(
+            "short.profraw",
+            &header(PROFRAW_MAGIC, 10, 8)[..40]
+        ));
+        assert!(!profraw_has_mcdc(&tmp_dir.path().join("missing.profraw")));
+    }
+
+    #[test]
+    fn test_profraws_to_lcov_mcdc() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+        let (binary, profraw_path) = match instrumented_profraw(&tmp_path, HELLO_WORLD) {
+            Some(paths) => paths,
+            None => return,
+        };
+        // rustc doesn't write MC/DC bitmaps.
+        assert!(!profraw_has_mcdc(&profraw_path));
+        let profraws = std::slice::from_ref(&profraw_path);
+        let export = |mcdc| {
+            profraws_to_lcov_with_stats(
+                profraws,
+                &tmp_path,
+                &ExportOptions {
+                    binary_path: Some(binary.as_path()),
+                    sort: true,
+                    json: mcdc,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .0
+        };
+
+        // The JSON export has the same lines as the lcov one.
+        let json = export(true);
+        assert_eq!(json.len(), 1);
+        let json_results = crate::parser::parse_llvm_cov_json(&json[0], true, true).unwrap();
+        let lcov_results = crate::parser::parse_lcov(&export(false).remove(0), true).unwrap();
+        assert_eq!(json_results.len(), lcov_results.len());
+        assert_eq!(json_results[0].1.lines, lcov_results[0].1.lines);
+
+        // rustc doesn't have the MC/DC instrumentation, so a C decision is built with clang when
+        // its version supports it.
+        let source = "int check(int a, int b) {\n  if (a && b)\n    return 1;\n  return 0;\n}\nint main(void) { return check(1, 0) + check(1, 1) - 1; }\n";
+        fs::write(tmp_path.join("check.c"), source).unwrap();
+        let c_binary = tmp_path.join(format!("check{}", EXE_SUFFIX));
+        let built = Command::new("clang")
+            .args([
+                "-fprofile-instr-generate",
+                "-fcoverage-mapping",
+                "-fcoverage-mcdc",
+                "-o",
+            ])
+            .arg(&c_binary)
+            .arg("check.c")
+            .current_dir(&tmp_path)
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if !built {
+            return;
+        }
+        let c_profraw_path = tmp_path.join("check.profraw");
+        run_instrumented(&c_binary, &c_profraw_path);
+        assert!(profraw_has_mcdc(&c_profraw_path));
+        // The profiles of another LLVM version than the one of the llvm tools can't be read.
+        let json = match profraws_to_lcov_with_stats(
+            std::slice::from_ref(&c_profraw_path),
+            &tmp_path,
+            &ExportOptions {
+                binary_path: Some(c_binary.as_path()),
+                json: true,
+                ..Default::default()
+            },
+        ) {
+            Ok((json, _)) if json.len() == 1 => json,
+            _ => return,
+        };
+        let results = crate::parser::parse_llvm_cov_json(&json[0], true, true).unwrap();
+        let (_, result) = results
+            .iter()
+            .find(|(name, _)| name.ends_with("check.c"))
+            .unwrap();
+        // Only the independence pair of `b` is covered.
+        assert_eq!(result.branches.get(&2), Some(&vec![false, true]));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_profraws_to_lcov_in_memory_profdata() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+        let (binary, profraw_path) = match instrumented_profraw(&tmp_path, HELLO_WORLD) {
+            Some(paths) => paths,
+            None => return,
+        };
+        let working_dir = tmp_path.join("working");
+        fs::create_dir(&working_dir).unwrap();
+        let export = |in_memory_profdata| {
+            profraws_to_lcov_with_stats(
+                std::slice::from_ref(&profraw_path),
+                &working_dir,
+                &ExportOptions {
+                    binary_path: Some(binary.as_path()),
+                    sort: true,
+                    in_memory_profdata,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .0
+        };
+
+        let lcovs = export(true);
+        assert_eq!(lcovs.len(), 1);
+        assert!(!working_dir.join("grcov.profdata").exists());
+        assert_eq!(fs::read_dir(&working_dir).unwrap().count(), 0);
+        assert_eq!(lcovs, export(false));
+        assert!(working_dir.join("grcov.profdata").exists());
+    }
+
+    #[test]
+    fn test_walk_binaries_wasm() {
+        // This needs the wasm32-unknown-unknown target of the Rust compiler. The target has no
+        // profiler runtime, the profiles are written by the module itself (e.g. with minicov).
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let source = "#![no_std]\n#[panic_handler]\nfn panic(_: &core::panic::PanicInfo) -> ! {\n    loop {}\n}\n#[no_mangle]\npub extern \"C\" fn add(a: i32, b: i32) -> i32 {\n    if a > 0 { a + b } else { b }\n}\n";
+        fs::write(tmp_dir.path().join("lib.rs"), source).unwrap();
+        let built = Command::new("rustc")
+            .args([
+                "--target",
+                "wasm32-unknown-unknown",
+                "--crate-type",
+                "cdylib",
+                "-Cinstrument-coverage",
+                "-Zno-profiler-runtime",
+                "-o",
+                "add.wasm",
+                "lib.rs",
+            ])
+            .env("RUSTC_BOOTSTRAP", "1")
+            .current_dir(tmp_dir.path())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if !built {
+            return;
+        }
+
+        let module = tmp_dir.path().join("add.wasm");
+        assert!(is_wasm(&module));
+        assert!(walk_binaries(tmp_dir.path(), false).is_empty());
+        assert_eq!(walk_binaries(tmp_dir.path(), true), vec![module.clone()]);
+        assert!(check_wasm_support(&module, Some(crate::wasm::WASM_MIN_LLVM_MAJOR)).is_ok());
+        let version = check_covmap_version(&module, crate::wasm::WASM_MIN_LLVM_MAJOR).unwrap();
+        assert!(version.is_some());
+    }
+
+    #[test]
+    fn test_get_tool_path_missing_tool() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+
+        for (tool, name) in [(Tool::Cov, "llvm-cov"), (Tool::Profdata, "llvm-profdata")] {
+            let err = get_tool_path(tool, Some(tmp_dir.path())).unwrap_err();
+            assert_eq!(
+                err,
+                format!("We couldn't find {}. Try installing the llvm-tools component with `rustup component add llvm-tools-preview` or specifying the --llvm-path option.", name)
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_tool_path_llvm_path() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let cov_path = tmp_dir.path().join(format!("llvm-cov{}", EXE_SUFFIX));
+        fs::write(&cov_path, b"").unwrap();
+
+        assert_eq!(
+            get_tool_path(Tool::Cov, Some(tmp_dir.path())).unwrap(),
+            cov_path
+        );
+        assert!(get_tool_path(Tool::Profdata, Some(tmp_dir.path())).is_err());
+    }
+
+    #[test]
+    fn test_sort_lcov() {
+        let lcov = "\
+TN:
+SF:src/main.rs
+FN:8,main
+FN:3,helper
+FNDA:1,main
+FNDA:0,helper
+FNF:2
+FNH:1
+DA:9,1
+DA:3,0
+DA:8,1
+LF:3
+LH:2
+end_of_record
+SF:src/lib.rs
+FN:1,lib
+FNDA:1,lib
+DA:2,1
+DA:1,1
+end_of_record
+";
+        let expected = "\
+TN:
+SF:src/lib.rs
+FN:1,lib
+FNDA:1,lib
+DA:1,1
+DA:2,1
+end_of_record
+SF:src/main.rs
+FN:3,helper
+FN:8,main
+FNDA:0,helper
+FNDA:1,main
+FNF:2
+FNH:1
+DA:3,0
+DA:8,1
+DA:9,1
+LF:3
+LH:2
+end_of_record
+";
+        assert_eq!(
+            String::from_utf8(sort_lcov(lcov.as_bytes())).unwrap(),
+            expected
+        );
+        // Sorting is idempotent.
+        assert_eq!(sort_lcov(expected.as_bytes()), expected.as_bytes());
+    }
+
+    #[test]
+    fn test_profraws_to_lcov_no_profraws() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let err = profraws_to_lcov(&[], tmp_dir.path(), &ExportOptions::default()).unwrap_err();
+        assert_eq!(err, LlvmToolError::NoProfraws);
+        assert_eq!(
+            err.to_string(),
+            "No profraw files were provided. Did your tests produce coverage data? Check that \
+             LLVM_PROFILE_FILE is set correctly."
+        );
+
+        let missing = tmp_dir.path().join("missing.profraw");
+        let err = profraws_to_lcov(
+            std::slice::from_ref(&missing),
+            tmp_dir.path(),
+            &ExportOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err, LlvmToolError::MissingProfraws(vec![missing.clone()]));
+        assert!(err.to_string().starts_with(&format!(
+            "None of the profraw files exist ({})",
+            missing.display()
+        )));
+    }
 
     #[test]
     fn test_profraws_to_lcov() {
@@ -209,8 +2242,11 @@ mod tests {
 
         let lcovs = profraws_to_lcov(
             &[tmp_path.join("default.profraw")],
-            &PathBuf::from("src"),
             &tmp_path,
+            &ExportOptions {
+                binary_path: Some(Path::new("src")),
+                ..Default::default()
+            },
         );
         assert!(lcovs.is_ok());
         let lcovs = lcovs.unwrap();
@@ -223,8 +2259,11 @@ mod tests {
 
         let lcovs = profraws_to_lcov(
             &[tmp_path.join("default.profraw")],
-            &tmp_path.join(binary_path),
             &tmp_path,
+            &ExportOptions {
+                binary_path: Some(tmp_path.join(binary_path).as_path()),
+                ..Default::default()
+            },
         );
         assert!(lcovs.is_ok());
         let lcovs = lcovs.unwrap();
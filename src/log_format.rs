@@ -0,0 +1,175 @@
+use chrono::{SecondsFormat, Utc};
+use log::kv::{self, Key, Value};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::{json, Map};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// The format of the log messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("{} is not a supported log format", s)),
+        }
+    }
+}
+
+// Collects the key-values of a record, e.g. `path` or `reason`, as JSON fields.
+struct JsonFields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> kv::Visitor<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(value) = value.to_u64() {
+            json!(value)
+        } else if let Some(value) = value.to_i64() {
+            json!(value)
+        } else if let Some(value) = value.to_bool() {
+            json!(value)
+        } else {
+            json!(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Formats a record as a JSON object with its level, timestamp, target and message, along
+/// with the fields given at the call site.
+pub fn json_record(record: &Record, timestamp: &str) -> serde_json::Value {
+    let mut object = Map::new();
+    object.insert("level".to_string(), json!(record.level().as_str()));
+    object.insert("timestamp".to_string(), json!(timestamp));
+    object.insert("target".to_string(), json!(record.target()));
+    object.insert("message".to_string(), json!(record.args().to_string()));
+    let _ = record.key_values().visit(&mut JsonFields(&mut object));
+    serde_json::Value::Object(object)
+}
+
+/// A logger writing each record as a JSON object on its own line, for the log processors.
+pub struct JsonLogger {
+    level: LevelFilter,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLogger {
+    pub fn new(level: LevelFilter, writer: Box<dyn Write + Send>) -> Self {
+        JsonLogger {
+            level,
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let mut writer = self.writer.lock().unwrap();
+        let _ = writeln!(writer, "{}", json_record(record, &timestamp));
+    }
+
+    fn flush(&self) {
+        let _ = self.writer.lock().unwrap().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!(LogFormat::from_str("text"), Ok(LogFormat::Text));
+        assert_eq!(LogFormat::from_str("json"), Ok(LogFormat::Json));
+        assert!(LogFormat::from_str("xml").is_err());
+    }
+
+    #[test]
+    fn test_json_record() {
+        let path = "src/main.rs".to_string();
+        let fields: &[(&str, &dyn kv::ToValue)] = &[("path", &path), ("count", &3u64)];
+        let value = json_record(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("grcov::parser")
+                .args(format_args!("Error parsing file {}", path))
+                .key_values(&fields)
+                .build(),
+            "2026-10-14T08:00:00.000Z",
+        );
+        assert_eq!(
+            value,
+            json!({
+                "level": "WARN",
+                "timestamp": "2026-10-14T08:00:00.000Z",
+                "target": "grcov::parser",
+                "message": "Error parsing file src/main.rs",
+                "path": "src/main.rs",
+                "count": 3,
+            })
+        );
+    }
+
+    // A writer shared with the test, to read what the logger wrote.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_logger_level() {
+        let buffer = SharedBuffer::default();
+        let logger = JsonLogger::new(LevelFilter::Warn, Box::new(buffer.clone()));
+        for (level, message) in [(Level::Error, "first"), (Level::Info, "hidden")] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("{}", message))
+                    .build(),
+            );
+        }
+        logger.log(
+            &Record::builder()
+                .level(Level::Warn)
+                .args(format_args!("second"))
+                .build(),
+        );
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let messages: Vec<String> = output
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                value["message"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(messages, vec!["first", "second"]);
+    }
+}
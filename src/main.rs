@@ -3,19 +3,28 @@
 static GLOBAL: tcmalloc::TCMalloc = tcmalloc::TCMalloc;
 
 use crossbeam_channel::bounded;
-use log::error;
+use log::{error, Log};
 use regex::Regex;
 use rustc_hash::FxHashMap;
 use serde_json::Value;
-use simplelog::{ColorChoice, Config, LevelFilter, TermLogger, TerminalMode, WriteLogger};
+use simplelog::{
+    ColorChoice, Config, LevelFilter, SharedLogger, TermLogger, TerminalMode, WriteLogger,
+};
+use std::ffi::OsString;
 use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
 use std::ops::Deref;
 use std::panic;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{process, thread};
-use structopt::{clap::ArgGroup, StructOpt};
+use structopt::{
+    clap::{self, App, ArgGroup, ArgMatches},
+    StructOpt,
+};
 
 use grcov::*;
 
@@ -29,6 +38,10 @@ enum OutputType {
     Html,
     Cobertura,
     Markdown,
+    MarkdownSummary,
+    Jsonl,
+    Sonar,
+    AnnotatedSource,
 }
 
 impl FromStr for OutputType {
@@ -45,12 +58,24 @@ impl FromStr for OutputType {
             "html" => Self::Html,
             "cobertura" => Self::Cobertura,
             "markdown" => Self::Markdown,
+            "markdown-summary" => Self::MarkdownSummary,
+            "jsonl" => Self::Jsonl,
+            "sonar" => Self::Sonar,
+            "annotated-source" => Self::AnnotatedSource,
             _ => return Err(format!("{} is not a supported output type", s)),
         })
     }
 }
 
 impl OutputType {
+    // Whether the output is written one file at a time, which --max-memory requires.
+    fn is_streamed(&self) -> bool {
+        matches!(
+            self,
+            OutputType::Lcov | OutputType::Covdir | OutputType::Html
+        )
+    }
+
     fn to_file_name(&self, output_path: Option<&Path>) -> Option<PathBuf> {
         output_path.map(|path| {
             if path.is_dir() {
@@ -64,6 +89,10 @@ impl OutputType {
                     OutputType::Html => path.join("html"),
                     OutputType::Cobertura => path.join("cobertura.xml"),
                     OutputType::Markdown => path.join("markdown.md"),
+                    OutputType::MarkdownSummary => path.join("summary.md"),
+                    OutputType::Jsonl => path.join("coverage.jsonl"),
+                    OutputType::Sonar => path.join("sonar.xml"),
+                    OutputType::AnnotatedSource => path.join("annotated"),
                 }
             } else {
                 path.to_path_buf()
@@ -95,15 +124,56 @@ impl FromStr for Filter {
     about = "Parse, collect and aggregate code coverage data for multiple source files"
 )]
 struct Opt {
-    /// Sets the input paths to use.
-    #[structopt(required = true)]
+    /// Sets the input paths to use. '@FILE' reads them from a list file, as with --input-list.
+    #[structopt(required_unless_one = &["raw-counters-file", "llvm-cov-json", "input-list"])]
     paths: Vec<String>,
-    /// Sets the path to the compiled binary to be used.
-    #[structopt(short, long, value_name = "PATH")]
+    /// Reads the input files and archives from the given file, with one path per line, relative
+    /// to its directory. The blank lines and the ones starting with '#' are ignored.
+    #[structopt(long, value_name = "FILE")]
+    input_list: Option<PathBuf>,
+    /// Warns about the files of the input lists which don't exist, instead of failing.
+    #[structopt(long)]
+    ignore_missing_inputs: bool,
+    /// Sets the path to the compiled binary to be used, or to a directory of binaries. Several
+    /// paths can be given as a list separated by ':' (';' on Windows).
+    #[structopt(short, long, alias = "binary", value_name = "PATH")]
     binary_path: Option<PathBuf>,
+    /// Doesn't look for binaries in the directories of the binary path whose name matches this
+    /// glob, e.g. deps, in addition to .fingerprint, incremental and .cargo-lock.
+    #[structopt(long, value_name = "PATTERN", number_of_values = 1)]
+    skip_dir: Vec<String>,
+    /// Also exports the WebAssembly modules (.wasm) found in the binary path, e.g. built for
+    /// wasm32-unknown-unknown with -Cinstrument-coverage. This needs the llvm tools of LLVM 19 or
+    /// newer.
+    #[structopt(long)]
+    wasm: bool,
+    /// Caches the exports of the binaries in this directory, and reuses them instead of running
+    /// llvm-cov when neither the merged profile nor the binary (its size and modification time)
+    /// changed.
+    #[structopt(long, value_name = "DIRECTORY")]
+    export_cache_dir: Option<PathBuf>,
+    /// Doesn't use the --export-cache-dir, e.g. given in a config file.
+    #[structopt(long)]
+    no_export_cache: bool,
+    /// Fails when the profiles have no counters for any of the binaries (none of their build IDs
+    /// is recorded in the profiles), which usually means that they come from another build.
+    #[structopt(long)]
+    fail_on_binary_mismatch: bool,
+    /// Merges the profiles in memory rather than in a grcov.profdata file of the working
+    /// directory: in a memfd on Linux, that the llvm tools read as /proc/self/fd/N. The file is
+    /// written as usual on the other platforms.
+    #[structopt(long)]
+    in_memory_profdata: bool,
     /// Sets the path to the LLVM bin directory.
     #[structopt(long, value_name = "PATH")]
     llvm_path: Option<PathBuf>,
+    /// Logs the stderr of the llvm tools, at the debug level when they succeed (e.g. the warnings
+    /// of llvm-cov about missing debug info) and at the error level when they fail.
+    #[structopt(long)]
+    capture_llvm_stderr: bool,
+    /// Appends the stderr of all the llvm tool invocations to the given file.
+    #[structopt(long, value_name = "PATH")]
+    llvm_stderr_to_file: Option<PathBuf>,
     /// Sets a custom output type.
     #[structopt(
         long,
@@ -117,7 +187,11 @@ struct Opt {
             - *ade* for the ActiveData-ETL specific format;\n\
             - *files* to only return a list of files.\n\
             - *markdown* for human easy read.\n\
+            - *markdown-summary* for a Markdown table of the line and function coverage.\n\
+            - *jsonl* for JSON Lines, with one JSON object per file.\n\
             - *cobertura* for output in cobertura format.\n\
+            - *sonar* for the generic test coverage XML format of SonarQube.\n\
+            - *annotated-source* for the source files annotated with their counts by llvm-cov.\n\
         ",
         value_name = "OUTPUT TYPE",
         requires_ifs = &[
@@ -143,7 +217,11 @@ struct Opt {
             - *ade* for the ActiveData-ETL specific format;\n\
             - *files* to only return a list of files.\n\
             - *markdown* for human easy read.\n\
+            - *markdown-summary* for a Markdown table of the line and function coverage.\n\
+            - *jsonl* for JSON Lines, with one JSON object per file.\n\
             - *cobertura* for output in cobertura format.\n\
+            - *sonar* for the generic test coverage XML format of SonarQube.\n\
+            - *annotated-source* for the source files annotated with their counts by llvm-cov.\n\
             ",
             value_name = "OUTPUT TYPE",
             requires_ifs = &[
@@ -169,6 +247,42 @@ struct Opt {
     /// than the one that generated the code coverage information).
     #[structopt(short, long, value_name = "PATH")]
     prefix_dir: Option<PathBuf>,
+    /// Replaces a prefix of the paths with another one, given as FROM=TO (e.g. when the code was
+    /// built in /build/src and the sources are in /home/user/proj). The option can be repeated,
+    /// and the first rule whose prefix matches the path applies.
+    #[structopt(
+        long,
+        value_name = "FROM=TO",
+        number_of_values = 1,
+        parse(try_from_str = parse_prefix_mapping)
+    )]
+    remap_path_prefix: Vec<(String, String)>,
+    /// Removes a prefix from the paths of the source files in the outputs, when they start with
+    /// it. The option can be repeated, and the first prefix the path starts with is removed.
+    #[structopt(long, value_name = "PREFIX", number_of_values = 1)]
+    strip_filename_prefix: Vec<PathBuf>,
+    /// Removes the longest directory containing all the source files from their paths in the
+    /// outputs, after the --strip-filename-prefix ones.
+    #[structopt(long)]
+    auto_strip_prefix: bool,
+    /// Prepends a prefix to the paths of the source files in the outputs, after the
+    /// --strip-filename-prefix and --auto-strip-prefix ones (e.g. when the tool reading the
+    /// report expects the paths from the root of a repository the project is in).
+    #[structopt(long, value_name = "PREFIX")]
+    add_filename_prefix: Option<PathBuf>,
+    /// Removes the specified number of leading components from the paths, after the prefix has
+    /// been removed (like tar's --strip-components).
+    #[structopt(long, value_name = "NUMBER", default_value = "0")]
+    strip_components: usize,
+    /// Makes the paths which are still absolute after the other rewritings relative to the root
+    /// of their git repository. The files outside of a repository keep their absolute paths.
+    #[structopt(long)]
+    repo_relative: bool,
+    /// Sets a directory with a copy of the source files as they were when the coverage was
+    /// recorded, at the same relative paths. The line numbers of the files which changed since,
+    /// e.g. reformatted by rustfmt, are moved to their current lines.
+    #[structopt(long, value_name = "DIRECTORY")]
+    source_snapshot: Option<PathBuf>,
     /// Ignore source files that can't be found on the disk.
     #[structopt(long)]
     ignore_not_existing: bool,
@@ -178,11 +292,28 @@ struct Opt {
     /// Keep only files/directories specified as globs.
     #[structopt(long = "keep-only", value_name = "PATH", number_of_values = 1)]
     keep_dir: Vec<String>,
+    /// Don't read the .grcovignore files in the source directory. They contain gitignore-style
+    /// patterns, relative to their directory, of the files to ignore.
+    #[structopt(long)]
+    no_grcovignore: bool,
+    /// Drops the files under the directories of the toolchains and of the system (by default
+    /// ~/.rustup, ~/.cargo and /usr/lib/rustlib), like the sources of the standard library.
+    #[structopt(long)]
+    strip_system_paths: bool,
+    /// Sets the directories dropped by --strip-system-paths, instead of the default ones.
+    #[structopt(long, value_name = "PATH", number_of_values = 1)]
+    system_path_prefix: Vec<PathBuf>,
     #[structopt(long, value_name = "PATH")]
     path_mapping: Option<PathBuf>,
     /// Enables parsing branch coverage information.
     #[structopt(long)]
     branch: bool,
+    /// Reports the MC/DC coverage of the decisions from the source-based coverage data (built
+    /// with MC/DC instrumentation, e.g. clang's -fcoverage-mcdc): the branches of the line of a
+    /// decision are replaced by its conditions, covered when their independence pair is. The
+    /// binaries are exported in JSON instead of lcov, which takes longer.
+    #[structopt(long)]
+    mcdc: bool,
     /// Filters out covered/uncovered files. Use 'covered' to only return covered files, 'uncovered'
     /// to only return uncovered files.
     #[structopt(long, possible_values = &["covered", "uncovered"])]
@@ -191,6 +322,14 @@ struct Opt {
     /// build.
     #[structopt(long)]
     llvm: bool,
+    /// Caches the gcno files in the LLVM format parsed by grcov in this directory, and reads them
+    /// back instead of parsing them again when they (their path, size and modification time)
+    /// didn't change. Only the gcda files are parsed then.
+    #[structopt(long, value_name = "DIRECTORY")]
+    cache_dir: Option<PathBuf>,
+    /// Doesn't use the --cache-dir, e.g. given in a config file.
+    #[structopt(long)]
+    no_cache: bool,
     /// Sets the repository token from Coveralls, required for the 'coveralls' and 'coveralls+'
     /// formats.
     #[structopt(long, value_name = "TOKEN")]
@@ -218,11 +357,30 @@ struct Opt {
     /// Sets the build type to be parallel for 'coveralls' and 'coveralls+' formats.
     #[structopt(long)]
     parallel: bool,
+    /// Sets the number of threads parsing the coverage files, which is never more than the number
+    /// of input files. By default or with 0, one less than the number of CPUs available, within
+    /// the CPU quota of the cgroup.
     #[structopt(long, value_name = "NUMBER")]
     threads: Option<usize>,
     /// Sets coverage decimal point precision on output reports.
     #[structopt(long, value_name = "NUMBER", default_value = "2")]
     precision: usize,
+    /// Writes the lcov output in this encoding, for the tools which don't read UTF-8. The paths
+    /// which can't be represented in it are written percent-encoded, with a warning.
+    #[structopt(
+        long,
+        value_name = "ENCODING",
+        default_value = "utf-8",
+        possible_values = &["utf-8", "latin-1", "utf-16-le", "utf-16-be"],
+    )]
+    output_encoding: OutputEncoding,
+    /// Shows the counts of the regions of the lines with several regions in the annotated-source
+    /// output.
+    #[structopt(long)]
+    show_line_counts_or_regions: bool,
+    /// Expands the macros under the lines using them in the annotated-source output.
+    #[structopt(long)]
+    show_expansions: bool,
     #[structopt(long = "guess-directory-when-missing")]
     guess_directory: bool,
     /// Set the branch for coveralls report. Defaults to 'master'.
@@ -239,6 +397,15 @@ struct Opt {
         possible_values = &["OFF", "ERROR","WARN", "INFO", "DEBUG", "TRACE"],
     )]
     log_level: LevelFilter,
+    /// Set the format of the log: 'text', or 'json' for a JSON object per message, with its level,
+    /// timestamp and message, along with fields like 'path' or 'reason' for some of them.
+    #[structopt(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        possible_values = &["text", "json"],
+    )]
+    log_format: LogFormat,
     /// Lines in covered files containing this marker will be excluded.
     #[structopt(long, value_name = "regex")]
     excl_line: Option<Regex>,
@@ -259,14 +426,516 @@ struct Opt {
     /// section.
     #[structopt(long, value_name = "regex")]
     excl_br_stop: Option<Regex>,
+    /// Excludes the lines with fewer characters than this once trimmed, e.g. 2 for the lines
+    /// with a lone brace.
+    #[structopt(long, value_name = "NUMBER", default_value = "0")]
+    minimum_line_length: usize,
+    /// Excludes the lines calling unreachable!, or panic! with a message starting with "internal",
+    /// which are never covered.
+    #[structopt(long)]
+    exclude_unreachable: bool,
+    /// Also excludes the lines calling this macro with --exclude-unreachable, e.g. unimplemented.
+    #[structopt(long, value_name = "NAME", number_of_values = 1)]
+    unreachable_macro: Vec<String>,
+    /// Only keeps the functions whose demangled name matches this regex, with their lines.
+    #[structopt(long, value_name = "regex")]
+    include_fn: Option<Regex>,
+    /// Excludes the functions whose demangled name matches this regex (e.g. 'as
+    /// core::fmt::Debug>::fmt'), with their lines. A line shared by several functions is only
+    /// excluded with all of them.
+    #[structopt(long, value_name = "regex")]
+    exclude_fn: Option<Regex>,
+    /// Excludes the functions synthesized by the compiler from the function coverage: the
+    /// closures and the methods of the derivable traits (e.g. Debug::fmt or Clone::clone, even
+    /// when implemented by hand). Their lines are kept.
+    #[structopt(long)]
+    exclude_synthesized_fn: bool,
+    /// Excludes the files written by code generators, whose first lines contain a marker
+    /// matching this regex ('@generated|Code generated by' by default).
+    #[structopt(long, value_name = "regex")]
+    excl_generated: Option<Option<Regex>>,
     /// No symbol demangling.
     #[structopt(long)]
     no_demangle: bool,
+    /// Copies the profraw files to a local temporary directory before merging them. This is faster
+    /// when they are on a networked filesystem, and needed to read the profraws that running
+    /// processes update in continuous mode.
+    #[structopt(long)]
+    stage_profraws: bool,
+    /// Sorts the files of the report by path, so that the order of its records doesn't depend on
+    /// the order of the inputs or on llvm internals, and the diffs of committed reports are
+    /// meaningful.
+    #[structopt(long)]
+    sort_lcov: bool,
+    /// Only exports the coverage of the binaries whose build ID is recorded in the profile, as
+    /// listed by `llvm-profdata show --binary-ids`, which avoids running llvm-cov for the binaries
+    /// which weren't run. Their files are then missing from the report, instead of being shown as
+    /// not covered.
+    #[structopt(long, requires = "binary-path")]
+    skip_unprofiled_binaries: bool,
+    /// Sets the path to the JSON messages of `cargo test --message-format=json`. Only the
+    /// executables built by cargo are exported, among the binaries in --binary-path.
+    #[structopt(long, value_name = "PATH", requires = "binary-path")]
+    cargo_messages: Option<PathBuf>,
+    /// Sets the path to the JSON messages of the last `cargo test --no-run --message-format=json`,
+    /// or `-` to read them from stdin. The executables built by cargo are exported, instead of
+    /// the binaries found in --binary-path, which is then only needed for other binaries (e.g.
+    /// the doctests persisted with `--persist-doctests`).
+    #[structopt(long, value_name = "PATH")]
+    cargo: Option<PathBuf>,
+    /// Sets the path to a JSON file containing raw coverage counters, e.g. dumped from the memory
+    /// of an embedded device, as an object mapping function names to arrays of counter values.
+    /// They are mapped to source lines using the coverage mapping of the binary.
+    #[structopt(long, value_name = "PATH", requires = "binary-path")]
+    raw_counters_file: Option<PathBuf>,
+    /// Sets the path to a JSON coverage report generated before, either by `llvm-cov export
+    /// --format=text` or in the JSON intermediate format of gcov. It is parsed as is, without
+    /// merging profiles or exporting binaries, e.g. to generate the reports on another machine.
+    #[structopt(long, value_name = "PATH")]
+    llvm_cov_json: Option<PathBuf>,
+    /// Selects the coverage the lines of the --llvm-cov-json export are counted from: `line`, the
+    /// lines where a region starts or which a region started before covers, as the lcov export
+    /// of llvm-cov does; `region`, only the lines where a region starts; or `function`, only the
+    /// first line of each function.
+    #[structopt(long, value_name = "KIND", default_value = "line")]
+    llvm_cov_json_lines: LineCoverageKind,
+    /// Adds the lines, branches and functions of the given lcov report as not covered, so that
+    /// the ones which are instrumented but were not run by the inputs appear in the report. The
+    /// execution counts only come from the inputs.
+    #[structopt(long, value_name = "PATH", number_of_values = 1)]
+    baseline_lcov: Vec<PathBuf>,
+    /// Sets the path to a TOML configuration file, whose keys are the names of the options (e.g.
+    /// `source-dir = "."`). Defaults to grcov.toml, if it exists in the working directory. The
+    /// options set on the command line take precedence, except for --ignore and --keep-only whose
+    /// globs are added to the ones from the file.
+    #[structopt(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Labels the coverage report of this run with the given tag, and stores it in the tag cache
+    /// directory so that it can be combined with the reports of other runs with `grcov aggregate`.
+    #[structopt(long, value_name = "TAG")]
+    tag: Option<String>,
+    /// Sets the directory where the reports of the tagged runs are stored.
+    #[structopt(long, value_name = "DIRECTORY", default_value = DEFAULT_TAG_CACHE_DIR)]
+    tag_cache_dir: PathBuf,
+    /// Appends the coverage of this run, overall and for each file, to the given JSON trend file
+    /// (created if missing). Use `grcov trend-stats` to compute the trends from it.
+    #[structopt(long, value_name = "PATH")]
+    trend_file: Option<PathBuf>,
+    /// Fails with exit code 1, after writing the outputs, if the percentage of covered lines is
+    /// lower than the given one. It also fails if there are no lines at all.
+    #[structopt(
+        long,
+        alias = "fail-under-lines",
+        value_name = "PERCENTAGE",
+        parse(try_from_str = parse_percentage)
+    )]
+    fail_under_line: Option<f64>,
+    /// Fails with exit code 1, after writing the outputs, if the percentage of covered branches is
+    /// lower than the given one.
+    #[structopt(
+        long,
+        alias = "fail-under-branches",
+        value_name = "PERCENTAGE",
+        parse(try_from_str = parse_percentage)
+    )]
+    fail_under_branch: Option<f64>,
+    /// Fails with exit code 1, after writing the outputs, if the percentage of covered functions
+    /// is lower than the given one.
+    #[structopt(
+        long,
+        alias = "fail-under-functions",
+        value_name = "PERCENTAGE",
+        parse(try_from_str = parse_percentage)
+    )]
+    fail_under_function: Option<f64>,
+    /// Fails with exit code 1, after writing the outputs, if the percentage of covered lines of a
+    /// file matching the glob is lower than the given one. Each file is checked against the most
+    /// specific rule matching it (e.g. 'src/parser/**=90' wins over 'src/**=70').
+    #[structopt(long, value_name = "GLOB=PERCENTAGE", number_of_values = 1)]
+    fail_under: Vec<FileThreshold>,
+    /// Merges the execution counts of the lines of the source files matching the glob, when
+    /// several compilation units or inputs have a record of them, with this mode: 'sum' (the
+    /// default, like lcov) or 'max'. The mode of all the files is given without a glob, and the
+    /// last rule matching the path of a file wins, e.g. '**/*.h=max' for the inline functions of
+    /// the headers. The mode applies as the records of the inputs are merged, including the
+    /// records of a file repeated in one lcov report, but the counts of a line repeated within a
+    /// record are always summed.
+    #[structopt(long, value_name = "[GLOB=]MODE", number_of_values = 1)]
+    merge_mode: Vec<MergeRule>,
+    /// Makes the files without any instrumented line fail their --fail-under threshold.
+    #[structopt(long)]
+    fail_empty_files: bool,
+    /// Writes the results of the coverage threshold checks in JSON to the given path.
+    #[structopt(long, value_name = "PATH")]
+    threshold_report: Option<PathBuf>,
+    /// Writes the source files of the coverage data excluded from the report to the given path,
+    /// one per line as 'REASON<TAB>PATH', with the filter which excluded them: ExclFile (--ignore,
+    /// .grcovignore or --excl-generated), ExclFn (--exclude-fn or --include-fn), SourceDirFilter
+    /// (--keep-only, --strip-system-paths or --ignore-not-existing), and ZeroLines or
+    /// MinLinesFilter (--min-lines-in-file).
+    #[structopt(long, value_name = "PATH")]
+    report_ignored: Option<PathBuf>,
+    /// Writes the binaries whose source-based coverage includes each source file in JSON to the
+    /// given path, as an object mapping the source files to the lists of binaries.
+    #[structopt(long, value_name = "PATH")]
+    source_index: Option<PathBuf>,
+    /// Limits the memory used by the results of the source files, in MB: beyond it, they are
+    /// written to temporary files while the coverage files are merged and while the outputs are
+    /// written, and read back by path. Only the lcov, covdir and html outputs, which are written
+    /// one file at a time, are supported, and --auto-strip-prefix isn't.
+    #[structopt(long, value_name = "MB")]
+    max_memory: Option<usize>,
+    /// Doesn't warn when most of the source files referenced by the source-based coverage data
+    /// don't exist.
+    #[structopt(long)]
+    no_path_warning: bool,
+    /// Excludes the source files with fewer than the given number of instrumented lines (e.g.
+    /// files with only re-exports or a simple main) from the outputs and the threshold checks.
+    #[structopt(long, value_name = "N")]
+    min_lines_in_file: Option<usize>,
+    /// Writes a Makefile dependency file to the given path, making the outputs depend on the
+    /// files read by grcov (coverage files, binaries and source files).
+    #[structopt(long, value_name = "PATH")]
+    depfile: Option<PathBuf>,
+    /// Writes the information to attach to bug reports in JSON to the given path: the versions
+    /// of grcov and of the Rust and LLVM tools, the platform, the sizes and modification times of
+    /// the .profraw files and of the binaries, the command line (without the Coveralls token) and
+    /// a summary of the coverage.
+    #[structopt(long, value_name = "PATH")]
+    diagnostics_dump: Option<PathBuf>,
+    /// Writes the versions of grcov, llvm-cov, llvm-profdata and rustc, the target triple of
+    /// rustc and the time of the run in JSON to the given path, to keep with the report.
+    #[structopt(long, value_name = "PATH")]
+    version_file: Option<PathBuf>,
+    /// Lists what would be processed, without parsing the coverage counters or writing any
+    /// outputs: the input files with their type, the binaries passed to llvm-cov, and whether
+    /// each source file found in the lcov reports and in the coverage mapping of the binaries is
+    /// kept, remapped (and to what) or skipped (and why).
+    #[structopt(long)]
+    dry_run: bool,
+    /// Sets the format of the --dry-run report.
+    #[structopt(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        possible_values = &["text", "json"],
+    )]
+    dry_run_format: DryRunFormat,
+    /// Skips the .profraw files with the same content as another one, which is now the default
+    /// (see --dedup-inputs).
+    #[structopt(long)]
+    dedup_profraws: bool,
+    /// Skips the input files of the given kinds with the same content as another one, e.g. the
+    /// same artifacts copied in the directories of several CI jobs: profraw, gcda, info and
+    /// jacoco-xml, separated by commas, or none. The .profraw files of a same run are always
+    /// duplicates (e.g. a run which overwrote another one in a file name including a reused PID),
+    /// while the lcov files are merged as many times as they're given unless 'info' is set, as
+    /// adding up the same report several times can be intended.
+    #[structopt(long, value_name = "KINDS", default_value = "profraw")]
+    dedup_inputs: DedupInputs,
+    /// Keeps running after writing the outputs, and writes them again each time the coverage
+    /// files in the input paths change.
+    #[structopt(long)]
+    watch: bool,
+    /// Waits for the given time without any change of the coverage files before writing the
+    /// outputs again in watch mode.
+    #[structopt(long, value_name = "MILLISECONDS", default_value = "500")]
+    watch_debounce: u64,
+    /// Serves the HTML output over HTTP at the given address (127.0.0.1:8000 by default) after
+    /// writing the outputs, until interrupted. With --watch, the regenerated pages are served.
+    #[structopt(long, value_name = "ADDR:PORT")]
+    serve: Option<Option<String>>,
+    /// Doesn't show the progress bar, the progress is only logged at the INFO level, nor the
+    /// coverage summary printed at the end of the run. The totals of --print-summary are still
+    /// printed when an output is written to stdout.
+    #[structopt(long)]
+    quiet: bool,
+    /// Prints the covered and total lines, branches and functions to stderr once the outputs are
+    /// written, with a ✓ or ✗ for the metrics with a threshold, even with --quiet. It is always
+    /// printed when an output is written to stdout.
+    #[structopt(long)]
+    print_summary: bool,
 }
 
-fn main() {
-    let opt = Opt::from_clap(
-        &Opt::clap()
+/// Combines the coverage reports of several runs labelled with --tag.
+#[derive(StructOpt)]
+#[structopt(name = "grcov aggregate")]
+struct AggregateOpt {
+    /// Comma separated list of the tags of the runs to combine.
+    #[structopt(long, value_name = "TAGS", use_delimiter = true, required = true)]
+    tags: Vec<String>,
+    /// Sets how the runs are combined: with 'union', a line is covered if it is covered in any
+    /// run, and its hit counts are summed up; with 'intersection', it is covered only if it is
+    /// covered in all the runs.
+    #[structopt(
+        long,
+        value_name = "MODE",
+        default_value = "union",
+        possible_values = &["union", "intersection"]
+    )]
+    mode: AggregateMode,
+    /// Specifies the output path of the combined lcov report.
+    #[structopt(short, long, value_name = "PATH")]
+    output_path: Option<PathBuf>,
+    /// Sets the directory where the reports of the tagged runs are stored.
+    #[structopt(long, value_name = "DIRECTORY", default_value = DEFAULT_TAG_CACHE_DIR)]
+    tag_cache_dir: PathBuf,
+}
+
+/// Computes the moving averages and the trends of the coverage stored in a trend file, overall
+/// and for each file.
+#[derive(StructOpt)]
+#[structopt(name = "grcov trend-stats")]
+struct TrendStatsOpt {
+    /// Sets the path of the trend file written with --trend-file.
+    #[structopt(long, value_name = "PATH")]
+    trend_file: PathBuf,
+    /// Sets the number of the most recent runs the statistics are computed from.
+    #[structopt(long, value_name = "N", default_value = "5")]
+    last_n: usize,
+}
+
+/// Compares the coverage of two reports: the lines which aren't covered anymore, the ones which
+/// are covered now, and the instrumented lines added since the first one.
+#[derive(StructOpt)]
+#[structopt(name = "grcov diff")]
+struct DiffOpt {
+    /// Sets the path of the report before the changes, in lcov, JSON or JaCoCo XML.
+    #[structopt(value_name = "BEFORE")]
+    before: PathBuf,
+    /// Sets the path of the report after the changes.
+    #[structopt(value_name = "AFTER")]
+    after: PathBuf,
+    /// Sets the format of the diff.
+    #[structopt(
+        short = "t",
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        possible_values = &["text", "json", "html"]
+    )]
+    output_type: DiffFormat,
+    /// Specifies the output path of the diff, instead of the standard output.
+    #[structopt(short, long, value_name = "PATH")]
+    output_path: Option<PathBuf>,
+    /// Exits with 1 when some lines aren't covered anymore.
+    #[structopt(long)]
+    fail_on_regression: bool,
+}
+
+/// Removes the coverage artifacts of previous runs, so that they don't end up in the next reports.
+#[derive(StructOpt)]
+#[structopt(name = "grcov clean")]
+struct CleanOpt {
+    /// Sets the directories where the artifacts are removed, like the input paths of a normal
+    /// run.
+    #[structopt(required = true)]
+    paths: Vec<PathBuf>,
+    /// Sets the path to the compiled binary, or to its directory, where the artifacts are removed
+    /// too.
+    #[structopt(short, long, value_name = "PATH")]
+    binary_path: Option<PathBuf>,
+    /// Lists the files which would be removed, without removing them.
+    #[structopt(long)]
+    dry_run: bool,
+    /// Keeps the .gcno files, which are only written when building.
+    #[structopt(long)]
+    keep_gcno: bool,
+    /// Keeps the .gcda files.
+    #[structopt(long)]
+    keep_gcda: bool,
+    /// Keeps the .profraw files.
+    #[structopt(long)]
+    keep_profraw: bool,
+    /// Cleans the root directory or the home directory too.
+    #[structopt(long)]
+    force: bool,
+}
+
+/// Runs `cargo test` with source-based coverage, and generates the report of the tests with the
+/// options of a normal run.
+#[derive(StructOpt)]
+#[structopt(name = "grcov test")]
+struct TestOpt {
+    /// Keeps the .profraw files written by the tests, instead of removing them with the other
+    /// temporary files.
+    #[structopt(long)]
+    keep_profraws: bool,
+    /// Still generates the report when the tests fail. The exit code is the one of cargo test
+    /// either way.
+    #[structopt(long)]
+    coverage_on_failure: bool,
+    #[structopt(flatten)]
+    opt: Opt,
+    /// Sets the arguments passed to cargo test, after `--`.
+    #[structopt(last = true)]
+    cargo_args: Vec<OsString>,
+}
+
+fn test_command(args: Vec<OsString>) {
+    let tmp_dir = tempfile::Builder::new()
+        .prefix("grcov-test-")
+        .tempdir()
+        .unwrap_or_else(|e| {
+            eprintln!("Unable to create the temporary directory: {}", e);
+            process::exit(ERROR_EXIT_CODE);
+        });
+    let profraw_dir = tmp_dir.path().join("profraws");
+    let messages_path = tmp_dir.path().join("cargo-messages.json");
+
+    // The profraws of the tests are the input paths, and only the executables built by cargo
+    // are exported.
+    let test_args = vec![
+        OsString::from("grcov test"),
+        profraw_dir.clone().into(),
+        "--cargo".into(),
+        messages_path.clone().into(),
+    ];
+    let matches = match TestOpt::clap()
+        .get_matches_from_safe(test_args.into_iter().chain(args.into_iter().skip(1)))
+    {
+        Ok(matches) => matches,
+        Err(e) => {
+            drop(tmp_dir);
+            exit_on_args_error(e)
+        }
+    };
+    let test_opt = TestOpt::from_clap(&matches);
+
+    let status = fs::create_dir(&profraw_dir)
+        .map_err(|e| format!("Unable to create {}: {}", profraw_dir.display(), e))
+        .and_then(|_| run_cargo_test(&test_opt.cargo_args, &profraw_dir, &messages_path));
+    let cargo_exit_code = match status {
+        Ok(status) if status.success() => 0,
+        Ok(status) => status.code().unwrap_or(ERROR_EXIT_CODE),
+        Err(e) => {
+            eprintln!("{}", e);
+            drop(tmp_dir);
+            process::exit(ERROR_EXIT_CODE);
+        }
+    };
+
+    let report_exit_code = if cargo_exit_code == 0 || test_opt.coverage_on_failure {
+        run(test_opt.opt, &matches)
+    } else {
+        0
+    };
+    if test_opt.keep_profraws {
+        let tmp_path = tmp_dir.into_path();
+        eprintln!(
+            "The profraw files are kept in {}",
+            tmp_path.join("profraws").display()
+        );
+    } else {
+        drop(tmp_dir);
+    }
+
+    let exit_code = if cargo_exit_code != 0 {
+        cargo_exit_code
+    } else {
+        report_exit_code
+    };
+    if exit_code != 0 {
+        process::exit(exit_code);
+    }
+}
+
+fn clean_command(opt: CleanOpt) {
+    let mut paths = opt.paths;
+    paths.extend(opt.binary_path);
+    if !opt.force {
+        for path in paths.iter().filter(|path| path.is_dir()) {
+            if let Err(e) = check_clean_root(path) {
+                eprintln!("{}", e);
+                process::exit(ERROR_EXIT_CODE);
+            }
+        }
+    }
+
+    let kinds: Vec<ArtifactKind> = [
+        (ArtifactKind::Profraw, opt.keep_profraw),
+        (ArtifactKind::Gcda, opt.keep_gcda),
+        (ArtifactKind::Gcno, opt.keep_gcno),
+        (ArtifactKind::Intermediate, false),
+    ]
+    .iter()
+    .filter(|(_, keep)| !keep)
+    .map(|(kind, _)| *kind)
+    .collect();
+    let artifacts = find_artifacts(&paths, &kinds);
+    if opt.dry_run {
+        for artifact in &artifacts {
+            println!("{}", artifact.display());
+        }
+        return;
+    }
+
+    let errors = remove_artifacts(&artifacts);
+    for e in &errors {
+        eprintln!("{}", e);
+    }
+    eprintln!("Removed {} files", artifacts.len() - errors.len());
+    if !errors.is_empty() {
+        process::exit(ERROR_EXIT_CODE);
+    }
+}
+
+fn trend_stats_command(opt: TrendStatsOpt) {
+    let records = match read_trend_file(&opt.trend_file) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(ERROR_EXIT_CODE);
+        }
+    };
+    let report = trend_stats(&records, opt.last_n);
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+}
+
+fn diff_command(opt: DiffOpt) {
+    let read = |path: &Path| {
+        read_coverage_report(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(ERROR_EXIT_CODE);
+        })
+    };
+    let diff = CoverageDiff::new(&read(&opt.before), &read(&opt.after));
+    match &opt.output_path {
+        Some(path) => {
+            if let Err(e) = fs::write(path, diff.format(opt.output_type, false)) {
+                eprintln!("Unable to write the diff to {}: {}", path.display(), e);
+                process::exit(ERROR_EXIT_CODE);
+            }
+        }
+        None => print!(
+            "{}",
+            diff.format(opt.output_type, std::io::stdout().is_terminal())
+        ),
+    }
+    if opt.fail_on_regression && diff.has_regressions() {
+        process::exit(THRESHOLD_FAILURE_EXIT_CODE);
+    }
+}
+
+fn aggregate(opt: AggregateOpt) {
+    let results = match aggregate_tags(&opt.tag_cache_dir, &opt.tags, opt.mode) {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(ERROR_EXIT_CODE);
+        }
+    };
+    let mut results: Vec<ResultTuple> = results
+        .into_iter()
+        .map(|(path, result)| (PathBuf::from(&path), PathBuf::from(path), result))
+        .collect();
+    results.sort_by(|a, b| a.1.cmp(&b.1));
+    output_lcov(&results, opt.output_path.as_deref(), false);
+}
+
+impl Opt {
+    fn app() -> App<'static, 'static> {
+        Opt::clap()
             // This group requires that at least one of --token and --service-job-id
             // be present. --service-job-id requires --service-name, so this
             // effectively means we accept the following combinations:
@@ -278,58 +947,348 @@ fn main() {
                     .args(&["token", "service-job-id"])
                     .multiple(true),
             )
-            .get_matches(),
-    );
+    }
+
+    // Sets the options which weren't given on the command line from the configuration file.
+    fn apply_config(&mut self, config: ConfigFile, matches: &ArgMatches) -> Result<(), String> {
+        fn parse<T: FromStr>(value: String) -> Result<T, String>
+        where
+            T::Err: std::fmt::Display,
+        {
+            value.parse::<T>().map_err(|e| e.to_string())
+        }
 
-    if let Some(path) = opt.llvm_path {
-        LLVM_PATH.set(path).unwrap();
+        macro_rules! set {
+            ($field:ident, |$v:ident| $convert:expr) => {
+                set!($field = $field, |$v| $convert)
+            };
+            ($field:ident = $key:ident, |$v:ident| $convert:expr) => {
+                // The names of the arguments are the names of the fields, in kebab case.
+                if matches.occurrences_of(stringify!($field).replace('_', "-")) == 0 {
+                    if let Some($v) = config.$key {
+                        let value: Result<_, String> = $convert;
+                        self.$field = value.map_err(|e| {
+                            format!(
+                                "Invalid value for '{}' in the configuration file: {}",
+                                stringify!($key).replace('_', "-"),
+                                e
+                            )
+                        })?;
+                    }
+                }
+            };
+        }
+
+        if matches.occurrences_of("output-type") == 0 {
+            set!(output_types, |v| v.into_iter().map(parse).collect());
+        }
+        set!(output_path, |v| Ok(Some(v)));
+        set!(output_config_file, |v| Ok(Some(v)));
+        set!(source_dir, |v| Ok(Some(v)));
+        set!(prefix_dir, |v| Ok(Some(v)));
+        set!(remap_path_prefix = remap_path_prefixes, |v| v
+            .iter()
+            .map(|v| parse_prefix_mapping(v))
+            .collect());
+        set!(strip_filename_prefix = strip_filename_prefixes, |v| Ok(v));
+        set!(auto_strip_prefix, |v| Ok(v));
+        set!(add_filename_prefix, |v| Ok(Some(v)));
+        set!(strip_components, |v| Ok(v));
+        set!(repo_relative, |v| Ok(v));
+        set!(source_snapshot, |v| Ok(Some(v)));
+        set!(binary_path, |v| Ok(Some(v)));
+        set!(llvm_path, |v| Ok(Some(v)));
+        set!(capture_llvm_stderr, |v| Ok(v));
+        set!(llvm_stderr_to_file, |v| Ok(Some(v)));
+        set!(path_mapping, |v| Ok(Some(v)));
+        set!(ignore_not_existing, |v| Ok(v));
+        set!(ignore_missing_inputs, |v| Ok(v));
+        set!(no_grcovignore, |v| Ok(v));
+        set!(strip_system_paths, |v| Ok(v));
+        set!(system_path_prefix = system_path_prefixes, |v| Ok(v));
+        set!(branch, |v| Ok(v));
+        set!(mcdc, |v| Ok(v));
+        set!(wasm, |v| Ok(v));
+        set!(export_cache_dir, |v| Ok(Some(v)));
+        set!(no_export_cache, |v| Ok(v));
+        set!(in_memory_profdata, |v| Ok(v));
+        set!(fail_on_binary_mismatch, |v| Ok(v));
+        set!(filter, |v| parse(v).map(Some));
+        set!(llvm, |v| Ok(v));
+        set!(cache_dir, |v| Ok(Some(v)));
+        set!(no_cache, |v| Ok(v));
+        set!(token, |v| Ok(Some(v)));
+        set!(commit_sha, |v| Ok(Some(v)));
+        set!(service_name, |v| Ok(Some(v)));
+        set!(service_number, |v| Ok(Some(v)));
+        set!(service_job_id, |v| Ok(Some(v)));
+        set!(service_pull_request, |v| Ok(Some(v)));
+        set!(parallel, |v| Ok(v));
+        set!(threads, |v| Ok(Some(v)));
+        set!(precision, |v| Ok(v));
+        set!(output_encoding, |v| parse(v));
+        set!(show_line_counts_or_regions, |v| Ok(v));
+        set!(show_expansions, |v| Ok(v));
+        set!(guess_directory = guess_directory_when_missing, |v| Ok(v));
+        set!(vcs_branch, |v| Ok(v));
+        set!(log, |v| Ok(v));
+        set!(log_level, |v| parse(v));
+        set!(log_format, |v| parse(v));
+        set!(excl_line, |v| parse(v).map(Some));
+        set!(excl_start, |v| parse(v).map(Some));
+        set!(excl_stop, |v| parse(v).map(Some));
+        set!(excl_br_line, |v| parse(v).map(Some));
+        set!(excl_br_start, |v| parse(v).map(Some));
+        set!(excl_br_stop, |v| parse(v).map(Some));
+        set!(minimum_line_length, |v| Ok(v));
+        set!(exclude_unreachable, |v| Ok(v));
+        set!(include_fn, |v| parse(v).map(Some));
+        set!(exclude_fn, |v| parse(v).map(Some));
+        set!(exclude_synthesized_fn, |v| Ok(v));
+        set!(excl_generated, |v| parse(v).map(|v| Some(Some(v))));
+        set!(no_demangle, |v| Ok(v));
+        set!(stage_profraws, |v| Ok(v));
+        set!(sort_lcov, |v| Ok(v));
+        set!(skip_unprofiled_binaries, |v| Ok(v));
+        set!(cargo_messages, |v| Ok(Some(v)));
+        set!(cargo, |v| Ok(Some(v)));
+        set!(baseline_lcov, |v| Ok(v));
+        set!(trend_file, |v| Ok(Some(v)));
+        set!(tag, |v| Ok(Some(v)));
+        set!(tag_cache_dir, |v| Ok(v));
+        set!(fail_under_line, |v| check_percentage(v).map(Some));
+        set!(fail_under_branch, |v| check_percentage(v).map(Some));
+        set!(fail_under_function, |v| check_percentage(v).map(Some));
+        set!(no_path_warning, |v| Ok(v));
+        set!(fail_empty_files, |v| Ok(v));
+        set!(threshold_report, |v| Ok(Some(v)));
+        set!(report_ignored, |v| Ok(Some(v)));
+        set!(source_index, |v| Ok(Some(v)));
+        set!(max_memory, |v| Ok(Some(v)));
+        set!(min_lines_in_file, |v| Ok(Some(v)));
+        set!(depfile, |v| Ok(Some(v)));
+        set!(diagnostics_dump, |v| Ok(Some(v)));
+        set!(version_file, |v| Ok(Some(v)));
+        set!(dry_run, |v| Ok(v));
+        set!(dry_run_format, |v| parse(v));
+        set!(dedup_profraws, |v| Ok(v));
+        set!(dedup_inputs, |v| parse(v));
+        set!(watch, |v| Ok(v));
+        set!(watch_debounce, |v| Ok(v));
+        set!(serve, |v| Ok(Some(Some(v))));
+        set!(quiet, |v| Ok(v));
+        set!(print_summary, |v| Ok(v));
+
+        // The globs from the command line are added to the ones from the file.
+        if let Some(mut ignore_dir) = config.ignore {
+            ignore_dir.append(&mut self.ignore_dir);
+            self.ignore_dir = ignore_dir;
+        }
+        if let Some(mut keep_dir) = config.keep_only {
+            keep_dir.append(&mut self.keep_dir);
+            self.keep_dir = keep_dir;
+        }
+        if let Some(mut unreachable_macro) = config.unreachable_macro {
+            unreachable_macro.append(&mut self.unreachable_macro);
+            self.unreachable_macro = unreachable_macro;
+        }
+        if let Some(mut skip_dir) = config.skip_dir {
+            skip_dir.append(&mut self.skip_dir);
+            self.skip_dir = skip_dir;
+        }
+        if let Some(fail_under) = config.fail_under {
+            let mut rules = fail_under
+                .iter()
+                .map(|rule| rule.parse())
+                .collect::<Result<Vec<FileThreshold>, _>>()
+                .map_err(|e| {
+                    format!(
+                        "Invalid value for 'fail-under' in the configuration file: {}",
+                        e
+                    )
+                })?;
+            rules.append(&mut self.fail_under);
+            self.fail_under = rules;
+        }
+        if let Some(merge_mode) = config.merge_mode {
+            let mut rules = merge_mode
+                .iter()
+                .map(|rule| rule.parse())
+                .collect::<Result<Vec<MergeRule>, _>>()
+                .map_err(|e| {
+                    format!(
+                        "Invalid value for 'merge-mode' in the configuration file: {}",
+                        e
+                    )
+                })?;
+            rules.append(&mut self.merge_mode);
+            self.merge_mode = rules;
+        }
+
+        Ok(())
+    }
+}
+
+// Like clap::Error::exit, but with the exit code used for errors, as 1 means that the coverage
+// is below the thresholds.
+fn exit_on_args_error(e: clap::Error) -> ! {
+    if e.use_stderr() {
+        eprintln!("{}", e.message);
+        process::exit(ERROR_EXIT_CODE);
+    }
+    e.exit()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Subcommand {
+    Aggregate,
+    Clean,
+    Diff,
+    Test,
+    TrendStats,
+}
+
+// The subcommand selected by the first argument, if any, and the arguments to parse, starting
+// with the name of the command. A report of an input path with the name of a subcommand, e.g. a
+// `test` directory, is generated with `--` first: `grcov -- test`.
+fn parse_subcommand(mut args: Vec<OsString>) -> (Option<Subcommand>, Vec<OsString>) {
+    let subcommand = match args.get(1).and_then(|arg| arg.to_str()) {
+        Some("aggregate") => Subcommand::Aggregate,
+        Some("clean") => Subcommand::Clean,
+        Some("diff") => Subcommand::Diff,
+        Some("test") => Subcommand::Test,
+        Some("trend-stats") => Subcommand::TrendStats,
+        Some("--") => {
+            args.remove(1);
+            return (None, args);
+        }
+        _ => return (None, args),
+    };
+    let name = format!("grcov {}", args[1].to_string_lossy());
+    args.splice(..2, std::iter::once(OsString::from(name)));
+    (Some(subcommand), args)
+}
+
+fn main() {
+    let (subcommand, args) = parse_subcommand(std::env::args_os().collect());
+    match subcommand {
+        Some(Subcommand::Aggregate) => {
+            return aggregate(
+                AggregateOpt::from_iter_safe(args).unwrap_or_else(|e| exit_on_args_error(e)),
+            )
+        }
+        Some(Subcommand::Clean) => {
+            return clean_command(
+                CleanOpt::from_iter_safe(args).unwrap_or_else(|e| exit_on_args_error(e)),
+            )
+        }
+        Some(Subcommand::Diff) => {
+            return diff_command(
+                DiffOpt::from_iter_safe(args).unwrap_or_else(|e| exit_on_args_error(e)),
+            )
+        }
+        Some(Subcommand::Test) => return test_command(args),
+        Some(Subcommand::TrendStats) => {
+            return trend_stats_command(
+                TrendStatsOpt::from_iter_safe(args).unwrap_or_else(|e| exit_on_args_error(e)),
+            )
+        }
+        None => {}
+    }
+
+    let matches = Opt::app()
+        .get_matches_from_safe(args)
+        .unwrap_or_else(|e| exit_on_args_error(e));
+    let opt = Opt::from_clap(&matches);
+    let exit_code = run(opt, &matches);
+    if exit_code != 0 {
+        process::exit(exit_code);
+    }
+}
+
+// Generates the report, and keeps running in watch and serve modes. Returns the exit code.
+fn run(mut opt: Opt, matches: &ArgMatches) -> i32 {
+    let config = ConfigFile::load(opt.config.as_deref())
+        .map_err(|e| e.to_string())
+        .and_then(|config| match config {
+            Some(config) => opt.apply_config(config, matches),
+            None => Ok(()),
+        });
+    if let Err(e) = config {
+        eprintln!("{}", e);
+        return ERROR_EXIT_CODE;
+    }
+
+    match expand_input_paths(
+        &opt.paths,
+        opt.input_list.as_deref(),
+        opt.ignore_missing_inputs,
+    ) {
+        Ok(paths) => opt.paths = paths,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ERROR_EXIT_CODE;
+        }
+    }
+
+    let tag_report_path = match opt
+        .tag
+        .as_deref()
+        .map(|tag| tag_report_path(&opt.tag_cache_dir, tag))
+    {
+        Some(Ok(path)) => Some(path),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return ERROR_EXIT_CODE;
+        }
+        None => None,
+    };
+
+    if let Some(path) = &opt.llvm_path {
+        LLVM_PATH.set(path.clone()).unwrap();
+    }
+    LLVM_STDERR
+        .set(StderrConfig::new(
+            opt.capture_llvm_stderr,
+            opt.llvm_stderr_to_file.clone(),
+        ))
+        .unwrap();
+    match skip_dirs_globset(&opt.skip_dir) {
+        Ok(skip_dirs) => SKIP_DIRS.set(skip_dirs).unwrap(),
+        Err(e) => {
+            eprintln!("{}", e);
+            return ERROR_EXIT_CODE;
+        }
     }
 
-    let filter_option = opt.filter.map(|filter| match filter {
-        Filter::Covered => true,
-        Filter::Uncovered => false,
-    });
     let stdout = Path::new("stdout");
     let stderr = Path::new("stderr");
 
-    if opt.log == stdout {
-        let _ = TermLogger::init(
-            opt.log_level,
-            Config::default(),
-            TerminalMode::Stdout,
-            ColorChoice::Auto,
-        );
+    let mut log_file_error = false;
+    let logger = if opt.log == stdout {
+        term_logger(&opt, TerminalMode::Stdout)
     } else if opt.log == stderr {
-        let _ = TermLogger::init(
-            opt.log_level,
-            Config::default(),
-            TerminalMode::Stderr,
-            ColorChoice::Auto,
-        );
+        term_logger(&opt, TerminalMode::Stderr)
     } else if let Ok(file) = File::create(&opt.log) {
-        let _ = WriteLogger::init(opt.log_level, Config::default(), file);
+        match opt.log_format {
+            LogFormat::Text => WriteLogger::new(opt.log_level, Config::default(), file).as_log(),
+            LogFormat::Json => Box::new(JsonLogger::new(opt.log_level, Box::new(file))),
+        }
     } else {
-        let _ = TermLogger::init(
-            opt.log_level,
-            Config::default(),
-            TerminalMode::Stderr,
-            ColorChoice::Auto,
-        );
+        log_file_error = true;
+        term_logger(&opt, TerminalMode::Stderr)
+    };
+    // The messages are logged through the progress bar, so that they aren't drawn over.
+    if log::set_boxed_logger(Box::new(ProgressLogger::new(logger))).is_ok() {
+        log::set_max_level(opt.log_level);
+    }
+    if log_file_error {
         error!(
             "Unable to create log file: {}. Switch to stderr",
             opt.log.display()
         );
     }
-
-    let file_filter = FileFilter::new(
-        opt.excl_line,
-        opt.excl_start,
-        opt.excl_stop,
-        opt.excl_br_line,
-        opt.excl_br_start,
-        opt.excl_br_stop,
-    );
-    let demangle = !opt.no_demangle;
+    let _ = PROGRESS.set(Progress::new(ProgressMode::detect(opt.quiet)));
 
     panic::set_hook(Box::new(|panic_info| {
         let (filename, line) = panic_info
@@ -350,13 +1309,301 @@ fn main() {
         error!("A panic occurred at {}:{}: {}", filename, line, cause);
     }));
 
-    let num_threads: usize = opt.threads.unwrap_or_else(|| 1.max(num_cpus::get() - 1));
-    let source_root = opt
-        .source_dir
-        .filter(|source_dir| source_dir != Path::new(""))
-        .map(|source_dir| canonicalize_path(source_dir).expect("Source directory does not exist."));
+    if opt.dry_run {
+        return match dry_run(&opt) {
+            Ok(dry_run) => {
+                print!("{}", dry_run.format(opt.dry_run_format));
+                0
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                ERROR_EXIT_CODE
+            }
+        };
+    }
 
-    let prefix_dir = opt.prefix_dir.or_else(|| source_root.clone());
+    let outputs = resolve_outputs(&opt);
+    if opt.depfile.is_some() && outputs.iter().any(|(_, output_path)| output_path.is_none()) {
+        eprintln!("--depfile requires the outputs to be written to files, with --output-path");
+        return ERROR_EXIT_CODE;
+    }
+    if opt.max_memory.is_some() {
+        if outputs
+            .iter()
+            .any(|(output_type, _)| !output_type.is_streamed())
+        {
+            eprintln!("--max-memory only supports the lcov, covdir and html output types");
+            return ERROR_EXIT_CODE;
+        }
+        if opt.auto_strip_prefix {
+            eprintln!("--max-memory can't be used with --auto-strip-prefix");
+            return ERROR_EXIT_CODE;
+        }
+    }
+    let server = opt.serve.as_ref().map(|address| {
+        start_server(
+            address.as_deref().unwrap_or(DEFAULT_SERVE_ADDRESS),
+            &outputs,
+        )
+    });
+
+    let print_summary = prints_summary(&opt, &outputs);
+
+    // In watch and serve modes, grcov keeps running whatever the result of the first run.
+    let keep_running = opt.watch || server.is_some();
+    match generate_report(&opt, &outputs, tag_report_path.as_deref()) {
+        Ok(summary) => {
+            if !opt.quiet {
+                eprintln!("{}", summary.coverage);
+            }
+            if print_summary {
+                eprintln!("{}", summary.coverage.totals(&summary.threshold_checks));
+            }
+            if let Some(violations) = summary.threshold_violations {
+                eprintln!("{}", violations);
+                if !keep_running {
+                    return THRESHOLD_FAILURE_EXIT_CODE;
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            if !opt.watch {
+                return ERROR_EXIT_CODE;
+            }
+        }
+    }
+    if !keep_running {
+        return 0;
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let stop = Arc::clone(&stop);
+        ctrlc::set_handler(move || stop.store(true, Ordering::SeqCst))
+            .expect("Failed to set the Ctrl-C handler");
+    }
+    if opt.watch {
+        let server = server.map(|server| {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || server.run(&stop))
+        });
+        watch(&opt, &outputs, tag_report_path.as_deref(), &stop);
+        if let Some(server) = server {
+            server.join().unwrap();
+        }
+    } else if let Some(server) = server {
+        server.run(&stop);
+    }
+    0
+}
+
+fn term_logger(opt: &Opt, mode: TerminalMode) -> Box<dyn Log> {
+    match opt.log_format {
+        LogFormat::Text => {
+            TermLogger::new(opt.log_level, Config::default(), mode, ColorChoice::Auto).as_log()
+        }
+        LogFormat::Json => {
+            let writer: Box<dyn Write + Send> = match mode {
+                TerminalMode::Stdout => Box::new(io::stdout()),
+                _ => Box::new(io::stderr()),
+            };
+            Box::new(JsonLogger::new(opt.log_level, writer))
+        }
+    }
+}
+
+struct ReportSummary {
+    coverage: CoverageSummary,
+    threshold_checks: Vec<ThresholdCheck>,
+    threshold_violations: Option<String>,
+}
+
+// The outputs along with the paths they are written to. The paths are resolved once, so that the
+// outputs are written to the same paths when they are generated again in watch mode.
+// Whether the totals of --print-summary are printed. The outputs written to stdout leave nothing
+// else to see the coverage in, so the totals are always printed along them, even with --quiet.
+fn prints_summary(opt: &Opt, outputs: &[(&OutputType, Option<PathBuf>)]) -> bool {
+    opt.print_summary || outputs.iter().any(|(_, output_path)| output_path.is_none())
+}
+
+fn resolve_outputs(opt: &Opt) -> Vec<(&OutputType, Option<PathBuf>)> {
+    let output_types = match &opt.output_type {
+        Some(output_type) => vec![output_type],
+        None => opt.output_types.iter().collect(),
+    };
+
+    let output_path = match output_types.len() {
+        0 => None,
+        1 => opt.output_path.as_deref(),
+        _ => match opt.output_path.as_deref() {
+            Some(output_path) => {
+                if output_path.is_dir() {
+                    Some(output_path)
+                } else {
+                    panic!("output_path must be a directory when using multiple outputs");
+                }
+            }
+            _ => None,
+        },
+    };
+
+    output_types
+        .into_iter()
+        .map(|output_type| (output_type, output_type.to_file_name(output_path)))
+        .collect()
+}
+
+// The canonical source directory, if it is given.
+fn source_root(opt: &Opt) -> Option<PathBuf> {
+    opt.source_dir
+        .as_ref()
+        .filter(|source_dir| source_dir.as_path() != Path::new(""))
+        .map(|source_dir| canonicalize_path(source_dir).expect("Source directory does not exist."))
+}
+
+// The directories whose files are dropped with --strip-system-paths.
+fn system_paths(opt: &Opt) -> Vec<PathBuf> {
+    if !opt.strip_system_paths {
+        Vec::new()
+    } else if opt.system_path_prefix.is_empty() {
+        default_system_paths()
+    } else {
+        opt.system_path_prefix
+            .iter()
+            .map(|path| expand_home_dir(path))
+            .collect()
+    }
+}
+
+// Lists what would be processed, for --dry-run, without parsing the coverage counters.
+fn dry_run(opt: &Opt) -> Result<DryRun, String> {
+    // The binaries are walked along with the inputs, they are only used if there are profiles.
+    let (discovery, walked_binaries) = rayon::join(
+        || {
+            if opt.paths.is_empty() {
+                Discovery::default()
+            } else {
+                discover_inputs(&opt.paths, opt.llvm)
+            }
+        },
+        || match &opt.binary_path {
+            Some(binary_path) => walk_binary_path(binary_path, opt.wasm),
+            None => Vec::new(),
+        },
+    );
+
+    // The binaries are only exported along with the profiles.
+    let has_profiles = discovery
+        .inputs
+        .iter()
+        .any(|input| matches!(input.kind, InputKind::Profraw | InputKind::Profdata));
+    let (binaries, binaries_skipped) = if has_profiles {
+        let allowed_binaries = match &opt.cargo_messages {
+            Some(cargo_messages) => Some(read_cargo_executables(cargo_messages)?),
+            None => None,
+        };
+        let cargo_executables = match &opt.cargo {
+            Some(cargo) => discover_cargo_executables(cargo)?,
+            None => Vec::new(),
+        };
+        select_binaries(
+            walked_binaries,
+            &cargo_executables,
+            allowed_binaries.as_deref(),
+        )
+    } else {
+        (Vec::new(), 0)
+    };
+
+    let mut source_paths = discovery.info_source_paths;
+    source_paths.extend(scan_binary_source_paths(&binaries));
+    if opt.raw_counters_file.is_some() {
+        source_paths.extend(scan_binary_source_paths(opt.binary_path.as_slice()));
+    }
+    source_paths.sort();
+    source_paths.dedup();
+
+    let path_mapping =
+        match &opt.path_mapping {
+            Some(path) => Some(fs::read(path).map_err(|e| {
+                format!("Unable to read the path mapping {}: {}", path.display(), e)
+            })?),
+            None => discovery.path_mapping,
+        };
+    let path_mapping = path_mapping
+        .map(|path_mapping| serde_json::from_slice(&path_mapping))
+        .transpose()
+        .map_err(|e| format!("Invalid path mapping: {}", e))?;
+    let source_root = source_root(opt);
+    let prefix_dir = opt.prefix_dir.clone().or_else(|| source_root.clone());
+    let ignore_dirs: Vec<_> = opt.ignore_dir.iter().map(String::as_str).collect();
+    let keep_dirs: Vec<_> = opt.keep_dir.iter().map(String::as_str).collect();
+    let paths = explain_paths(
+        source_paths,
+        &RewriteOptions {
+            path_mapping,
+            prefix_mapping: &opt.remap_path_prefix,
+            source_dir: source_root.as_deref(),
+            prefix_dir: prefix_dir.as_deref(),
+            strip_components: opt.strip_components,
+            ignore_not_existing: opt.ignore_not_existing,
+            to_ignore_dirs: &ignore_dirs,
+            to_keep_dirs: &keep_dirs,
+            use_ignore_files: !opt.no_grcovignore,
+            system_paths: &system_paths(opt),
+            repo_relative: opt.repo_relative,
+            ..Default::default()
+        },
+    );
+
+    Ok(DryRun {
+        inputs: discovery.inputs,
+        binaries,
+        binaries_skipped,
+        paths,
+    })
+}
+
+// Parses the coverage files and writes all the outputs, from scratch.
+fn generate_report(
+    opt: &Opt,
+    outputs: &[(&OutputType, Option<PathBuf>)],
+    tag_report_path: Option<&Path>,
+) -> Result<ReportSummary, String> {
+    let _progress = start_progress();
+    // The versions are collected first, for the timestamp to be the start of the run.
+    let version_info = opt.version_file.as_ref().map(|_| VersionInfo::collect());
+    let filter_option = opt.filter.as_ref().map(|filter| match filter {
+        Filter::Covered => true,
+        Filter::Uncovered => false,
+    });
+    let file_filter = FileFilter::new(
+        opt.excl_line.clone(),
+        opt.excl_start.clone(),
+        opt.excl_stop.clone(),
+        opt.excl_br_line.clone(),
+        opt.excl_br_start.clone(),
+        opt.excl_br_stop.clone(),
+        opt.minimum_line_length,
+        opt.exclude_unreachable
+            .then(|| unreachable_regex(&opt.unreachable_macro)),
+    );
+    let demangle = !opt.no_demangle;
+
+    let num_threads = thread_count(opt.threads, num_cpus::get(), cgroup_cpu_quota());
+    // There is no point in more consumers than files, e.g. for a few lcov reports.
+    let num_consumers = consumer_count(num_threads, count_input_files(&opt.paths, num_threads));
+    let source_root = source_root(opt);
+
+    // The paths are expected not to exist when they are going to be rewritten.
+    let path_warning = !opt.no_path_warning
+        && opt.prefix_dir.is_none()
+        && opt.remap_path_prefix.is_empty()
+        && opt.path_mapping.is_none()
+        && opt.strip_components == 0;
+
+    let prefix_dir = opt.prefix_dir.clone().or_else(|| source_root.clone());
 
     let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
     let tmp_path = tmp_dir.path().to_owned();
@@ -365,27 +1612,50 @@ fn main() {
     let result_map: Arc<SyncCovResultMap> = Arc::new(Mutex::new(
         FxHashMap::with_capacity_and_hasher(20_000, Default::default()),
     ));
-    let (sender, receiver) = bounded(2 * num_threads);
+    let budget = opt.max_memory.map(|max_memory| max_memory << 20);
+    let merge_spill = match budget {
+        Some(budget) => Some(Arc::new(MergeSpill::new(budget).map_err(|e| {
+            format!(
+                "Unable to create a temporary directory for the results: {}",
+                e
+            )
+        })?)),
+        None => None,
+    };
+    let (sender, receiver) = bounded(2 * num_consumers);
     let path_mapping: Arc<Mutex<Option<Value>>> = Arc::new(Mutex::new(None));
 
+    let input_counts = Arc::new(InputCounts::default());
     let producer = {
         let sender: JobSender = sender.clone();
         let tmp_path = tmp_path.clone();
-        let path_mapping_file = opt.path_mapping;
+        let path_mapping_file = opt.path_mapping.clone();
         let path_mapping = Arc::clone(&path_mapping);
-        let paths = opt.paths;
+        let paths = opt.paths.clone();
         let is_llvm = opt.llvm;
+        let mut dedup_inputs = opt.dedup_inputs.clone();
+        if opt.dedup_profraws {
+            dedup_inputs.add(InputKind::Profraw);
+        }
+        let input_counts = Arc::clone(&input_counts);
 
         thread::Builder::new()
             .name(String::from("Producer"))
             .spawn(move || {
-                let producer_path_mapping_buf = producer(
-                    &tmp_path,
-                    &paths,
-                    &sender,
-                    filter_option.is_some() && filter_option.unwrap(),
-                    is_llvm,
-                );
+                // There are no input paths when only raw counters are used.
+                let producer_path_mapping_buf = if paths.is_empty() {
+                    None
+                } else {
+                    producer(
+                        &tmp_path,
+                        &paths,
+                        &sender,
+                        filter_option.is_some() && filter_option.unwrap(),
+                        is_llvm,
+                        &dedup_inputs,
+                        &input_counts,
+                    )
+                };
 
                 let mut path_mapping = path_mapping.lock().unwrap();
                 *path_mapping = if let Some(path) = path_mapping_file {
@@ -400,16 +1670,52 @@ fn main() {
             .unwrap()
     };
 
+    let allowed_binaries = match &opt.cargo_messages {
+        Some(cargo_messages) => Some(Arc::new(read_cargo_executables(cargo_messages)?)),
+        None => None,
+    };
+
+    let cargo_executables = Arc::new(match &opt.cargo {
+        Some(cargo) => discover_cargo_executables(cargo)?,
+        None => Vec::new(),
+    });
+
+    let source_index = opt
+        .source_index
+        .as_ref()
+        .map(|_| Arc::new(SourceIndex::default()));
+    let merge_rules = Arc::new(opt.merge_mode.clone());
     let mut parsers = Vec::new();
 
-    for i in 0..num_threads {
+    for i in 0..num_consumers {
         let receiver = receiver.clone();
         let result_map = Arc::clone(&result_map);
         let working_dir = tmp_path.join(format!("{}", i));
         let source_root = source_root.clone();
         let binary_path = opt.binary_path.clone();
         let branch_enabled = opt.branch;
+        let mcdc_enabled = opt.mcdc;
+        let wasm = opt.wasm;
+        let export_cache_dir = opt
+            .export_cache_dir
+            .clone()
+            .filter(|_| !opt.no_export_cache);
+        let gcno_cache = opt
+            .cache_dir
+            .as_deref()
+            .filter(|_| !opt.no_cache)
+            .map(GcnoCache::new);
+        let in_memory_profdata = opt.in_memory_profdata;
+        let fail_on_binary_mismatch = opt.fail_on_binary_mismatch;
         let guess_directory = opt.guess_directory;
+        let stage_profraws = opt.stage_profraws;
+        let skip_unprofiled_binaries = opt.skip_unprofiled_binaries;
+        let allowed_binaries = allowed_binaries.clone();
+        let cargo_executables = Arc::clone(&cargo_executables);
+        let input_counts = Arc::clone(&input_counts);
+        let source_index = source_index.clone();
+        let merge_rules = Arc::clone(&merge_rules);
+        let merge_spill = merge_spill.clone();
 
         let t = thread::Builder::new()
             .name(format!("Consumer {}", i))
@@ -417,12 +1723,29 @@ fn main() {
                 fs::create_dir(&working_dir).expect("Failed to create working directory");
                 consumer(
                     &working_dir,
-                    source_root.as_deref(),
                     &result_map,
                     receiver,
-                    branch_enabled,
-                    guess_directory,
-                    binary_path.as_deref(),
+                    &input_counts,
+                    &ConsumerOptions {
+                        source_dir: source_root.as_deref(),
+                        branch_enabled,
+                        mcdc_enabled,
+                        guess_directory,
+                        binary_path: binary_path.as_deref(),
+                        cargo_executables: &cargo_executables,
+                        stage_profraws,
+                        path_warning,
+                        skip_unprofiled_binaries,
+                        allowed_binaries: allowed_binaries.as_deref().map(Vec::as_slice),
+                        wasm,
+                        export_cache_dir: export_cache_dir.as_deref(),
+                        gcno_cache: gcno_cache.as_ref(),
+                        in_memory_profdata,
+                        fail_on_binary_mismatch,
+                        source_index: source_index.as_deref(),
+                        merge_rules: &merge_rules,
+                        merge_spill: merge_spill.as_deref(),
+                    },
                 );
             })
             .unwrap();
@@ -430,18 +1753,114 @@ fn main() {
         parsers.push(t);
     }
 
-    if producer.join().is_err() {
-        process::exit(1);
-    }
+    let producer_failed = producer.join().is_err();
+    report_phase(Phase::Parsing);
 
     // Poison the receiver, now that the producer is finished.
-    for _ in 0..num_threads {
+    for _ in 0..num_consumers {
         sender.send(None).unwrap();
     }
 
+    let mut consumer_failed = false;
     for parser in parsers {
-        if parser.join().is_err() {
-            process::exit(1);
+        consumer_failed |= parser.join().is_err();
+    }
+    if producer_failed || consumer_failed {
+        return Err("Failed to parse the coverage files".to_string());
+    }
+    if input_counts.mismatched_profiles() > 0 {
+        return Err("The profiles don't match the binaries".to_string());
+    }
+    if let (Some(path), Some(source_index)) = (&opt.source_index, &source_index) {
+        source_index.write(path)?;
+    }
+    report_phase(Phase::Merging);
+
+    if let Some(raw_counters_file) = &opt.raw_counters_file {
+        let binary_path = opt.binary_path.as_ref().unwrap();
+        let branch_enabled = opt.branch;
+        let results = fs::read(raw_counters_file)
+            .map_err(RawCoverageError::from)
+            .and_then(|buffer| parse_raw_counters(&buffer))
+            .and_then(|raw_counters| {
+                let binary = fs::read(binary_path)?;
+                raw_counters_to_results(&binary, &raw_counters, branch_enabled)
+            });
+        match results {
+            Ok(results) => {
+                let added = add_results(
+                    results,
+                    &result_map,
+                    source_root.as_deref(),
+                    &opt.merge_mode,
+                );
+                spill_merged(merge_spill.as_deref(), &result_map, added)?;
+            }
+            Err(e) => {
+                return Err(format!(
+                    "Error while mapping raw counters from {} to {}: {}",
+                    raw_counters_file.display(),
+                    binary_path.display(),
+                    e
+                ));
+            }
+        }
+    }
+
+    let branch_enabled = opt.branch;
+    if let Some(llvm_cov_json) = &opt.llvm_cov_json {
+        let results = fs::read(llvm_cov_json)
+            .map_err(|e| e.to_string())
+            .and_then(|buffer| {
+                parse_llvm_cov_json_with_kind(
+                    &buffer,
+                    branch_enabled,
+                    opt.mcdc,
+                    opt.llvm_cov_json_lines,
+                )
+                .map_err(|e| e.to_string())
+            });
+        match results {
+            Ok(results) => {
+                let added = add_results(
+                    results,
+                    &result_map,
+                    source_root.as_deref(),
+                    &opt.merge_mode,
+                );
+                spill_merged(merge_spill.as_deref(), &result_map, added)?;
+            }
+            Err(e) => {
+                return Err(format!(
+                    "Error while reading the JSON coverage report {}: {}",
+                    llvm_cov_json.display(),
+                    e
+                ));
+            }
+        }
+    }
+
+    for baseline_lcov in &opt.baseline_lcov {
+        let results = fs::read(baseline_lcov)
+            .map_err(|e| e.to_string())
+            .and_then(|buffer| parse_lcov(&buffer, branch_enabled).map_err(|e| e.to_string()));
+        match results {
+            Ok(results) => {
+                let added = add_results(
+                    baseline_results(results),
+                    &result_map,
+                    source_root.as_deref(),
+                    &opt.merge_mode,
+                );
+                spill_merged(merge_spill.as_deref(), &result_map, added)?;
+            }
+            Err(e) => {
+                return Err(format!(
+                    "Error while reading the baseline {}: {}",
+                    baseline_lcov.display(),
+                    e
+                ));
+            }
         }
     }
 
@@ -451,50 +1870,172 @@ fn main() {
     let path_mapping_mutex = Arc::try_unwrap(path_mapping).unwrap();
     let path_mapping = path_mapping_mutex.into_inner().unwrap();
 
-    let iterator = rewrite_paths(
-        result_map,
+    // With --max-memory, the merged results are read back in chunks of about the budget, each one
+    // rewritten and filtered on its own.
+    let chunks: Box<dyn Iterator<Item = CovResultMap>> = match merge_spill {
+        Some(merge_spill) => Box::new(
+            Arc::into_inner(merge_spill)
+                .unwrap()
+                .into_chunks(result_map, &opt.merge_mode)
+                .map_err(|e| format!("Unable to read the spilled results: {}", e))?,
+        ),
+        None => Box::new(std::iter::once(result_map)),
+    };
+
+    let system_paths = system_paths(opt);
+    let ignore_dirs: Vec<_> = opt.ignore_dir.iter().map(String::as_str).collect();
+    let keep_dirs: Vec<_> = opt.keep_dir.iter().map(String::as_str).collect();
+    let rewrite_options = RewriteOptions {
         path_mapping,
-        source_root.as_deref(),
-        prefix_dir.as_deref(),
-        opt.ignore_not_existing,
-        &opt.ignore_dir,
-        &opt.keep_dir,
+        prefix_mapping: &opt.remap_path_prefix,
+        source_dir: source_root.as_deref(),
+        prefix_dir: prefix_dir.as_deref(),
+        strip_components: opt.strip_components,
+        ignore_not_existing: opt.ignore_not_existing,
+        to_ignore_dirs: &ignore_dirs,
+        to_keep_dirs: &keep_dirs,
+        use_ignore_files: !opt.no_grcovignore,
+        system_paths: &system_paths,
+        repo_relative: opt.repo_relative,
         filter_option,
         file_filter,
+    };
+    let function_filter = FunctionFilter::new(
+        opt.include_fn.clone(),
+        opt.exclude_fn.clone(),
+        opt.exclude_synthesized_fn,
     );
+    let generated_marker = opt.excl_generated.as_ref().map(|marker| {
+        marker
+            .clone()
+            .unwrap_or_else(|| Regex::new(DEFAULT_GENERATED_MARKER).unwrap())
+    });
+    let mut ignored = IgnoredFiles::default();
+    let mut results = StoredResults::new(budget)
+        .map_err(|e| format!("Unable to store the results in a temporary file: {}", e))?;
 
-    let service_number = opt.service_number.unwrap_or_default();
-    let service_pull_request = opt.service_pull_request.unwrap_or_default();
-    let commit_sha = opt.commit_sha.unwrap_or_default();
+    for result_map in chunks {
+        let (iterator, skipped) = rewrite_paths_with_skipped(result_map, &rewrite_options);
+        let iterator = match &opt.source_snapshot {
+            Some(source_snapshot) => remap_to_snapshot(iterator, source_snapshot),
+            None => iterator,
+        };
+        let iterator = strip_filename_prefixes(iterator, &opt.strip_filename_prefix);
+        let iterator = if opt.auto_strip_prefix {
+            strip_common_prefix(iterator)
+        } else {
+            iterator
+        };
+        let iterator = add_filename_prefix(iterator, opt.add_filename_prefix.as_deref());
+        ignored.add_skipped(&skipped);
+        let iterator = if function_filter.is_empty() {
+            iterator
+        } else {
+            ignored.track(
+                iterator,
+                |iterator| filter_functions(iterator, &function_filter),
+                |_| IgnoredReason::ExclFn,
+            )
+        };
+        let iterator = match &generated_marker {
+            Some(marker) => ignored.track(
+                iterator,
+                |iterator| filter_generated_files(iterator, marker),
+                |_| IgnoredReason::ExclFile,
+            ),
+            None => iterator,
+        };
+        let iterator = match opt.min_lines_in_file {
+            Some(min_lines) => ignored.track(
+                iterator,
+                |iterator| filter_min_lines_in_file(iterator, min_lines),
+                |result| {
+                    if result.lines.is_empty() {
+                        IgnoredReason::ZeroLines
+                    } else {
+                        IgnoredReason::MinLinesFilter
+                    }
+                },
+            ),
+            None => iterator,
+        };
+        results
+            .extend(iterator)
+            .map_err(|e| format!("Unable to store the results in a temporary file: {}", e))?;
+    }
+    if let Some(report_ignored) = &opt.report_ignored {
+        ignored.write(report_ignored)?;
+    }
+    if opt.sort_lcov {
+        results.sort();
+    }
 
-    let output_types = match opt.output_type {
-        Some(output_type) => vec![output_type],
-        None => opt.output_types,
+    let thresholds = ThresholdConfig {
+        lines: opt.fail_under_line,
+        branches: opt.fail_under_branch,
+        functions: opt.fail_under_function,
     };
-
-    let output_path = match output_types.len() {
-        0 => return,
-        1 => opt.output_path.as_deref(),
-        _ => match opt.output_path.as_deref() {
-            Some(output_path) => {
-                if output_path.is_dir() {
-                    Some(output_path)
-                } else {
-                    panic!("output_path must be a directory when using multiple outputs");
-                }
+    let threshold_checks = check_thresholds(results.iter(), &thresholds, opt.precision);
+    if !threshold_checks.is_empty() {
+        suspend_progress(|| {
+            eprintln!("Coverage thresholds:");
+            for check in &threshold_checks {
+                eprintln!("    {}", check);
             }
-            _ => None,
-        },
-    };
+        });
+    }
+    let file_threshold_violations = check_file_thresholds(
+        results.iter(),
+        &opt.fail_under,
+        opt.fail_empty_files,
+        opt.precision,
+    );
+    if let Some(threshold_report) = &opt.threshold_report {
+        let report = threshold_report_json(&threshold_checks, &file_threshold_violations);
+        fs::write(threshold_report, report.to_string()).map_err(|e| {
+            format!(
+                "Unable to write the threshold report to {}: {}",
+                threshold_report.display(),
+                e
+            )
+        })?;
+    }
+
+    if let Some(trend_file) = &opt.trend_file {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let commit = opt.commit_sha.as_deref().unwrap_or_default();
+        let record = TrendRecord::new(results.iter(), commit, timestamp);
+        append_trend_record(trend_file, record)?;
+    }
+
+    if let Some(tag_report_path) = tag_report_path {
+        fs::create_dir_all(&opt.tag_cache_dir).expect("Failed to create the tag cache directory");
+        output_lcov(results.iter(), Some(tag_report_path), demangle);
+    }
+
+    let coverage = CoverageSummary::new(results.iter(), &input_counts, opt.precision);
 
-    for output_type in &output_types {
-        let output_path = output_type.to_file_name(output_path);
+    report_phase(Phase::Writing);
+    let service_number = opt.service_number.clone().unwrap_or_default();
+    let service_pull_request = opt.service_pull_request.clone().unwrap_or_default();
+    let commit_sha = opt.commit_sha.clone().unwrap_or_default();
 
+    for (output_type, output_path) in outputs {
         match output_type {
-            OutputType::Ade => output_activedata_etl(&iterator, output_path.as_deref(), demangle),
-            OutputType::Lcov => output_lcov(&iterator, output_path.as_deref(), demangle),
+            OutputType::Ade => {
+                output_activedata_etl(results.as_slice(), output_path.as_deref(), demangle)
+            }
+            OutputType::Lcov => output_lcov_with_encoding(
+                results.iter(),
+                output_path.as_deref(),
+                demangle,
+                opt.output_encoding,
+            ),
             OutputType::Coveralls => output_coveralls(
-                &iterator,
+                results.as_slice(),
                 opt.token.as_deref(),
                 opt.service_name.as_deref(),
                 &service_number,
@@ -508,7 +2049,7 @@ fn main() {
                 demangle,
             ),
             OutputType::CoverallsPlus => output_coveralls(
-                &iterator,
+                results.as_slice(),
                 opt.token.as_deref(),
                 opt.service_name.as_deref(),
                 &service_number,
@@ -521,10 +2062,12 @@ fn main() {
                 opt.parallel,
                 demangle,
             ),
-            OutputType::Files => output_files(&iterator, output_path.as_deref()),
-            OutputType::Covdir => output_covdir(&iterator, output_path.as_deref(), opt.precision),
+            OutputType::Files => output_files(results.as_slice(), output_path.as_deref()),
+            OutputType::Covdir => {
+                output_covdir(results.iter(), output_path.as_deref(), opt.precision)
+            }
             OutputType::Html => output_html(
-                &iterator,
+                results.iter(),
                 output_path.as_deref(),
                 num_threads,
                 opt.branch,
@@ -533,13 +2076,450 @@ fn main() {
             ),
             OutputType::Cobertura => output_cobertura(
                 source_root.as_deref(),
-                &iterator,
+                results.as_slice(),
                 output_path.as_deref(),
                 demangle,
             ),
             OutputType::Markdown => {
-                output_markdown(&iterator, output_path.as_deref(), opt.precision)
+                output_markdown(results.as_slice(), output_path.as_deref(), opt.precision)
+            }
+            OutputType::MarkdownSummary => {
+                output_markdown_summary(results.as_slice(), output_path.as_deref())
             }
+            OutputType::Jsonl => output_jsonl(results.as_slice(), output_path.as_deref(), demangle),
+            OutputType::Sonar => output_sonar(results.as_slice(), output_path.as_deref()),
+            OutputType::AnnotatedSource => {
+                let profraws: Vec<PathBuf> = find_input_files(&opt.paths)
+                    .into_iter()
+                    .filter(|path| path.extension() == Some("profraw".as_ref()))
+                    .collect();
+                output_annotated_source(
+                    results.as_slice(),
+                    &profraws,
+                    &ExportOptions {
+                        binary_path: opt.binary_path.as_deref(),
+                        executables: &cargo_executables,
+                        allowed_binaries: allowed_binaries.as_deref().map(Vec::as_slice),
+                        ..Default::default()
+                    },
+                    output_path.as_deref(),
+                    opt.output_path.as_deref().is_some_and(Path::is_dir),
+                    opt.show_line_counts_or_regions,
+                    opt.show_expansions,
+                )
+            }
+        };
+    }
+
+    if let Some(depfile) = &opt.depfile {
+        let mut dependencies = find_input_files(&opt.paths);
+        if let Some(binary_path) = &opt.binary_path {
+            dependencies.append(&mut find_binaries(binary_path));
+        }
+        if let Some(cargo) = opt
+            .cargo
+            .as_ref()
+            .filter(|cargo| cargo.as_path() != Path::new("-"))
+        {
+            dependencies.push(cargo.clone());
+            dependencies.extend(read_cargo_executables(cargo).unwrap_or_default());
+        }
+        dependencies.extend(opt.path_mapping.iter().cloned());
+        dependencies.extend(opt.raw_counters_file.iter().cloned());
+        dependencies.extend(opt.llvm_cov_json.iter().cloned());
+        dependencies.extend(opt.baseline_lcov.iter().cloned());
+        // The source files are only read to check that they exist, or to be embedded.
+        let reads_sources = opt.ignore_not_existing
+            || outputs.iter().any(|(output_type, _)| {
+                matches!(
+                    output_type,
+                    OutputType::Html | OutputType::Coveralls | OutputType::CoverallsPlus
+                )
+            });
+        if reads_sources {
+            dependencies.extend(
+                results
+                    .iter()
+                    .map(|result| result.0.clone())
+                    .filter(|abs_path| abs_path.is_file()),
+            );
+        }
+        let targets: Vec<PathBuf> = outputs
+            .iter()
+            .filter_map(|(_, output_path)| output_path.clone())
+            .collect();
+        write_depfile(depfile, &targets, dependencies)?;
+    }
+
+    if let Some(diagnostics_dump) = &opt.diagnostics_dump {
+        let profraws: Vec<PathBuf> = find_input_files(&opt.paths)
+            .into_iter()
+            .filter(|path| path.extension() == Some("profraw".as_ref()))
+            .collect();
+        let mut binaries = match &opt.binary_path {
+            Some(binary_path) => find_binaries(binary_path),
+            None => Vec::new(),
         };
+        binaries.extend(cargo_executables.iter().cloned());
+        let args: Vec<String> = std::env::args().collect();
+        let diagnostics = diagnostics_json(&args, &profraws, &binaries, results.iter());
+        write_diagnostics(diagnostics_dump, &diagnostics)?;
+    }
+
+    if let (Some(version_file), Some(version_info)) = (&opt.version_file, &version_info) {
+        version_info.write(version_file)?;
+    }
+
+    Ok(ReportSummary {
+        coverage,
+        threshold_violations: threshold_violations(&threshold_checks, &file_threshold_violations),
+        threshold_checks,
+    })
+}
+
+// Accounts for the results merged into the map by the main thread, which are spilled once they
+// exceed the budget of --max-memory.
+fn spill_merged(
+    merge_spill: Option<&MergeSpill>,
+    result_map: &SyncCovResultMap,
+    added: usize,
+) -> Result<(), String> {
+    match merge_spill {
+        Some(merge_spill) => merge_spill
+            .add(result_map, added)
+            .map_err(|e| format!("Unable to spill the results to a temporary file: {}", e)),
+        None => Ok(()),
+    }
+}
+
+// Starts serving the HTML output, which must be one of the outputs. The requests are only handled
+// once the server runs, after the first report is generated.
+fn start_server(address: &str, outputs: &[(&OutputType, Option<PathBuf>)]) -> ReportServer {
+    let root = match outputs
+        .iter()
+        .find(|(output_type, _)| matches!(output_type, OutputType::Html))
+    {
+        Some((_, output_path)) => output_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("./html")),
+        None => {
+            eprintln!("--serve requires the html output type");
+            process::exit(ERROR_EXIT_CODE);
+        }
+    };
+    match ReportServer::new(address, &root) {
+        Ok(server) => {
+            eprintln!(
+                "Serving the HTML report at http://{}/, press Ctrl-C to stop.",
+                server.address()
+            );
+            server
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(ERROR_EXIT_CODE);
+        }
+    }
+}
+
+// Regenerates the reports each time the coverage files change, until interrupted.
+fn watch(
+    opt: &Opt,
+    outputs: &[(&OutputType, Option<PathBuf>)],
+    tag_report_path: Option<&Path>,
+    stop: &AtomicBool,
+) {
+    let paths: Vec<PathBuf> = opt.paths.iter().map(PathBuf::from).collect();
+    let mut watcher = match CoverageWatcher::new(&paths) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(ERROR_EXIT_CODE);
+        }
+    };
+    let debounce = Duration::from_millis(opt.watch_debounce);
+
+    eprintln!("Watching for changes of the coverage files, press Ctrl-C to stop.");
+    while let Some(changed) = watcher.wait_for_changes(debounce, stop) {
+        let start = Instant::now();
+        match generate_report(opt, outputs, tag_report_path) {
+            Ok(summary) => {
+                eprintln!(
+                    "{} coverage files changed, report updated in {:.2}s: {}",
+                    changed.len(),
+                    start.elapsed().as_secs_f64(),
+                    summary.coverage
+                );
+                if prints_summary(opt, outputs) {
+                    eprintln!("{}", summary.coverage.totals(&summary.threshold_checks));
+                }
+                if let Some(violations) = summary.threshold_violations {
+                    eprintln!("{}", violations);
+                }
+            }
+            // The coverage files can be missing for a while (e.g. after a cargo clean), so keep
+            // watching until they are produced again.
+            Err(e) => eprintln!("Failed to update the report: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str], config: &str) -> Result<Opt, String> {
+        let matches = Opt::app().get_matches_from(args);
+        let mut opt = Opt::from_clap(&matches);
+        let config = ConfigFile::parse(config).map_err(|e| e.to_string())?;
+        opt.apply_config(config, &matches)?;
+        Ok(opt)
+    }
+
+    const CONFIG: &str = r#"
+        output-types = ["html", "covdir"]
+        source-dir = "src"
+        binary-path = "target/debug"
+        ignore = ["/*"]
+        keep-only = ["src/*"]
+        branch = true
+        guess-directory-when-missing = true
+        threads = 4
+        log-level = "WARN"
+        excl-line = "no-cov"
+    "#;
+
+    #[test]
+    fn test_config_file_values() {
+        let opt = parse_args(&["grcov", "."], CONFIG).unwrap();
+        assert_eq!(opt.output_types.len(), 2);
+        assert!(matches!(opt.output_types[0], OutputType::Html));
+        assert!(matches!(opt.output_types[1], OutputType::Covdir));
+        assert_eq!(opt.source_dir, Some(PathBuf::from("src")));
+        assert_eq!(opt.binary_path, Some(PathBuf::from("target/debug")));
+        assert!(opt.branch);
+        assert!(opt.guess_directory);
+        assert_eq!(opt.threads, Some(4));
+        assert_eq!(opt.log_level, LevelFilter::Warn);
+        assert_eq!(opt.excl_line.unwrap().as_str(), "no-cov");
+        // The options which aren't in the file keep their defaults.
+        assert_eq!(opt.precision, 2);
+        assert_eq!(opt.vcs_branch, "master");
+    }
+
+    #[test]
+    fn test_config_command_line_precedence() {
+        let opt = parse_args(
+            &[
+                "grcov",
+                ".",
+                "-t",
+                "lcov",
+                "-s",
+                "other",
+                "--threads",
+                "2",
+                "--log-level",
+                "DEBUG",
+            ],
+            CONFIG,
+        )
+        .unwrap();
+        assert!(opt.output_types.is_empty());
+        assert!(matches!(opt.output_type, Some(OutputType::Lcov)));
+        assert_eq!(opt.source_dir, Some(PathBuf::from("other")));
+        assert_eq!(opt.threads, Some(2));
+        assert_eq!(opt.log_level, LevelFilter::Debug);
+        assert_eq!(opt.binary_path, Some(PathBuf::from("target/debug")));
+
+        let opt = parse_args(&["grcov", ".", "--output-types", "files"], CONFIG).unwrap();
+        assert_eq!(opt.output_types.len(), 1);
+        assert!(matches!(opt.output_types[0], OutputType::Files));
+    }
+
+    #[test]
+    fn test_config_lists_are_merged() {
+        let opt = parse_args(
+            &[
+                "grcov",
+                ".",
+                "--ignore",
+                "target/*",
+                "--keep-only",
+                "tests/*",
+            ],
+            CONFIG,
+        )
+        .unwrap();
+        assert_eq!(opt.ignore_dir, vec!["/*", "target/*"]);
+        assert_eq!(opt.keep_dir, vec!["src/*", "tests/*"]);
+    }
+
+    #[test]
+    fn test_config_thresholds() {
+        let opt = parse_args(
+            &["grcov", ".", "--fail-under-line", "90"],
+            "fail-under-line = 80\nfail-under-branch = 50.5",
+        )
+        .unwrap();
+        assert_eq!(opt.fail_under_line, Some(90.0));
+        assert_eq!(opt.fail_under_branch, Some(50.5));
+        assert_eq!(opt.fail_under_function, None);
+
+        let err = parse_args(&["grcov", "."], "fail-under-function = 120")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            "Invalid value for 'fail-under-function' in the configuration file: 120 is not a percentage between 0 and 100"
+        );
+    }
+
+    #[test]
+    fn test_config_file_thresholds() {
+        let opt = parse_args(
+            &["grcov", ".", "--fail-under", "src/**=70"],
+            "fail-under = [\"src/parser/**=90\"]\nfail-empty-files = true",
+        )
+        .unwrap();
+        let rules: Vec<(&str, f64)> = opt
+            .fail_under
+            .iter()
+            .map(|rule| (rule.glob.as_str(), rule.threshold))
+            .collect();
+        assert_eq!(rules, vec![("src/parser/**", 90.0), ("src/**", 70.0)]);
+        assert!(opt.fail_empty_files);
+
+        let err = parse_args(&["grcov", "."], "fail-under = [\"src/**\"]")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            "Invalid value for 'fail-under' in the configuration file: Invalid threshold 'src/**', expected GLOB=PERCENTAGE"
+        );
+    }
+
+    #[test]
+    fn test_config_invalid_values() {
+        let err = parse_args(&["grcov", "."], "output-types = [\"pdf\"]")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            "Invalid value for 'output-types' in the configuration file: pdf is not a supported output type"
+        );
+
+        assert!(parse_args(&["grcov", "."], "excl-line = \"(\"").is_err());
+
+        // Invalid values are ignored when the option is set on the command line.
+        assert!(parse_args(&["grcov", ".", "--excl-line", "x"], "excl-line = \"(\"").is_ok());
+    }
+
+    #[test]
+    fn test_prints_summary() {
+        let prints = |args: &[&str]| {
+            let opt = Opt::from_iter(args);
+            prints_summary(&opt, &resolve_outputs(&opt))
+        };
+        // The outputs written to stdout always have the totals, even with --quiet.
+        assert!(prints(&["grcov", ".", "-t", "lcov"]));
+        assert!(prints(&["grcov", ".", "-t", "lcov", "--quiet"]));
+        assert!(!prints(&["grcov", ".", "-t", "lcov", "-o", "lcov.info"]));
+        assert!(prints(&[
+            "grcov",
+            ".",
+            "-t",
+            "lcov",
+            "-o",
+            "lcov.info",
+            "--print-summary",
+            "--quiet"
+        ]));
+    }
+
+    #[test]
+    fn test_parse_subcommand() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            parse_subcommand(args(&["grcov", "test", "--keep-profraws"])),
+            (
+                Some(Subcommand::Test),
+                args(&["grcov test", "--keep-profraws"])
+            )
+        );
+        assert_eq!(
+            parse_subcommand(args(&["grcov", "trend-stats"])),
+            (Some(Subcommand::TrendStats), args(&["grcov trend-stats"]))
+        );
+        assert_eq!(
+            parse_subcommand(args(&["grcov", ".", "test"])),
+            (None, args(&["grcov", ".", "test"]))
+        );
+        assert_eq!(parse_subcommand(args(&["grcov"])), (None, args(&["grcov"])));
+
+        // An input path named like a subcommand.
+        let (subcommand, report_args) =
+            parse_subcommand(args(&["grcov", "--", "test", "-t", "lcov"]));
+        assert_eq!(subcommand, None);
+        let opt = Opt::from_iter(report_args);
+        assert_eq!(opt.paths, vec!["test"]);
+        assert!(matches!(opt.output_type, Some(OutputType::Lcov)));
+    }
+
+    #[test]
+    fn test_aggregate_args() {
+        let opt = AggregateOpt::from_iter(&[
+            "grcov aggregate",
+            "--tags",
+            "default,all-features",
+            "--mode",
+            "intersection",
+        ]);
+        assert_eq!(opt.tags, vec!["default", "all-features"]);
+        assert_eq!(opt.mode, AggregateMode::Intersection);
+        assert_eq!(opt.tag_cache_dir, PathBuf::from(DEFAULT_TAG_CACHE_DIR));
+
+        assert!(AggregateOpt::from_iter_safe(&["grcov aggregate"]).is_err());
+    }
+
+    #[test]
+    fn test_clean_args() {
+        let opt = CleanOpt::from_iter(&[
+            "grcov clean",
+            "target/debug",
+            "--binary-path",
+            "target/debug/main",
+            "--dry-run",
+            "--keep-gcno",
+        ]);
+        assert_eq!(opt.paths, vec![PathBuf::from("target/debug")]);
+        assert_eq!(opt.binary_path, Some(PathBuf::from("target/debug/main")));
+        assert!(opt.dry_run);
+        assert!(opt.keep_gcno);
+        assert!(!opt.keep_gcda && !opt.keep_profraw && !opt.force);
+
+        assert!(CleanOpt::from_iter_safe(&["grcov clean"]).is_err());
+    }
+
+    #[test]
+    fn test_test_args() {
+        let opt = TestOpt::from_iter(&[
+            "grcov test",
+            "/tmp/grcov-test/profraws",
+            "--cargo",
+            "/tmp/grcov-test/cargo-messages.json",
+            "--keep-profraws",
+            "-t",
+            "lcov",
+            "--",
+            "--lib",
+            "--features",
+            "extra",
+        ]);
+        assert!(opt.keep_profraws);
+        assert!(!opt.coverage_on_failure);
+        assert_eq!(opt.opt.paths, vec!["/tmp/grcov-test/profraws"]);
+        assert!(matches!(opt.opt.output_type, Some(OutputType::Lcov)));
+        assert_eq!(opt.cargo_args, vec!["--lib", "--features", "extra"]);
     }
 }
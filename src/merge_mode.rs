@@ -0,0 +1,92 @@
+use globset::{Glob, GlobMatcher};
+use std::str::FromStr;
+
+/// How the execution counts of a line are merged, when several compilation units or inputs have
+/// a record of the same source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeMode {
+    /// The counts are summed up, as lcov does: a line of an inline function of a header counts
+    /// the executions of all its copies.
+    Sum,
+    /// The highest count is kept, i.e. the executions of the copy which ran the most.
+    Max,
+}
+
+impl FromStr for MergeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "sum" => Self::Sum,
+            "max" => Self::Max,
+            _ => return Err(format!("{} is not a supported merge mode", s)),
+        })
+    }
+}
+
+/// A --merge-mode: the mode of the source files matching the glob, or of all of them.
+#[derive(Debug, Clone)]
+pub struct MergeRule {
+    matcher: Option<GlobMatcher>,
+    pub mode: MergeMode,
+}
+
+impl FromStr for MergeRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (glob, mode) = match s.rfind('=') {
+            Some(pos) => (Some(&s[..pos]), &s[pos + 1..]),
+            None => (None, s),
+        };
+        let matcher = glob
+            .map(|glob| {
+                Glob::new(glob)
+                    .map(|glob| glob.compile_matcher())
+                    .map_err(|e| format!("Invalid glob in merge mode '{}': {}", s, e))
+            })
+            .transpose()?;
+        Ok(MergeRule {
+            matcher,
+            mode: mode.parse()?,
+        })
+    }
+}
+
+/// The merge mode of a source file, given by the last rule which matches its path, as it is in
+/// the coverage files. The counts are summed up when there is none.
+pub fn merge_mode(rules: &[MergeRule], path: &str) -> MergeMode {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| {
+            rule.matcher
+                .as_ref()
+                .is_none_or(|matcher| matcher.is_match(path))
+        })
+        .map_or(MergeMode::Sum, |rule| rule.mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_mode() {
+        let rules = |rules: &[&str]| -> Vec<MergeRule> {
+            rules.iter().map(|rule| rule.parse().unwrap()).collect()
+        };
+        assert_eq!(merge_mode(&[], "include/stack.h"), MergeMode::Sum);
+        assert_eq!(merge_mode(&rules(&["max"]), "src/a.cpp"), MergeMode::Max);
+
+        let header_rules = rules(&["sum", "**/*.h=max"]);
+        assert_eq!(merge_mode(&header_rules, "include/stack.h"), MergeMode::Max);
+        assert_eq!(merge_mode(&header_rules, "src/a.cpp"), MergeMode::Sum);
+        // The last matching rule wins.
+        let rules = rules(&["**/*.h=max", "sum"]);
+        assert_eq!(merge_mode(&rules, "include/stack.h"), MergeMode::Sum);
+
+        assert!("average".parse::<MergeRule>().is_err());
+        assert!("[*.h=max".parse::<MergeRule>().is_err());
+    }
+}
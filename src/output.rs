@@ -1,31 +1,37 @@
-use crossbeam_channel::unbounded;
+use crossbeam_channel::bounded;
+use encoding_rs::{EncoderResult, WINDOWS_1252};
+use log::warn;
 use md5::{Digest, Md5};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use rustc_hash::FxHashMap;
 use serde_json::{self, json, Value};
+use std::borrow::{Borrow, Cow};
 use std::cell::RefCell;
-use std::collections::{hash_map, BTreeMap, BTreeSet};
+use std::collections::{hash_map, BTreeSet};
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs::File;
 use std::io::{self, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::str::{self, FromStr};
 use std::{
     process::{self, Command, Stdio},
     thread,
 };
-use symbolic_common::Name;
-use symbolic_demangle::{Demangle, DemangleOptions};
 use tabled::{Style, Table, Tabled};
 use uuid::Uuid;
 
 use crate::defs::*;
+use crate::demangle_cache::demangle_name;
 use crate::html;
+use crate::summary::{coverage_ratio_or, plural};
+use crate::threshold::ERROR_EXIT_CODE;
 
 macro_rules! demangle {
-    ($name: expr, $demangle: expr, $options: expr) => {{
+    ($name: expr, $demangle: expr) => {{
         if $demangle {
-            if let Some(name) = Name::from($name).demangle($options) {
+            if let Some(name) = demangle_name($name) {
                 StringOrRef::S(name)
             } else {
                 StringOrRef::R($name)
@@ -36,6 +42,86 @@ macro_rules! demangle {
     }};
 }
 
+/// A file which is written under a temporary name next to it, and renamed when dropped, so that
+/// its readers (e.g. a browser refreshing a report, or a concurrent run reading a cache) never see
+/// it partially written. `commit` renames it and returns the error of the rename, which dropping it
+/// can only log.
+pub struct AtomicFile {
+    file: File,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    done: bool,
+}
+
+impl AtomicFile {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<AtomicFile> {
+        let path = path.as_ref().to_path_buf();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        // The name is unique to the process, for two runs writing the same file.
+        let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+        Ok(AtomicFile {
+            file: File::create(&tmp_path)?,
+            path,
+            tmp_path,
+            done: false,
+        })
+    }
+
+    pub fn commit(mut self) -> io::Result<()> {
+        self.done = true;
+        self.rename()
+    }
+
+    // Removes the temporary file, leaving the previous one in place.
+    fn discard(mut self) {
+        self.done = true;
+        let _ = std::fs::remove_file(&self.tmp_path);
+    }
+
+    fn rename(&self) -> io::Result<()> {
+        std::fs::rename(&self.tmp_path, &self.path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&self.tmp_path);
+        })
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        // Don't replace the previous file with a partial one.
+        if std::thread::panicking() {
+            let _ = std::fs::remove_file(&self.tmp_path);
+        } else if let Err(e) = self.rename() {
+            warn!("Cannot replace {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Writes `content` to `path` through an `AtomicFile`, returning the errors of the write and of
+/// the rename.
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    match file.write_all(content) {
+        Ok(()) => file.commit(),
+        Err(e) => {
+            file.discard();
+            Err(e)
+        }
+    }
+}
+
 pub fn get_target_output_writable(output_file: Option<&Path>) -> Box<dyn Write> {
     let write_target: Box<dyn Write> = match output_file {
         Some(output) => {
@@ -45,7 +131,7 @@ pub fn get_target_output_writable(output_file: Option<&Path>) -> Box<dyn Write>
                     output.display()
                 )
             }
-            Box::new(File::create(output).unwrap_or_else(|_| {
+            Box::new(AtomicFile::create(output).unwrap_or_else(|_| {
                 let parent = output.parent();
                 if let Some(parent_path) = parent {
                     if !parent_path.exists() {
@@ -71,7 +157,6 @@ pub fn get_target_output_writable(output_file: Option<&Path>) -> Box<dyn Write>
 }
 
 pub fn output_activedata_etl(results: &[ResultTuple], output_file: Option<&Path>, demangle: bool) {
-    let demangle_options = DemangleOptions::name_only();
     let mut writer = BufWriter::new(get_target_output_writable(output_file));
 
     for (_, rel_path, result) in results {
@@ -139,12 +224,12 @@ pub fn output_activedata_etl(results: &[ResultTuple], output_file: Option<&Path>
                         "name": rel_path,
                     },
                     "method": {
-                        "name": demangle!(name, demangle, demangle_options),
+                        "name": demangle!(name, demangle),
                         "covered": lines_covered,
                         "uncovered": lines_uncovered,
                         "total_covered": lines_covered.len(),
                         "total_uncovered": lines_uncovered.len(),
-                        "percentage_covered": lines_covered.len() as f32 / (lines_covered.len() + lines_uncovered.len()) as f32,
+                        "percentage_covered": coverage_ratio_or(lines_covered.len() as u64, (lines_covered.len() + lines_uncovered.len()) as u64, 1.0) as f32,
                     }
                 })
             ).unwrap();
@@ -166,27 +251,32 @@ pub fn output_activedata_etl(results: &[ResultTuple], output_file: Option<&Path>
                     "uncovered": uncovered,
                     "total_covered": covered.len(),
                     "total_uncovered": uncovered.len(),
-                    "percentage_covered": covered.len() as f32 / (covered.len() + uncovered.len()) as f32,
+                    "percentage_covered": coverage_ratio_or(covered.len() as u64, (covered.len() + uncovered.len()) as u64, 1.0) as f32,
                 },
                 "method": {
                     "covered": orphan_covered,
                     "uncovered": orphan_uncovered,
                     "total_covered": orphan_covered.len(),
                     "total_uncovered": orphan_uncovered.len(),
-                    "percentage_covered": orphan_covered.len() as f32 / (orphan_covered.len() + orphan_uncovered.len()) as f32,
+                    "percentage_covered": coverage_ratio_or(orphan_covered.len() as u64, (orphan_covered.len() + orphan_uncovered.len()) as u64, 1.0) as f32,
                 }
             })
         ).unwrap();
     }
 }
 
-pub fn output_covdir(results: &[ResultTuple], output_file: Option<&Path>, precision: usize) {
+pub fn output_covdir(
+    results: impl IntoIterator<Item = impl Borrow<ResultTuple>>,
+    output_file: Option<&Path>,
+    precision: usize,
+) {
     let mut writer = BufWriter::new(get_target_output_writable(output_file));
     let mut relative: FxHashMap<PathBuf, Rc<RefCell<CDDirStats>>> = FxHashMap::default();
     let global = Rc::new(RefCell::new(CDDirStats::new("".to_string())));
     relative.insert(PathBuf::from(""), global.clone());
 
-    for (abs_path, rel_path, result) in results {
+    for item in results {
+        let (abs_path, rel_path, result) = item.borrow();
         let path = if rel_path.is_relative() {
             rel_path
         } else {
@@ -226,7 +316,7 @@ pub fn output_covdir(results: &[ResultTuple], output_file: Option<&Path>, precis
 
         prev_stats.borrow_mut().files.push(CDFileStats::new(
             path.file_name().unwrap().to_str().unwrap().to_string(),
-            result.lines.clone(),
+            &result.lines,
             precision,
         ));
     }
@@ -237,78 +327,268 @@ pub fn output_covdir(results: &[ResultTuple], output_file: Option<&Path>, precis
     serde_json::to_writer(&mut writer, &global.into_json()).unwrap();
 }
 
-pub fn output_lcov(results: &[ResultTuple], output_file: Option<&Path>, demangle: bool) {
-    let demangle_options = DemangleOptions::name_only();
-    let mut writer = BufWriter::new(get_target_output_writable(output_file));
-    writer.write_all(b"TN:\n").unwrap();
-
-    for (_, rel_path, result) in results {
-        // println!("{} {:?}", rel_path, result.lines);
+// Writes the lcov record of a source file, from its SF line to its end_of_record line.
+pub(crate) fn write_lcov_record(
+    writer: &mut impl Write,
+    path: impl fmt::Display,
+    result: &CovResult,
+    demangle: bool,
+) -> io::Result<()> {
+    writeln!(writer, "SF:{}", path)?;
 
-        writeln!(writer, "SF:{}", rel_path.display()).unwrap();
+    // The functions are in the order of their lines, rather than the one of the map.
+    let mut functions: Vec<(&String, &Function)> = result.functions.iter().collect();
+    functions.sort_unstable_by_key(|&(name, function)| (function.start, name));
+    for &(name, function) in &functions {
+        writeln!(
+            writer,
+            "FN:{},{}",
+            function.start,
+            demangle!(name, demangle)
+        )?;
+    }
+    for &(name, function) in &functions {
+        writeln!(
+            writer,
+            "FNDA:{},{}",
+            i32::from(function.executed),
+            demangle!(name, demangle)
+        )?;
+    }
+    if !result.functions.is_empty() {
+        writeln!(writer, "FNF:{}", result.functions.len())?;
+        writeln!(
+            writer,
+            "FNH:{}",
+            result.functions.values().filter(|x| x.executed).count()
+        )?;
+    }
 
-        for (name, function) in &result.functions {
+    // branch coverage information
+    let mut branch_count = 0;
+    let mut branch_hit = 0;
+    for (line, taken) in &result.branches {
+        branch_count += taken.len();
+        for (n, b_t) in taken.iter().enumerate() {
             writeln!(
                 writer,
-                "FN:{},{}",
-                function.start,
-                demangle!(name, demangle, demangle_options)
-            )
-            .unwrap();
+                "BRDA:{},0,{},{}",
+                line,
+                n,
+                if *b_t { "1" } else { "-" }
+            )?;
+            if *b_t {
+                branch_hit += 1;
+            }
         }
-        for (name, function) in &result.functions {
-            writeln!(
-                writer,
-                "FNDA:{},{}",
-                i32::from(function.executed),
-                demangle!(name, demangle, demangle_options)
-            )
-            .unwrap();
+    }
+
+    writeln!(writer, "BRF:{}", branch_count)?;
+    writeln!(writer, "BRH:{}", branch_hit)?;
+
+    // MC/DC coverage information, both senses of a condition being taken when it is covered
+    if let Some(mcdc) = &result.mcdc {
+        let mut condition_count = 0;
+        let mut condition_hit = 0;
+        for record in mcdc {
+            let group_size = record.conditions.len();
+            for (index, covered) in record.conditions.iter().enumerate() {
+                for sense in ["t", "f"] {
+                    writeln!(
+                        writer,
+                        "MCDC:{},{},{},{},{},condition {}",
+                        record.line,
+                        group_size,
+                        sense,
+                        i32::from(*covered),
+                        index,
+                        index
+                    )?;
+                }
+                condition_count += 2;
+                if *covered {
+                    condition_hit += 2;
+                }
+            }
         }
-        if !result.functions.is_empty() {
-            writeln!(writer, "FNF:{}", result.functions.len()).unwrap();
-            writeln!(
-                writer,
-                "FNH:{}",
-                result.functions.values().filter(|x| x.executed).count()
-            )
-            .unwrap();
+        writeln!(writer, "MCF:{}", condition_count)?;
+        writeln!(writer, "MCH:{}", condition_hit)?;
+    }
+
+    // The lines are most of the record, so they are formatted without the formatting machinery.
+    let mut line_buf = itoa::Buffer::new();
+    let mut count_buf = itoa::Buffer::new();
+    for (line, execution_count) in &result.lines {
+        writer.write_all(b"DA:")?;
+        writer.write_all(line_buf.format(*line).as_bytes())?;
+        writer.write_all(b",")?;
+        writer.write_all(count_buf.format(*execution_count).as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+    writeln!(writer, "LF:{}", result.lines.len())?;
+    writeln!(
+        writer,
+        "LH:{}",
+        result.lines.values().filter(|&v| *v > 0).count()
+    )?;
+    writer.write_all(b"end_of_record\n")
+}
+
+/// The encoding of the lcov output, set with --output-encoding for the tools which don't read
+/// UTF-8. Latin-1 is windows-1252, as the WHATWG encodings define it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputEncoding {
+    Utf8,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl FromStr for OutputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf-8" => Ok(OutputEncoding::Utf8),
+            "latin-1" => Ok(OutputEncoding::Latin1),
+            "utf-16-le" => Ok(OutputEncoding::Utf16Le),
+            "utf-16-be" => Ok(OutputEncoding::Utf16Be),
+            _ => Err(format!("{} is not a supported output encoding", s)),
         }
+    }
+}
 
-        // branch coverage information
-        let mut branch_count = 0;
-        let mut branch_hit = 0;
-        for (line, taken) in &result.branches {
-            branch_count += taken.len();
-            for (n, b_t) in taken.iter().enumerate() {
-                writeln!(
-                    writer,
-                    "BRDA:{},0,{},{}",
-                    line,
-                    n,
-                    if *b_t { "1" } else { "-" }
-                )
-                .unwrap();
-                if *b_t {
-                    branch_hit += 1;
+impl fmt::Display for OutputEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputEncoding::Utf8 => "utf-8",
+            OutputEncoding::Latin1 => "latin-1",
+            OutputEncoding::Utf16Le => "utf-16-le",
+            OutputEncoding::Utf16Be => "utf-16-be",
+        })
+    }
+}
+
+// The characters escaped in the paths which can't be represented in the output encoding, along
+// with the ones which aren't ASCII.
+const PATH_ESCAPES: &AsciiSet = &CONTROLS.add(b'%');
+
+impl OutputEncoding {
+    /// Encodes a text, with the characters which can't be represented percent-encoded. Returns
+    /// whether they all could be.
+    pub fn encode(self, text: &str, out: &mut Vec<u8>) -> bool {
+        match self {
+            OutputEncoding::Utf8 => out.extend_from_slice(text.as_bytes()),
+            OutputEncoding::Utf16Le => text
+                .encode_utf16()
+                .for_each(|unit| out.extend_from_slice(&unit.to_le_bytes())),
+            OutputEncoding::Utf16Be => text
+                .encode_utf16()
+                .for_each(|unit| out.extend_from_slice(&unit.to_be_bytes())),
+            OutputEncoding::Latin1 => {
+                let mut encoder = WINDOWS_1252.new_encoder();
+                let mut representable = true;
+                let mut text = text;
+                loop {
+                    // A character is never longer in windows-1252 than in UTF-8.
+                    let start = out.len();
+                    out.resize(start + text.len(), 0);
+                    let (result, read, written) =
+                        encoder.encode_from_utf8_without_replacement(text, &mut out[start..], true);
+                    out.truncate(start + written);
+                    text = &text[read..];
+                    match result {
+                        EncoderResult::InputEmpty => break,
+                        EncoderResult::OutputFull => {}
+                        EncoderResult::Unmappable(c) => {
+                            representable = false;
+                            let mut buf = [0; 4];
+                            out.extend(
+                                utf8_percent_encode(c.encode_utf8(&mut buf), PATH_ESCAPES)
+                                    .flat_map(str::bytes),
+                            );
+                        }
+                    }
                 }
+                return representable;
             }
         }
+        true
+    }
 
-        writeln!(writer, "BRF:{}", branch_count).unwrap();
-        writeln!(writer, "BRH:{}", branch_hit).unwrap();
-
-        for (line, execution_count) in &result.lines {
-            writeln!(writer, "DA:{},{}", line, execution_count).unwrap();
+    /// Returns a path as written to the output: percent-encoded, with a warning, when it can't be
+    /// represented in the encoding.
+    pub fn output_path(self, path: &str) -> Cow<'_, str> {
+        if self.encode(path, &mut Vec::new()) {
+            return Cow::Borrowed(path);
         }
-        writeln!(writer, "LF:{}", result.lines.len()).unwrap();
-        writeln!(
-            writer,
-            "LH:{}",
-            result.lines.values().filter(|&v| *v > 0).count()
-        )
-        .unwrap();
-        writer.write_all(b"end_of_record\n").unwrap();
+        warn!(
+            "The path {} can't be represented in {}, it is written percent-encoded",
+            path, self
+        );
+        Cow::Owned(utf8_percent_encode(path, PATH_ESCAPES).to_string())
+    }
+}
+
+// Transcodes the UTF-8 text written to it, keeping the end of an incomplete character until the
+// next write.
+struct EncodingWriter<W: Write> {
+    inner: W,
+    encoding: OutputEncoding,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid = match str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let mut out = Vec::with_capacity(valid * 2);
+        self.encoding
+            .encode(str::from_utf8(&self.pending[..valid]).unwrap(), &mut out);
+        self.inner.write_all(&out)?;
+        self.pending.drain(..valid);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn output_lcov(
+    results: impl IntoIterator<Item = impl Borrow<ResultTuple>>,
+    output_file: Option<&Path>,
+    demangle: bool,
+) {
+    output_lcov_with_encoding(results, output_file, demangle, OutputEncoding::Utf8)
+}
+
+/// Like output_lcov, with the report in the given encoding rather than UTF-8.
+pub fn output_lcov_with_encoding(
+    results: impl IntoIterator<Item = impl Borrow<ResultTuple>>,
+    output_file: Option<&Path>,
+    demangle: bool,
+    encoding: OutputEncoding,
+) {
+    let output = get_target_output_writable(output_file);
+    let mut writer: BufWriter<Box<dyn Write>> = match encoding {
+        OutputEncoding::Utf8 => BufWriter::new(output),
+        encoding => BufWriter::new(Box::new(EncodingWriter {
+            inner: output,
+            encoding,
+            pending: Vec::new(),
+        })),
+    };
+    writer.write_all(b"TN:\n").unwrap();
+
+    for item in results {
+        let (_, rel_path, result) = item.borrow();
+        let path = rel_path.to_string_lossy();
+        write_lcov_record(&mut writer, encoding.output_path(&path), result, demangle).unwrap();
     }
 }
 
@@ -413,6 +693,7 @@ fn get_coveralls_git_info(commit_sha: &str, vcs_branch: &str) -> Value {
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn output_coveralls(
     results: &[ResultTuple],
     repo_token: Option<&str>,
@@ -427,7 +708,6 @@ pub fn output_coveralls(
     parallel: bool,
     demangle: bool,
 ) {
-    let demangle_options = DemangleOptions::name_only();
     let mut source_files = Vec::new();
 
     for (abs_path, rel_path, result) in results {
@@ -464,7 +744,7 @@ pub fn output_coveralls(
             let mut functions = Vec::new();
             for (name, function) in &result.functions {
                 functions.push(json!({
-                    "name": demangle!(name, demangle, demangle_options),
+                    "name": demangle!(name, demangle),
                     "start": function.start,
                     "exec": function.executed,
                 }));
@@ -506,6 +786,56 @@ pub fn output_coveralls(
     serde_json::to_writer(&mut writer, &result).unwrap();
 }
 
+/// Writes the results as JSON Lines: one JSON object per file, so that they can be processed
+/// without loading the whole report.
+pub fn results_to_jsonl(
+    results: &[ResultTuple],
+    writer: &mut impl Write,
+    demangle: bool,
+) -> io::Result<()> {
+    for (_, rel_path, result) in results {
+        let lines: serde_json::Map<String, Value> = result
+            .lines
+            .iter()
+            .map(|(line_no, execution_count)| (line_no.to_string(), json!(execution_count)))
+            .collect();
+        let branches: serde_json::Map<String, Value> = result
+            .branches
+            .iter()
+            .map(|(line_no, taken)| (line_no.to_string(), json!(taken)))
+            .collect();
+        let functions: serde_json::Map<String, Value> = result
+            .functions
+            .iter()
+            .map(|(name, function)| {
+                (
+                    demangle!(name, demangle).to_string(),
+                    json!({
+                        "start": function.start,
+                        "executed": function.executed,
+                    }),
+                )
+            })
+            .collect();
+        let record = json!({
+            "path": rel_path,
+            "covered_lines": result.lines.values().filter(|&&count| count > 0).count(),
+            "total_lines": result.lines.len(),
+            "lines": lines,
+            "branches": branches,
+            "functions": functions,
+        });
+        serde_json::to_writer(&mut *writer, &record)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+pub fn output_jsonl(results: &[ResultTuple], output_file: Option<&Path>, demangle: bool) {
+    let mut writer = BufWriter::new(get_target_output_writable(output_file));
+    results_to_jsonl(results, &mut writer, demangle).unwrap();
+}
+
 pub fn output_files(results: &[ResultTuple], output_file: Option<&Path>) {
     let mut writer = BufWriter::new(get_target_output_writable(output_file));
     for (_, rel_path, _) in results {
@@ -514,7 +844,7 @@ pub fn output_files(results: &[ResultTuple], output_file: Option<&Path>) {
 }
 
 pub fn output_html(
-    results: &[ResultTuple],
+    results: impl IntoIterator<Item = impl Borrow<ResultTuple>>,
     output_dir: Option<&Path>,
     num_threads: usize,
     branch_enabled: bool,
@@ -537,36 +867,28 @@ pub fn output_html(
         return;
     }
 
-    let (sender, receiver) = unbounded();
+    // The results can be read back from temporary files, as they are written.
+    let (sender, receiver) = bounded(2 * num_threads);
 
-    let stats = Arc::new(Mutex::new(HtmlGlobalStats::default()));
     let mut threads = Vec::with_capacity(num_threads);
     let (tera, config) = html::get_config(output_config_file);
     for i in 0..num_threads {
         let receiver = receiver.clone();
         let output = output.clone();
         let config = config.clone();
-        let stats = stats.clone();
         let tera = tera.clone();
         let t = thread::Builder::new()
             .name(format!("Consumer HTML {}", i))
             .spawn(move || {
-                html::consumer_html(
-                    &tera,
-                    receiver,
-                    stats,
-                    &output,
-                    config,
-                    branch_enabled,
-                    precision,
-                );
+                html::consumer_html(&tera, receiver, &output, config, branch_enabled, precision)
             })
             .unwrap();
 
         threads.push(t);
     }
 
-    for (abs_path, rel_path, result) in results {
+    for item in results {
+        let (abs_path, rel_path, result) = item.borrow();
         sender
             .send(Some(HtmlItem {
                 abs_path: abs_path.to_path_buf(),
@@ -580,15 +902,39 @@ pub fn output_html(
         sender.send(None).unwrap();
     }
 
+    // The stats of the directories are summed once all the pages are written, in the same order
+    // whatever the consumer which wrote each page.
+    let mut global = HtmlGlobalStats::default();
+    let mut errors = Vec::new();
     for t in threads {
-        if t.join().is_err() {
-            process::exit(1);
+        match t.join() {
+            Ok(pages) => {
+                for (rel_path, stats) in &pages.files {
+                    global.add_file(rel_path, stats);
+                }
+                errors.extend(pages.errors);
+            }
+            Err(_) => process::exit(ERROR_EXIT_CODE),
         }
     }
 
-    let global = Arc::try_unwrap(stats).unwrap().into_inner().unwrap();
-
-    html::gen_index(&tera, &global, &config, &output, branch_enabled, precision);
+    errors.extend(html::gen_index(
+        &tera,
+        &global,
+        &config,
+        &output,
+        branch_enabled,
+        precision,
+    ));
+    if !errors.is_empty() {
+        eprintln!(
+            "Failed to write {} of the HTML report:",
+            plural(errors.len(), "page", "pages")
+        );
+        for error in errors {
+            eprintln!("  {}", error);
+        }
+    }
 
     for style in html::BadgeStyle::iter() {
         html::gen_badge(&tera, &global.stats, &config, &output, style);
@@ -614,7 +960,7 @@ pub fn output_markdown(results: &[ResultTuple], output_file: Option<&Path>, prec
         }
     }
 
-    fn format_lines(lines: &BTreeMap<u32, u64>) -> (usize, String) {
+    fn format_lines(lines: &LineMap) -> (usize, String) {
         let mut total_missed = 0;
         let mut missed = Vec::new();
         let mut start: u32 = 0;
@@ -666,10 +1012,56 @@ pub fn output_markdown(results: &[ResultTuple], output_file: Option<&Path>, prec
     .unwrap()
 }
 
+/// Produces a GitHub-flavored Markdown table of the line and function coverage of each file, with
+/// a row for the totals, e.g. for the comments of a bot on pull requests.
+pub fn results_to_markdown(results: &[ResultTuple]) -> String {
+    #[derive(Tabled)]
+    struct FileSummary {
+        #[tabled(rename = "File")]
+        file: String,
+        #[tabled(rename = "Lines")]
+        lines: String,
+        #[tabled(rename = "Functions")]
+        functions: String,
+        #[tabled(rename = "Coverage %")]
+        coverage: String,
+    }
+
+    fn summary(file: String, stats: &HtmlStats) -> FileSummary {
+        FileSummary {
+            file,
+            lines: format!("{}/{}", stats.covered_lines, stats.total_lines),
+            functions: format!("{}/{}", stats.covered_funs, stats.total_funs),
+            coverage: format!(
+                "{:.1}%",
+                html::get_percentage_of_covered_lines(stats.covered_lines, stats.total_lines)
+            ),
+        }
+    }
+
+    let mut rows = Vec::with_capacity(results.len() + 1);
+    let mut total = HtmlStats::default();
+    for (_, rel_path, result) in results {
+        let stats = html::get_stats(result);
+        total.add(&stats);
+        // A '|' would end the cell.
+        let file = rel_path.display().to_string().replace('|', "\\|");
+        rows.push(summary(file, &stats));
+    }
+    rows.push(summary("**Total**".to_string(), &total));
+
+    format!("{}\n", Table::new(rows).with(Style::markdown()))
+}
+
+pub fn output_markdown_summary(results: &[ResultTuple], output_file: Option<&Path>) {
+    let mut writer = BufWriter::new(get_target_output_writable(output_file));
+    write!(writer, "{}", results_to_markdown(results)).unwrap();
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{collections::BTreeMap, path::Path};
+    use std::{collections::BTreeMap, fs, path::Path};
 
     fn read_file(path: &Path) -> String {
         let mut f =
@@ -698,6 +1090,7 @@ mod tests {
                     map
                 },
                 functions: FxHashMap::default(),
+                mcdc: None,
             },
         )];
 
@@ -709,6 +1102,199 @@ mod tests {
         assert!(results.contains("BRH:3\n"));
     }
 
+    #[test]
+    fn test_lcov_functions_in_line_order() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file_path = tmp_dir.path().join("test_lcov_functions_in_line_order");
+        let mut functions = FxHashMap::default();
+        for (name, start) in &[("last", 30), ("first", 1), ("b", 10), ("a", 10)] {
+            functions.insert(
+                name.to_string(),
+                Function {
+                    start: *start,
+                    executed: *start != 30,
+                },
+            );
+        }
+        let results = vec![(
+            PathBuf::from("foo/bar/a.cpp"),
+            PathBuf::from("foo/bar/a.cpp"),
+            CovResult {
+                functions,
+                ..CovResult::default()
+            },
+        )];
+
+        output_lcov(&results, Some(&file_path), false);
+
+        let lcov = read_file(&file_path);
+        let records: Vec<&str> = lcov.lines().filter(|l| l.starts_with("FN")).collect();
+        assert_eq!(
+            records,
+            vec![
+                "FN:1,first",
+                "FN:10,a",
+                "FN:10,b",
+                "FN:30,last",
+                "FNDA:1,first",
+                "FNDA:1,a",
+                "FNDA:1,b",
+                "FNDA:0,last",
+                "FNF:4",
+                "FNH:3"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lcov_brda_from_llvm_cov_json() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file_path = tmp_dir.path().join("test_lcov_brda_from_llvm_cov_json");
+        let json = fs::read("test/llvm_cov_export.json").unwrap();
+        let results: Vec<ResultTuple> = crate::parse_llvm_cov_json(&json, true, false)
+            .unwrap()
+            .into_iter()
+            .map(|(path, result)| (PathBuf::from(&path), PathBuf::from(path), result))
+            .collect();
+
+        output_lcov(&results, Some(&file_path), false);
+
+        // A BRDA record for each side of the branches of the export, the ones never taken with a
+        // `-` count.
+        let lcov = read_file(&file_path);
+        let brda: Vec<&str> = lcov.lines().filter(|l| l.starts_with("BRDA:")).collect();
+        assert_eq!(
+            brda,
+            vec![
+                "BRDA:2,0,0,1",
+                "BRDA:2,0,1,1",
+                "BRDA:15,0,0,-",
+                "BRDA:15,0,1,1"
+            ]
+        );
+        assert!(lcov.contains("BRF:4\n"));
+        assert!(lcov.contains("BRH:3\n"));
+    }
+
+    #[test]
+    fn test_lcov_mcdc_from_llvm_cov_json() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file_path = tmp_dir.path().join("test_lcov_mcdc_from_llvm_cov_json");
+        // The export of a C function with the `a && b` decision, where only the independence
+        // pair of `b` is covered.
+        let json = fs::read("test/llvm_cov_export_mcdc.json").unwrap();
+        let json_results = crate::parse_llvm_cov_json(&json, true, false).unwrap();
+        assert_eq!(
+            json_results[0].1.mcdc,
+            Some(vec![McdcRecord {
+                line: 2,
+                conditions: vec![false, true],
+            }])
+        );
+        let results: Vec<ResultTuple> = json_results
+            .iter()
+            .cloned()
+            .map(|(path, result)| (PathBuf::from(&path), PathBuf::from(path), result))
+            .collect();
+
+        output_lcov(&results, Some(&file_path), false);
+
+        let lcov = read_file(&file_path);
+        let mcdc: Vec<&str> = lcov.lines().filter(|l| l.starts_with("MCDC:")).collect();
+        assert_eq!(
+            mcdc,
+            vec![
+                "MCDC:2,2,t,0,0,condition 0",
+                "MCDC:2,2,f,0,0,condition 0",
+                "MCDC:2,2,t,1,1,condition 1",
+                "MCDC:2,2,f,1,1,condition 1"
+            ]
+        );
+        assert!(lcov.contains("MCF:4\n"));
+        assert!(lcov.contains("MCH:2\n"));
+
+        // The MC/DC records round-trip through the lcov report.
+        let lcov_results = crate::parse_lcov(lcov.as_bytes(), true).unwrap();
+        assert_eq!(lcov_results.len(), 1);
+        assert_eq!(lcov_results[0].1.mcdc, json_results[0].1.mcdc);
+    }
+
+    #[test]
+    fn test_lcov_output_encoding() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file_path = tmp_dir.path().join("test_lcov_output_encoding");
+        let result = CovResult {
+            lines: [(1, 1)].iter().cloned().collect(),
+            branches: BTreeMap::new(),
+            functions: FxHashMap::default(),
+            mcdc: None,
+        };
+        let results = vec![
+            (
+                PathBuf::from("src/café.rs"),
+                PathBuf::from("src/café.rs"),
+                result.clone(),
+            ),
+            (
+                PathBuf::from("src/日本 100%.rs"),
+                PathBuf::from("src/日本 100%.rs"),
+                result,
+            ),
+        ];
+        output_lcov(&results, Some(&file_path), false);
+        let utf8 = read_file(&file_path);
+
+        // The paths which can't be represented are percent-encoded.
+        output_lcov_with_encoding(&results, Some(&file_path), false, OutputEncoding::Latin1);
+        let latin1 = fs::read(&file_path).unwrap();
+        // The other characters are the same in Latin-1.
+        let expected: Vec<u8> = utf8
+            .replace("日本 100%", "%E6%97%A5%E6%9C%AC 100%25")
+            .chars()
+            .map(|c| c as u8)
+            .collect();
+        assert_eq!(latin1, expected);
+        assert!(latin1.contains(&0xe9));
+
+        // The UTF-16 outputs have all the characters, whatever the writes split them.
+        for (encoding, to_unit) in [
+            (
+                OutputEncoding::Utf16Le,
+                u16::from_le_bytes as fn([u8; 2]) -> u16,
+            ),
+            (OutputEncoding::Utf16Be, u16::from_be_bytes),
+        ] {
+            output_lcov_with_encoding(&results, Some(&file_path), false, encoding);
+            let units: Vec<u16> = fs::read(&file_path)
+                .unwrap()
+                .chunks(2)
+                .map(|unit| to_unit([unit[0], unit[1]]))
+                .collect();
+            assert_eq!(String::from_utf16(&units).unwrap(), utf8);
+
+            let mut out = Vec::new();
+            let mut writer = EncodingWriter {
+                inner: &mut out,
+                encoding,
+                pending: Vec::new(),
+            };
+            for byte in "café 日本".as_bytes() {
+                writer.write_all(std::slice::from_ref(byte)).unwrap();
+            }
+            let units: Vec<u16> = out
+                .chunks(2)
+                .map(|unit| to_unit([unit[0], unit[1]]))
+                .collect();
+            assert_eq!(String::from_utf16(&units).unwrap(), "café 日本");
+        }
+
+        assert_eq!(
+            "latin-1".parse::<OutputEncoding>(),
+            Ok(OutputEncoding::Latin1)
+        );
+        assert!("ebcdic".parse::<OutputEncoding>().is_err());
+    }
+
     #[test]
     fn test_lcov_demangle() {
         let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
@@ -719,7 +1305,7 @@ mod tests {
             PathBuf::from("foo/bar/a.cpp"),
             PathBuf::from("foo/bar/a.cpp"),
             CovResult {
-                lines: BTreeMap::new(),
+                lines: LineMap::new(),
                 branches: BTreeMap::new(),
                 functions: {
                     let mut map = FxHashMap::default();
@@ -746,6 +1332,7 @@ mod tests {
                     );
                     map
                 },
+                mcdc: None,
             },
         )];
 
@@ -772,6 +1359,7 @@ mod tests {
                     lines: [(1, 10), (2, 11)].iter().cloned().collect(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    mcdc: None,
                 },
             ),
             (
@@ -781,6 +1369,7 @@ mod tests {
                     lines: [(1, 0), (2, 10), (4, 0)].iter().cloned().collect(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    mcdc: None,
                 },
             ),
             (
@@ -790,6 +1379,7 @@ mod tests {
                     lines: [(1, 10), (4, 1)].iter().cloned().collect(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    mcdc: None,
                 },
             ),
             (
@@ -799,6 +1389,7 @@ mod tests {
                     lines: [(1, 10), (2, 0)].iter().cloned().collect(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    mcdc: None,
                 },
             ),
         ];
@@ -825,6 +1416,7 @@ mod tests {
                 lines: [(1, 10), (2, 11)].iter().cloned().collect(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                mcdc: None,
             },
         )];
 
@@ -864,6 +1456,7 @@ mod tests {
                 lines: [(1, 10), (2, 11)].iter().cloned().collect(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                mcdc: None,
             },
         )];
 
@@ -903,6 +1496,7 @@ mod tests {
                 lines: [(1, 10), (2, 11)].iter().cloned().collect(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                mcdc: None,
             },
         )];
 
@@ -931,6 +1525,96 @@ mod tests {
         assert_eq!(results.get("service_job_id"), None);
     }
 
+    #[test]
+    fn test_atomic_file() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let path = tmp_dir.path().join("lcov.info");
+        fs::write(&path, "previous").unwrap();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new").unwrap();
+        // The previous content is kept until the new one is complete.
+        assert_eq!(fs::read_to_string(&path).unwrap(), "previous");
+        drop(file);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 1);
+
+        write_atomically(&path, b"newer").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "newer");
+
+        // The rename fails over a directory which isn't empty, and the temporary file is removed.
+        let dir = tmp_dir.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("file"), "").unwrap();
+        assert!(write_atomically(&dir, b"new").is_err());
+        assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_results_to_jsonl() {
+        let mut functions = FxHashMap::default();
+        functions.insert(
+            "_ZN3foo3barEv".to_string(),
+            Function {
+                start: 1,
+                executed: true,
+            },
+        );
+        let results = vec![
+            (
+                PathBuf::from("foo/bar/a.cpp"),
+                PathBuf::from("foo/bar/a.cpp"),
+                CovResult {
+                    lines: [(1, 10), (2, 0)].iter().cloned().collect(),
+                    branches: [(2, vec![true, false])].iter().cloned().collect(),
+                    functions,
+                    mcdc: None,
+                },
+            ),
+            (
+                PathBuf::from("foo/bar/b.cpp"),
+                PathBuf::from("foo/bar/b.cpp"),
+                CovResult {
+                    lines: [(3, 1)].iter().cloned().collect(),
+                    branches: BTreeMap::new(),
+                    functions: FxHashMap::default(),
+                    mcdc: None,
+                },
+            ),
+        ];
+
+        let mut buffer = Vec::new();
+        results_to_jsonl(&results, &mut buffer, true).unwrap();
+        let output = String::from_utf8(buffer).unwrap();
+
+        // Each line is a complete JSON document.
+        let records: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                json!({
+                    "path": "foo/bar/a.cpp",
+                    "covered_lines": 1,
+                    "total_lines": 2,
+                    "lines": {"1": 10, "2": 0},
+                    "branches": {"2": [true, false]},
+                    "functions": {"foo::bar": {"start": 1, "executed": true}},
+                }),
+                json!({
+                    "path": "foo/bar/b.cpp",
+                    "covered_lines": 1,
+                    "total_lines": 1,
+                    "lines": {"3": 1},
+                    "branches": {},
+                    "functions": {},
+                }),
+            ]
+        );
+    }
+
     #[test]
     fn test_markdown() {
         let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
@@ -945,6 +1629,7 @@ mod tests {
                     lines: [(1, 10), (2, 11)].iter().cloned().collect(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    mcdc: None,
                 },
             ),
             (
@@ -957,6 +1642,7 @@ mod tests {
                         .collect(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    mcdc: None,
                 },
             ),
         ];
@@ -973,4 +1659,52 @@ Total coverage: 57.14%
 ";
         assert_eq!(results, expected);
     }
+
+    #[test]
+    fn test_results_to_markdown() {
+        let function = |executed| Function { start: 1, executed };
+        let results = vec![
+            (
+                PathBuf::from("src/main.rs"),
+                PathBuf::from("src/main.rs"),
+                CovResult {
+                    lines: [(1, 1), (2, 1), (3, 0), (4, 2), (5, 1), (6, 1)]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                    branches: BTreeMap::new(),
+                    functions: [
+                        ("main".to_string(), function(true)),
+                        ("unused".to_string(), function(false)),
+                    ]
+                    .iter()
+                    .cloned()
+                    .collect(),
+                    mcdc: None,
+                },
+            ),
+            (
+                PathBuf::from("src/lib.rs"),
+                PathBuf::from("src/lib.rs"),
+                CovResult {
+                    lines: [(1, 0), (2, 1), (3, 0)].iter().cloned().collect(),
+                    branches: BTreeMap::new(),
+                    functions: [("run".to_string(), function(true))]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                    mcdc: None,
+                },
+            ),
+        ];
+
+        let markdown = results_to_markdown(&results);
+        let expected = "| File        | Lines | Functions | Coverage % |
+|-------------|-------|-----------|------------|
+| src/main.rs | 5/6   | 1/2       | 83.3%      |
+| src/lib.rs  | 1/3   | 1/1       | 33.3%      |
+| **Total**   | 6/9   | 2/3       | 66.7%      |
+";
+        assert_eq!(markdown, expected);
+    }
 }
@@ -8,6 +8,7 @@ use std::io::{self, BufRead, BufReader, Read};
 use std::num::ParseIntError;
 use std::path::Path;
 use std::str;
+use std::str::FromStr;
 
 use log::error;
 
@@ -19,6 +20,7 @@ use quick_xml::Reader;
 use rustc_hash::FxHashMap;
 
 use crate::defs::*;
+use crate::path_interner::{resolve_results, InternedResults, PathInterner};
 
 #[derive(Debug)]
 pub enum ParserError {
@@ -131,20 +133,100 @@ pub fn add_branch(branches: &mut BTreeMap<u32, Vec<bool>>, line_no: u32, no: u32
     };
 }
 
+// Adds a condition of an lcov MCDC record, made of the line and the number of conditions of its
+// decision, the sense of the condition, how many times it was taken with this sense, its index and
+// its expression. The first sense of the first condition starts a decision, and a condition is
+// covered when both of its senses are taken.
+fn add_mcdc_condition(decisions: &mut Vec<McdcRecord>, record: &str) -> Option<()> {
+    let mut fields = record.splitn(6, ',');
+    let line: u32 = fields.next()?.parse().ok()?;
+    let group_size: usize = fields.next()?.parse().ok()?;
+    let sense = fields.next()?;
+    let taken = fields.next()?;
+    let taken = taken != "-" && taken.parse::<u64>().ok()? > 0;
+    let index: usize = fields.next()?.parse().ok()?;
+    if index >= group_size || (sense != "t" && sense != "f") {
+        return None;
+    }
+
+    let starts_decision = match decisions.last() {
+        Some(last) => {
+            last.line != line || last.conditions.len() != group_size || (index == 0 && sense == "t")
+        }
+        None => true,
+    };
+    if starts_decision {
+        decisions.push(McdcRecord {
+            line,
+            conditions: vec![true; group_size],
+        });
+    }
+    decisions.last_mut().unwrap().conditions[index] &= taken;
+    Some(())
+}
+
 pub fn parse_lcov(
-    buffer: Vec<u8>,
+    buffer: &[u8],
     branch_enabled: bool,
 ) -> Result<Vec<(String, CovResult)>, ParserError> {
+    parse_lcov_interned(buffer, branch_enabled, &mut PathInterner::default()).map(resolve_results)
+}
+
+/// Like parse_lcov, with the paths of the records of the same file sharing one copy interned in
+/// `interner`.
+pub fn parse_lcov_interned(
+    buffer: &[u8],
+    branch_enabled: bool,
+    interner: &mut PathInterner,
+) -> Result<InternedResults, ParserError> {
+    parse_lcov_bytes(buffer.iter().copied(), branch_enabled, interner)
+}
+
+/// Parses an lcov report like parse_lcov, reading it as it goes instead of from a buffer, e.g. for
+/// the big reports of a zip archive.
+pub fn parse_lcov_reader<R: Read>(
+    reader: R,
+    branch_enabled: bool,
+) -> Result<Vec<(String, CovResult)>, ParserError> {
+    parse_lcov_reader_interned(reader, branch_enabled, &mut PathInterner::default())
+        .map(resolve_results)
+}
+
+/// Like parse_lcov_reader, with the paths interned as in parse_lcov_interned.
+pub fn parse_lcov_reader_interned<R: Read>(
+    reader: R,
+    branch_enabled: bool,
+    interner: &mut PathInterner,
+) -> Result<InternedResults, ParserError> {
+    let mut error = None;
+    let bytes = BufReader::new(reader)
+        .bytes()
+        .map_while(|byte| byte.map_err(|e| error = Some(e)).ok());
+    let results = parse_lcov_bytes(bytes, branch_enabled, interner);
+    match error {
+        Some(e) => Err(ParserError::Io(e)),
+        None => results,
+    }
+}
+
+fn parse_lcov_bytes<I: Iterator<Item = u8>>(
+    bytes: I,
+    branch_enabled: bool,
+    interner: &mut PathInterner,
+) -> Result<InternedResults, ParserError> {
     let mut cur_file = None;
-    let mut cur_lines = BTreeMap::new();
+    // The buffer of the SF records, whose paths are only copied when they are interned.
+    let mut sf_path = String::new();
+    let mut cur_lines = LineMap::new();
     let mut cur_branches = BTreeMap::new();
     let mut cur_functions = FxHashMap::default();
+    let mut cur_mcdc = Vec::new();
 
     // We only log the duplicated FN error once per parse_lcov call.
     let mut duplicated_error_logged = false;
 
     let mut results = Vec::new();
-    let iter = &mut buffer.iter().peekable();
+    let iter = &mut bytes.peekable();
 
     const SF: u32 = (b'S' as u32) * (1 << 8) + (b'F' as u32);
     const DA: u32 = (b'D' as u32) * (1 << 8) + (b'A' as u32);
@@ -157,12 +239,16 @@ pub fn parse_lcov(
         + (b'R' as u32) * (1 << 16)
         + (b'D' as u32) * (1 << 8)
         + (b'A' as u32);
+    const MCDC: u32 = (b'M' as u32) * (1 << 24)
+        + (b'C' as u32) * (1 << 16)
+        + (b'D' as u32) * (1 << 8)
+        + (b'C' as u32);
 
     let mut line = 0;
 
     while let Some(c) = iter.next() {
         line += 1;
-        match *c {
+        match c {
             b'e' => {
                 // we've a end_of_record
                 results.push((
@@ -171,53 +257,58 @@ pub fn parse_lcov(
                         lines: cur_lines,
                         branches: cur_branches,
                         functions: cur_functions,
+                        mcdc: if cur_mcdc.is_empty() {
+                            None
+                        } else {
+                            Some(cur_mcdc)
+                        },
                     },
                 ));
 
                 cur_file = None;
-                cur_lines = BTreeMap::new();
+                cur_lines = LineMap::new();
                 cur_branches = BTreeMap::new();
                 cur_functions = FxHashMap::default();
-                iter.take_while(|&&c| c != b'\n').last();
+                cur_mcdc = Vec::new();
+                iter.take_while(|&c| c != b'\n').last();
             }
             b'\n' => {
                 continue;
             }
             _ => {
-                if *c != b'S' && *c != b'D' && *c != b'F' && *c != b'B' {
-                    iter.take_while(|&&c| c != b'\n').last();
+                if c != b'S' && c != b'D' && c != b'F' && c != b'B' && c != b'M' {
+                    iter.take_while(|&c| c != b'\n').last();
                     continue;
                 }
 
                 let key = iter
-                    .take_while(|&&c| c != b':')
-                    .fold(*c as u32, |r, &x| r * (1 << 8) + u32::from(x));
+                    .take_while(|&c| c != b':')
+                    .fold(c as u32, |r, x| r * (1 << 8) + u32::from(x));
                 match key {
                     SF => {
                         // SF:string
-                        cur_file = Some(
-                            iter.take_while(|&&c| c != b'\n' && c != b'\r')
-                                .map(|&c| c as char)
-                                .collect(),
+                        sf_path.clear();
+                        sf_path.extend(
+                            iter.take_while(|&c| c != b'\n' && c != b'\r')
+                                .map(|c| c as char),
                         );
+                        cur_file = Some(interner.intern(&sf_path));
                     }
                     DA => {
                         // DA:uint,int
                         let line_no = iter
-                            .take_while(|&&c| c != b',')
-                            .fold(0, |r, &x| r * 10 + u32::from(x - b'0'));
+                            .take_while(|&c| c != b',')
+                            .fold(0, |r, x| r * 10 + u32::from(x - b'0'));
                         if iter.peek().is_none() {
                             return Err(ParserError::InvalidRecord(format!("DA at line {}", line)));
                         }
                         let execution_count = if let Some(c) = iter.next() {
-                            if *c == b'-' {
-                                iter.take_while(|&&c| c != b'\n').last();
+                            if c == b'-' {
+                                iter.take_while(|&c| c != b'\n').last();
                                 0
                             } else {
-                                iter.take_while(|&&c| c != b'\n' && c != b'\r')
-                                    .fold(u64::from(*c - b'0'), |r, &x| {
-                                        r * 10 + u64::from(x - b'0')
-                                    })
+                                iter.take_while(|&c| c != b'\n' && c != b'\r')
+                                    .fold(u64::from(c - b'0'), |r, x| r * 10 + u64::from(x - b'0'))
                             }
                         } else {
                             0
@@ -227,17 +318,18 @@ pub fn parse_lcov(
                     FN => {
                         // FN:int,string
                         let start = iter
-                            .take_while(|&&c| c != b',')
-                            .fold(0, |r, &x| r * 10 + u32::from(x - b'0'));
+                            .take_while(|&c| c != b',')
+                            .fold(0, |r, x| r * 10 + u32::from(x - b'0'));
                         if iter.peek().is_none() {
                             return Err(ParserError::InvalidRecord(format!("FN at line {}", line)));
                         }
                         let f_name: String = iter
-                            .take_while(|&&c| c != b'\n' && c != b'\r')
-                            .map(|&c| c as char)
+                            .take_while(|&c| c != b'\n' && c != b'\r')
+                            .map(|c| c as char)
                             .collect();
                         if !duplicated_error_logged && cur_functions.contains_key(&f_name) {
                             error!(
+                                path = cur_file.as_deref().unwrap(), reason = "duplicated function";
                                 "FN '{}' duplicated for '{}' in a lcov file",
                                 f_name,
                                 cur_file.as_ref().unwrap()
@@ -255,8 +347,8 @@ pub fn parse_lcov(
                     FNDA => {
                         // FNDA:int,string
                         let executed = iter
-                            .take_while(|&&c| c != b',')
-                            .fold(0, |r, &x| r * 10 + u64::from(x - b'0'));
+                            .take_while(|&c| c != b',')
+                            .fold(0, |r, x| r * 10 + u64::from(x - b'0'));
                         if iter.peek().is_none() {
                             return Err(ParserError::InvalidRecord(format!(
                                 "FNDA at line {}",
@@ -264,8 +356,8 @@ pub fn parse_lcov(
                             )));
                         }
                         let f_name: String = iter
-                            .take_while(|&&c| c != b'\n' && c != b'\r')
-                            .map(|&c| c as char)
+                            .take_while(|&c| c != b'\n' && c != b'\r')
+                            .map(|c| c as char)
                             .collect();
                         if let Some(f) = cur_functions.get_mut(&f_name) {
                             f.executed |= executed != 0;
@@ -280,8 +372,8 @@ pub fn parse_lcov(
                         // BRDA:int,int,int,int or -
                         if branch_enabled {
                             let line_no = iter
-                                .take_while(|&&c| c != b',')
-                                .fold(0, |r, &x| r * 10 + u32::from(x - b'0'));
+                                .take_while(|&c| c != b',')
+                                .fold(0, |r, x| r * 10 + u32::from(x - b'0'));
                             if iter.peek().is_none() {
                                 return Err(ParserError::InvalidRecord(format!(
                                     "BRDA at line {}",
@@ -289,8 +381,8 @@ pub fn parse_lcov(
                                 )));
                             }
                             let _block_number = iter
-                                .take_while(|&&c| c != b',')
-                                .fold(0, |r, &x| r * 10 + u64::from(x - b'0'));
+                                .take_while(|&c| c != b',')
+                                .fold(0, |r, x| r * 10 + u64::from(x - b'0'));
                             if iter.peek().is_none() {
                                 return Err(ParserError::InvalidRecord(format!(
                                     "BRDA at line {}",
@@ -298,8 +390,8 @@ pub fn parse_lcov(
                                 )));
                             }
                             let branch_number = iter
-                                .take_while(|&&c| c != b',')
-                                .fold(0, |r, &x| r * 10 + u32::from(x - b'0'));
+                                .take_while(|&c| c != b',')
+                                .fold(0, |r, x| r * 10 + u32::from(x - b'0'));
                             if iter.peek().is_none() {
                                 return Err(ParserError::InvalidRecord(format!(
                                     "BRDA at line {}",
@@ -307,15 +399,28 @@ pub fn parse_lcov(
                                 )));
                             }
                             let taken = iter
-                                .take_while(|&&c| c != b'\n' && c != b'\r')
-                                .any(|&x| x != b'-');
+                                .take_while(|&c| c != b'\n' && c != b'\r')
+                                .any(|x| x != b'-');
                             add_branch(&mut cur_branches, line_no, branch_number, taken);
                         } else {
-                            iter.take_while(|&&c| c != b'\n').last();
+                            iter.take_while(|&c| c != b'\n').last();
+                        }
+                    }
+                    MCDC => {
+                        // MCDC:int,int,t or f,int or -,int,string
+                        let record: String = iter
+                            .take_while(|&c| c != b'\n' && c != b'\r')
+                            .map(|c| c as char)
+                            .collect();
+                        if add_mcdc_condition(&mut cur_mcdc, &record).is_none() {
+                            return Err(ParserError::InvalidRecord(format!(
+                                "MCDC at line {}",
+                                line
+                            )));
                         }
                     }
                     _ => {
-                        iter.take_while(|&&c| c != b'\n').last();
+                        iter.take_while(|&c| c != b'\n').last();
                     }
                 }
             }
@@ -408,7 +513,11 @@ pub fn parse_gcov_gz(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, Parse
 
     let file = BufReader::new(&f);
     let gz = GzDecoder::new(file);
-    let mut gcov: GcovJson = serde_json::from_reader(gz).unwrap();
+    let gcov: GcovJson = serde_json::from_reader(gz).unwrap();
+    Ok(gcov_json_results(gcov))
+}
+
+fn gcov_json_results(mut gcov: GcovJson) -> Vec<(String, CovResult)> {
     let mut results = Vec::new();
 
     if gcov.format_version != "1" {
@@ -419,7 +528,7 @@ pub fn parse_gcov_gz(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, Parse
     }
 
     for mut file in gcov.files.drain(..) {
-        let mut lines = BTreeMap::new();
+        let mut lines = LineMap::new();
         let mut branches = BTreeMap::new();
         for mut line in file.lines.drain(..) {
             lines.insert(line.line_number, line.count);
@@ -449,16 +558,343 @@ pub fn parse_gcov_gz(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, Parse
                 lines,
                 branches,
                 functions,
+                mcdc: None,
             },
         ));
     }
 
-    Ok(results)
+    results
+}
+
+// The type of the JSON written by `llvm-cov export`, and the major versions of its format whose
+// files, segments and branches are read (version 3 added the MC/DC records).
+const LLVM_COV_EXPORT_TYPE: &str = "llvm.coverage.json.export";
+const LLVM_COV_EXPORT_MAJOR_VERSIONS: &[&str] = &["2", "3"];
+
+#[derive(Debug, Deserialize)]
+struct LlvmCovExport {
+    data: Vec<LlvmCovData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LlvmCovData {
+    files: Vec<LlvmCovFile>,
+    functions: Vec<LlvmCovFunction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LlvmCovFile {
+    filename: String,
+    segments: Vec<LlvmCovSegment>,
+    #[serde(default)]
+    branches: Vec<Vec<u64>>,
+    #[serde(default)]
+    mcdc_records: Vec<Vec<serde_json::Value>>,
+}
+
+// A segment is an array of its line, column, count, whether it has a count, whether it is the
+// entry of a region and whether it is the entry of a gap region.
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+struct LlvmCovSegment(u32, u32, u64, bool, bool, bool);
+
+impl LlvmCovSegment {
+    fn line(&self) -> u32 {
+        self.0
+    }
+
+    fn count(&self) -> u64 {
+        self.2
+    }
+
+    fn has_count(&self) -> bool {
+        self.3
+    }
+
+    fn is_region_entry(&self) -> bool {
+        self.4
+    }
+
+    fn is_start_of_region(&self) -> bool {
+        !self.5 && self.3 && self.4
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LlvmCovFunction {
+    name: String,
+    count: u64,
+    // Each region is an array of its start line, start column, end line, end column, count,
+    // file ID, expanded file ID and kind.
+    regions: Vec<Vec<u64>>,
+    filenames: Vec<String>,
+}
+
+// Computes the execution counts of the lines from the segments of a file, as `llvm-cov export
+// --format=lcov` does: a line is only instrumented when a region starts on it, or when it is
+// covered by a region started before it, and its count is the highest of these regions.
+fn llvm_cov_lines(segments: &[LlvmCovSegment]) -> LineMap {
+    let mut lines = LineMap::new();
+    let (first, last) = match (segments.first(), segments.last()) {
+        (Some(first), Some(last)) => (first.line(), last.line()),
+        _ => return lines,
+    };
+
+    let mut wrapped: Option<&LlvmCovSegment> = None;
+    let mut next = 0;
+    for line in first..=last {
+        let start = next;
+        while next < segments.len() && segments[next].line() == line {
+            next += 1;
+        }
+        let line_segments = &segments[start..next];
+
+        let start_of_skipped_region = line_segments
+            .first()
+            .is_some_and(|segment| !segment.has_count() && segment.is_region_entry());
+        let region_starts: Vec<&LlvmCovSegment> = line_segments
+            .iter()
+            .filter(|segment| segment.is_start_of_region())
+            .collect();
+        let mapped = !start_of_skipped_region
+            && (wrapped.is_some_and(LlvmCovSegment::has_count) || !region_starts.is_empty());
+        if mapped {
+            let count = region_starts
+                .iter()
+                .map(|segment| segment.count())
+                .chain(wrapped.map(LlvmCovSegment::count))
+                .max()
+                .unwrap_or(0);
+            lines.insert(line, count);
+        }
+
+        if let Some(segment) = line_segments.last() {
+            wrapped = Some(segment);
+        }
+    }
+    lines
+}
+
+// Computes the execution counts of the lines from the regions starting on them only, with the
+// highest count of these regions. The lines only covered by a region started before them aren't
+// instrumented.
+fn llvm_cov_region_lines(segments: &[LlvmCovSegment]) -> LineMap {
+    let mut lines = LineMap::new();
+    for segment in segments
+        .iter()
+        .filter(|segment| segment.is_start_of_region())
+    {
+        let count = lines.entry(segment.line()).or_insert(0);
+        *count = segment.count().max(*count);
+    }
+    lines
+}
+
+/// The coverage the lines of an llvm-cov JSON export get their execution counts from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineCoverageKind {
+    /// The lines where a region starts, or covered by a region started before them, as
+    /// `llvm-cov export --format=lcov` does. This is the default.
+    Line,
+    /// Only the lines where a region starts.
+    Region,
+    /// Only the first line of each function, with the count of the function.
+    Function,
+}
+
+impl FromStr for LineCoverageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "line" => Ok(LineCoverageKind::Line),
+            "region" => Ok(LineCoverageKind::Region),
+            "function" => Ok(LineCoverageKind::Function),
+            _ => Err(format!(
+                "{} is not a coverage kind, the kinds are line, region and function",
+                s
+            )),
+        }
+    }
+}
+
+// Returns the line of an MC/DC decision and whether the independence pair of each of its
+// conditions is covered. A record is an array starting with the start line, start column, end
+// line and end column of the decision, and ending with the array of its conditions.
+fn mcdc_conditions(record: &[serde_json::Value]) -> Option<(u32, Vec<bool>)> {
+    let line = record.first()?.as_u64()? as u32;
+    let conditions = record
+        .last()?
+        .as_array()?
+        .iter()
+        .map(serde_json::Value::as_bool)
+        .collect::<Option<Vec<bool>>>()?;
+    Some((line, conditions))
+}
+
+fn llvm_cov_export_results(
+    export: LlvmCovExport,
+    branch_enabled: bool,
+    mcdc_enabled: bool,
+    line_kind: LineCoverageKind,
+) -> Vec<(String, CovResult)> {
+    let mut results: BTreeMap<String, CovResult> = BTreeMap::new();
+    for data in export.data {
+        for file in data.files {
+            // The lines of the functions are only known once their files are.
+            let lines = match line_kind {
+                LineCoverageKind::Line => llvm_cov_lines(&file.segments),
+                LineCoverageKind::Region => llvm_cov_region_lines(&file.segments),
+                LineCoverageKind::Function => LineMap::new(),
+            };
+            if lines.is_empty() && line_kind != LineCoverageKind::Function {
+                continue;
+            }
+            let mut branches = BTreeMap::new();
+            if branch_enabled {
+                let mut file_branches: Vec<&Vec<u64>> =
+                    file.branches.iter().filter(|b| b.len() >= 6).collect();
+                file_branches.sort_by_key(|b| (b[0], b[1]));
+                for branch in file_branches {
+                    branches
+                        .entry(branch[0] as u32)
+                        .or_insert_with(Vec::new)
+                        .extend([branch[4] > 0, branch[5] > 0]);
+                }
+            }
+            let mcdc: Vec<McdcRecord> = file
+                .mcdc_records
+                .iter()
+                .filter_map(|r| mcdc_conditions(r))
+                .map(|(line, conditions)| McdcRecord { line, conditions })
+                .collect();
+            if mcdc_enabled {
+                // The conditions of the decisions replace the branches of their lines.
+                let mut decisions: BTreeMap<u32, Vec<bool>> = BTreeMap::new();
+                for record in &mcdc {
+                    decisions
+                        .entry(record.line)
+                        .or_default()
+                        .extend(&record.conditions);
+                }
+                branches.extend(decisions);
+            }
+            results.insert(
+                file.filename,
+                CovResult {
+                    lines,
+                    branches,
+                    functions: FxHashMap::default(),
+                    mcdc: if mcdc.is_empty() { None } else { Some(mcdc) },
+                },
+            );
+        }
+
+        for function in data.functions {
+            let region = match function.regions.first() {
+                Some(region) if region.len() >= 6 => region,
+                _ => continue,
+            };
+            let filename = match function.filenames.get(region[5] as usize) {
+                Some(filename) => filename,
+                None => continue,
+            };
+            if let Some(result) = results.get_mut(filename) {
+                if line_kind == LineCoverageKind::Function {
+                    let count = result.lines.entry(region[0] as u32).or_insert(0);
+                    *count = function.count.max(*count);
+                }
+                result.functions.insert(
+                    function.name,
+                    Function {
+                        start: region[0] as u32,
+                        executed: function.count > 0,
+                    },
+                );
+            }
+        }
+    }
+    results
+        .into_iter()
+        .filter(|(_, result)| !result.lines.is_empty())
+        .collect()
+}
+
+/// Parses a JSON coverage report written before, either the output of `llvm-cov export
+/// --format=text`, or the JSON intermediate format of gcov which grcov parses when it runs gcov.
+/// They are told apart by their version field: `version` for llvm-cov, `format_version` for gcov.
+/// With `mcdc_enabled`, the branches of the lines with an MC/DC decision are replaced by its
+/// conditions, covered when their independence pair is.
+pub fn parse_llvm_cov_json(
+    buffer: &[u8],
+    branch_enabled: bool,
+    mcdc_enabled: bool,
+) -> Result<Vec<(String, CovResult)>, ParserError> {
+    parse_llvm_cov_json_with_kind(buffer, branch_enabled, mcdc_enabled, LineCoverageKind::Line)
+}
+
+/// Like parse_llvm_cov_json, with the execution counts of the lines of the llvm-cov exports taken
+/// from the given kind of coverage. The kind doesn't change the gcov reports, whose lines are
+/// counted by gcov.
+pub fn parse_llvm_cov_json_with_kind(
+    buffer: &[u8],
+    branch_enabled: bool,
+    mcdc_enabled: bool,
+    line_kind: LineCoverageKind,
+) -> Result<Vec<(String, CovResult)>, ParserError> {
+    let value: serde_json::Value =
+        serde_json::from_slice(buffer).map_err(|e| ParserError::InvalidData(e.to_string()))?;
+
+    if value.get("format_version").is_some() {
+        let gcov: GcovJson =
+            serde_json::from_value(value).map_err(|e| ParserError::InvalidData(e.to_string()))?;
+        let mut results = gcov_json_results(gcov);
+        if !branch_enabled {
+            for (_, result) in &mut results {
+                result.branches.clear();
+            }
+        }
+        return Ok(results);
+    }
+
+    let version = match value.get("version").and_then(|version| version.as_str()) {
+        Some(version) => version,
+        None => {
+            return Err(ParserError::InvalidData(
+                "No format version in the JSON coverage report".to_string(),
+            ))
+        }
+    };
+    if value.get("type").and_then(|t| t.as_str()) != Some(LLVM_COV_EXPORT_TYPE) {
+        return Err(ParserError::InvalidData(format!(
+            "The JSON coverage report isn't of type {}",
+            LLVM_COV_EXPORT_TYPE
+        )));
+    }
+    if !version
+        .split('.')
+        .next()
+        .is_some_and(|major| LLVM_COV_EXPORT_MAJOR_VERSIONS.contains(&major))
+    {
+        return Err(ParserError::InvalidData(format!(
+            "Version {} of the llvm-cov export format is not supported",
+            version
+        )));
+    }
+
+    let export: LlvmCovExport =
+        serde_json::from_value(value).map_err(|e| ParserError::InvalidData(e.to_string()))?;
+    Ok(llvm_cov_export_results(
+        export,
+        branch_enabled,
+        mcdc_enabled,
+        line_kind,
+    ))
 }
 
 pub fn parse_gcov(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, ParserError> {
     let mut cur_file = None;
-    let mut cur_lines = BTreeMap::new();
+    let mut cur_lines = LineMap::new();
     let mut cur_branches = BTreeMap::new();
     let mut cur_functions = FxHashMap::default();
     let mut results = Vec::new();
@@ -494,12 +930,13 @@ pub fn parse_gcov(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, ParserEr
                             lines: cur_lines,
                             branches: cur_branches,
                             functions: cur_functions,
+                            mcdc: None,
                         },
                     ));
                 }
 
                 cur_file = Some(value.to_owned());
-                cur_lines = BTreeMap::new();
+                cur_lines = LineMap::new();
                 cur_branches = BTreeMap::new();
                 cur_functions = FxHashMap::default();
             }
@@ -545,6 +982,7 @@ pub fn parse_gcov(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, ParserEr
                 lines: cur_lines,
                 branches: cur_branches,
                 functions: cur_functions,
+                mcdc: None,
             },
         ));
     }
@@ -573,7 +1011,7 @@ fn parse_jacoco_report_sourcefile<T: BufRead>(
     parser: &mut Reader<T>,
     buf: &mut Vec<u8>,
 ) -> Result<JacocoReport, ParserError> {
-    let mut lines: BTreeMap<u32, u64> = BTreeMap::new();
+    let mut lines = LineMap::new();
     let mut branches: BTreeMap<u32, Vec<bool>> = BTreeMap::new();
 
     loop {
@@ -713,8 +1151,9 @@ fn parse_jacoco_report_package<T: BufRead>(
                             hash_map::Entry::Vacant(v) => {
                                 v.insert(CovResult {
                                     functions,
-                                    lines: BTreeMap::new(),
+                                    lines: LineMap::new(),
                                     branches: BTreeMap::new(),
+                                    mcdc: None,
                                 });
                             }
                         };
@@ -736,6 +1175,7 @@ fn parse_jacoco_report_package<T: BufRead>(
                                     functions: FxHashMap::default(),
                                     lines,
                                     branches,
+                                    mcdc: None,
                                 });
                             }
                         };
@@ -828,12 +1268,32 @@ mod tests {
         assert_eq!(l, "".as_bytes().to_vec());
     }
 
+    #[test]
+    fn test_lcov_parser_reader() {
+        let buf = std::fs::read("./test/prova.info").unwrap();
+        let results = parse_lcov_reader(File::open("./test/prova.info").unwrap(), true).unwrap();
+        assert_eq!(results, parse_lcov(&buf, true).unwrap());
+
+        // The errors of the reads are returned rather than parsing the report up to them.
+        struct FailingReader;
+        impl Read for FailingReader {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("disconnected"))
+            }
+        }
+        let reader = io::Cursor::new(&buf[..100]).chain(FailingReader);
+        assert!(matches!(
+            parse_lcov_reader(reader, true),
+            Err(ParserError::Io(_))
+        ));
+    }
+
     #[test]
     fn test_lcov_parser() {
         let mut f = File::open("./test/prova.info").expect("Failed to open lcov file");
         let mut buf = Vec::new();
         f.read_to_end(&mut buf).unwrap();
-        let results = parse_lcov(buf, false).unwrap();
+        let results = parse_lcov(&buf, false).unwrap();
 
         assert_eq!(results.len(), 603);
 
@@ -919,7 +1379,7 @@ mod tests {
         let mut f = File::open("./test/prova.info").expect("Failed to open lcov file");
         let mut buf = Vec::new();
         f.read_to_end(&mut buf).unwrap();
-        let results = parse_lcov(buf, true).unwrap();
+        let results = parse_lcov(&buf, true).unwrap();
 
         assert_eq!(results.len(), 603);
 
@@ -1018,7 +1478,7 @@ mod tests {
             File::open("./test/prova_fn_with_commas.info").expect("Failed to open lcov file");
         let mut buf = Vec::new();
         f.read_to_end(&mut buf).unwrap();
-        let results = parse_lcov(buf, true).unwrap();
+        let results = parse_lcov(&buf, true).unwrap();
 
         assert_eq!(results.len(), 1);
 
@@ -1109,7 +1569,7 @@ mod tests {
         let mut f = File::open("./test/empty_line.info").expect("Failed to open lcov file");
         let mut buf = Vec::new();
         f.read_to_end(&mut buf).unwrap();
-        let results = parse_lcov(buf, true).unwrap();
+        let results = parse_lcov(&buf, true).unwrap();
 
         assert_eq!(results.len(), 1);
 
@@ -1201,10 +1661,37 @@ mod tests {
         let mut f = File::open("./test/invalid_DA_record.info").expect("Failed to open lcov file");
         let mut buf = Vec::new();
         f.read_to_end(&mut buf).unwrap();
-        let result = parse_lcov(buf, true);
+        let result = parse_lcov(&buf, true);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_lcov_parser_mcdc() {
+        // Two decisions on line 3: a condition is covered when both of its senses are taken.
+        let lcov =
+            b"SF:a.c\nMCDC:3,2,t,1,0,a\nMCDC:3,2,f,2,0,a\nMCDC:3,2,t,1,1,b\nMCDC:3,2,f,-,1,b\n\
+                     MCDC:3,2,t,0,0,c\nMCDC:3,2,f,0,0,c\nMCDC:3,2,t,3,1,d\nMCDC:3,2,f,1,1,d\n\
+                     MCF:8\nMCH:5\nDA:3,1\nend_of_record\nSF:b.c\nDA:1,1\nend_of_record\n";
+        let results = parse_lcov(lcov, false).unwrap();
+        assert_eq!(
+            results[0].1.mcdc,
+            Some(vec![
+                McdcRecord {
+                    line: 3,
+                    conditions: vec![true, false],
+                },
+                McdcRecord {
+                    line: 3,
+                    conditions: vec![false, true],
+                },
+            ])
+        );
+        assert_eq!(results[1].1.mcdc, None);
+
+        assert!(parse_lcov(b"SF:a.c\nMCDC:3,2,t,1,2,a\nend_of_record\n", false).is_err());
+        assert!(parse_lcov(b"SF:a.c\nMCDC:3,2,x,1,0,a\nend_of_record\n", false).is_err());
+    }
+
     #[test]
     fn test_parser() {
         let results = parse_gcov(Path::new("./test/prova.gcov")).unwrap();
@@ -1932,9 +2419,135 @@ mod tests {
         assert!(!func.executed);
     }
 
+    #[test]
+    fn test_parse_llvm_cov_json() {
+        let json = std::fs::read("./test/llvm_cov_export.json").unwrap();
+        let lcov = std::fs::read("./test/llvm_cov_export.info").unwrap();
+
+        // The JSON export has the same lines and functions as the lcov export of the same profile.
+        assert_eq!(
+            parse_llvm_cov_json(&json, false, false).unwrap(),
+            parse_lcov(&lcov, false).unwrap()
+        );
+
+        let results = parse_llvm_cov_json(&json, true, false).unwrap();
+        assert_eq!(results.len(), 1);
+        let (ref source_name, ref result) = results[0];
+        assert_eq!(source_name, "main.rs");
+        assert_eq!(result.lines.get(&9), Some(&0));
+        assert_eq!(result.lines.get(&4), None);
+        assert_eq!(result.branches.get(&2), Some(&vec![true, true]));
+        assert_eq!(result.branches.get(&15), Some(&vec![false, true]));
+    }
+
+    #[test]
+    fn test_parse_llvm_cov_json_line_kinds() {
+        let json = std::fs::read("./test/llvm_cov_export.json").unwrap();
+        let lines = |line_kind| -> Vec<(u32, u64)> {
+            let results = parse_llvm_cov_json_with_kind(&json, true, false, line_kind).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].1.functions.len(), 3);
+            results[0].1.lines.iter().map(|(&l, &c)| (l, c)).collect()
+        };
+
+        assert_eq!(
+            parse_llvm_cov_json_with_kind(&json, true, false, LineCoverageKind::Line).unwrap(),
+            parse_llvm_cov_json(&json, true, false).unwrap()
+        );
+        // Line 16 is only covered by the region started on line 15, and line 14 starts regions
+        // run 3 and 1 times.
+        assert_eq!(
+            lines(LineCoverageKind::Region),
+            vec![
+                (1, 3),
+                (2, 3),
+                (3, 2),
+                (5, 1),
+                (7, 3),
+                (9, 0),
+                (10, 0),
+                (11, 0),
+                (13, 1),
+                (14, 3),
+                (15, 3),
+                (17, 3),
+                (18, 3),
+                (20, 1)
+            ]
+        );
+        assert!(lines(LineCoverageKind::Line).contains(&(16, 0)));
+        assert_eq!(
+            lines(LineCoverageKind::Function),
+            vec![(1, 3), (9, 0), (13, 1)]
+        );
+
+        assert_eq!(
+            "region".parse::<LineCoverageKind>(),
+            Ok(LineCoverageKind::Region)
+        );
+        assert!("instantiation".parse::<LineCoverageKind>().is_err());
+    }
+
+    #[test]
+    fn test_parse_llvm_cov_json_mcdc() {
+        // The export of a C function with the `a && b` decision, built with -fcoverage-mcdc and
+        // called with (1, 0) and (1, 1): only the independence pair of `b` is covered.
+        let json = std::fs::read("./test/llvm_cov_export_mcdc.json").unwrap();
+
+        let results = parse_llvm_cov_json(&json, true, false).unwrap();
+        assert_eq!(
+            results[0].1.branches.get(&2),
+            Some(&vec![true, false, true, true])
+        );
+
+        let results = parse_llvm_cov_json(&json, true, true).unwrap();
+        assert_eq!(results.len(), 1);
+        let (ref source_name, ref result) = results[0];
+        assert_eq!(source_name, "check.c");
+        assert_eq!(result.branches.get(&2), Some(&vec![false, true]));
+        assert_eq!(result.functions.len(), 2);
+        assert!(result.functions["check"].executed);
+    }
+
+    #[test]
+    fn test_parse_llvm_cov_json_gcov_format() {
+        let mut json = Vec::new();
+        GzDecoder::new(File::open("./test/mozillavpn_serverconnection.gcno.gcov.json.gz").unwrap())
+            .read_to_end(&mut json)
+            .unwrap();
+        let expected = parse_gcov_gz(Path::new(
+            "./test/mozillavpn_serverconnection.gcno.gcov.json.gz",
+        ))
+        .unwrap();
+        assert_eq!(parse_llvm_cov_json(&json, true, false).unwrap(), expected);
+
+        let results = parse_llvm_cov_json(&json, false, false).unwrap();
+        assert_eq!(results.len(), expected.len());
+        assert!(results.iter().all(|(_, result)| result.branches.is_empty()));
+    }
+
+    #[test]
+    fn test_parse_llvm_cov_json_invalid() {
+        for json in [
+            r#"{"data":[],"type":"llvm.coverage.json.export","version":"4.0.0"}"#,
+            r#"{"data":[],"type":"llvm.coverage.json.export"}"#,
+            r#"{"data":[],"type":"other","version":"2.0.1"}"#,
+            "not json",
+        ] {
+            assert!(parse_llvm_cov_json(json.as_bytes(), false, false).is_err());
+        }
+        assert!(parse_llvm_cov_json(
+            br#"{"data":[],"type":"llvm.coverage.json.export","version":"2.0.0"}"#,
+            false,
+            false,
+        )
+        .unwrap()
+        .is_empty());
+    }
+
     #[test]
     fn test_parser_jacoco_xml_basic() {
-        let mut lines: BTreeMap<u32, u64> = BTreeMap::new();
+        let mut lines = LineMap::new();
         lines.insert(1, 0);
         lines.insert(4, 1);
         lines.insert(6, 1);
@@ -1961,6 +2574,7 @@ mod tests {
                 lines,
                 branches,
                 functions,
+                mcdc: None,
             },
         )];
 
@@ -1973,7 +2587,7 @@ mod tests {
 
     #[test]
     fn test_parser_jacoco_xml_inner_classes() {
-        let mut lines: BTreeMap<u32, u64> = BTreeMap::new();
+        let mut lines = LineMap::new();
         for i in &[5, 10, 14, 15, 18, 22, 23, 25, 27, 31, 34, 37, 44, 49] {
             lines.insert(*i, 0);
         }
@@ -2008,6 +2622,7 @@ mod tests {
                 lines,
                 branches,
                 functions,
+                mcdc: None,
             },
         )];
 
@@ -0,0 +1,82 @@
+//! The interning of the paths of the results parsed from one input: the records of the same file,
+//! e.g. of a header for every compilation unit including it, or of a source file for every binary
+//! exported from the same profiles, share one copy of its path until they are merged.
+
+use rustc_hash::FxHashSet;
+use std::sync::Arc;
+
+use crate::defs::CovResult;
+
+/// The results of one input, with their interned paths.
+pub type InternedResults = Vec<(Arc<str>, CovResult)>;
+
+/// The distinct paths of the results of an input.
+#[derive(Debug, Default)]
+pub struct PathInterner(FxHashSet<Arc<str>>);
+
+impl PathInterner {
+    /// Returns the interned copy of `path`, stored the first time it is seen.
+    pub fn intern(&mut self, path: &str) -> Arc<str> {
+        if let Some(interned) = self.0.get(path) {
+            return Arc::clone(interned);
+        }
+        let interned: Arc<str> = Arc::from(path);
+        self.0.insert(Arc::clone(&interned));
+        interned
+    }
+
+    /// Interns the paths of results parsed with owned paths.
+    pub fn intern_results(&mut self, results: Vec<(String, CovResult)>) -> InternedResults {
+        results
+            .into_iter()
+            .map(|(path, result)| (self.intern(&path), result))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The number of bytes of the distinct paths.
+    pub fn path_bytes(&self) -> usize {
+        self.0.iter().map(|path| path.len()).sum()
+    }
+}
+
+/// Turns the interned paths of results back into owned ones.
+pub fn resolve_results(results: InternedResults) -> Vec<(String, CovResult)> {
+    results
+        .into_iter()
+        .map(|(path, result)| (path.to_string(), result))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_interner() {
+        let mut interner = PathInterner::default();
+        let results = interner.intern_results(vec![
+            ("src/lib.rs".to_string(), CovResult::default()),
+            ("src/main.rs".to_string(), CovResult::default()),
+            ("src/lib.rs".to_string(), CovResult::default()),
+        ]);
+        assert_eq!(interner.len(), 2);
+        assert_eq!(interner.path_bytes(), "src/lib.rssrc/main.rs".len());
+        assert!(Arc::ptr_eq(&results[0].0, &results[2].0));
+        assert!(Arc::ptr_eq(&results[0].0, &interner.intern("src/lib.rs")));
+        assert_eq!(
+            resolve_results(results)
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<_>>(),
+            vec!["src/lib.rs", "src/main.rs", "src/lib.rs"]
+        );
+    }
+}
@@ -1,15 +1,24 @@
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use globset::{Candidate, Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::Gitignore;
+use log::{debug, warn};
+use rayon::iter::Either;
 use rayon::prelude::*;
 use rustc_hash::FxHashMap;
+use serde::Serialize;
 use serde_json::Value;
+use std::borrow::Cow;
 use std::collections::hash_map;
+use std::env;
+use std::fmt;
 use std::fs;
 use std::io;
 use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
 use walkdir::{DirEntry, WalkDir};
 
 use crate::defs::*;
 use crate::filter::*;
+use crate::path_utils::strip_filename_prefix;
 
 fn to_lowercase_first(s: &str) -> String {
     let mut c = s.chars();
@@ -35,6 +44,47 @@ pub fn canonicalize_path<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
     Ok(path)
 }
 
+fn has_drive_letter(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && (bytes.len() == 2 || bytes[2] == b'/' || bytes[2] == b'\\')
+}
+
+// Whether a path is one of Windows, whose backslashes are separators: on other platforms, a
+// backslash is a valid character of a file name, so only the paths with a drive letter or a UNC
+// prefix are.
+fn is_windows_path(path: &str) -> bool {
+    cfg!(windows) || has_drive_letter(path) || path.starts_with(r"\\")
+}
+
+/// Puts the path of a source file, as the parsers read it, in the form the path rewriting works
+/// on: with '/' as the separator, and an uppercase drive letter. llvm-cov writes the paths of
+/// Windows with backslashes, and sometimes with a lowercase drive letter, which the globs and the
+/// prefixes given as `C:/...` wouldn't match otherwise.
+pub fn normalize_source_path(path: String) -> String {
+    let mut path = if path.contains('\\') && is_windows_path(&path) {
+        path.replace('\\', "/")
+    } else {
+        path
+    };
+    if has_drive_letter(&path) {
+        path[..1].make_ascii_uppercase();
+    }
+    path
+}
+
+// The --ignore and --keep-only globs of Windows paths are matched in the same form as the paths,
+// whichever separator and drive letter case they were written with.
+fn normalize_glob(glob: &str) -> Cow<'_, str> {
+    if has_drive_letter(glob) {
+        Cow::Owned(normalize_source_path(glob.to_string()))
+    } else {
+        Cow::Borrowed(glob)
+    }
+}
+
 pub fn has_no_parent(path: &str) -> bool {
     PathBuf::from(path).parent() == Some(&PathBuf::from(""))
 }
@@ -58,10 +108,12 @@ pub fn normalize_path<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
             Component::CurDir => {}
             Component::ParentDir => {
                 if !ret.pop() {
-                    eprintln!(
-                        "Warning: {:?} cannot be normalized because of \"..\", so skip it.",
-                        path.as_ref()
-                    );
+                    crate::suspend_progress(|| {
+                        eprintln!(
+                            "Warning: {:?} cannot be normalized because of \"..\", so skip it.",
+                            path.as_ref()
+                        )
+                    });
                     return None;
                 }
             }
@@ -103,16 +155,78 @@ fn guess_abs_path(prefix_dir: &Path, path: &Path) -> PathBuf {
 }
 
 // Remove prefix from the source file's path.
-fn remove_prefix(prefix_dir: Option<&Path>, path: PathBuf) -> PathBuf {
-    if let Some(prefix_dir) = prefix_dir {
-        if path.starts_with(prefix_dir) {
-            return path.strip_prefix(prefix_dir).unwrap().to_path_buf();
-        }
+/// Parses a path prefix mapping rule, given as `FROM=TO`.
+pub fn parse_prefix_mapping(rule: &str) -> Result<(String, String), String> {
+    match rule.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok((from.to_string(), to.to_string())),
+        _ => Err(format!("{} is not a FROM=TO path prefix mapping", rule)),
     }
+}
+
+// Normalize the prefixes of the rules, so that they can be compared with the normalized paths
+// of the files, and resolve the symlinks of the replacements which exist, as the source
+// directory is.
+fn canonicalize_prefix_mapping(prefix_mapping: &[(String, String)]) -> Vec<(PathBuf, PathBuf)> {
+    prefix_mapping
+        .iter()
+        .filter_map(|(from, to)| {
+            let from = normalize_path(normalize_source_path(from.clone()))?;
+            let to = canonicalize_path(to).unwrap_or_else(|_| PathBuf::from(to));
+            Some((from, to))
+        })
+        .collect()
+}
 
+// Replace the prefix of the source file's path with the replacement of the first rule whose
+// prefix it starts with.
+fn apply_prefix_mapping(prefix_mapping: &[(PathBuf, PathBuf)], path: PathBuf) -> PathBuf {
+    if prefix_mapping.is_empty() {
+        return path;
+    }
+    let normalized = match normalize_path(&path) {
+        Some(normalized) => normalized,
+        None => return path,
+    };
+    for (from, to) in prefix_mapping {
+        if let Ok(rest) = normalized.strip_prefix(from) {
+            return to.join(rest);
+        }
+    }
     path
 }
 
+// Remove the first `strip_components` components from the source file's path, like tar's
+// --strip-components.
+fn strip_path_components(strip_components: usize, path: PathBuf) -> PathBuf {
+    if strip_components == 0 {
+        return path;
+    }
+
+    let components: Vec<&std::ffi::OsStr> = path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(c) => Some(c),
+            _ => None,
+        })
+        .collect();
+
+    if path.is_relative() && components.len() <= 1 {
+        return path;
+    }
+
+    if components.len() <= strip_components {
+        debug!(
+            "Not stripping {} components from {}, as it only has {}",
+            strip_components,
+            path.display(),
+            components.len()
+        );
+        return path;
+    }
+
+    components[strip_components..].iter().collect()
+}
+
 fn fixup_rel_path(source_dir: Option<&Path>, abs_path: &Path, rel_path: PathBuf) -> PathBuf {
     if let Some(ref source_dir) = source_dir {
         if abs_path.starts_with(source_dir) {
@@ -219,100 +333,448 @@ fn to_globset(dirs: &[impl AsRef<str>]) -> GlobSet {
     let mut glob_builder = GlobSetBuilder::new();
 
     for dir in dirs {
-        glob_builder.add(Glob::new(dir.as_ref()).unwrap());
+        glob_builder.add(Glob::new(&normalize_glob(dir.as_ref())).unwrap());
     }
 
     glob_builder.build().unwrap()
 }
 
-pub fn rewrite_paths(
-    result_map: CovResultMap,
-    path_mapping: Option<Value>,
-    source_dir: Option<&Path>,
-    prefix_dir: Option<&Path>,
-    ignore_not_existing: bool,
-    to_ignore_dirs: &[impl AsRef<str>],
-    to_keep_dirs: &[impl AsRef<str>],
-    filter_option: Option<bool>,
-    file_filter: crate::FileFilter,
-) -> Vec<ResultTuple> {
-    let to_ignore_globset = to_globset(to_ignore_dirs);
-    let to_keep_globset = to_globset(to_keep_dirs);
+// The --ignore and --keep-only globs, each compiled once in a single set. The results of all the
+// inputs are merged by source file before their paths are rewritten, so each path is only matched
+// once.
+struct PathGlobs<'a, S: AsRef<str>> {
+    to_ignore_dirs: &'a [S],
+    to_ignore_globset: GlobSet,
+    to_keep_globset: GlobSet,
+}
 
-    if let Some(p) = &source_dir {
-        assert!(p.is_absolute());
+impl<'a, S: AsRef<str>> PathGlobs<'a, S> {
+    fn new(to_ignore_dirs: &'a [S], to_keep_dirs: &[impl AsRef<str>]) -> Self {
+        PathGlobs {
+            to_ignore_dirs,
+            to_ignore_globset: to_globset(to_ignore_dirs),
+            to_keep_globset: to_globset(to_keep_dirs),
+        }
     }
 
-    // Traverse source dir and store all paths, reversed.
-    let mut file_to_paths: FxHashMap<String, Vec<PathBuf>> = FxHashMap::default();
-    if let Some(ref source_dir) = source_dir {
-        for entry in WalkDir::new(source_dir)
-            .into_iter()
-            .filter_entry(|e| !is_hidden(e) && !is_symbolic_link(e))
-        {
-            let entry = entry
-                .unwrap_or_else(|_| panic!("Failed to open directory '{}'.", source_dir.display()));
+    fn is_ignored(&self, path: &Path) -> bool {
+        self.to_ignore_globset.is_match(path)
+    }
 
-            let full_path = entry.path();
-            if !full_path.is_file() {
-                continue;
+    fn has_keep_globs(&self) -> bool {
+        !self.to_keep_globset.is_empty()
+    }
+
+    // Returns whether the relative path of a source file is left out by an --ignore glob, or by
+    // not matching any --keep-only glob. The path is only converted once for both sets.
+    fn check(&self, rel_path: &Path) -> Result<(), SkipReason> {
+        let candidate = Candidate::new(rel_path);
+        match self.to_ignore_globset.matches_candidate(&candidate).first() {
+            Some(&index) => Err(SkipReason::Ignored(
+                self.to_ignore_dirs[index].as_ref().to_string(),
+            )),
+            None if self.has_keep_globs()
+                && !self.to_keep_globset.is_match_candidate(&candidate) =>
+            {
+                Err(SkipReason::NotKept)
             }
+            None => Ok(()),
+        }
+    }
+}
+
+const IGNORE_FILE_NAME: &str = ".grcovignore";
+
+// The .grcovignore files found in the source directory, the deepest ones first.
+#[derive(Default)]
+struct IgnoreFiles(Vec<Gitignore>);
 
-            let path = full_path.strip_prefix(source_dir).unwrap().to_path_buf();
-            if to_ignore_globset.is_match(&path) {
+impl IgnoreFiles {
+    fn add(&mut self, path: &Path) {
+        let (gitignore, err) = Gitignore::new(path);
+        if let Some(err) = err {
+            warn!(
+                path = path.display().to_string(), reason = err.to_string();
+                "Error while parsing {}: {}", path.display(), err
+            );
+        }
+        self.0.push(gitignore);
+    }
+
+    fn sort(&mut self) {
+        self.0
+            .sort_by_key(|gitignore| std::cmp::Reverse(gitignore.path().components().count()));
+    }
+
+    // As with git, the patterns in the deepest files take precedence.
+    fn is_ignored(&self, path: &Path) -> bool {
+        for gitignore in &self.0 {
+            if !path.starts_with(gitignore.path()) {
                 continue;
             }
-
-            let name = entry.file_name().to_str().unwrap().to_string();
-            match file_to_paths.entry(name) {
-                hash_map::Entry::Occupied(f) => f.into_mut().push(path),
-                hash_map::Entry::Vacant(v) => {
-                    v.insert(vec![path]);
-                }
-            };
+            let m = gitignore.matched_path_or_any_parents(path, false);
+            if m.is_ignore() {
+                return true;
+            }
+            if m.is_whitelist() {
+                return false;
+            }
         }
+        false
     }
+}
 
-    let results = result_map
-        .into_par_iter()
-        .filter_map(move |(path, mut result)| {
-            let path = path.replace('\\', "/");
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
 
-            // Get path from the mapping.
-            let rel_path = apply_mapping(&path_mapping, &path);
+/// Expands a leading `~` to the home directory.
+pub fn expand_home_dir(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
 
-            // Remove prefix from the path.
-            let rel_path = remove_prefix(prefix_dir, rel_path);
+/// Returns the root of the git repository the path is in (the closest ancestor with a `.git`,
+/// which is a file in the worktrees and submodules), if there is one.
+pub fn git_repository_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(Path::to_path_buf)
+}
 
-            // Try mapping a partial path to a full path.
-            let rel_path = if check_extension(&rel_path, "java") {
-                map_partial_path(&file_to_paths, rel_path)
-            } else {
-                rel_path
-            };
+// The git repositories of the directories of the source files, for --repo-relative.
+#[derive(Default)]
+struct RepoRoots(Mutex<FxHashMap<PathBuf, Option<PathBuf>>>);
+
+impl RepoRoots {
+    // Makes an absolute path relative to the root of its repository, or leaves it absolute when
+    // it isn't in a repository.
+    fn relative_path(&self, path: PathBuf) -> PathBuf {
+        let dir = match path.parent() {
+            Some(dir) => dir,
+            None => return path,
+        };
+        let root = self
+            .0
+            .lock()
+            .unwrap()
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| git_repository_root(dir))
+            .clone();
+        match root.and_then(|root| path.strip_prefix(root).ok().map(Path::to_path_buf)) {
+            Some(rel_path) => rel_path,
+            None => path,
+        }
+    }
+}
 
-            // Get absolute path to the source file.
-            let (abs_path, rel_path) = get_abs_path(source_dir, rel_path)?;
+/// The directories of the toolchains and of the system, whose sources are usually not wanted in
+/// the reports.
+pub fn default_system_paths() -> Vec<PathBuf> {
+    let rustup_home = env::var_os("RUSTUP_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| expand_home_dir(Path::new("~/.rustup")));
+    let cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| expand_home_dir(Path::new("~/.cargo")));
+    vec![rustup_home, cargo_home, PathBuf::from("/usr/lib/rustlib")]
+}
 
-            if to_ignore_globset.is_match(&rel_path) {
-                return None;
+/// Why a source file is left out of the report by the path rewriting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// The file is under a directory dropped by --strip-system-paths.
+    SystemPath(PathBuf),
+    /// The path has more `..` components than parent directories.
+    NotNormalizable,
+    /// The file matches an --ignore glob.
+    Ignored(String),
+    /// The file doesn't match any --keep-only glob.
+    NotKept,
+    /// The file is ignored by a .grcovignore file.
+    Grcovignore,
+    /// The file doesn't exist, with --ignore-not-existing.
+    NotExisting,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::SystemPath(prefix) => {
+                write!(f, "under the system path {}", prefix.display())
             }
+            SkipReason::NotNormalizable => write!(f, "can't be normalized"),
+            SkipReason::Ignored(glob) => write!(f, "matches the --ignore glob '{}'", glob),
+            SkipReason::NotKept => write!(f, "doesn't match any --keep-only glob"),
+            SkipReason::Grcovignore => write!(f, "ignored by a {} file", IGNORE_FILE_NAME),
+            SkipReason::NotExisting => write!(f, "doesn't exist"),
+        }
+    }
+}
+
+/// What the path rewriting does with the path of a source file, as explained by --dry-run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum PathAction {
+    /// The path is kept as it is.
+    Kept,
+    /// The path is rewritten, e.g. made relative to the source directory.
+    Remapped {
+        abs_path: PathBuf,
+        rel_path: PathBuf,
+    },
+    /// The file is left out of the report.
+    Skipped { reason: SkipReason },
+}
+
+/// The path of a source file, along with what the path rewriting does with it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PathExplanation {
+    pub path: String,
+    #[serde(flatten)]
+    pub action: PathAction,
+}
+
+// The state of the path rewriting shared by all the source files: the globs, and the files and
+// .grcovignore files found in the source directory.
+struct PathRewriter<'a> {
+    path_mapping: Option<Value>,
+    prefix_mapping: Vec<(PathBuf, PathBuf)>,
+    source_dir: Option<&'a Path>,
+    prefix_dir: Option<PathBuf>,
+    strip_components: usize,
+    ignore_not_existing: bool,
+    globs: PathGlobs<'a, &'a str>,
+    system_paths: &'a [PathBuf],
+    file_to_paths: FxHashMap<String, Vec<PathBuf>>,
+    ignore_files: IgnoreFiles,
+    repo_roots: Option<RepoRoots>,
+}
+
+impl<'a> PathRewriter<'a> {
+    fn new(options: &RewriteOptions<'a>) -> Self {
+        let &RewriteOptions {
+            ref path_mapping,
+            prefix_mapping,
+            source_dir,
+            prefix_dir,
+            strip_components,
+            ignore_not_existing,
+            to_ignore_dirs,
+            to_keep_dirs,
+            use_ignore_files,
+            system_paths,
+            repo_relative,
+            ..
+        } = options;
+        let globs = PathGlobs::new(to_ignore_dirs, to_keep_dirs);
+        let prefix_mapping = canonicalize_prefix_mapping(prefix_mapping);
+
+        if let Some(p) = &source_dir {
+            assert!(p.is_absolute());
+        }
+
+        // Traverse source dir and store all paths, reversed.
+        let mut file_to_paths: FxHashMap<String, Vec<PathBuf>> = FxHashMap::default();
+        let mut ignore_files = IgnoreFiles::default();
+        if let Some(ref source_dir) = source_dir {
+            for entry in WalkDir::new(source_dir)
+                .into_iter()
+                .filter_entry(|e| !is_hidden(e) && !is_symbolic_link(e))
+            {
+                let entry = entry.unwrap_or_else(|_| {
+                    panic!("Failed to open directory '{}'.", source_dir.display())
+                });
+
+                let full_path = entry.path();
+                if !full_path.is_file() {
+                    let ignore_file = full_path.join(IGNORE_FILE_NAME);
+                    if use_ignore_files && ignore_file.is_file() {
+                        ignore_files.add(&ignore_file);
+                    }
+                    continue;
+                }
+
+                let path = full_path.strip_prefix(source_dir).unwrap().to_path_buf();
+                if globs.is_ignored(&path) {
+                    continue;
+                }
 
-            if !to_keep_globset.is_empty() && !to_keep_globset.is_match(&rel_path) {
-                return None;
+                let name = entry.file_name().to_str().unwrap().to_string();
+                match file_to_paths.entry(name) {
+                    hash_map::Entry::Occupied(f) => f.into_mut().push(path),
+                    hash_map::Entry::Vacant(v) => {
+                        v.insert(vec![path]);
+                    }
+                };
             }
+            ignore_files.sort();
+        }
 
-            if ignore_not_existing && !abs_path.exists() {
-                return None;
+        PathRewriter {
+            path_mapping: path_mapping.clone(),
+            prefix_mapping,
+            source_dir,
+            prefix_dir: prefix_dir
+                .map(|dir| PathBuf::from(normalize_source_path(dir.display().to_string()))),
+            strip_components,
+            ignore_not_existing,
+            globs,
+            system_paths,
+            file_to_paths,
+            ignore_files,
+            repo_roots: if repo_relative {
+                Some(RepoRoots::default())
+            } else {
+                None
+            },
+        }
+    }
+
+    // Returns the absolute and relative paths of a source file, or why it is skipped.
+    fn rewrite(&self, path: &str) -> Result<(PathBuf, PathBuf), SkipReason> {
+        let path = normalize_source_path(path.to_string());
+
+        if let Some(system_path) = self
+            .system_paths
+            .iter()
+            .find(|system_path| Path::new(&path).starts_with(system_path))
+        {
+            return Err(SkipReason::SystemPath(system_path.clone()));
+        }
+
+        // Get path from the mapping.
+        let rel_path = apply_mapping(&self.path_mapping, &path);
+
+        // Replace the prefix of the path with the first matching rule.
+        let rel_path = apply_prefix_mapping(&self.prefix_mapping, rel_path);
+
+        // Remove prefix from the path.
+        let rel_path = match &self.prefix_dir {
+            Some(prefix_dir) => strip_filename_prefix(rel_path, std::slice::from_ref(prefix_dir)),
+            None => rel_path,
+        };
+
+        // Remove the leading components from the path.
+        let rel_path = strip_path_components(self.strip_components, rel_path);
+
+        // Try mapping a partial path to a full path.
+        let rel_path = if check_extension(&rel_path, "java") {
+            map_partial_path(&self.file_to_paths, rel_path)
+        } else {
+            rel_path
+        };
+
+        // Get absolute path to the source file.
+        let (abs_path, rel_path) =
+            get_abs_path(self.source_dir, rel_path).ok_or(SkipReason::NotNormalizable)?;
+
+        // The paths left absolute are made relative to their git repository.
+        let rel_path = match &self.repo_roots {
+            Some(repo_roots) if rel_path.is_absolute() => repo_roots.relative_path(rel_path),
+            _ => rel_path,
+        };
+
+        self.globs.check(&rel_path)?;
+
+        // The files explicitly kept from the command line can't be ignored through
+        // .grcovignore files.
+        if !self.globs.has_keep_globs() && rel_path.is_relative() {
+            if let Some(source_dir) = self.source_dir {
+                if self.ignore_files.is_ignored(&source_dir.join(&rel_path)) {
+                    return Err(SkipReason::Grcovignore);
+                }
             }
+        }
 
-            // Always return results with '/'.
-            let rel_path = PathBuf::from(rel_path.to_str().unwrap().replace('\\', "/"));
+        if self.ignore_not_existing && !abs_path.exists() {
+            debug!(
+                path = abs_path.display().to_string(), reason = "not existing";
+                "Skipping {}, which doesn't exist", abs_path.display()
+            );
+            return Err(SkipReason::NotExisting);
+        }
+
+        // Always return results with '/'.
+        let rel_path = PathBuf::from(rel_path.to_str().unwrap().replace('\\', "/"));
+
+        Ok((abs_path, rel_path))
+    }
+}
+
+/// The options of the path rewriting of the source files.
+pub struct RewriteOptions<'a> {
+    pub path_mapping: Option<Value>,
+    pub prefix_mapping: &'a [(String, String)],
+    pub source_dir: Option<&'a Path>,
+    pub prefix_dir: Option<&'a Path>,
+    pub strip_components: usize,
+    pub ignore_not_existing: bool,
+    pub to_ignore_dirs: &'a [&'a str],
+    pub to_keep_dirs: &'a [&'a str],
+    pub use_ignore_files: bool,
+    pub system_paths: &'a [PathBuf],
+    pub repo_relative: bool,
+    pub filter_option: Option<bool>,
+    pub file_filter: crate::FileFilter,
+}
+
+impl Default for RewriteOptions<'_> {
+    fn default() -> Self {
+        RewriteOptions {
+            path_mapping: None,
+            prefix_mapping: &[],
+            source_dir: None,
+            prefix_dir: None,
+            strip_components: 0,
+            ignore_not_existing: false,
+            to_ignore_dirs: &[],
+            to_keep_dirs: &[],
+            use_ignore_files: true,
+            system_paths: &[],
+            repo_relative: false,
+            filter_option: None,
+            file_filter: Default::default(),
+        }
+    }
+}
+
+pub fn rewrite_paths(result_map: CovResultMap, options: RewriteOptions) -> Vec<ResultTuple> {
+    rewrite_paths_with_skipped(result_map, &options).0
+}
+
+/// Like rewrite_paths, but also returns the paths of the source files skipped by the path
+/// rewriting, along with the reason. The options can be used for several maps, e.g. the chunks of
+/// the results spilled with --max-memory.
+pub fn rewrite_paths_with_skipped(
+    result_map: CovResultMap,
+    options: &RewriteOptions,
+) -> (Vec<ResultTuple>, Vec<(String, SkipReason)>) {
+    let rewriter = PathRewriter::new(options);
+    let &RewriteOptions {
+        filter_option,
+        ref file_filter,
+        ..
+    } = options;
+
+    let results = result_map
+        .into_par_iter()
+        .filter_map(move |(path, mut result)| {
+            let (abs_path, rel_path) = match rewriter.rewrite(&path) {
+                Ok(paths) => paths,
+                Err(reason) => return Some(Err((path, reason))),
+            };
 
             for filter in file_filter.create(&abs_path) {
                 match filter {
                     crate::FilterType::Both(number) => {
                         result.branches.remove(&number);
+                        result.remap_mcdc(|line| Some(line).filter(|&line| line != number));
                         result.lines.remove(&number);
                     }
                     crate::FilterType::Line(number) => {
@@ -320,6 +782,7 @@ pub fn rewrite_paths(
                     }
                     crate::FilterType::Branch(number) => {
                         result.branches.remove(&number);
+                        result.remap_mcdc(|line| Some(line).filter(|&line| line != number));
                     }
                 }
             }
@@ -338,17 +801,42 @@ pub fn rewrite_paths(
                 None => (),
             };
 
-            Some((abs_path, rel_path, result))
+            Some(Ok((abs_path, rel_path, result)))
         });
 
-    results.collect()
+    let (results, skipped): (Vec<_>, Vec<_>) = results.partition_map(|result| match result {
+        Ok(result) => Either::Left(result),
+        Err(skipped) => Either::Right(skipped),
+    });
+    (results, skipped)
+}
+
+/// Explains what the path rewriting does with the paths of source files, without their coverage,
+/// for --dry-run: whether they are kept, remapped (and to what) or skipped (and why).
+pub fn explain_paths(
+    paths: impl IntoIterator<Item = String>,
+    options: &RewriteOptions,
+) -> Vec<PathExplanation> {
+    let rewriter = PathRewriter::new(options);
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let action = match rewriter.rewrite(&path) {
+                Ok((_, rel_path)) if rel_path == Path::new(&path) => PathAction::Kept,
+                Ok((abs_path, rel_path)) => PathAction::Remapped { abs_path, rel_path },
+                Err(reason) => PathAction::Skipped { reason },
+            };
+            PathExplanation { path, action }
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde_json::json;
-    use std::collections::BTreeMap;
+    use std::collections::{BTreeMap, BTreeSet};
 
     #[test]
     fn test_to_lowercase_first() {
@@ -374,12 +862,39 @@ mod tests {
         to_uppercase_first("");
     }
 
+    #[test]
+    fn test_normalize_source_path() {
+        for (path, expected) in &[
+            ("c:\\proj\\src\\lib.rs", "C:/proj/src/lib.rs"),
+            ("d:/proj/lib.rs", "D:/proj/lib.rs"),
+            ("C:\\", "C:/"),
+            ("\\\\server\\share\\lib.rs", "//server/share/lib.rs"),
+            ("/home/worker/src/lib.rs", "/home/worker/src/lib.rs"),
+            ("a:b/lib.rs", "a:b/lib.rs"),
+            ("", ""),
+        ] {
+            assert_eq!(normalize_source_path(path.to_string()), *expected);
+        }
+        // The backslashes of a relative path are only separators on Windows.
+        assert_eq!(
+            normalize_source_path("src\\my\\lib.rs".to_string()),
+            if cfg!(windows) {
+                "src/my/lib.rs"
+            } else {
+                "src\\my\\lib.rs"
+            }
+        );
+        assert_eq!(normalize_glob("c:\\proj\\**"), "C:/proj/**");
+        assert_eq!(normalize_glob("src/\\[a\\].rs"), "src/\\[a\\].rs");
+    }
+
     macro_rules! empty_result {
         () => {{
             CovResult {
-                lines: BTreeMap::new(),
+                lines: LineMap::new(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                mcdc: None,
             }
         }};
     }
@@ -390,6 +905,7 @@ mod tests {
                 lines: [(42, 1)].iter().cloned().collect(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                mcdc: None,
             }
         }};
     }
@@ -400,6 +916,7 @@ mod tests {
                 lines: [(42, 0)].iter().cloned().collect(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                mcdc: None,
             }
         }};
     }
@@ -421,14 +938,9 @@ mod tests {
         result_map.insert("main.cpp".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            None,
-            false,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -450,14 +962,10 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            Some(Path::new("/home/worker/src/workspace/")),
-            false,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                prefix_dir: Some(Path::new("/home/worker/src/workspace/")),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -479,14 +987,10 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            Some(Path::new("C:\\Users\\worker\\src\\workspace\\")),
-            false,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                prefix_dir: Some(Path::new("C:\\Users\\worker\\src\\workspace\\")),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -508,14 +1012,10 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            Some(Path::new("C:/Users/worker/src/workspace/")),
-            false,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                prefix_dir: Some(Path::new("C:/Users/worker/src/workspace/")),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -537,14 +1037,10 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            Some(Path::new("C:/Users/worker/src/")),
-            false,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                prefix_dir: Some(Path::new("C:/Users/worker/src/")),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -564,14 +1060,10 @@ mod tests {
         result_map.insert("tests/class/doesntexist.cpp".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -596,14 +1088,10 @@ mod tests {
         result_map.insert("tests\\class\\doesntexist.cpp".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -624,14 +1112,10 @@ mod tests {
         result_map.insert("mydir/prova.h".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            None,
-            false,
-            &["mydir/*"],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                to_ignore_dirs: &["mydir/*"],
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -651,14 +1135,10 @@ mod tests {
         result_map.insert("mydir\\prova.h".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            None,
-            false,
-            &["mydir/*"],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                to_ignore_dirs: &["mydir/*"],
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -682,14 +1162,10 @@ mod tests {
             result_map.insert("mydir2/prova.h".to_string(), empty_result!());
             let results = rewrite_paths(
                 result_map,
-                None,
-                None,
-                None,
-                false,
-                &ignore_dirs,
-                &[""; 0],
-                None,
-                Default::default(),
+                RewriteOptions {
+                    to_ignore_dirs: &ignore_dirs,
+                    ..Default::default()
+                },
             );
             let mut count = 0;
             for (abs_path, rel_path, result) in results {
@@ -715,14 +1191,10 @@ mod tests {
             result_map.insert("mydir2\\prova.h".to_string(), empty_result!());
             let results = rewrite_paths(
                 result_map,
-                None,
-                None,
-                None,
-                false,
-                &ignore_dirs,
-                &[""; 0],
-                None,
-                Default::default(),
+                RewriteOptions {
+                    to_ignore_dirs: &ignore_dirs,
+                    ..Default::default()
+                },
             );
             let mut count = 0;
             for (abs_path, rel_path, result) in results {
@@ -736,6 +1208,61 @@ mod tests {
         }
     }
 
+    // The paths of the lcov files written by llvm-cov on Windows, on any platform.
+    fn windows_results(paths: &[&str]) -> CovResultMap {
+        let lcov: String = paths
+            .iter()
+            .map(|path| format!("SF:{}\nDA:1,1\nend_of_record\n", path))
+            .collect();
+        let result_map: crate::SyncCovResultMap = Mutex::new(FxHashMap::default());
+        crate::add_results(
+            crate::parse_lcov(lcov.as_bytes(), false).unwrap(),
+            &result_map,
+            None,
+            &[],
+        );
+        result_map.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_rewrite_paths_windows_paths() {
+        let results = rewrite_paths(
+            windows_results(&["C:\\proj\\src\\lib.rs", "C:\\proj\\tests\\lib.rs"]),
+            RewriteOptions {
+                to_keep_dirs: &["C:/proj/src/**"],
+                ..Default::default()
+            },
+        );
+        assert_eq!(rel_paths(&results), vec!["C:/proj/src/lib.rs"]);
+
+        // The lowercase drive letter of a path is merged with the uppercase one, and matches the
+        // globs and the prefix whatever their separators and case.
+        let result_map = windows_results(&[
+            "c:\\proj\\src\\lib.rs",
+            "C:\\proj\\src\\lib.rs",
+            "c:\\proj\\vendor\\dep.rs",
+        ]);
+        assert_eq!(result_map.len(), 2);
+        assert_eq!(result_map["C:/proj/src/lib.rs"].lines[&1], 2);
+        let results = rewrite_paths(
+            result_map.clone(),
+            RewriteOptions {
+                to_ignore_dirs: &["c:\\proj\\vendor\\**"],
+                ..Default::default()
+            },
+        );
+        assert_eq!(rel_paths(&results), vec!["C:/proj/src/lib.rs"]);
+        let results = rewrite_paths(
+            result_map,
+            RewriteOptions {
+                prefix_dir: Some(Path::new("c:\\proj\\")),
+                to_keep_dirs: &["src/**"],
+                ..Default::default()
+            },
+        );
+        assert_eq!(rel_paths(&results), vec!["src/lib.rs"]);
+    }
+
     #[cfg(unix)]
     #[test]
     fn test_rewrite_paths_keep_only_a_directory() {
@@ -744,14 +1271,10 @@ mod tests {
         result_map.insert("mydir/prova.h".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            None,
-            false,
-            &[""; 0],
-            &["mydir/*"],
-            None,
-            Default::default(),
+            RewriteOptions {
+                to_keep_dirs: &["mydir/*"],
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -771,14 +1294,10 @@ mod tests {
         result_map.insert("mydir\\prova.h".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            None,
-            false,
-            &[""; 0],
-            &["mydir/*"],
-            None,
-            Default::default(),
+            RewriteOptions {
+                to_keep_dirs: &["mydir/*"],
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -802,14 +1321,10 @@ mod tests {
             result_map.insert("mydir2/prova.h".to_string(), empty_result!());
             let results = rewrite_paths(
                 result_map,
-                None,
-                None,
-                None,
-                false,
-                &[""; 0],
-                &keep_only_dirs,
-                None,
-                Default::default(),
+                RewriteOptions {
+                    to_keep_dirs: &keep_only_dirs,
+                    ..Default::default()
+                },
             );
             let mut count = 0;
             for (abs_path, rel_path, result) in results {
@@ -835,14 +1350,10 @@ mod tests {
             result_map.insert("mydir2\\prova.h".to_string(), empty_result!());
             let results = rewrite_paths(
                 result_map,
-                None,
-                None,
-                None,
-                false,
-                &[""; 0],
-                &keep_only_dirs,
-                None,
-                Default::default(),
+                RewriteOptions {
+                    to_keep_dirs: &keep_only_dirs,
+                    ..Default::default()
+                },
             );
             let mut count = 0;
             for (abs_path, rel_path, result) in results {
@@ -866,14 +1377,11 @@ mod tests {
         result_map.insert("foo/bar_ignore.rs".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            None,
-            false,
-            &["foo/bar_*.rs"],
-            &["foo/*.rs"],
-            None,
-            Default::default(),
+            RewriteOptions {
+                to_ignore_dirs: &["foo/bar_*.rs"],
+                to_keep_dirs: &["foo/*.rs"],
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -895,14 +1403,11 @@ mod tests {
         result_map.insert("foo\\bar_ignore.rs".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            None,
-            false,
-            &["foo/bar_*.rs"],
-            &["foo/*.rs"],
-            None,
-            Default::default(),
+            RewriteOptions {
+                to_ignore_dirs: &["foo/bar_*.rs"],
+                to_keep_dirs: &["foo/*.rs"],
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -920,14 +1425,11 @@ mod tests {
         let result_map: CovResultMap = FxHashMap::default();
         rewrite_paths(
             result_map,
-            None,
-            Some(Path::new("tests")),
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                source_dir: Some(Path::new("tests")),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         )
         .iter()
         .any(|_| false);
@@ -941,14 +1443,11 @@ mod tests {
         result_map.insert("test/java/main.java".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path("test").unwrap()),
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path("test").unwrap()),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -969,14 +1468,11 @@ mod tests {
         result_map.insert("test\\java\\main.java".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path("test").unwrap()),
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path("test").unwrap()),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -996,14 +1492,10 @@ mod tests {
         result_map.insert("test/main.rs".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path("test").unwrap()),
-            None,
-            false,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path("test").unwrap()),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1023,14 +1515,10 @@ mod tests {
         result_map.insert("test\\main.rs".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path("test").unwrap()),
-            None,
-            false,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path("test").unwrap()),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1051,14 +1539,11 @@ mod tests {
         result_map.insert("main.rs".to_string(), empty_result!());
         let mut results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path(".").unwrap()),
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path(".").unwrap()),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         assert!(results.len() == 1);
 
@@ -1077,14 +1562,11 @@ mod tests {
         result_map.insert("main.rs".to_string(), empty_result!());
         let mut results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path(".").unwrap()),
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path(".").unwrap()),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         assert!(results.len() == 1);
 
@@ -1102,14 +1584,11 @@ mod tests {
         result_map.insert("java/main.java".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path(".").unwrap()),
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path(".").unwrap()),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1129,14 +1608,11 @@ mod tests {
         result_map.insert("java\\main.java".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path(".").unwrap()),
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path(".").unwrap()),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1159,14 +1635,12 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path("tests").unwrap()),
-            Some(Path::new("/home/worker/src/workspace")),
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path("tests").unwrap()),
+                prefix_dir: Some(Path::new("/home/worker/src/workspace")),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1190,14 +1664,12 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path("tests").unwrap()),
-            Some(Path::new("C:\\Users\\worker\\src\\workspace")),
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path("tests").unwrap()),
+                prefix_dir: Some(Path::new("C:\\Users\\worker\\src\\workspace")),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1217,14 +1689,10 @@ mod tests {
         result_map.insert("class/main.cpp".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            Some(json!({"class/main.cpp": "rewritten/main.cpp"})),
-            None,
-            None,
-            false,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                path_mapping: Some(json!({"class/main.cpp": "rewritten/main.cpp"})),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1243,14 +1711,10 @@ mod tests {
         result_map.insert("class\\main.cpp".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            Some(json!({"class/main.cpp": "rewritten/main.cpp"})),
-            None,
-            None,
-            false,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                path_mapping: Some(json!({"class/main.cpp": "rewritten/main.cpp"})),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1270,16 +1734,13 @@ mod tests {
         result_map.insert("tests/class/main.cpp".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            Some(
-                json!({"rewritten/main.cpp": "tests/class/main.cpp", "tests/class/main.cpp": "rewritten/main.cpp"}),
-            ),
-            None,
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                path_mapping: Some(
+                    json!({"rewritten/main.cpp": "tests/class/main.cpp", "tests/class/main.cpp": "rewritten/main.cpp"}),
+                ),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1300,16 +1761,13 @@ mod tests {
         result_map.insert("tests\\class\\main.cpp".to_string(), empty_result!());
         let results = rewrite_paths(
             result_map,
-            Some(
-                json!({"rewritten/main.cpp": "tests/class/main.cpp", "tests/class/main.cpp": "rewritten/main.cpp"}),
-            ),
-            None,
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                path_mapping: Some(
+                    json!({"rewritten/main.cpp": "tests/class/main.cpp", "tests/class/main.cpp": "rewritten/main.cpp"}),
+                ),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1332,14 +1790,14 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            Some(json!({"/home/worker/src/workspace/rewritten/main.cpp": "tests/class/main.cpp"})),
-            None,
-            Some(Path::new("/home/worker/src/workspace")),
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                path_mapping: Some(
+                    json!({"/home/worker/src/workspace/rewritten/main.cpp": "tests/class/main.cpp"}),
+                ),
+                prefix_dir: Some(Path::new("/home/worker/src/workspace")),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1363,16 +1821,14 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            Some(
-                json!({"C:/Users/worker/src/workspace/rewritten/main.cpp": "tests/class/main.cpp"}),
-            ),
-            None,
-            Some(Path::new("C:\\Users\\worker\\src\\workspace")),
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                path_mapping: Some(
+                    json!({"C:/Users/worker/src/workspace/rewritten/main.cpp": "tests/class/main.cpp"}),
+                ),
+                prefix_dir: Some(Path::new("C:\\Users\\worker\\src\\workspace")),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1392,16 +1848,14 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            Some(
-                json!({"c:/Users/worker/src/workspace/rewritten/main.cpp": "tests/class/main.cpp"}),
-            ),
-            None,
-            Some(Path::new("C:\\Users\\worker\\src\\workspace")),
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                path_mapping: Some(
+                    json!({"c:/Users/worker/src/workspace/rewritten/main.cpp": "tests/class/main.cpp"}),
+                ),
+                prefix_dir: Some(Path::new("C:\\Users\\worker\\src\\workspace")),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1421,16 +1875,14 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            Some(
-                json!({"C:/Users/worker/src/workspace/rewritten/main.cpp": "tests/class/main.cpp"}),
-            ),
-            None,
-            Some(Path::new("c:\\Users\\worker\\src\\workspace")),
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                path_mapping: Some(
+                    json!({"C:/Users/worker/src/workspace/rewritten/main.cpp": "tests/class/main.cpp"}),
+                ),
+                prefix_dir: Some(Path::new("c:\\Users\\worker\\src\\workspace")),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1450,16 +1902,14 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            Some(
-                json!({"c:/Users/worker/src/workspace/rewritten/main.cpp": "tests/class/main.cpp"}),
-            ),
-            None,
-            Some(Path::new("c:\\Users\\worker\\src\\workspace")),
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                path_mapping: Some(
+                    json!({"c:/Users/worker/src/workspace/rewritten/main.cpp": "tests/class/main.cpp"}),
+                ),
+                prefix_dir: Some(Path::new("c:\\Users\\worker\\src\\workspace")),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1482,14 +1932,15 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            Some(json!({"/home/worker/src/workspace/rewritten/main.cpp": "class/main.cpp"})),
-            Some(&canonicalize_path("tests").unwrap()),
-            Some(Path::new("/home/worker/src/workspace")),
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                path_mapping: Some(
+                    json!({"/home/worker/src/workspace/rewritten/main.cpp": "class/main.cpp"}),
+                ),
+                source_dir: Some(&canonicalize_path("tests").unwrap()),
+                prefix_dir: Some(Path::new("/home/worker/src/workspace")),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1512,14 +1963,15 @@ mod tests {
         );
         let results = rewrite_paths(
             result_map,
-            Some(json!({"C:/Users/worker/src/workspace/rewritten/main.cpp": "class/main.cpp"})),
-            Some(&canonicalize_path("tests").unwrap()),
-            Some(Path::new("C:\\Users\\worker\\src\\workspace")),
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            Default::default(),
+            RewriteOptions {
+                path_mapping: Some(
+                    json!({"C:/Users/worker/src/workspace/rewritten/main.cpp": "class/main.cpp"}),
+                ),
+                source_dir: Some(&canonicalize_path("tests").unwrap()),
+                prefix_dir: Some(Path::new("C:\\Users\\worker\\src\\workspace")),
+                ignore_not_existing: true,
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1539,14 +1991,10 @@ mod tests {
         result_map.insert("uncovered.cpp".to_string(), uncovered_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            None,
-            false,
-            &[""; 0],
-            &[""; 0],
-            Some(true),
-            Default::default(),
+            RewriteOptions {
+                filter_option: Some(true),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1565,14 +2013,10 @@ mod tests {
         result_map.insert("uncovered.cpp".to_string(), uncovered_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            None,
-            None,
-            false,
-            &[""; 0],
-            &[""; 0],
-            Some(false),
-            Default::default(),
+            RewriteOptions {
+                filter_option: Some(false),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (abs_path, rel_path, result) in results {
@@ -1626,21 +2070,21 @@ mod tests {
         result_map.insert("test/java/skip.java".to_string(), skipping_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path("test").unwrap()),
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            crate::FileFilter::new(
-                Some(regex::Regex::new("excluded line").unwrap()),
-                Some(regex::Regex::new("skip line start").unwrap()),
-                Some(regex::Regex::new("skip line end").unwrap()),
-                Some(regex::Regex::new("excluded branch").unwrap()),
-                Some(regex::Regex::new("skip branch start").unwrap()),
-                Some(regex::Regex::new("skip branch end").unwrap()),
-            ),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path("test").unwrap()),
+                ignore_not_existing: true,
+                file_filter: crate::FileFilter::new(
+                    Some(regex::Regex::new("excluded line").unwrap()),
+                    Some(regex::Regex::new("skip line start").unwrap()),
+                    Some(regex::Regex::new("skip line end").unwrap()),
+                    Some(regex::Regex::new("excluded branch").unwrap()),
+                    Some(regex::Regex::new("skip branch start").unwrap()),
+                    Some(regex::Regex::new("skip branch end").unwrap()),
+                    0,
+                    None,
+                ),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (_, _, result) in results {
@@ -1669,21 +2113,21 @@ mod tests {
         result_map.insert("test\\java\\skip.java".to_string(), skipping_result!());
         let results = rewrite_paths(
             result_map,
-            None,
-            Some(&canonicalize_path("test").unwrap()),
-            None,
-            true,
-            &[""; 0],
-            &[""; 0],
-            None,
-            crate::FileFilter::new(
-                Some(regex::Regex::new("excluded line").unwrap()),
-                Some(regex::Regex::new("skip line start").unwrap()),
-                Some(regex::Regex::new("skip line end").unwrap()),
-                Some(regex::Regex::new("excluded branch").unwrap()),
-                Some(regex::Regex::new("skip branch start").unwrap()),
-                Some(regex::Regex::new("skip branch end").unwrap()),
-            ),
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path("test").unwrap()),
+                ignore_not_existing: true,
+                file_filter: crate::FileFilter::new(
+                    Some(regex::Regex::new("excluded line").unwrap()),
+                    Some(regex::Regex::new("skip line start").unwrap()),
+                    Some(regex::Regex::new("skip line end").unwrap()),
+                    Some(regex::Regex::new("excluded branch").unwrap()),
+                    Some(regex::Regex::new("skip branch start").unwrap()),
+                    Some(regex::Regex::new("skip branch end").unwrap()),
+                    0,
+                    None,
+                ),
+                ..Default::default()
+            },
         );
         let mut count = 0;
         for (_, _, result) in results {
@@ -1704,4 +2148,668 @@ mod tests {
         }
         assert_eq!(count, 1);
     }
+
+    #[test]
+    fn test_rewrite_paths_minimum_line_length() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let source_dir = canonicalize_path(tmp_dir.path()).unwrap();
+        fs::write(
+            source_dir.join("lib.rs"),
+            "fn main()\n{\n    let x = 1;\n  }  \n",
+        )
+        .unwrap();
+        let rewrite = |excl_line: Option<regex::Regex>, min_line_length| {
+            let mut result_map: CovResultMap = FxHashMap::default();
+            result_map.insert(
+                "lib.rs".to_string(),
+                CovResult {
+                    lines: [(1, 1), (2, 1), (3, 1), (4, 0)].iter().cloned().collect(),
+                    ..CovResult::default()
+                },
+            );
+            let results = rewrite_paths(
+                result_map,
+                RewriteOptions {
+                    source_dir: Some(&source_dir),
+                    use_ignore_files: false,
+                    file_filter: crate::FileFilter::new(
+                        excl_line,
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        min_line_length,
+                        None,
+                    ),
+                    ..Default::default()
+                },
+            );
+            assert_eq!(results.len(), 1);
+            results[0].2.lines.keys().cloned().collect::<Vec<u32>>()
+        };
+
+        // The braces alone on their line are left out, whatever the spaces around them.
+        assert_eq!(rewrite(None, 2), vec![1, 3]);
+        assert_eq!(rewrite(None, 0), vec![1, 2, 3, 4]);
+        let trivial = regex::Regex::new(r"^\s*[{}]\s*$").unwrap();
+        assert_eq!(rewrite(Some(trivial), 0), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_rewrite_paths_exclude_unreachable() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let source_dir = canonicalize_path(tmp_dir.path()).unwrap();
+        fs::write(
+            source_dir.join("lib.rs"),
+            "fn f(x: u32) -> u32 {\n    match x {\n        0 => 1,\n        _ => unreachable!(),\n    }\n}\n",
+        )
+        .unwrap();
+        let rewrite = |excl_unreachable| {
+            let mut result_map: CovResultMap = FxHashMap::default();
+            result_map.insert(
+                "lib.rs".to_string(),
+                CovResult {
+                    lines: [(1, 1), (3, 1), (4, 0), (6, 1)].iter().cloned().collect(),
+                    ..CovResult::default()
+                },
+            );
+            let results = rewrite_paths(
+                result_map,
+                RewriteOptions {
+                    source_dir: Some(&source_dir),
+                    use_ignore_files: false,
+                    file_filter: crate::FileFilter::new(
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        0,
+                        excl_unreachable,
+                    ),
+                    ..Default::default()
+                },
+            );
+            assert_eq!(results.len(), 1);
+            let lines = &results[0].2.lines;
+            lines.values().filter(|&&count| count == 0).count()
+        };
+
+        // The unreachable!() line is the only one missed, and isn't counted anymore.
+        assert_eq!(rewrite(None), 1);
+        assert_eq!(rewrite(Some(crate::unreachable_regex(&[] as &[&str]))), 0);
+    }
+
+    #[test]
+    fn test_rewrite_paths_lcov_excl_markers() {
+        let mut result_map: CovResultMap = FxHashMap::default();
+        result_map.insert(
+            "lcov_excl/excl.c".to_string(),
+            CovResult {
+                lines: (1..=12).map(|line| (line, 1)).collect(),
+                branches: [3, 6, 10]
+                    .iter()
+                    .map(|&line| (line, vec![true, false]))
+                    .collect(),
+                ..CovResult::default()
+            },
+        );
+        let results = rewrite_paths(
+            result_map,
+            RewriteOptions {
+                source_dir: Some(&canonicalize_path("test").unwrap()),
+                ignore_not_existing: true,
+                use_ignore_files: false,
+                file_filter: crate::FileFilter::new(
+                    Some(regex::Regex::new("LCOV_EXCL_LINE").unwrap()),
+                    Some(regex::Regex::new("LCOV_EXCL_START").unwrap()),
+                    Some(regex::Regex::new("LCOV_EXCL_STOP").unwrap()),
+                    Some(regex::Regex::new("LCOV_EXCL_BR_LINE").unwrap()),
+                    None,
+                    None,
+                    0,
+                    None,
+                ),
+                ..Default::default()
+            },
+        );
+
+        // The marked line and the lines from the start marker to the one before the stop marker
+        // are excluded, and the branches of the line with the branch marker.
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let lcov_path = tmp_dir.path().join("lcov.info");
+        crate::output_lcov(&results, Some(&lcov_path), false);
+        let lcov = fs::read_to_string(&lcov_path).unwrap();
+        let lines: Vec<&str> = lcov.lines().filter(|l| l.starts_with("DA:")).collect();
+        assert_eq!(
+            lines,
+            vec!["DA:1,1", "DA:2,1", "DA:4,1", "DA:9,1", "DA:10,1", "DA:11,1", "DA:12,1"]
+        );
+        let branch_lines: BTreeSet<&str> = lcov
+            .lines()
+            .filter_map(|l| l.strip_prefix("BRDA:"))
+            .map(|l| l.split(',').next().unwrap())
+            .collect();
+        assert_eq!(branch_lines, ["3", "6"].iter().cloned().collect());
+    }
+
+    #[test]
+    fn test_rewrite_paths_repo_relative() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = canonicalize_path(tmp_dir.path()).unwrap();
+        let repo = tmp_path.join("repo");
+        fs::create_dir_all(repo.join("src/nested")).unwrap();
+        fs::create_dir_all(tmp_path.join("outside")).unwrap();
+        let status = std::process::Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(&repo)
+            .status();
+        if !status.is_ok_and(|status| status.success()) {
+            // git isn't installed.
+            return;
+        }
+        assert_eq!(
+            git_repository_root(&repo.join("src/nested")),
+            Some(repo.clone())
+        );
+        assert_eq!(git_repository_root(&tmp_path.join("outside")), None);
+
+        let paths = vec![
+            repo.join("src/lib.rs"),
+            repo.join("src/nested/mod.rs"),
+            tmp_path.join("outside/lib.rs"),
+        ];
+        let rewrite = |repo_relative| {
+            let mut result_map: CovResultMap = FxHashMap::default();
+            for path in &paths {
+                fs::write(path, "fn f() {}\n").unwrap();
+                result_map.insert(path.to_string_lossy().into_owned(), CovResult::default());
+            }
+            let mut results: Vec<(PathBuf, PathBuf)> = rewrite_paths(
+                result_map,
+                RewriteOptions {
+                    use_ignore_files: false,
+                    repo_relative,
+                    ..Default::default()
+                },
+            )
+            .into_iter()
+            .map(|(abs_path, rel_path, _)| (abs_path, rel_path))
+            .collect();
+            results.sort();
+            results
+        };
+
+        assert_eq!(
+            rewrite(true),
+            vec![
+                (paths[2].clone(), paths[2].clone()),
+                (paths[0].clone(), PathBuf::from("src/lib.rs")),
+                (paths[1].clone(), PathBuf::from("src/nested/mod.rs")),
+            ]
+        );
+        assert!(rewrite(false)
+            .iter()
+            .all(|(abs_path, rel_path)| abs_path == rel_path));
+    }
+
+    #[test]
+    fn test_strip_path_components() {
+        assert_eq!(
+            strip_path_components(0, PathBuf::from("a/b/c.cpp")),
+            PathBuf::from("a/b/c.cpp")
+        );
+        assert_eq!(
+            strip_path_components(1, PathBuf::from("a/b/c.cpp")),
+            PathBuf::from("b/c.cpp")
+        );
+        assert_eq!(
+            strip_path_components(2, PathBuf::from("./a/b/c.cpp")),
+            PathBuf::from("c.cpp")
+        );
+        // Not enough components to strip.
+        assert_eq!(
+            strip_path_components(3, PathBuf::from("a/b/c.cpp")),
+            PathBuf::from("a/b/c.cpp")
+        );
+        // Single-component relative paths are left alone.
+        assert_eq!(
+            strip_path_components(1, PathBuf::from("c.cpp")),
+            PathBuf::from("c.cpp")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_strip_path_components_absolute() {
+        assert_eq!(
+            strip_path_components(2, PathBuf::from("/a/b/c.cpp")),
+            PathBuf::from("c.cpp")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rewrite_paths_strip_components() {
+        let mut result_map: CovResultMap = FxHashMap::default();
+        result_map.insert(
+            "bazel-out/k8-fastbuild/bin/src/main.cpp".to_string(),
+            empty_result!(),
+        );
+        result_map.insert("main.cpp".to_string(), empty_result!());
+        let results = rewrite_paths(
+            result_map,
+            RewriteOptions {
+                strip_components: 3,
+                ..Default::default()
+            },
+        );
+        let mut rel_paths: Vec<PathBuf> = results.into_iter().map(|(_, p, _)| p).collect();
+        rel_paths.sort();
+        assert_eq!(
+            rel_paths,
+            vec![PathBuf::from("main.cpp"), PathBuf::from("src/main.cpp")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rewrite_paths_strip_components_and_remove_prefix() {
+        let mut result_map: CovResultMap = FxHashMap::default();
+        result_map.insert(
+            "/home/worker/bazel-out/k8-fastbuild/bin/src/main.cpp".to_string(),
+            empty_result!(),
+        );
+        result_map.insert(
+            "/home/worker/bazel-out/k8-fastbuild/bin/src/ignored.cpp".to_string(),
+            empty_result!(),
+        );
+        let results = rewrite_paths(
+            result_map,
+            RewriteOptions {
+                prefix_dir: Some(Path::new("/home/worker/")),
+                strip_components: 3,
+                to_ignore_dirs: &["src/ignored.cpp"],
+                ..Default::default()
+            },
+        );
+        let mut count = 0;
+        for (abs_path, rel_path, result) in results {
+            count += 1;
+            assert_eq!(abs_path, PathBuf::from("src/main.cpp"));
+            assert_eq!(rel_path, PathBuf::from("src/main.cpp"));
+            assert_eq!(result, empty_result!());
+        }
+        assert_eq!(count, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rewrite_paths_prefix_mapping() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let source_dir = canonicalize_path(tmp_dir.path()).unwrap();
+        fs::create_dir_all(source_dir.join("src")).unwrap();
+        fs::create_dir_all(source_dir.join("gen")).unwrap();
+        fs::write(source_dir.join("src/main.rs"), "").unwrap();
+        fs::write(source_dir.join("gen/out.rs"), "").unwrap();
+
+        let mut result_map: CovResultMap = FxHashMap::default();
+        result_map.insert("/build/src/./main.rs".to_string(), empty_result!());
+        result_map.insert("/build/src/gen/out.rs".to_string(), empty_result!());
+        result_map.insert("/other/lib.rs".to_string(), empty_result!());
+        // The first matching rule applies, even if a later one is longer.
+        let prefix_mapping = vec![
+            (
+                "/build/src/gen".to_string(),
+                source_dir.join("gen").to_string_lossy().into_owned(),
+            ),
+            (
+                "/build/src".to_string(),
+                source_dir.join("src").to_string_lossy().into_owned(),
+            ),
+            ("/build/src/lib".to_string(), "/unused".to_string()),
+        ];
+        let results = rewrite_paths(
+            result_map,
+            RewriteOptions {
+                prefix_mapping: &prefix_mapping,
+                source_dir: Some(&source_dir),
+                prefix_dir: Some(&source_dir),
+                ..Default::default()
+            },
+        );
+        let mut rel_paths: Vec<_> = results
+            .into_iter()
+            .map(|(abs_path, rel_path, _)| {
+                if rel_path.is_relative() {
+                    assert_eq!(abs_path, source_dir.join(&rel_path));
+                }
+                rel_path
+            })
+            .collect();
+        rel_paths.sort();
+        assert_eq!(
+            rel_paths,
+            vec![
+                PathBuf::from("/other/lib.rs"),
+                PathBuf::from("gen/out.rs"),
+                PathBuf::from("src/main.rs"),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_explain_paths() {
+        let source_dir = canonicalize_path(".").unwrap();
+        let paths = vec![
+            "src/lib.rs".to_string(),
+            source_dir
+                .join("src/main.rs")
+                .to_string_lossy()
+                .into_owned(),
+            "tests/offline.rs".to_string(),
+            "/usr/lib/rustlib/src/rust/library/core/src/option.rs".to_string(),
+        ];
+        let explanations = explain_paths(
+            paths,
+            &RewriteOptions {
+                source_dir: Some(&source_dir),
+                prefix_dir: Some(&source_dir),
+                to_ignore_dirs: &["tests/*"],
+                system_paths: &[PathBuf::from("/usr/lib/rustlib")],
+                ..Default::default()
+            },
+        );
+        let actions: Vec<_> = explanations
+            .into_iter()
+            .map(|explanation| explanation.action)
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                PathAction::Kept,
+                PathAction::Remapped {
+                    abs_path: source_dir.join("src/main.rs"),
+                    rel_path: PathBuf::from("src/main.rs"),
+                },
+                PathAction::Skipped {
+                    reason: SkipReason::Ignored("tests/*".to_string()),
+                },
+                PathAction::Skipped {
+                    reason: SkipReason::SystemPath(PathBuf::from("/usr/lib/rustlib")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_paths_with_skipped() {
+        let mut result_map: CovResultMap = FxHashMap::default();
+        result_map.insert("src/main.rs".to_string(), empty_result!());
+        result_map.insert("tests/it.rs".to_string(), empty_result!());
+        let (results, skipped) = rewrite_paths_with_skipped(
+            result_map,
+            &RewriteOptions {
+                to_ignore_dirs: &["tests/*"],
+                ..Default::default()
+            },
+        );
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].1, PathBuf::from("src/main.rs"));
+        assert_eq!(
+            skipped,
+            vec![(
+                "tests/it.rs".to_string(),
+                SkipReason::Ignored("tests/*".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_path_globs() {
+        let globs = PathGlobs::new(&["**/generated/**", "src/*.rs"], &[""; 0]);
+        assert_eq!(
+            globs.check(Path::new("a/b/generated/c.rs")),
+            Err(SkipReason::Ignored("**/generated/**".to_string()))
+        );
+        assert_eq!(
+            globs.check(Path::new("generated/c.rs")),
+            Err(SkipReason::Ignored("**/generated/**".to_string()))
+        );
+        // `*` matches across the separators, and the globs are case sensitive.
+        assert_eq!(
+            globs.check(Path::new("src/a/b.rs")),
+            Err(SkipReason::Ignored("src/*.rs".to_string()))
+        );
+        assert_eq!(globs.check(Path::new("SRC/a.rs")), Ok(()));
+        assert_eq!(globs.check(Path::new("a/Generated/c.rs")), Ok(()));
+
+        let globs = PathGlobs::new(&["**/tests/**"], &["src/**"]);
+        assert_eq!(globs.check(Path::new("src/lib.rs")), Ok(()));
+        assert_eq!(
+            globs.check(Path::new("src/tests/it.rs")),
+            Err(SkipReason::Ignored("**/tests/**".to_string()))
+        );
+        assert_eq!(
+            globs.check(Path::new("build/out.rs")),
+            Err(SkipReason::NotKept)
+        );
+    }
+
+    #[test]
+    fn test_parse_prefix_mapping() {
+        assert_eq!(
+            parse_prefix_mapping("/build/src=/home/user/proj"),
+            Ok(("/build/src".to_string(), "/home/user/proj".to_string()))
+        );
+        assert_eq!(
+            parse_prefix_mapping("/build/src="),
+            Ok(("/build/src".to_string(), String::new()))
+        );
+        assert!(parse_prefix_mapping("/build/src").is_err());
+        assert!(parse_prefix_mapping("=/home/user/proj").is_err());
+    }
+
+    fn rel_paths(results: &[ResultTuple]) -> Vec<&str> {
+        results
+            .iter()
+            .map(|(_, rel_path, _)| rel_path.to_str().unwrap())
+            .collect()
+    }
+
+    // Creates a source directory with nested .grcovignore files, with overlapping patterns.
+    fn create_ignore_files_source_dir() -> tempfile::TempDir {
+        // The source directory must not be hidden, or it wouldn't be traversed.
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("grcov")
+            .tempdir()
+            .expect("Failed to create temporary directory");
+        let files = [
+            ".grcovignore",
+            "main.rs",
+            "main.gen.rs",
+            "vendor/dep.rs",
+            "lib/.grcovignore",
+            "lib/lib.rs",
+            "lib/lib.tmp.rs",
+            "lib/special.gen.rs",
+            "lib/vendor/dep.rs",
+            "lib/sub/.grcovignore",
+            "lib/sub/special.gen.rs",
+            "lib/sub/sub.rs",
+        ];
+        for file in &files {
+            let path = tmp_dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::File::create(path).unwrap();
+        }
+        fs::write(tmp_dir.path().join(".grcovignore"), "*.gen.rs\n/vendor/\n").unwrap();
+        fs::write(
+            tmp_dir.path().join("lib/.grcovignore"),
+            "# Temporary files.\n*.tmp.rs\n!special.gen.rs\n",
+        )
+        .unwrap();
+        fs::write(
+            tmp_dir.path().join("lib/sub/.grcovignore"),
+            "special.gen.rs\n",
+        )
+        .unwrap();
+        tmp_dir
+    }
+
+    fn rewrite_paths_with_ignore_files(
+        to_ignore_dirs: &[&str],
+        to_keep_dirs: &[&str],
+        use_ignore_files: bool,
+    ) -> Vec<PathBuf> {
+        let tmp_dir = create_ignore_files_source_dir();
+        let source_dir = canonicalize_path(tmp_dir.path()).unwrap();
+        let mut result_map: CovResultMap = FxHashMap::default();
+        for file in &[
+            "main.rs",
+            "main.gen.rs",
+            "vendor/dep.rs",
+            "lib/lib.rs",
+            "lib/lib.tmp.rs",
+            "lib/special.gen.rs",
+            "lib/vendor/dep.rs",
+            "lib/sub/special.gen.rs",
+            "lib/sub/sub.rs",
+        ] {
+            result_map.insert(file.to_string(), empty_result!());
+        }
+        let results = rewrite_paths(
+            result_map,
+            RewriteOptions {
+                source_dir: Some(&source_dir),
+                ignore_not_existing: true,
+                to_ignore_dirs,
+                to_keep_dirs,
+                use_ignore_files,
+                ..Default::default()
+            },
+        );
+        let mut rel_paths: Vec<PathBuf> = results
+            .into_iter()
+            .map(|(abs_path, rel_path, _)| {
+                assert_eq!(abs_path, source_dir.join(&rel_path));
+                rel_path
+            })
+            .collect();
+        rel_paths.sort();
+        rel_paths
+    }
+
+    #[test]
+    fn test_rewrite_paths_ignore_files() {
+        assert_eq!(
+            rewrite_paths_with_ignore_files(&[], &[], true),
+            vec![
+                PathBuf::from("lib/lib.rs"),
+                PathBuf::from("lib/special.gen.rs"),
+                PathBuf::from("lib/sub/sub.rs"),
+                PathBuf::from("lib/vendor/dep.rs"),
+                PathBuf::from("main.rs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_paths_ignore_files_disabled() {
+        assert_eq!(rewrite_paths_with_ignore_files(&[], &[], false).len(), 9);
+    }
+
+    #[test]
+    fn test_rewrite_paths_ignore_files_and_ignore() {
+        assert_eq!(
+            rewrite_paths_with_ignore_files(&["lib/sub/*", "main.rs"], &[], true),
+            vec![
+                PathBuf::from("lib/lib.rs"),
+                PathBuf::from("lib/special.gen.rs"),
+                PathBuf::from("lib/vendor/dep.rs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_paths_ignore_files_and_keep_only() {
+        assert_eq!(
+            rewrite_paths_with_ignore_files(&[], &["lib/sub/*"], true),
+            vec![
+                PathBuf::from("lib/sub/special.gen.rs"),
+                PathBuf::from("lib/sub/sub.rs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_paths_strip_system_paths() {
+        let lcov = "SF:/home/user/.rustup/toolchains/stable/lib/rustlib/src/rust/library/core/src/option.rs\n\
+                    DA:1,1\n\
+                    end_of_record\n\
+                    SF:/home/user/.cargo/registry/src/dep/lib.rs\n\
+                    DA:1,1\n\
+                    end_of_record\n\
+                    SF:/home/user/project/src/main.rs\n\
+                    DA:1,1\n\
+                    end_of_record\n";
+        let rewrite = |system_paths: &[PathBuf]| -> Vec<PathBuf> {
+            let result_map: CovResultMap = crate::parse_lcov(lcov.as_bytes(), false)
+                .unwrap()
+                .into_iter()
+                .collect();
+            let mut paths: Vec<PathBuf> = rewrite_paths(
+                result_map,
+                RewriteOptions {
+                    use_ignore_files: false,
+                    system_paths,
+                    ..Default::default()
+                },
+            )
+            .into_iter()
+            .map(|(_, rel_path, _)| rel_path)
+            .collect();
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(
+            rewrite(&[PathBuf::from("/home/user/.rustup")]),
+            vec![
+                PathBuf::from("/home/user/.cargo/registry/src/dep/lib.rs"),
+                PathBuf::from("/home/user/project/src/main.rs"),
+            ]
+        );
+        assert_eq!(
+            rewrite(&[
+                PathBuf::from("/home/user/.rustup"),
+                PathBuf::from("/home/user/.cargo/"),
+            ]),
+            vec![PathBuf::from("/home/user/project/src/main.rs")]
+        );
+        // A prefix only matches whole path components.
+        assert_eq!(rewrite(&[PathBuf::from("/home/user/.rust")]).len(), 3);
+        assert_eq!(rewrite(&[]).len(), 3);
+    }
+
+    #[test]
+    fn test_expand_home_dir() {
+        assert_eq!(
+            expand_home_dir(Path::new("/usr/lib/rustlib")),
+            PathBuf::from("/usr/lib/rustlib")
+        );
+        assert_eq!(
+            expand_home_dir(Path::new("~.rustup")),
+            PathBuf::from("~.rustup")
+        );
+        if let Some(home) = home_dir() {
+            assert_eq!(
+                expand_home_dir(Path::new("~/.rustup")),
+                home.join(".rustup")
+            );
+        }
+    }
 }
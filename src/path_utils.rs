@@ -0,0 +1,217 @@
+//! The transformations of the paths of the results before they are written to the outputs: the
+//! prefixes removed by --source-dir, --strip-filename-prefix and --auto-strip-prefix, the one added
+//! by --add-filename-prefix, and the files left out by --excl-generated. CoverageData::map_paths
+//! and filter_by_path are built on the same functions.
+
+use std::path::{Path, PathBuf};
+
+use crate::defs::*;
+
+/// Transforms the relative paths of the results, the ones written to the outputs. The absolute
+/// paths, where the source files are read, are left as they are.
+pub fn map_result_paths<F>(mut results: Vec<ResultTuple>, f: F) -> Vec<ResultTuple>
+where
+    F: Fn(PathBuf) -> PathBuf,
+{
+    for (_, rel_path, _) in &mut results {
+        *rel_path = f(std::mem::take(rel_path));
+    }
+    results
+}
+
+/// Only keeps the results whose source file, at its absolute path, matches the predicate.
+pub fn filter_results_by_path<F>(mut results: Vec<ResultTuple>, predicate: F) -> Vec<ResultTuple>
+where
+    F: Fn(&Path) -> bool,
+{
+    results.retain(|(abs_path, _, _)| predicate(abs_path));
+    results
+}
+
+/// Removes the first of the prefixes which the relative path of each result starts with, for
+/// --strip-filename-prefix. The paths equal to a prefix, and the ones without any of the
+/// prefixes, are left as they are.
+pub fn strip_filename_prefixes(
+    results: Vec<ResultTuple>,
+    prefixes: &[PathBuf],
+) -> Vec<ResultTuple> {
+    if prefixes.is_empty() {
+        return results;
+    }
+    map_result_paths(results, |rel_path| {
+        strip_filename_prefix(rel_path, prefixes)
+    })
+}
+
+/// Removes the first of the prefixes which a path starts with, as --strip-filename-prefix does,
+/// and as the source directory of --source-dir is removed from the paths under it.
+pub fn strip_filename_prefix(path: PathBuf, prefixes: &[PathBuf]) -> PathBuf {
+    prefixes
+        .iter()
+        .filter_map(|prefix| path.strip_prefix(prefix).ok())
+        .find(|stripped| !stripped.as_os_str().is_empty())
+        .map_or_else(|| path.clone(), Path::to_path_buf)
+}
+
+/// Prepends a prefix to the relative path of each result, for --add-filename-prefix, e.g. for
+/// the tools which expect the paths from the root of a repository containing the project. The
+/// absolute paths, where the source files are read, are left as they are.
+pub fn add_filename_prefix(results: Vec<ResultTuple>, prefix: Option<&Path>) -> Vec<ResultTuple> {
+    match prefix {
+        Some(prefix) => map_result_paths(results, |rel_path| prefix.join(rel_path)),
+        None => results,
+    }
+}
+
+/// Returns the longest directory which contains all the paths.
+pub fn common_dir_prefix<'a>(paths: impl IntoIterator<Item = &'a Path>) -> PathBuf {
+    let mut paths = paths.into_iter();
+    let mut prefix = match paths.next().and_then(Path::parent) {
+        Some(parent) => parent.to_path_buf(),
+        None => return PathBuf::new(),
+    };
+    for path in paths {
+        while !path.starts_with(&prefix) || path == prefix {
+            if !prefix.pop() {
+                return PathBuf::new();
+            }
+        }
+    }
+    prefix
+}
+
+/// Removes the longest directory containing the relative paths of all the results, for
+/// --auto-strip-prefix.
+pub fn strip_common_prefix(results: Vec<ResultTuple>) -> Vec<ResultTuple> {
+    let prefix = common_dir_prefix(results.iter().map(|(_, rel_path, _)| rel_path.as_path()));
+    if prefix.as_os_str().is_empty() {
+        return results;
+    }
+    strip_filename_prefixes(results, &[prefix])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn results_with_paths(rel_paths: &[&str]) -> Vec<ResultTuple> {
+        rel_paths
+            .iter()
+            .map(|rel_path| {
+                (
+                    PathBuf::from("/work").join(rel_path),
+                    PathBuf::from(rel_path),
+                    CovResult::default(),
+                )
+            })
+            .collect()
+    }
+
+    fn rel_paths(results: &[ResultTuple]) -> Vec<&str> {
+        results
+            .iter()
+            .map(|(_, rel_path, _)| rel_path.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_strip_filename_prefixes() {
+        let results = results_with_paths(&[
+            "/home/runner/work/proj/proj/src/main.rs",
+            "/home/runner/work/proj/proj/tests/it.rs",
+            "/home/runner/.cargo/registry/dep/src/lib.rs",
+            "/home/runner/work/proj/proj-utils/src/lib.rs",
+            "/home/runner/work/proj/proj",
+            "src/lib.rs",
+        ]);
+        let results = strip_filename_prefixes(
+            results,
+            &[
+                PathBuf::from("/home/runner/work/proj/proj/"),
+                PathBuf::from("/home/runner/.cargo"),
+                PathBuf::from("/home/runner"),
+            ],
+        );
+        assert_eq!(
+            rel_paths(&results),
+            vec![
+                "src/main.rs",
+                "tests/it.rs",
+                "registry/dep/src/lib.rs",
+                "work/proj/proj-utils/src/lib.rs",
+                "work/proj/proj",
+                "src/lib.rs",
+            ]
+        );
+        // The absolute paths are still the ones of the source files.
+        assert_eq!(
+            results[0].0,
+            PathBuf::from("/home/runner/work/proj/proj/src/main.rs")
+        );
+    }
+
+    #[test]
+    fn test_add_filename_prefix() {
+        let results = add_filename_prefix(
+            results_with_paths(&["src/main.rs", "tests/it.rs"]),
+            Some(Path::new("project/")),
+        );
+        assert_eq!(
+            rel_paths(&results),
+            vec!["project/src/main.rs", "project/tests/it.rs"]
+        );
+        assert_eq!(results[0].0, PathBuf::from("/work/src/main.rs"));
+
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let lcov = tmp_dir.path().join("lcov.info");
+        crate::output_lcov(&results, Some(&lcov), false);
+        let records: Vec<String> = fs::read_to_string(&lcov)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with("SF:"))
+            .map(str::to_string)
+            .collect();
+        assert_eq!(
+            records,
+            vec!["SF:project/src/main.rs", "SF:project/tests/it.rs"]
+        );
+
+        let results = add_filename_prefix(results_with_paths(&["src/main.rs"]), None);
+        assert_eq!(rel_paths(&results), vec!["src/main.rs"]);
+    }
+
+    #[test]
+    fn test_strip_common_prefix() {
+        let results = strip_common_prefix(results_with_paths(&[
+            "/home/runner/work/proj/proj/src/main.rs",
+            "/home/runner/work/proj/proj/src/lib/mod.rs",
+            "/home/runner/work/proj/proj/tests/it.rs",
+        ]));
+        assert_eq!(
+            rel_paths(&results),
+            vec!["src/main.rs", "src/lib/mod.rs", "tests/it.rs"]
+        );
+
+        // A single file keeps its name.
+        let results = strip_common_prefix(results_with_paths(&["/work/src/main.rs"]));
+        assert_eq!(rel_paths(&results), vec!["main.rs"]);
+
+        // Nothing in common.
+        let results = strip_common_prefix(results_with_paths(&["src/main.rs", "build.rs"]));
+        assert_eq!(rel_paths(&results), vec!["src/main.rs", "build.rs"]);
+
+        assert_eq!(
+            common_dir_prefix(vec![Path::new("a/b/c"), Path::new("a/b")]),
+            PathBuf::from("a")
+        );
+    }
+
+    #[test]
+    fn test_filter_results_by_path() {
+        let results = results_with_paths(&["src/main.rs", "tests/it.rs", "src/lib.rs"]);
+        // The predicate is given the absolute paths.
+        let results = filter_results_by_path(results, |abs_path| abs_path.starts_with("/work/src"));
+        assert_eq!(rel_paths(&results), vec!["src/main.rs", "src/lib.rs"]);
+    }
+}
@@ -1,13 +1,22 @@
+use log::debug;
+use md5::{Digest, Md5};
 use rustc_hash::FxHashMap;
+use serde::Serialize;
 use std::cell::RefCell;
+use std::collections::hash_map::Entry;
 use std::env;
+use std::fmt;
 use std::fs::{self, File};
 use std::io::{self, BufReader, Read};
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 use zip::ZipArchive;
 
+use crate::dedup::{DedupInputs, SeenInputs};
 use crate::defs::*;
+use crate::parser::ParserError;
+use crate::progress::{report_discovered, suspend_progress};
+use crate::summary::InputCounts;
 
 #[derive(Debug)]
 pub enum ArchiveType {
@@ -28,6 +37,28 @@ pub struct GCNOStem {
     pub llvm: bool,
 }
 
+// The size from which the input files are mapped in memory rather than read, and the files of
+// the zip archives read as they're parsed.
+const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+// Maps a regular file of at least MMAP_THRESHOLD bytes in memory. The mapping fails on some
+// platforms and filesystems, and then the file is read instead; GRCOV_NO_MMAP disables it, e.g.
+// for the network filesystems where a file changed while it's mapped makes the reads crash.
+fn map_file(path: &Path) -> Option<FileContent> {
+    if env::var_os("GRCOV_NO_MMAP").is_some() {
+        return None;
+    }
+    let file = File::open(path).ok()?;
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() || metadata.len() < MMAP_THRESHOLD {
+        return None;
+    }
+    // SAFETY: the input files aren't expected to change while grcov reads them.
+    unsafe { memmap2::Mmap::map(&file) }
+        .ok()
+        .map(FileContent::Mapped)
+}
+
 #[cfg(not(windows))]
 fn clean_path(path: &Path) -> String {
     path.to_str().unwrap().to_string()
@@ -50,6 +81,7 @@ impl Archive {
             .push(self);
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn handle_file<'a>(
         &'a self,
         file: Option<&mut impl Read>,
@@ -79,10 +111,20 @@ impl Archive {
                     let filename = clean_path(&path.with_extension(""));
                     self.insert_vec(filename, gcda_stem_archives);
                 }
-                "profraw" => {
+                // Indexed profiles (e.g. from other runs) are merged with the raw ones.
+                "profraw" | "profdata" => {
                     let filename = clean_path(path);
                     self.insert_vec(filename, profraws);
                 }
+                // The compressed profiles are decompressed before they're merged. The tarballs
+                // can contain anything, so they're only read when they're given as inputs.
+                "gz" | "tgz" => {
+                    let is_plain = matches!(*self.item.borrow(), ArchiveType::Plain(_));
+                    if is_plain || path.to_string_lossy().ends_with(".profraw.gz") {
+                        let filename = clean_path(path);
+                        self.insert_vec(filename, profraws);
+                    }
+                }
                 "info" => {
                     if Archive::check_file(file, &Archive::is_info) {
                         let filename = clean_path(path);
@@ -139,6 +181,7 @@ impl Archive {
         &self.name
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn explore<'a>(
         &'a mut self,
         gcno_stem_archives: &RefCell<FxHashMap<GCNOStem, &'a Archive>>,
@@ -261,6 +304,43 @@ impl Archive {
         }
     }
 
+    /// Reads a file of the archive like `read`, but maps the big regular files in memory instead
+    /// of copying them. The files in zip archives are always read.
+    pub fn read_content(&self, name: &str) -> Option<FileContent> {
+        let path = match *self.item.borrow() {
+            ArchiveType::Zip(_) => None,
+            ArchiveType::Dir(ref dir) => Some(dir.join(name)),
+            ArchiveType::Plain(_) => Some(PathBuf::from(name)),
+        };
+        path.and_then(|path| map_file(&path))
+            .or_else(|| self.read(name).map(FileContent::Buffer))
+    }
+
+    fn is_zip(&self) -> bool {
+        matches!(*self.item.borrow(), ArchiveType::Zip(_))
+    }
+
+    /// Returns where a file of the archive is, to read it later.
+    pub fn entry(&self, name: &str) -> ArchiveEntry {
+        match *self.item.borrow() {
+            ArchiveType::Zip(_) => ArchiveEntry::Zip(PathBuf::from(&self.name), name.to_string()),
+            ArchiveType::Dir(ref dir) => ArchiveEntry::File(dir.join(name)),
+            ArchiveType::Plain(_) => ArchiveEntry::File(PathBuf::from(name)),
+        }
+    }
+
+    /// Returns whether a file of the archive is in a zip archive, and big enough to be read as
+    /// it's parsed rather than in memory.
+    fn is_big_zip_entry(&self, name: &str) -> bool {
+        match *self.item.borrow() {
+            ArchiveType::Zip(ref zip) => zip
+                .borrow_mut()
+                .by_name(name)
+                .is_ok_and(|file| file.size() >= MMAP_THRESHOLD),
+            _ => false,
+        }
+    }
+
     pub fn extract(&self, name: &str, path: &Path) -> bool {
         let dest_parent = path.parent().unwrap();
         if !dest_parent.exists() {
@@ -301,15 +381,37 @@ fn gcno_gcda_producer(
     gcda_stem_archives: &FxHashMap<String, Vec<&Archive>>,
     sender: &JobSender,
     ignore_orphan_gcno: bool,
+    dedup_inputs: &DedupInputs,
+    input_counts: &InputCounts,
 ) {
+    let mut seen = SeenInputs::default();
+    let mut is_duplicate = |stem: &str, content: &[u8], name: String| {
+        if !dedup_inputs.contains(InputKind::Gcda) {
+            return false;
+        }
+        match seen.first_with_content(InputKind::Gcda, stem, content, &name) {
+            Some(first) => {
+                debug!("{} has the same content as {}, it is skipped", name, first);
+                input_counts.add_duplicate();
+                true
+            }
+            None => false,
+        }
+    };
+    // The files of the zip archives are read by the consumers, in parallel, unless the gcda
+    // files are deduplicated, which needs their content here.
+    let read_by_consumers = |gcno_archive: &Archive, gcda_archives: &[&Archive]| {
+        !dedup_inputs.contains(InputKind::Gcda)
+            && (gcno_archive.is_zip() || gcda_archives.iter().any(|archive| archive.is_zip()))
+    };
     let send_job = |item, name| {
-        sender
-            .send(Some(WorkItem {
-                format: ItemFormat::Gcno,
-                item,
-                name,
-            }))
-            .unwrap()
+        let work_item = WorkItem {
+            format: ItemFormat::Gcno,
+            item,
+            name,
+        };
+        report_discovered(&work_item);
+        sender.send(Some(work_item)).unwrap()
     };
 
     for (gcno_stem, gcno_archive) in gcno_stem_archives {
@@ -318,13 +420,28 @@ fn gcno_gcda_producer(
             let gcno_archive = *gcno_archive;
             let gcno = format!("{}.gcno", stem).to_string();
             let physical_gcno_path = tmp_dir.join(format!("{}_{}.gcno", stem, 1));
-            if gcno_stem.llvm {
+            if gcno_stem.llvm && read_by_consumers(gcno_archive, gcda_archives) {
+                let gcda = format!("{}.gcda", stem);
+                send_job(
+                    ItemType::Entries(GcnoEntries {
+                        stem: stem.clone(),
+                        gcno: gcno_archive.entry(&gcno),
+                        gcdas: gcda_archives
+                            .iter()
+                            .map(|gcda_archive| gcda_archive.entry(&gcda))
+                            .collect(),
+                    }),
+                    "".to_string(),
+                );
+            } else if gcno_stem.llvm {
                 let mut gcda_buffers: Vec<Vec<u8>> = Vec::with_capacity(gcda_archives.len());
                 if let Some(gcno_buffer) = gcno_archive.read(&gcno) {
                     for gcda_archive in gcda_archives {
                         let gcda = format!("{}.gcda", stem).to_string();
                         if let Some(gcda_buf) = gcda_archive.read(&gcda) {
-                            gcda_buffers.push(gcda_buf);
+                            if !is_duplicate(stem, &gcda_buf, input_name(gcda_archive, &gcda)) {
+                                gcda_buffers.push(gcda_buf);
+                            }
                         }
                     }
                     send_job(
@@ -332,10 +449,23 @@ fn gcno_gcda_producer(
                             stem: stem.clone(),
                             gcno_buf: gcno_buffer,
                             gcda_buf: gcda_buffers,
+                            gcno_entry: Some(gcno_archive.entry(&gcno)),
                         }),
                         "".to_string(),
                     );
                 }
+            } else if read_by_consumers(gcno_archive, gcda_archives) {
+                let gcda = format!("{}.gcda", stem);
+                for gcda_archive in gcda_archives {
+                    send_job(
+                        ItemType::GccEntries(GccEntries {
+                            stem: stem.clone(),
+                            gcno: gcno_archive.entry(&gcno),
+                            gcda: Some(gcda_archive.entry(&gcda)),
+                        }),
+                        gcda_archive.get_name().to_string(),
+                    );
+                }
             } else {
                 gcno_archive.extract(&gcno, &physical_gcno_path);
                 for (num, &gcda_archive) in gcda_archives.iter().enumerate() {
@@ -350,8 +480,17 @@ fn gcno_gcda_producer(
                     }
 
                     let gcda_path = tmp_dir.join(format!("{}_{}.gcda", stem, num + 1));
-                    if gcda_archive.extract(&gcda, &gcda_path) || (num == 0 && !ignore_orphan_gcno)
+                    let extracted = gcda_archive.extract(&gcda, &gcda_path);
+                    if extracted
+                        && is_duplicate(
+                            stem,
+                            &fs::read(&gcda_path).unwrap_or_default(),
+                            input_name(gcda_archive, &gcda),
+                        )
                     {
+                        continue;
+                    }
+                    if extracted || (num == 0 && !ignore_orphan_gcno) {
                         send_job(
                             ItemType::Path((stem.clone(), gcno_path)),
                             gcda_archive.get_name().to_string(),
@@ -362,17 +501,36 @@ fn gcno_gcda_producer(
         } else if !ignore_orphan_gcno {
             let gcno_archive = *gcno_archive;
             let gcno = format!("{}.gcno", stem).to_string();
-            if gcno_stem.llvm {
+            if gcno_stem.llvm && read_by_consumers(gcno_archive, &[]) {
+                send_job(
+                    ItemType::Entries(GcnoEntries {
+                        stem: stem.clone(),
+                        gcno: gcno_archive.entry(&gcno),
+                        gcdas: Vec::new(),
+                    }),
+                    gcno_archive.get_name().to_string(),
+                );
+            } else if gcno_stem.llvm {
                 if let Some(gcno_buf) = gcno_archive.read(&gcno) {
                     send_job(
                         ItemType::Buffers(GcnoBuffers {
                             stem: stem.clone(),
                             gcno_buf,
                             gcda_buf: Vec::new(),
+                            gcno_entry: Some(gcno_archive.entry(&gcno)),
                         }),
                         gcno_archive.get_name().to_string(),
                     );
                 }
+            } else if read_by_consumers(gcno_archive, &[]) {
+                send_job(
+                    ItemType::GccEntries(GccEntries {
+                        stem: stem.clone(),
+                        gcno: gcno_archive.entry(&gcno),
+                        gcda: None,
+                    }),
+                    gcno_archive.get_name().to_string(),
+                );
             } else {
                 let physical_gcno_path = tmp_dir.join(format!("{}_{}.gcno", stem, 1));
                 if gcno_archive.extract(&gcno, &physical_gcno_path) {
@@ -386,10 +544,35 @@ fn gcno_gcda_producer(
     }
 }
 
+// Finds the profraw files with the same content as a previous one. They are usually the same run
+// written twice (e.g. when a PID was reused with a `%p` pattern), whose counts would be added
+// twice by the merge. Returns the index of each duplicate with the index of the first file with
+// its content.
+fn find_duplicate_profraws(profraw_paths: &[PathBuf]) -> Vec<(usize, usize)> {
+    let mut first_with_hash = FxHashMap::default();
+    let mut duplicates = Vec::new();
+    for (i, path) in profraw_paths.iter().enumerate() {
+        // The unreadable files are reported by llvm-profdata, and the empty ones have no counts.
+        let buffer = match fs::read(path) {
+            Ok(buffer) if !buffer.is_empty() => buffer,
+            _ => continue,
+        };
+        match first_with_hash.entry(Md5::digest(&buffer)) {
+            Entry::Occupied(first) => duplicates.push((i, *first.get())),
+            Entry::Vacant(entry) => {
+                entry.insert(i);
+            }
+        }
+    }
+    duplicates
+}
+
 fn profraw_producer(
     tmp_dir: &Path,
     profraws: &FxHashMap<String, Vec<&Archive>>,
     sender: &JobSender,
+    dedup_inputs: &DedupInputs,
+    input_counts: &InputCounts,
 ) {
     if profraws.is_empty() {
         return;
@@ -413,7 +596,8 @@ fn profraw_producer(
             let profraw_path = if let Some(profraw_path) = profraw_path {
                 profraw_path
             } else {
-                let tmp_path = tmp_dir.join(format!("{}_{}.profraw", stem, num + 1));
+                let ext = path.extension().unwrap().to_str().unwrap();
+                let tmp_path = tmp_dir.join(format!("{}_{}.{}", stem, num + 1, ext));
                 archive.extract(name, &tmp_path);
                 tmp_path
             };
@@ -422,30 +606,97 @@ fn profraw_producer(
         }
     }
 
-    sender
-        .send(Some(WorkItem {
-            format: ItemFormat::Profraw,
-            item: ItemType::Paths(profraw_paths),
-            name: "profraws".to_string(),
-        }))
-        .unwrap()
+    let dedup_profraws = dedup_inputs.contains(InputKind::Profraw);
+    let duplicates = find_duplicate_profraws(&profraw_paths);
+    for &(duplicate, first) in &duplicates {
+        if dedup_profraws {
+            debug!(
+                "{} has the same content as {}, it is skipped",
+                profraw_paths[duplicate].display(),
+                profraw_paths[first].display()
+            );
+            input_counts.add_duplicate();
+        } else {
+            suspend_progress(|| {
+                eprintln!(
+                    "warning: {} has the same content as {}, its counts are likely added twice (use --dedup-inputs=profraw to skip it)",
+                    profraw_paths[duplicate].display(),
+                    profraw_paths[first].display()
+                );
+            });
+        }
+    }
+    if dedup_profraws && !duplicates.is_empty() {
+        let duplicates: Vec<usize> = duplicates.iter().map(|&(duplicate, _)| duplicate).collect();
+        profraw_paths = profraw_paths
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !duplicates.contains(i))
+            .map(|(_, path)| path)
+            .collect();
+    }
+
+    let work_item = WorkItem {
+        format: ItemFormat::Profraw,
+        item: ItemType::Paths(profraw_paths),
+        name: "profraws".to_string(),
+    };
+    report_discovered(&work_item);
+    sender.send(Some(work_item)).unwrap()
+}
+
+// The name of an input file in the messages: its path, or its path in its directory or zip archive.
+fn input_name(archive: &Archive, name: &str) -> String {
+    match *archive.item.borrow() {
+        ArchiveType::Plain(_) => name.to_string(),
+        _ => Path::new(archive.get_name())
+            .join(name)
+            .to_string_lossy()
+            .into_owned(),
+    }
 }
 
 fn file_content_producer(
     files: &FxHashMap<String, Vec<&Archive>>,
     sender: &JobSender,
     item_format: ItemFormat,
+    dedup_inputs: &DedupInputs,
+    input_counts: &InputCounts,
 ) {
+    let kind = match item_format {
+        ItemFormat::JacocoXml => InputKind::JacocoXml,
+        _ => InputKind::Info,
+    };
+    let mut seen = SeenInputs::default();
     for (name, archives) in files {
         for archive in archives {
-            if let Some(buffer) = archive.read(name) {
-                sender
-                    .send(Some(WorkItem {
-                        format: item_format,
-                        item: ItemType::Content(buffer),
-                        name: archive.get_name().to_string(),
-                    }))
-                    .unwrap();
+            // The deduplicated files are hashed, so they're read in memory anyway.
+            if !dedup_inputs.contains(kind) && archive.is_big_zip_entry(name) {
+                let work_item = WorkItem {
+                    format: item_format,
+                    item: ItemType::ZipEntry((PathBuf::from(archive.get_name()), name.clone())),
+                    name: archive.get_name().to_string(),
+                };
+                report_discovered(&work_item);
+                sender.send(Some(work_item)).unwrap();
+                continue;
+            }
+            if let Some(content) = archive.read_content(name) {
+                if dedup_inputs.contains(kind) {
+                    let name = input_name(archive, name);
+                    if let Some(first) = seen.first_with_content(kind, "", &content, &name) {
+                        debug!("{} has the same content as {}, it is skipped", name, first);
+                        input_counts.add_duplicate();
+                        continue;
+                    }
+                }
+                let work_item = WorkItem {
+                    format: item_format,
+                    item: ItemType::Content(content),
+                    name: archive.get_name().to_string(),
+                };
+                report_discovered(&work_item);
+                sender.send(Some(work_item)).unwrap();
             }
         }
     }
@@ -459,19 +710,86 @@ pub fn get_mapping(linked_files_maps: &FxHashMap<String, &Archive>) -> Option<Ve
     }
 }
 
+/// The zip archives opened by a consumer to read the files of GcnoEntries and GccEntries, each one
+/// opened once.
+#[derive(Default)]
+pub struct ZipReaders(FxHashMap<PathBuf, ZipArchive<BufReader<File>>>);
+
+impl ZipReaders {
+    /// Reads a file, from its zip archive or from the disk.
+    pub fn read(&mut self, entry: &ArchiveEntry) -> io::Result<Vec<u8>> {
+        let (zip_path, name) = match entry {
+            ArchiveEntry::Zip(zip_path, name) => (zip_path, name),
+            ArchiveEntry::File(path) => return fs::read(path),
+        };
+        let zip = match self.0.entry(zip_path.clone()) {
+            Entry::Occupied(zip) => zip.into_mut(),
+            Entry::Vacant(entry) => {
+                let file = BufReader::new(File::open(zip_path)?);
+                entry.insert(ZipArchive::new(file).map_err(io::Error::other)?)
+            }
+        };
+        let mut file = zip.by_name(name).map_err(io::Error::other)?;
+        let mut buf = Vec::with_capacity(file.size() as usize + 1);
+        file.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Reads the gcno file and the gcda files of a stem. The gcda files which can't be read are
+    /// skipped, like the producer does.
+    pub fn read_gcno_entries(&mut self, entries: GcnoEntries) -> io::Result<GcnoBuffers> {
+        let gcno_buf = self.read(&entries.gcno)?;
+        let gcda_buf = entries
+            .gcdas
+            .iter()
+            .filter_map(|gcda| self.read(gcda).ok())
+            .collect();
+        Ok(GcnoBuffers {
+            stem: entries.stem,
+            gcno_buf,
+            gcda_buf,
+            gcno_entry: Some(entries.gcno),
+        })
+    }
+
+    /// Extracts the gcno file of a GCC pair to `gcno_path`, and its gcda file next to it, for gcov
+    /// to read them.
+    pub fn extract_gcc_entries(
+        &mut self,
+        entries: &GccEntries,
+        gcno_path: &Path,
+    ) -> io::Result<()> {
+        fs::write(gcno_path, self.read(&entries.gcno)?)?;
+        if let Some(gcda) = &entries.gcda {
+            fs::write(gcno_path.with_extension("gcda"), self.read(gcda)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a file of a zip archive with `parse`, as it's read.
+pub fn parse_zip_entry<T>(
+    zip_path: &Path,
+    name: &str,
+    parse: impl FnOnce(&mut dyn Read) -> Result<T, ParserError>,
+) -> Result<T, ParserError> {
+    let file = File::open(zip_path)?;
+    let mut zip = ZipArchive::new(BufReader::new(file))
+        .map_err(|e| ParserError::Parse(format!("Invalid zip archive: {}", e)))?;
+    let mut file = zip
+        .by_name(name)
+        .map_err(|e| ParserError::Parse(format!("Unable to read {}: {}", name, e)))?;
+    parse(&mut file)
+}
+
 fn open_archive(path: &str) -> ZipArchive<BufReader<File>> {
     let file = File::open(path).unwrap_or_else(|_| panic!("Failed to open ZIP file '{}'.", path));
     let reader = BufReader::new(file);
     ZipArchive::new(reader).unwrap_or_else(|_| panic!("Failed to parse ZIP file: {}", path))
 }
 
-pub fn producer(
-    tmp_dir: &Path,
-    paths: &[String],
-    sender: &JobSender,
-    ignore_orphan_gcno: bool,
-    is_llvm: bool,
-) -> Option<Vec<u8>> {
+// Opens the input paths: the zip archives, the directories and the plain files.
+fn open_archives(paths: &[String]) -> Vec<Archive> {
     let mut archives: Vec<Archive> = Vec::new();
     let mut plain_files: Vec<PathBuf> = Vec::new();
 
@@ -498,7 +816,14 @@ pub fn producer(
                 });
             } else if let Some(ext) = full_path.clone().extension() {
                 let ext = ext.to_str().unwrap();
-                if ext == "info" || ext == "json" || ext == "xml" || ext == "profraw" {
+                if ext == "info"
+                    || ext == "json"
+                    || ext == "xml"
+                    || ext == "profraw"
+                    || ext == "profdata"
+                    || ext == "gz"
+                    || ext == "tgz"
+                {
                     plain_files.push(full_path);
                 } else {
                     panic!(
@@ -519,48 +844,206 @@ pub fn producer(
         });
     }
 
-    let gcno_stems_archives: RefCell<FxHashMap<GCNOStem, &Archive>> =
-        RefCell::new(FxHashMap::default());
-    let gcda_stems_archives: RefCell<FxHashMap<String, Vec<&Archive>>> =
-        RefCell::new(FxHashMap::default());
-    let profraws: RefCell<FxHashMap<String, Vec<&Archive>>> = RefCell::new(FxHashMap::default());
-    let infos: RefCell<FxHashMap<String, Vec<&Archive>>> = RefCell::new(FxHashMap::default());
-    let xmls: RefCell<FxHashMap<String, Vec<&Archive>>> = RefCell::new(FxHashMap::default());
-    let linked_files_maps: RefCell<FxHashMap<String, &Archive>> =
-        RefCell::new(FxHashMap::default());
+    archives
+}
+
+// The input files found in the archives, by type.
+#[derive(Default)]
+struct InputFiles<'a> {
+    gcno_stems_archives: RefCell<FxHashMap<GCNOStem, &'a Archive>>,
+    gcda_stems_archives: RefCell<FxHashMap<String, Vec<&'a Archive>>>,
+    profraws: RefCell<FxHashMap<String, Vec<&'a Archive>>>,
+    infos: RefCell<FxHashMap<String, Vec<&'a Archive>>>,
+    xmls: RefCell<FxHashMap<String, Vec<&'a Archive>>>,
+    linked_files_maps: RefCell<FxHashMap<String, &'a Archive>>,
+}
 
-    for archive in &mut archives {
+fn explore_archives(archives: &mut [Archive], is_llvm: bool) -> InputFiles<'_> {
+    let input_files = InputFiles::default();
+    for archive in archives {
         archive.explore(
-            &gcno_stems_archives,
-            &gcda_stems_archives,
-            &profraws,
-            &infos,
-            &xmls,
-            &linked_files_maps,
+            &input_files.gcno_stems_archives,
+            &input_files.gcda_stems_archives,
+            &input_files.profraws,
+            &input_files.infos,
+            &input_files.xmls,
+            &input_files.linked_files_maps,
             is_llvm,
         );
     }
 
     assert!(
-        !(gcno_stems_archives.borrow().is_empty()
-            && profraws.borrow().is_empty()
-            && infos.borrow().is_empty()
-            && xmls.borrow().is_empty()),
+        !(input_files.gcno_stems_archives.borrow().is_empty()
+            && input_files.profraws.borrow().is_empty()
+            && input_files.infos.borrow().is_empty()
+            && input_files.xmls.borrow().is_empty()),
         "No input files found"
     );
 
-    file_content_producer(&infos.into_inner(), sender, ItemFormat::Info);
-    file_content_producer(&xmls.into_inner(), sender, ItemFormat::JacocoXml);
-    profraw_producer(tmp_dir, &profraws.into_inner(), sender);
+    input_files
+}
+
+pub fn producer(
+    tmp_dir: &Path,
+    paths: &[String],
+    sender: &JobSender,
+    ignore_orphan_gcno: bool,
+    is_llvm: bool,
+    dedup_inputs: &DedupInputs,
+    input_counts: &InputCounts,
+) -> Option<Vec<u8>> {
+    let mut archives = open_archives(paths);
+    let input_files = explore_archives(&mut archives, is_llvm);
+
+    file_content_producer(
+        &input_files.infos.into_inner(),
+        sender,
+        ItemFormat::Info,
+        dedup_inputs,
+        input_counts,
+    );
+    file_content_producer(
+        &input_files.xmls.into_inner(),
+        sender,
+        ItemFormat::JacocoXml,
+        dedup_inputs,
+        input_counts,
+    );
+    profraw_producer(
+        tmp_dir,
+        &input_files.profraws.into_inner(),
+        sender,
+        dedup_inputs,
+        input_counts,
+    );
     gcno_gcda_producer(
         tmp_dir,
-        &gcno_stems_archives.into_inner(),
-        &gcda_stems_archives.into_inner(),
+        &input_files.gcno_stems_archives.into_inner(),
+        &input_files.gcda_stems_archives.into_inner(),
         sender,
         ignore_orphan_gcno,
+        dedup_inputs,
+        input_counts,
     );
 
-    get_mapping(&linked_files_maps.into_inner())
+    get_mapping(&input_files.linked_files_maps.into_inner())
+}
+
+/// The type of an input file, as detected by the producer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputKind {
+    Gcno,
+    LlvmGcno,
+    Gcda,
+    Profraw,
+    Profdata,
+    Info,
+    JacocoXml,
+    LinkedFilesMap,
+}
+
+impl fmt::Display for InputKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InputKind::Gcno => "gcno",
+            InputKind::LlvmGcno => "gcno (LLVM)",
+            InputKind::Gcda => "gcda",
+            InputKind::Profraw => "profraw",
+            InputKind::Profdata => "profdata",
+            InputKind::Info => "lcov",
+            InputKind::JacocoXml => "JaCoCo XML",
+            InputKind::LinkedFilesMap => "linked files map",
+        })
+    }
+}
+
+/// An input file found by the producer, in an archive (a zip file, a directory, or the plain
+/// files given on the command line).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct DiscoveredInput {
+    pub archive: String,
+    pub path: String,
+    pub kind: InputKind,
+}
+
+/// The input files found by the producer, without parsing them, for --dry-run.
+#[derive(Debug, Default)]
+pub struct Discovery {
+    pub inputs: Vec<DiscoveredInput>,
+    /// The source files of the lcov reports, from their `SF:` records.
+    pub info_source_paths: Vec<String>,
+    /// The content of the linked files map, if there is one.
+    pub path_mapping: Option<Vec<u8>>,
+}
+
+/// Finds the input files of the paths like the producer, without sending them to the consumers.
+pub fn discover_inputs(paths: &[String], is_llvm: bool) -> Discovery {
+    let mut archives = open_archives(paths);
+    let input_files = explore_archives(&mut archives, is_llvm);
+
+    let mut inputs = Vec::new();
+    let mut push = |archive: &Archive, path: String, kind| {
+        inputs.push(DiscoveredInput {
+            archive: archive.get_name().to_string(),
+            path,
+            kind,
+        })
+    };
+    for (gcno_stem, archive) in input_files.gcno_stems_archives.borrow().iter() {
+        let kind = if gcno_stem.llvm {
+            InputKind::LlvmGcno
+        } else {
+            InputKind::Gcno
+        };
+        push(archive, format!("{}.gcno", gcno_stem.stem), kind);
+    }
+    for (stem, archives) in input_files.gcda_stems_archives.borrow().iter() {
+        for archive in archives {
+            push(archive, format!("{}.gcda", stem), InputKind::Gcda);
+        }
+    }
+    for (name, archives) in input_files.profraws.borrow().iter() {
+        let kind = if name.ends_with(".profdata") {
+            InputKind::Profdata
+        } else {
+            InputKind::Profraw
+        };
+        for archive in archives {
+            push(archive, name.clone(), kind);
+        }
+    }
+    let mut info_source_paths = Vec::new();
+    for (name, archives) in input_files.infos.borrow().iter() {
+        for archive in archives {
+            push(archive, name.clone(), InputKind::Info);
+            if let Some(buffer) = archive.read(name) {
+                info_source_paths.extend(String::from_utf8_lossy(&buffer).lines().filter_map(
+                    |line| {
+                        line.strip_prefix("SF:")
+                            .map(|path| path.trim_end().to_string())
+                    },
+                ));
+            }
+        }
+    }
+    for (name, archives) in input_files.xmls.borrow().iter() {
+        for archive in archives {
+            push(archive, name.clone(), InputKind::JacocoXml);
+        }
+    }
+    for (name, archive) in input_files.linked_files_maps.borrow().iter() {
+        push(archive, name.clone(), InputKind::LinkedFilesMap);
+    }
+    inputs.sort();
+    info_source_paths.sort();
+    info_source_paths.dedup();
+
+    Discovery {
+        inputs,
+        info_source_paths,
+        path_mapping: get_mapping(&input_files.linked_files_maps.into_inner()),
+    }
 }
 
 #[cfg(test)]
@@ -568,6 +1051,7 @@ mod tests {
     use super::*;
     use crossbeam_channel::unbounded;
     use serde_json::{self, Value};
+    use std::io::Write;
 
     fn check_produced(
         directory: PathBuf,
@@ -576,7 +1060,24 @@ mod tests {
     ) {
         let mut vec: Vec<Option<WorkItem>> = Vec::new();
 
+        // The GCC pairs of zip archives are extracted as the producer used to, numbered by their
+        // gcda archive, for the checks below.
+        let mut zip_readers = ZipReaders::default();
+        let mut numbers: FxHashMap<String, usize> = FxHashMap::default();
         while let Ok(elem) = receiver.try_recv() {
+            let elem = elem.map(|mut item| {
+                if let ItemType::GccEntries(entries) = item.item {
+                    let num = numbers.entry(entries.stem.clone()).or_insert(0);
+                    *num += 1;
+                    let gcno_path = directory.join(format!("{}_{}.gcno", entries.stem, num));
+                    fs::create_dir_all(gcno_path.parent().unwrap()).unwrap();
+                    zip_readers
+                        .extract_gcc_entries(&entries, &gcno_path)
+                        .expect("Failed to extract the GCC files");
+                    item.item = ItemType::Path((entries.stem, gcno_path));
+                }
+                item
+            });
             vec.push(elem);
         }
 
@@ -596,8 +1097,11 @@ mod tests {
                     match x.item {
                         ItemType::Content(_) => !elem.1,
                         ItemType::Path((_, ref p)) => elem.1 && p.ends_with(elem.2),
+                        ItemType::ZipEntry((_, ref name)) => elem.1 && name.ends_with(elem.2),
                         ItemType::Paths(ref paths) => paths.iter().any(|p| p.ends_with(elem.2)),
                         ItemType::Buffers(ref b) => b.stem.replace('\\', "/").ends_with(elem.2),
+                        ItemType::Entries(ref e) => e.stem.replace('\\', "/").ends_with(elem.2),
+                        ItemType::GccEntries(_) => unreachable!(),
                     }
                 }),
                 "Missing {:?}",
@@ -616,8 +1120,11 @@ mod tests {
                     match v.item {
                         ItemType::Content(_) => !x.1,
                         ItemType::Path((_, ref p)) => x.1 && p.ends_with(x.2),
+                        ItemType::ZipEntry((_, ref name)) => x.1 && name.ends_with(x.2),
                         ItemType::Paths(ref paths) => paths.iter().any(|p| p.ends_with(x.2)),
                         ItemType::Buffers(ref b) => b.stem.replace('\\', "/").ends_with(x.2),
+                        ItemType::Entries(ref e) => e.stem.replace('\\', "/").ends_with(x.2),
+                        ItemType::GccEntries(_) => unreachable!(),
                     }
                 }),
                 "Unexpected {:?}",
@@ -654,7 +1161,15 @@ mod tests {
 
         let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
         let tmp_path = tmp_dir.path().to_owned();
-        let mapping = producer(&tmp_path, &["test".to_string()], &sender, false, false);
+        let mapping = producer(
+            &tmp_path,
+            &["test".to_string()],
+            &sender,
+            false,
+            false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
+        );
 
         let expected = vec![
             (ItemFormat::Gcno, true, "Platform_1.gcno", true),
@@ -713,6 +1228,7 @@ mod tests {
             (ItemFormat::Info, false, "prova_fn_with_commas.info", false),
             (ItemFormat::Info, false, "empty_line.info", false),
             (ItemFormat::Info, false, "invalid_DA_record.info", false),
+            (ItemFormat::Info, false, "llvm_cov_export.info", false),
             (
                 ItemFormat::Info,
                 false,
@@ -768,6 +1284,110 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_archive_read_content() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let big_info = "SF:src/lib.rs\nDA:1,1\nend_of_record\n"
+            .repeat(MMAP_THRESHOLD as usize / 32 + 1)
+            .into_bytes();
+        fs::write(tmp_dir.path().join("small.info"), b"SF:src/lib.rs\n").unwrap();
+        fs::write(tmp_dir.path().join("big.info"), &big_info).unwrap();
+        let archive = Archive {
+            name: "dir".to_string(),
+            item: RefCell::new(ArchiveType::Dir(tmp_dir.path().to_owned())),
+        };
+
+        let small = archive.read_content("small.info").unwrap();
+        assert!(matches!(small, FileContent::Buffer(_)));
+        assert_eq!(&*small, b"SF:src/lib.rs\n");
+        let big = archive.read_content("big.info").unwrap();
+        assert!(matches!(big, FileContent::Mapped(_)));
+        assert_eq!(&*big, &big_info[..]);
+        assert!(archive.read_content("missing.info").is_none());
+    }
+
+    #[test]
+    fn test_zip_producer_big_entries() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let zip_path = tmp_dir.path().join("coverage.zip");
+        let mut big_info = String::from("SF:src/lib.rs\nDA:1,1\n");
+        let padding = format!("#{}\n", "-".repeat(1023));
+        big_info.push_str(&padding.repeat(MMAP_THRESHOLD as usize / 1024 + 1));
+        big_info.push_str("end_of_record\n");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("nested/dir\\big.info", options).unwrap();
+        zip.write_all(big_info.as_bytes()).unwrap();
+        zip.start_file("nested\\small.info", options).unwrap();
+        zip.write_all(b"SF:src/main.rs\nDA:2,0\nend_of_record\n")
+            .unwrap();
+        zip.finish().unwrap();
+
+        let (sender, receiver) = unbounded();
+        producer(
+            tmp_dir.path(),
+            &[zip_path.to_string_lossy().into_owned()],
+            &sender,
+            false,
+            false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
+        );
+        let mut streamed = Vec::new();
+        let mut read = Vec::new();
+        while let Ok(Some(work_item)) = receiver.try_recv() {
+            assert_eq!(work_item.format, ItemFormat::Info);
+            match work_item.item {
+                ItemType::ZipEntry((path, name)) => {
+                    assert_eq!(path, zip_path);
+                    streamed.extend(
+                        parse_zip_entry(&path, &name, |file| crate::parse_lcov_reader(file, false))
+                            .unwrap(),
+                    );
+                }
+                ItemType::Content(content) => {
+                    read.extend(crate::parse_lcov(&content, false).unwrap())
+                }
+                item => panic!("Unexpected item {:?}", item),
+            }
+        }
+        assert_eq!(streamed.len(), 1);
+        assert_eq!(streamed[0].0, "src/lib.rs");
+        assert_eq!(streamed[0].1.lines, [(1, 1)].iter().cloned().collect());
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].0, "src/main.rs");
+    }
+
+    #[test]
+    fn test_dir_producer_profdata_files() {
+        let (sender, receiver) = unbounded();
+
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+        let input_dir = tmp_path.join("input");
+        fs::create_dir(&input_dir).unwrap();
+        fs::write(input_dir.join("default.profraw"), b"").unwrap();
+        fs::write(input_dir.join("sanitizer.profdata"), b"").unwrap();
+        let mapping = producer(
+            &tmp_path,
+            &[input_dir.to_str().unwrap().to_string()],
+            &sender,
+            false,
+            false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
+        );
+
+        let expected = vec![
+            (ItemFormat::Profraw, true, "default_1.profraw", false),
+            (ItemFormat::Profraw, true, "sanitizer_1.profdata", false),
+        ];
+
+        check_produced(tmp_path, &receiver, expected);
+        assert!(mapping.is_none());
+    }
+
     #[test]
     fn test_dir_producer_multiple_directories() {
         let (sender, receiver) = unbounded();
@@ -780,6 +1400,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -803,6 +1425,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![(ItemFormat::Gcno, true, "main_1.gcno", true)];
@@ -823,6 +1447,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -846,6 +1472,8 @@ mod tests {
             &sender,
             true,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![(ItemFormat::Gcno, true, "main_1.gcno", true)];
@@ -854,6 +1482,44 @@ mod tests {
         assert!(mapping.is_none());
     }
 
+    #[test]
+    fn test_discover_inputs() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let profraw = tmp_dir.path().join("default.profraw");
+        fs::write(&profraw, b"").unwrap();
+        let info = tmp_dir.path().join("unit.info");
+        fs::write(
+            &info,
+            "SF:src/b.rs\nend_of_record\nSF:src/a.rs\nend_of_record\n",
+        )
+        .unwrap();
+        let paths = vec![
+            "test/sub".to_string(),
+            profraw.to_string_lossy().into_owned(),
+            info.to_string_lossy().into_owned(),
+        ];
+
+        let discovery = discover_inputs(&paths, false);
+        let inputs: Vec<(&str, InputKind)> = discovery
+            .inputs
+            .iter()
+            .map(|input| (input.path.as_str(), input.kind))
+            .collect();
+        assert_eq!(
+            inputs,
+            vec![
+                (profraw.to_str().unwrap(), InputKind::Profraw),
+                (info.to_str().unwrap(), InputKind::Info),
+                ("prova2.gcda", InputKind::Gcda),
+                ("prova2.gcno", InputKind::Gcno),
+            ]
+        );
+        assert_eq!(discovery.inputs[0].archive, "plain files");
+        assert_eq!(discovery.inputs[2].archive, "test/sub");
+        assert_eq!(discovery.info_source_paths, vec!["src/a.rs", "src/b.rs"]);
+        assert!(discovery.path_mapping.is_none());
+    }
+
     #[test]
     fn test_zip_producer_with_gcda_dir() {
         let (sender, receiver) = unbounded();
@@ -869,6 +1535,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -920,6 +1588,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -979,6 +1649,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -1021,6 +1693,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -1070,6 +1744,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -1093,6 +1769,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -1129,6 +1807,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -1162,6 +1842,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -1207,6 +1889,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -1259,6 +1943,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![(ItemFormat::Gcno, true, "main_1.gcno", false)];
@@ -1284,6 +1970,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![(ItemFormat::Gcno, true, "main_1.gcno", true)];
@@ -1306,6 +1994,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
     }
 
@@ -1322,6 +2012,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -1364,6 +2056,8 @@ mod tests {
             &sender,
             false,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -1410,6 +2104,8 @@ mod tests {
             &sender,
             true,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -1444,6 +2140,8 @@ mod tests {
             &sender,
             true,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![
@@ -1486,6 +2184,8 @@ mod tests {
             &sender,
             true,
             true,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
         let gcno_buf: Vec<u8> = vec![
             111, 110, 99, 103, 42, 50, 48, 52, 74, 200, 254, 66, 0, 0, 0, 1, 9, 0, 0, 0, 0, 0, 0,
@@ -1512,15 +2212,44 @@ mod tests {
             0, 0, 0, 0, 0, 0, 163, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
         ];
 
+        // The files of the zip archives are read by the consumers.
+        let mut zip_readers = ZipReaders::default();
         while let Ok(elem) = receiver.try_recv() {
             let elem = elem.unwrap();
-            if let ItemType::Buffers(buffers) = elem.item {
+            if let ItemType::Entries(entries) = elem.item {
+                let buffers = zip_readers.read_gcno_entries(entries).unwrap();
                 let stem = PathBuf::from(buffers.stem);
                 let stem = stem.file_stem().expect("Unable to get file_stem");
 
                 assert!(stem == "file", "Unexpected file: {:?}", stem);
                 assert_eq!(buffers.gcno_buf, gcno_buf);
                 assert_eq!(buffers.gcda_buf, vec![gcda1_buf.clone(), gcda2_buf.clone()]);
+            } else {
+                panic!("Entries expected");
+            }
+        }
+
+        // The deduplicated gcda files are read by the producer, to compare their contents.
+        let mut dedup_inputs = DedupInputs::none();
+        dedup_inputs.add(InputKind::Gcda);
+        producer(
+            &tmp_path,
+            &[
+                "test/llvm/gcno.zip".to_string(),
+                "test/llvm/gcda1.zip".to_string(),
+                "test/llvm/gcda2.zip".to_string(),
+            ],
+            &sender,
+            true,
+            true,
+            &dedup_inputs,
+            &InputCounts::default(),
+        );
+        while let Ok(elem) = receiver.try_recv() {
+            let elem = elem.unwrap();
+            if let ItemType::Buffers(buffers) = elem.item {
+                assert_eq!(buffers.gcno_buf, gcno_buf);
+                assert_eq!(buffers.gcda_buf, vec![gcda1_buf.clone()]);
             } else {
                 panic!("Buffers expected");
             }
@@ -1540,6 +2269,8 @@ mod tests {
             &sender,
             true,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         assert!(mapping.is_some());
@@ -1570,6 +2301,8 @@ mod tests {
             &sender,
             true,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
 
         let expected = vec![(ItemFormat::Profraw, true, "default.profraw", false)];
@@ -1577,6 +2310,142 @@ mod tests {
         check_produced(PathBuf::from("test"), &receiver, expected);
     }
 
+    #[test]
+    fn test_profraw_producer_duplicates() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let input_dir = tmp_dir.path().join("input");
+        fs::create_dir(&input_dir).unwrap();
+        // Two runs which got the same PID, and a different one.
+        fs::copy(
+            "test/default.profraw",
+            input_dir.join("default_1234.profraw"),
+        )
+        .unwrap();
+        fs::copy(
+            "test/default.profraw",
+            input_dir.join("default_1234_2.profraw"),
+        )
+        .unwrap();
+        fs::write(input_dir.join("default_5678.profraw"), b"other run").unwrap();
+
+        let input_counts = InputCounts::default();
+        let produced_paths = |dedup_inputs: DedupInputs| {
+            let (sender, receiver) = unbounded();
+            let working_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+            producer(
+                working_dir.path(),
+                &[input_dir.to_string_lossy().into_owned()],
+                &sender,
+                true,
+                true,
+                &dedup_inputs,
+                &input_counts,
+            );
+            match receiver.try_recv().unwrap().unwrap().item {
+                ItemType::Paths(mut paths) => {
+                    paths.sort();
+                    paths
+                }
+                _ => panic!("Expected the profraw paths"),
+            }
+        };
+
+        assert_eq!(produced_paths(DedupInputs::none()).len(), 3);
+        assert_eq!(input_counts.duplicates(), 0);
+        let paths = produced_paths(DedupInputs::default());
+        assert_eq!(paths.len(), 2);
+        assert_eq!(input_counts.duplicates(), 1);
+        assert!(paths
+            .iter()
+            .any(|path| path.to_string_lossy().contains("default_5678")));
+
+        let mut paths = vec![
+            input_dir.join("default_1234.profraw"),
+            input_dir.join("default_5678.profraw"),
+            input_dir.join("default_1234_2.profraw"),
+        ];
+        assert_eq!(find_duplicate_profraws(&paths), vec![(2, 0)]);
+        paths.remove(2);
+        assert!(find_duplicate_profraws(&paths).is_empty());
+    }
+
+    #[test]
+    fn test_compressed_profraw_producer() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let input_dir = tmp_dir.path().join("input");
+        fs::create_dir(&input_dir).unwrap();
+        fs::write(input_dir.join("default.profraw.gz"), b"").unwrap();
+        // The tarballs in the directories aren't profiles, unlike the ones given as inputs.
+        fs::write(input_dir.join("release.tar.gz"), b"").unwrap();
+        let tarball = tmp_dir.path().join("profraws.tgz");
+        fs::write(&tarball, b"").unwrap();
+
+        let (sender, receiver) = unbounded();
+        producer(
+            tmp_dir.path(),
+            &[
+                input_dir.to_string_lossy().into_owned(),
+                tarball.to_string_lossy().into_owned(),
+            ],
+            &sender,
+            true,
+            true,
+            &DedupInputs::none(),
+            &InputCounts::default(),
+        );
+        match receiver.try_recv().unwrap().unwrap().item {
+            ItemType::Paths(mut paths) => {
+                paths.sort();
+                assert_eq!(paths.len(), 2);
+                assert!(paths[0].ends_with("default.profraw_1.gz"));
+                assert_eq!(paths[1], tarball);
+            }
+            _ => panic!("Expected the profraw paths"),
+        }
+    }
+
+    #[test]
+    fn test_info_producer_duplicates() {
+        // The same report copied in the artifacts of two jobs.
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        for job in &["job1", "job2"] {
+            fs::create_dir(tmp_dir.path().join(job)).unwrap();
+            fs::copy(
+                "test/prova.info",
+                tmp_dir.path().join(job).join("prova.info"),
+            )
+            .unwrap();
+        }
+        let inputs = [
+            tmp_dir.path().join("job1").to_string_lossy().into_owned(),
+            tmp_dir.path().join("job2").to_string_lossy().into_owned(),
+        ];
+
+        let produced = |dedup_inputs: DedupInputs| {
+            let (sender, receiver) = unbounded();
+            let input_counts = InputCounts::default();
+            producer(
+                tmp_dir.path(),
+                &inputs,
+                &sender,
+                true,
+                false,
+                &dedup_inputs,
+                &input_counts,
+            );
+            let items = receiver
+                .try_iter()
+                .flatten()
+                .filter(|item| item.format == ItemFormat::Info)
+                .count();
+            (items, input_counts.duplicates())
+        };
+
+        // The lcov files are merged as many times as they're given by default.
+        assert_eq!(produced(DedupInputs::default()), (2, 0));
+        assert_eq!(produced("info".parse().unwrap()), (1, 1));
+    }
+
     #[test]
     #[should_panic]
     fn test_plain_producer_with_gcno() {
@@ -1590,6 +2459,8 @@ mod tests {
             &sender,
             true,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
     }
 
@@ -1606,6 +2477,8 @@ mod tests {
             &sender,
             true,
             false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
         );
     }
 
@@ -0,0 +1,340 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{info, Log, Metadata, Record};
+use once_cell::sync::OnceCell;
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::defs::{ItemType, WorkItem};
+
+/// The progress of the current run, updated by the producer and the consumers when it is set.
+pub static PROGRESS: OnceCell<Progress> = OnceCell::new();
+
+// How often the progress is printed when there is no progress bar.
+const LINE_INTERVAL: Duration = Duration::from_secs(10);
+// How often the progress bar is redrawn, so that it keeps moving while a large file is parsed.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The phases of a run, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Collecting,
+    Parsing,
+    Merging,
+    Writing,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Collecting => "collecting",
+            Phase::Parsing => "parsing",
+            Phase::Merging => "merging",
+            Phase::Writing => "writing outputs",
+        })
+    }
+}
+
+/// How the progress is reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// A progress bar on stderr.
+    Bar,
+    /// A line on stderr every few seconds.
+    Lines,
+    /// An info message every few seconds, only shown with --log-level INFO or lower.
+    Log,
+}
+
+impl ProgressMode {
+    /// The progress bar is only shown when stderr is a terminal, and --quiet isn't set.
+    pub fn detect(quiet: bool) -> Self {
+        if quiet {
+            ProgressMode::Log
+        } else if std::io::stderr().is_terminal() {
+            ProgressMode::Bar
+        } else {
+            ProgressMode::Lines
+        }
+    }
+}
+
+/// Returns the number of coverage files of a work item. The profraw files are sent all together,
+/// and the gcno files are counted with their gcda files.
+pub fn work_item_files(work_item: &WorkItem) -> u64 {
+    match &work_item.item {
+        ItemType::Paths(paths) => paths.len() as u64,
+        ItemType::Buffers(buffers) => buffers.gcda_buf.len().max(1) as u64,
+        ItemType::Entries(entries) => entries.gcdas.len().max(1) as u64,
+        ItemType::Path(_)
+        | ItemType::Content(_)
+        | ItemType::ZipEntry(_)
+        | ItemType::GccEntries(_) => 1,
+    }
+}
+
+pub struct Progress {
+    mode: ProgressMode,
+    discovered: AtomicU64,
+    parsed: AtomicU64,
+    phase: Mutex<Phase>,
+    bar: Mutex<Option<ProgressBar>>,
+    // Incremented at the start and at the end of each run, to stop the periodic lines.
+    generation: AtomicU64,
+}
+
+impl Progress {
+    pub fn new(mode: ProgressMode) -> Self {
+        Progress {
+            mode,
+            discovered: AtomicU64::new(0),
+            parsed: AtomicU64::new(0),
+            phase: Mutex::new(Phase::Collecting),
+            bar: Mutex::new(None),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Starts reporting the progress of a new run, from the collecting phase.
+    pub fn start(&'static self) {
+        self.discovered.store(0, Ordering::SeqCst);
+        self.parsed.store(0, Ordering::SeqCst);
+        *self.phase.lock().unwrap() = Phase::Collecting;
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        if self.mode == ProgressMode::Bar {
+            let style = ProgressStyle::with_template(
+                "{spinner} {msg:15} [{bar:40}] {pos}/{len} files ({elapsed})",
+            )
+            .unwrap()
+            .progress_chars("=> ");
+            let bar = ProgressBar::new(0).with_style(style);
+            bar.set_message(Phase::Collecting.to_string());
+            bar.enable_steady_tick(TICK_INTERVAL);
+            *self.bar.lock().unwrap() = Some(bar);
+        } else {
+            thread::spawn(move || loop {
+                thread::sleep(LINE_INTERVAL);
+                if self.generation.load(Ordering::SeqCst) != generation {
+                    break;
+                }
+                match self.mode {
+                    ProgressMode::Log => info!("{}", self.status()),
+                    _ => eprintln!("{}", self.status()),
+                }
+            });
+        }
+    }
+
+    /// Stops reporting the progress of the current run, and clears the progress bar.
+    pub fn finish(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        if let Some(bar) = self.bar.lock().unwrap().take() {
+            bar.finish_and_clear();
+        }
+    }
+
+    pub fn set_phase(&self, phase: Phase) {
+        *self.phase.lock().unwrap() = phase;
+        if let Some(bar) = self.bar() {
+            bar.set_message(phase.to_string());
+        }
+    }
+
+    pub fn add_discovered(&self, files: u64) {
+        self.discovered.fetch_add(files, Ordering::SeqCst);
+        if let Some(bar) = self.bar() {
+            bar.inc_length(files);
+        }
+    }
+
+    pub fn add_parsed(&self, files: u64) {
+        self.parsed.fetch_add(files, Ordering::SeqCst);
+        if let Some(bar) = self.bar() {
+            bar.inc(files);
+        }
+    }
+
+    /// Returns a summary of the progress, e.g. "parsing: 12/40 files".
+    pub fn status(&self) -> String {
+        format!(
+            "{}: {}/{} files",
+            self.phase.lock().unwrap(),
+            self.parsed.load(Ordering::SeqCst),
+            self.discovered.load(Ordering::SeqCst)
+        )
+    }
+
+    /// Hides the progress bar while `f` prints to the terminal.
+    pub fn suspend<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        match self.bar() {
+            Some(bar) => bar.suspend(f),
+            None => f(),
+        }
+    }
+
+    fn bar(&self) -> Option<ProgressBar> {
+        self.bar.lock().unwrap().clone()
+    }
+}
+
+/// Reports the progress of a run, until dropped.
+pub struct ProgressRun(());
+
+pub fn start_progress() -> ProgressRun {
+    if let Some(progress) = PROGRESS.get() {
+        progress.start();
+    }
+    ProgressRun(())
+}
+
+impl Drop for ProgressRun {
+    fn drop(&mut self) {
+        if let Some(progress) = PROGRESS.get() {
+            progress.finish();
+        }
+    }
+}
+
+/// Counts the coverage files of a work item sent to the consumers.
+pub fn report_discovered(work_item: &WorkItem) {
+    if let Some(progress) = PROGRESS.get() {
+        progress.add_discovered(work_item_files(work_item));
+    }
+}
+
+/// Counts the coverage files of a work item as parsed when dropped, whether their parsing
+/// succeeded or not.
+pub struct ParsedFiles(pub u64);
+
+impl Drop for ParsedFiles {
+    fn drop(&mut self) {
+        if let Some(progress) = PROGRESS.get() {
+            progress.add_parsed(self.0);
+        }
+    }
+}
+
+pub fn report_phase(phase: Phase) {
+    if let Some(progress) = PROGRESS.get() {
+        progress.set_phase(phase);
+    }
+}
+
+/// Hides the progress bar, if any, while `f` prints to the terminal.
+pub fn suspend_progress<F: FnOnce() -> R, R>(f: F) -> R {
+    match PROGRESS.get() {
+        Some(progress) => progress.suspend(f),
+        None => f(),
+    }
+}
+
+/// A logger hiding the progress bar while the messages are written, so that they don't get mixed
+/// up with it.
+pub struct ProgressLogger {
+    inner: Box<dyn Log>,
+}
+
+impl ProgressLogger {
+    pub fn new(inner: Box<dyn Log>) -> Self {
+        ProgressLogger { inner }
+    }
+}
+
+impl Log for ProgressLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.enabled(record.metadata()) {
+            suspend_progress(|| self.inner.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dedup::DedupInputs;
+    use crate::defs::{FileContent, GcnoBuffers, ItemFormat};
+    use crate::producer::producer;
+    use crate::summary::InputCounts;
+    use crossbeam_channel::unbounded;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_work_item_files() {
+        let profraws = WorkItem {
+            format: ItemFormat::Profraw,
+            item: ItemType::Paths(vec![PathBuf::from("a.profraw"), PathBuf::from("b.profraw")]),
+            name: "profraws".to_string(),
+        };
+        assert_eq!(work_item_files(&profraws), 2);
+
+        let gcno = WorkItem {
+            format: ItemFormat::Gcno,
+            item: ItemType::Buffers(GcnoBuffers {
+                stem: "main".to_string(),
+                gcno_buf: Vec::new(),
+                gcda_buf: vec![Vec::new(), Vec::new(), Vec::new()],
+                gcno_entry: None,
+            }),
+            name: "".to_string(),
+        };
+        assert_eq!(work_item_files(&gcno), 3);
+
+        let lcov = WorkItem {
+            format: ItemFormat::Info,
+            item: ItemType::Content(FileContent::Buffer(Vec::new())),
+            name: "lcov.info".to_string(),
+        };
+        assert_eq!(work_item_files(&lcov), 1);
+    }
+
+    #[test]
+    fn test_work_item_files_archives() {
+        let (sender, receiver) = unbounded();
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        producer(
+            tmp_dir.path(),
+            &[
+                "test/info1.zip".to_string(),
+                "test/info2.zip".to_string(),
+                "test/profraw1.zip".to_string(),
+                "test/profraw2.zip".to_string(),
+            ],
+            &sender,
+            false,
+            false,
+            &DedupInputs::none(),
+            &InputCounts::default(),
+        );
+        drop(sender);
+
+        // Each archive entry is counted once.
+        let files: u64 = receiver
+            .iter()
+            .map(|work_item| work_item_files(&work_item.unwrap()))
+            .sum();
+        assert_eq!(files, 14);
+    }
+
+    #[test]
+    fn test_progress_status() {
+        let progress = Progress::new(ProgressMode::Log);
+        progress.add_discovered(3);
+        progress.add_discovered(2);
+        progress.add_parsed(4);
+        assert_eq!(progress.status(), "collecting: 4/5 files");
+        progress.set_phase(Phase::Writing);
+        assert_eq!(progress.status(), "writing outputs: 4/5 files");
+    }
+}
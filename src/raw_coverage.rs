@@ -0,0 +1,871 @@
+//! Support for coverage counters extracted from a device (e.g. via JTAG/SWD) instead of profraw
+//! files written by the profiling runtime.
+//!
+//! The counters are correlated with the LLVM coverage mapping embedded in the binary
+//! (see https://llvm.org/docs/CoverageMappingFormat.html).
+
+use flate2::read::ZlibDecoder;
+use md5::{Digest, Md5};
+use object::{Object, ObjectSection};
+use rustc_hash::FxHashMap;
+use std::collections::{btree_map, BTreeMap};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::defs::*;
+use crate::parser::add_branch;
+use crate::wasm::{is_wasm_module, wasm_custom_sections};
+
+pub(crate) const COVMAP_SECTIONS: &[&str] = &["__llvm_covmap", ".lcovmap$M"];
+const COVFUN_SECTIONS: &[&str] = &["__llvm_covfun", ".lcovfun$M"];
+const PRF_NAMES_SECTIONS: &[&str] = &["__llvm_prf_names", ".lprfn$M"];
+
+// The coverage mapping versions are encoded starting from 0 (i.e. 3 is Version4).
+const COVMAP_VERSION_4: u32 = 3;
+const COVMAP_VERSION_6: u32 = 5;
+const COVMAP_VERSION_7: u32 = 6;
+
+const PRF_NAME_SEPARATOR: u8 = 1;
+// The most lines of a region: the coverage of each line of the regions is computed, so a region
+// of billions of lines of a corrupt mapping would never end.
+const MAX_REGION_LINES: u32 = 1 << 24;
+
+#[derive(Debug)]
+pub enum RawCoverageError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Object(object::Error),
+    InvalidData(String),
+    Unsupported(String),
+}
+
+impl From<io::Error> for RawCoverageError {
+    fn from(err: io::Error) -> RawCoverageError {
+        RawCoverageError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for RawCoverageError {
+    fn from(err: serde_json::Error) -> RawCoverageError {
+        RawCoverageError::Json(err)
+    }
+}
+
+impl From<object::Error> for RawCoverageError {
+    fn from(err: object::Error) -> RawCoverageError {
+        RawCoverageError::Object(err)
+    }
+}
+
+impl fmt::Display for RawCoverageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RawCoverageError::Io(ref err) => write!(f, "IO error: {}", err),
+            RawCoverageError::Json(ref err) => write!(f, "Invalid raw counters file: {}", err),
+            RawCoverageError::Object(ref err) => write!(f, "Invalid binary: {}", err),
+            RawCoverageError::InvalidData(ref s) => write!(f, "Invalid coverage mapping: {}", s),
+            RawCoverageError::Unsupported(ref s) => {
+                write!(f, "Unsupported coverage mapping: {}", s)
+            }
+        }
+    }
+}
+
+/// Raw counter values, indexed by function. A function is identified either by its name (as
+/// stored in the `__llvm_prf_names` section, i.e. the mangled name for Rust and C++) or by the
+/// hexadecimal MD5-based hash of its name used by LLVM (e.g. `0x082a7eaa4b906112`).
+pub type RawCounters = FxHashMap<String, Vec<u64>>;
+
+pub fn parse_raw_counters(buffer: &[u8]) -> Result<RawCounters, RawCoverageError> {
+    Ok(serde_json::from_slice(buffer)?)
+}
+
+struct Data<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    little_endian: bool,
+}
+
+impl<'a> Data<'a> {
+    fn new(buf: &'a [u8], little_endian: bool) -> Self {
+        Data {
+            buf,
+            pos: 0,
+            little_endian,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    // The number of bytes left, which bounds the number of the items still to be read, as each
+    // one takes at least a byte: the counts read from the data can't be trusted for allocations.
+    fn remaining(&self) -> usize {
+        self.buf.len().saturating_sub(self.pos)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], RawCoverageError> {
+        if self.buf.len() - self.pos < len {
+            return Err(RawCoverageError::InvalidData(format!(
+                "unexpected end of data at offset {}",
+                self.pos
+            )));
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, RawCoverageError> {
+        let bytes: [u8; 4] = self.bytes(4)?.try_into().unwrap();
+        Ok(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn u64(&mut self) -> Result<u64, RawCoverageError> {
+        let bytes: [u8; 8] = self.bytes(8)?.try_into().unwrap();
+        Ok(if self.little_endian {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    }
+
+    fn leb128(&mut self) -> Result<u64, RawCoverageError> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.bytes(1)?[0];
+            if shift >= 64 {
+                return Err(RawCoverageError::InvalidData(
+                    "LEB128 value too large".to_string(),
+                ));
+            }
+            result |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+
+    fn u32_leb128(&mut self) -> Result<u32, RawCoverageError> {
+        let value = self.leb128()?;
+        u32::try_from(value)
+            .map_err(|_| RawCoverageError::InvalidData(format!("value {} too large", value)))
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.pos = (self.pos + alignment - 1) & !(alignment - 1);
+    }
+}
+
+/// The LLVM hash of a name: the low 64 bits of its MD5 digest.
+fn md5_hash(data: &[u8]) -> u64 {
+    let digest = Md5::digest(data);
+    u64::from_le_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Reads a list of strings which can be zlib-compressed, as used for the file names in
+/// `__llvm_covmap` and the function names in `__llvm_prf_names`.
+fn read_maybe_compressed(
+    data: &mut Data,
+    uncompressed_len: usize,
+    compressed_len: usize,
+) -> Result<Vec<u8>, RawCoverageError> {
+    if compressed_len == 0 {
+        return Ok(data.bytes(uncompressed_len)?.to_vec());
+    }
+
+    let mut uncompressed = Vec::with_capacity(uncompressed_len.min(data.remaining()));
+    // Decompressing past the expected length is enough to know that it's wrong.
+    ZlibDecoder::new(data.bytes(compressed_len)?)
+        .take(uncompressed_len as u64 + 1)
+        .read_to_end(&mut uncompressed)?;
+    if uncompressed.len() != uncompressed_len {
+        return Err(RawCoverageError::InvalidData(
+            "unexpected length of decompressed data".to_string(),
+        ));
+    }
+    Ok(uncompressed)
+}
+
+fn read_filenames(blob: &[u8], version: u32) -> Result<Vec<String>, RawCoverageError> {
+    let mut data = Data::new(blob, true);
+    let count = data.leb128()? as usize;
+    let uncompressed_len = data.leb128()? as usize;
+    let compressed_len = data.leb128()? as usize;
+    let uncompressed = read_maybe_compressed(&mut data, uncompressed_len, compressed_len)?;
+
+    let mut data = Data::new(&uncompressed, true);
+    let mut filenames = Vec::with_capacity(count.min(data.remaining()));
+    for i in 0..count {
+        let len = data.leb128()? as usize;
+        let filename = String::from_utf8_lossy(data.bytes(len)?).to_string();
+        // Starting from Version6, the first file name is the compilation directory, and all the
+        // relative paths are relative to it.
+        if version >= COVMAP_VERSION_6 && i > 0 && Path::new(&filename).is_relative() {
+            let filename = Path::new(&filenames[0]).join(filename);
+            filenames.push(filename.to_string_lossy().to_string());
+        } else {
+            filenames.push(filename);
+        }
+    }
+    Ok(filenames)
+}
+
+fn read_prf_names(section: &[u8]) -> Result<FxHashMap<u64, String>, RawCoverageError> {
+    let mut data = Data::new(section, true);
+    let mut names = FxHashMap::default();
+    while !data.is_empty() {
+        let uncompressed_len = data.leb128()? as usize;
+        let compressed_len = data.leb128()? as usize;
+        let uncompressed = read_maybe_compressed(&mut data, uncompressed_len, compressed_len)?;
+        for name in uncompressed.split(|&c| c == PRF_NAME_SEPARATOR) {
+            if !name.is_empty() {
+                names.insert(md5_hash(name), String::from_utf8_lossy(name).to_string());
+            }
+        }
+        // The section is padded with zeros.
+        while !data.is_empty() && data.buf[data.pos] == 0 {
+            data.pos += 1;
+        }
+    }
+    Ok(names)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Counter {
+    Zero,
+    Reference(usize),
+    Subtract(usize),
+    Add(usize),
+}
+
+impl Counter {
+    fn decode(value: u64) -> Counter {
+        let id = (value >> 2) as usize;
+        match value & 3 {
+            0 => Counter::Zero,
+            1 => Counter::Reference(id),
+            2 => Counter::Subtract(id),
+            _ => Counter::Add(id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RegionKind {
+    Code,
+    Expansion(usize),
+    Skipped,
+    Gap,
+    Branch(Counter),
+    McdcDecision,
+}
+
+#[derive(Debug, Clone)]
+struct Region {
+    counter: Counter,
+    kind: RegionKind,
+    file_id: usize,
+    line_start: u32,
+    column_start: u32,
+    line_end: u32,
+}
+
+struct FunctionMapping {
+    files: Vec<usize>,
+    expressions: Vec<(Counter, Counter)>,
+    regions: Vec<Region>,
+}
+
+fn read_function_mapping(data: &[u8], version: u32) -> Result<FunctionMapping, RawCoverageError> {
+    let mut data = Data::new(data, true);
+
+    let num_files = data.leb128()? as usize;
+    let mut files = Vec::with_capacity(num_files.min(data.remaining()));
+    for _ in 0..num_files {
+        files.push(data.leb128()? as usize);
+    }
+
+    let num_expressions = data.leb128()? as usize;
+    let mut expressions = Vec::with_capacity(num_expressions.min(data.remaining()));
+    for _ in 0..num_expressions {
+        let lhs = Counter::decode(data.leb128()?);
+        let rhs = Counter::decode(data.leb128()?);
+        expressions.push((lhs, rhs));
+    }
+
+    let mut regions = Vec::new();
+    for file_id in 0..num_files {
+        let num_regions = data.leb128()?;
+        let mut line_start = 0u32;
+        for _ in 0..num_regions {
+            let encoded = data.leb128()?;
+            let mut counter = Counter::Zero;
+            let mut kind = RegionKind::Code;
+
+            if encoded & 3 != 0 {
+                counter = Counter::decode(encoded);
+            } else if encoded & 4 != 0 {
+                let expanded_file_id = (encoded >> 3) as usize;
+                if expanded_file_id >= num_files {
+                    return Err(RawCoverageError::InvalidData(format!(
+                        "invalid expanded file id {}",
+                        expanded_file_id
+                    )));
+                }
+                kind = RegionKind::Expansion(expanded_file_id);
+            } else {
+                match encoded >> 3 {
+                    0 => {}
+                    2 => kind = RegionKind::Skipped,
+                    4 => {
+                        counter = Counter::decode(data.leb128()?);
+                        kind = RegionKind::Branch(Counter::decode(data.leb128()?));
+                    }
+                    5 if version >= COVMAP_VERSION_7 => {
+                        // Bitmap index and number of conditions.
+                        data.leb128()?;
+                        data.leb128()?;
+                        kind = RegionKind::McdcDecision;
+                    }
+                    6 if version >= COVMAP_VERSION_7 => {
+                        counter = Counter::decode(data.leb128()?);
+                        let false_counter = Counter::decode(data.leb128()?);
+                        // Condition ID, true and false IDs.
+                        data.leb128()?;
+                        data.leb128()?;
+                        data.leb128()?;
+                        kind = RegionKind::Branch(false_counter);
+                    }
+                    kind => {
+                        return Err(RawCoverageError::InvalidData(format!(
+                            "unknown region kind {}",
+                            kind
+                        )))
+                    }
+                }
+            }
+
+            // The lines are read from the file, so they are checked not to overflow, which also
+            // means that a region never ends before it starts.
+            let line_overflow =
+                || RawCoverageError::InvalidData("region line out of range".to_string());
+            line_start = line_start
+                .checked_add(data.u32_leb128()?)
+                .ok_or_else(line_overflow)?;
+            let column_start = data.u32_leb128()?;
+            let num_lines = data.u32_leb128()?;
+            let column_end = data.u32_leb128()?;
+            if column_end & (1 << 31) != 0 && kind == RegionKind::Code {
+                kind = RegionKind::Gap;
+            }
+            if num_lines > MAX_REGION_LINES {
+                return Err(RawCoverageError::InvalidData(format!(
+                    "region of {} lines",
+                    num_lines
+                )));
+            }
+            let line_end = line_start
+                .checked_add(num_lines)
+                .ok_or_else(line_overflow)?;
+
+            regions.push(Region {
+                counter,
+                kind,
+                file_id,
+                line_start,
+                column_start,
+                line_end,
+            });
+        }
+    }
+
+    Ok(FunctionMapping {
+        files,
+        expressions,
+        regions,
+    })
+}
+
+fn evaluate(counter: Counter, expressions: &[(Counter, Counter)], counters: &[u64]) -> u64 {
+    // The expressions only ever reference earlier expressions, but guard against malformed data
+    // by limiting the depth of the evaluation.
+    fn eval(
+        counter: Counter,
+        expressions: &[(Counter, Counter)],
+        counters: &[u64],
+        depth: usize,
+    ) -> u64 {
+        if depth > expressions.len() {
+            return 0;
+        }
+        match counter {
+            Counter::Zero => 0,
+            Counter::Reference(id) => counters.get(id).copied().unwrap_or(0),
+            Counter::Subtract(id) | Counter::Add(id) => {
+                let (lhs, rhs) = match expressions.get(id) {
+                    Some(expression) => *expression,
+                    None => return 0,
+                };
+                let lhs = eval(lhs, expressions, counters, depth + 1);
+                let rhs = eval(rhs, expressions, counters, depth + 1);
+                if let Counter::Add(_) = counter {
+                    lhs.saturating_add(rhs)
+                } else {
+                    lhs.saturating_sub(rhs)
+                }
+            }
+        }
+    }
+
+    eval(counter, expressions, counters, 0)
+}
+
+fn add_function_results(
+    results: &mut FxHashMap<String, CovResult>,
+    name: &str,
+    mapping: &FunctionMapping,
+    filenames: &[String],
+    counters: &[u64],
+    branch_enabled: bool,
+) -> Result<(), RawCoverageError> {
+    let count = |counter| evaluate(counter, &mapping.expressions, counters);
+    let filename = |file_id: usize| {
+        mapping
+            .files
+            .get(file_id)
+            .and_then(|&index| filenames.get(index))
+            .ok_or_else(|| RawCoverageError::InvalidData(format!("invalid file id {}", file_id)))
+    };
+
+    // The count of an expansion region is the count of the first region of the expanded file.
+    let region_count = |region: &Region| match region.kind {
+        RegionKind::Expansion(expanded_file_id) => mapping
+            .regions
+            .iter()
+            .find(|r| r.file_id == expanded_file_id)
+            .map_or(0, |r| count(r.counter)),
+        _ => count(region.counter),
+    };
+
+    for file_id in 0..mapping.files.len() {
+        let file_regions: Vec<&Region> = mapping
+            .regions
+            .iter()
+            .filter(|r| r.file_id == file_id)
+            .collect();
+        if file_regions.is_empty() {
+            continue;
+        }
+
+        // A line is covered as many times as the regions starting on it, if any, or as the
+        // innermost region that wraps it otherwise.
+        let mut starts: BTreeMap<u32, u64> = BTreeMap::new();
+        let mut wrapped: BTreeMap<u32, ((u32, u32), u64)> = BTreeMap::new();
+        let mut skipped = Vec::new();
+        let mut branches: BTreeMap<u32, Vec<bool>> = BTreeMap::new();
+
+        for region in &file_regions {
+            match region.kind {
+                RegionKind::Code | RegionKind::Expansion(_) => {
+                    let c = region_count(region);
+                    let entry = starts.entry(region.line_start).or_insert(0);
+                    *entry = (*entry).max(c);
+                    for line in region.line_start + 1..=region.line_end {
+                        let key = (region.line_start, region.column_start);
+                        match wrapped.entry(line) {
+                            btree_map::Entry::Occupied(mut e) => {
+                                if e.get().0 <= key {
+                                    e.insert((key, c));
+                                }
+                            }
+                            btree_map::Entry::Vacant(e) => {
+                                e.insert((key, c));
+                            }
+                        }
+                    }
+                }
+                RegionKind::Gap => {
+                    let c = region_count(region);
+                    for line in region.line_start + 1..region.line_end {
+                        wrapped.insert(line, ((region.line_start, region.column_start), c));
+                    }
+                }
+                RegionKind::Skipped => skipped.push((region.line_start, region.line_end)),
+                RegionKind::Branch(false_counter) => {
+                    if branch_enabled {
+                        let n = branches.get(&region.line_start).map_or(0, |b| b.len()) as u32;
+                        add_branch(
+                            &mut branches,
+                            region.line_start,
+                            n,
+                            count(region.counter) > 0,
+                        );
+                        add_branch(
+                            &mut branches,
+                            region.line_start,
+                            n + 1,
+                            count(false_counter) > 0,
+                        );
+                    }
+                }
+                RegionKind::McdcDecision => {}
+            }
+        }
+
+        let mut lines: BTreeMap<u32, u64> = wrapped
+            .into_iter()
+            .filter(|(line, _)| !skipped.iter().any(|&(s, e)| s <= *line && *line <= e))
+            .map(|(line, (_, c))| (line, c))
+            .collect();
+        lines.extend(starts);
+
+        let result = results.entry(filename(file_id)?.clone()).or_default();
+        for (line, c) in lines {
+            let entry = result.lines.entry(line).or_insert(0);
+            *entry = entry.saturating_add(c);
+        }
+        for (line, taken) in branches {
+            for (n, taken) in taken.into_iter().enumerate() {
+                add_branch(&mut result.branches, line, n as u32, taken);
+            }
+        }
+
+        if file_id == 0 {
+            let executed = region_count(file_regions[0]) > 0;
+            result
+                .functions
+                .entry(name.to_string())
+                .and_modify(|f| f.executed |= executed)
+                .or_insert(Function {
+                    start: file_regions[0].line_start,
+                    executed,
+                });
+        }
+    }
+
+    Ok(())
+}
+
+fn sections<'a>(
+    obj: &'a object::File<'a>,
+    names: &'a [&str],
+) -> impl Iterator<Item = Result<&'a [u8], object::Error>> + 'a {
+    obj.sections()
+        .filter(move |section| matches!(section.name(), Ok(name) if names.contains(&name)))
+        .map(|section| section.data())
+}
+
+// Reads the file names tables of a `__llvm_covmap` section, indexed by their hash.
+fn read_filenames_tables(
+    section: &[u8],
+    little_endian: bool,
+    filenames: &mut FxHashMap<u64, (u32, Vec<String>)>,
+) -> Result<(), RawCoverageError> {
+    let mut data = Data::new(section, little_endian);
+    while !data.is_empty() {
+        let _n_records = data.u32()?;
+        let filenames_size = data.u32()? as usize;
+        let _coverage_size = data.u32()?;
+        let version = data.u32()?;
+        if version < COVMAP_VERSION_4 {
+            return Err(RawCoverageError::Unsupported(format!(
+                "version {} is too old, at least version {} is required",
+                version + 1,
+                COVMAP_VERSION_4 + 1
+            )));
+        }
+        let blob = data.bytes(filenames_size)?;
+        filenames.insert(md5_hash(blob), (version, read_filenames(blob, version)?));
+        data.align(8);
+    }
+    Ok(())
+}
+
+/// Lists the source files in the coverage mapping of a binary or of a WebAssembly module,
+/// without reading any counters.
+pub fn covmap_filenames(binary: &[u8]) -> Result<Vec<String>, RawCoverageError> {
+    let mut filenames = FxHashMap::default();
+    if is_wasm_module(binary) {
+        let sections = wasm_custom_sections(binary).map_err(RawCoverageError::InvalidData)?;
+        for (_, section) in sections
+            .into_iter()
+            .filter(|(name, _)| COVMAP_SECTIONS.contains(name))
+        {
+            read_filenames_tables(section, true, &mut filenames)?;
+        }
+    } else {
+        let obj = object::File::parse(binary)?;
+        for section in sections(&obj, COVMAP_SECTIONS) {
+            read_filenames_tables(section?, obj.is_little_endian(), &mut filenames)?;
+        }
+    }
+
+    let mut source_files: Vec<String> = filenames
+        .into_values()
+        .flat_map(|(version, filenames)| {
+            // Starting from Version6, the first file name is the compilation directory.
+            let skip = usize::from(version >= COVMAP_VERSION_6);
+            filenames.into_iter().skip(skip)
+        })
+        .collect();
+    source_files.sort();
+    source_files.dedup();
+    Ok(source_files)
+}
+
+/// Computes the coverage results of a binary from raw counters, using the coverage mapping
+/// stored in its `__llvm_covmap` and `__llvm_covfun` sections.
+pub fn raw_counters_to_results(
+    binary: &[u8],
+    raw_counters: &RawCounters,
+    branch_enabled: bool,
+) -> Result<Vec<(String, CovResult)>, RawCoverageError> {
+    let obj = object::File::parse(binary)?;
+    let little_endian = obj.is_little_endian();
+
+    let mut function_names = FxHashMap::default();
+    for section in sections(&obj, PRF_NAMES_SECTIONS) {
+        function_names.extend(read_prf_names(section?)?);
+    }
+
+    // The file names tables, indexed by their hash.
+    let mut filenames: FxHashMap<u64, (u32, Vec<String>)> = FxHashMap::default();
+    for section in sections(&obj, COVMAP_SECTIONS) {
+        read_filenames_tables(section?, little_endian, &mut filenames)?;
+    }
+
+    if filenames.is_empty() {
+        return Err(RawCoverageError::InvalidData(
+            "the binary doesn't contain a coverage mapping".to_string(),
+        ));
+    }
+
+    let mut results = FxHashMap::default();
+    for section in sections(&obj, COVFUN_SECTIONS) {
+        let mut data = Data::new(section?, little_endian);
+        while !data.is_empty() {
+            let name_ref = data.u64()?;
+            let data_size = data.u32()? as usize;
+            let _func_hash = data.u64()?;
+            let filenames_ref = data.u64()?;
+            let mapping_data = data.bytes(data_size)?;
+            data.align(8);
+
+            let (version, filenames) = filenames.get(&filenames_ref).ok_or_else(|| {
+                RawCoverageError::InvalidData(format!(
+                    "no file names table with hash {:#x}",
+                    filenames_ref
+                ))
+            })?;
+            let mapping = read_function_mapping(mapping_data, *version)?;
+
+            let id = format!("{:#018x}", name_ref);
+            let name = function_names.get(&name_ref).unwrap_or(&id);
+            let counters = raw_counters
+                .get(name)
+                .or_else(|| raw_counters.get(&id))
+                .map_or(&[][..], |c| c.as_slice());
+
+            add_function_results(
+                &mut results,
+                name,
+                &mapping,
+                filenames,
+                counters,
+                branch_enabled,
+            )?;
+        }
+    }
+
+    Ok(results.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+
+    fn load_sample() -> Vec<u8> {
+        fs::read("test/raw_coverage/sample.o").unwrap()
+    }
+
+    #[test]
+    fn test_leb128() {
+        let mut data = Data::new(&[0xe5, 0x8e, 0x26, 0x7f], true);
+        assert_eq!(data.leb128().unwrap(), 624485);
+        assert_eq!(data.leb128().unwrap(), 127);
+        assert!(data.leb128().is_err());
+    }
+
+    #[test]
+    fn test_read_function_mapping_line_overflow() {
+        fn leb128(mut value: u64, out: &mut Vec<u8>) {
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value == 0 {
+                    out.push(byte);
+                    return;
+                }
+                out.push(byte | 0x80);
+            }
+        }
+        // One file without expressions, with code regions given by their line delta and number
+        // of lines.
+        let mapping = |regions: &[(u64, u64)]| {
+            let mut data = Vec::new();
+            for value in [1, 0, 0, regions.len() as u64] {
+                leb128(value, &mut data);
+            }
+            for &(line_delta, num_lines) in regions {
+                for value in [1, line_delta, 1, num_lines, 2] {
+                    leb128(value, &mut data);
+                }
+            }
+            data
+        };
+
+        let ok = read_function_mapping(&mapping(&[(3, 2), (4, 0)]), COVMAP_VERSION_7).unwrap();
+        let lines: Vec<_> = ok
+            .regions
+            .iter()
+            .map(|r| (r.line_start, r.line_end))
+            .collect();
+        assert_eq!(lines, vec![(3, 5), (7, 7)]);
+
+        for regions in [
+            &[(0xffff_fff0, 0), (0x20, 0)][..],
+            &[(0xffff_fff0, 0x20)][..],
+            &[(1, u64::from(MAX_REGION_LINES) + 1)][..],
+        ] {
+            assert!(matches!(
+                read_function_mapping(&mapping(regions), COVMAP_VERSION_7),
+                Err(RawCoverageError::InvalidData(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_read_invalid_lengths() {
+        // Counts much bigger than the data.
+        let mut filenames = vec![0xff, 0xff, 0xff, 0xff, 0x0f, 2, 0];
+        filenames.extend_from_slice(b"\x01a");
+        assert!(read_filenames(&filenames, COVMAP_VERSION_7).is_err());
+        assert!(
+            read_function_mapping(&[0xff, 0xff, 0xff, 0xff, 0x0f, 0], COVMAP_VERSION_7).is_err()
+        );
+
+        // Compressed data longer than its given length.
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&[b'a'; 4096]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut data = Data::new(&compressed, true);
+        assert!(matches!(
+            read_maybe_compressed(&mut data, 10, compressed.len()),
+            Err(RawCoverageError::InvalidData(_))
+        ));
+        let mut data = Data::new(&compressed, true);
+        assert_eq!(
+            read_maybe_compressed(&mut data, 4096, compressed.len()).unwrap(),
+            vec![b'a'; 4096]
+        );
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let expressions = vec![
+            (Counter::Reference(0), Counter::Reference(1)),
+            (Counter::Subtract(0), Counter::Reference(2)),
+        ];
+        let counters = [10, 3, 2];
+        assert_eq!(evaluate(Counter::Zero, &expressions, &counters), 0);
+        assert_eq!(evaluate(Counter::Reference(1), &expressions, &counters), 3);
+        assert_eq!(evaluate(Counter::Subtract(0), &expressions, &counters), 7);
+        assert_eq!(evaluate(Counter::Add(1), &expressions, &counters), 9);
+        // Missing counters are considered as never executed.
+        assert_eq!(evaluate(Counter::Reference(5), &expressions, &counters), 0);
+    }
+
+    #[test]
+    fn test_parse_raw_counters() {
+        let counters =
+            parse_raw_counters(br#"{"main": [1, 0, 3], "0x00000000000000ff": []}"#).unwrap();
+        assert_eq!(counters["main"], vec![1, 0, 3]);
+        assert!(counters["0x00000000000000ff"].is_empty());
+        assert!(parse_raw_counters(b"[1, 2]").is_err());
+    }
+
+    #[test]
+    fn test_raw_counters_to_results() {
+        let raw_counters = parse_raw_counters(
+            br#"{
+                "_RNvCsi2yhfSsWT8O_6sample8classify": [4, 1],
+                "0x082a7eaa4b906112": [0]
+            }"#,
+        )
+        .unwrap();
+        let results = raw_counters_to_results(&load_sample(), &raw_counters, true).unwrap();
+        assert_eq!(results.len(), 1);
+
+        let (path, result) = &results[0];
+        assert!(path.ends_with("sample.rs"), "{}", path);
+        assert_eq!(
+            result.lines,
+            [
+                (1, 4),
+                (2, 4),
+                (3, 1),
+                (5, 3),
+                (7, 4),
+                (9, 0),
+                (10, 0),
+                (11, 0)
+            ]
+            .iter()
+            .cloned()
+            .collect()
+        );
+        assert_eq!(result.functions.len(), 2);
+        assert_eq!(
+            result.functions["_RNvCsi2yhfSsWT8O_6sample8classify"],
+            Function {
+                start: 1,
+                executed: true,
+            }
+        );
+        assert_eq!(
+            result.functions["_RNvCsi2yhfSsWT8O_6sample6unused"],
+            Function {
+                start: 9,
+                executed: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_raw_counters_to_results_no_counters() {
+        let results =
+            raw_counters_to_results(&load_sample(), &RawCounters::default(), false).unwrap();
+        assert_eq!(results.len(), 1);
+        let (_, result) = &results[0];
+        assert!(result.lines.values().all(|&c| c == 0));
+        assert!(result.functions.values().all(|f| !f.executed));
+    }
+
+    #[test]
+    fn test_raw_counters_to_results_no_coverage_mapping() {
+        let binary = fs::read("test/raw_coverage/no_coverage.o").unwrap();
+        assert!(raw_counters_to_results(&binary, &RawCounters::default(), false).is_err());
+    }
+}
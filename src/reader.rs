@@ -1,4 +1,5 @@
 use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use std::cmp;
 use std::collections::{btree_map, hash_map, BTreeMap};
@@ -11,7 +12,7 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::result::Result;
 
-use crate::defs::{CovResult, Function};
+use crate::defs::{CovResult, Function, LineMap};
 
 const GCOV_ARC_ON_TREE: u32 = 1 << 0;
 const GCOV_ARC_FAKE: u32 = 1 << 1;
@@ -74,7 +75,7 @@ enum FileType {
     Gcda,
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct Gcno {
     version: u32,
     checksum: u32,
@@ -86,7 +87,7 @@ pub struct Gcno {
     ident_to_fun: FxHashMap<u32, usize>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct GcovFunction {
     identifier: u32,
     start_line: u32,
@@ -108,7 +109,7 @@ struct GcovFunction {
     executed: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct GcovBlock {
     no: usize,
     source: SmallVec<[usize; 2]>,
@@ -118,7 +119,7 @@ struct GcovBlock {
     counter: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct GcovEdge {
     source: usize,
     destination: usize,
@@ -305,6 +306,65 @@ impl<E: Endian> GcovReader<E> for GcovReaderBuf<E> {
     }
 }
 
+/// Finds where a gcda file is cut, when it's empty or truncated (e.g. by a process killed while
+/// writing it): the byte offset of its header, or of its first record which isn't complete. The
+/// files which aren't gcda files are left to the reader.
+pub fn gcda_truncation(buf: &[u8]) -> Option<usize> {
+    if buf.len() < 8 {
+        return Some(0);
+    }
+    let little_endian = Gcno::guess_endianness(*b"adcg", buf, "").ok()?;
+    let version = if little_endian {
+        GcovReaderBuf::<LittleEndian>::new("", buf[..8].to_vec()).read_version()
+    } else {
+        GcovReaderBuf::<BigEndian>::new("", buf[..8].to_vec()).read_version()
+    }
+    .ok()?;
+    let read_u32 = |pos: usize| {
+        let bytes = [buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]];
+        if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+
+    // GCC 12 added a checksum after the stamp, and gives the lengths of the records in bytes
+    // rather than in words, with a negative length for the counters which are all 0 and left out.
+    let mut pos = if version >= 120 { 16 } else { 12 };
+    if buf.len() < pos {
+        return Some(0);
+    }
+    while pos < buf.len() {
+        if buf.len() < pos + 8 {
+            // The files of LLVM end with a 0 tag.
+            return if buf.len() >= pos + 4 && read_u32(pos) == 0 {
+                None
+            } else {
+                Some(pos)
+            };
+        }
+        if read_u32(pos) == 0 {
+            return None;
+        }
+        let length = read_u32(pos + 4);
+        let end = pos
+            + 8
+            + if version < 120 {
+                4 * length as usize
+            } else if (length as i32) < 0 {
+                0
+            } else {
+                length as usize
+            };
+        if end > buf.len() {
+            return Some(pos);
+        }
+        pos = end;
+    }
+    None
+}
+
 impl Display for GcovError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -434,13 +494,28 @@ impl Gcno {
         gcda_bufs: Vec<Vec<u8>>,
         branch_enabled: bool,
     ) -> Result<Vec<(String, CovResult)>, GcovError> {
+        Self::parse(stem, gcno_buf)?.compute_counters(stem, gcda_bufs, branch_enabled)
+    }
+
+    /// Reads a gcno file, without any counters.
+    pub fn parse(stem: &str, gcno_buf: Vec<u8>) -> Result<Self, GcovError> {
         let mut gcno = Self::new();
         gcno.read(FileType::Gcno, gcno_buf, stem)?;
+        Ok(gcno)
+    }
+
+    /// Computes the coverage of a gcno file read by parse, with the counters of its gcda files.
+    pub fn compute_counters(
+        mut self,
+        stem: &str,
+        gcda_bufs: Vec<Vec<u8>>,
+        branch_enabled: bool,
+    ) -> Result<Vec<(String, CovResult)>, GcovError> {
         for gcda_buf in gcda_bufs.into_iter() {
-            gcno.read(FileType::Gcda, gcda_buf, stem)?;
+            self.read(FileType::Gcda, gcda_buf, stem)?;
         }
-        gcno.stop();
-        Ok(gcno.finalize(branch_enabled))
+        self.stop();
+        Ok(self.finalize(branch_enabled))
     }
 
     pub fn stop(&mut self) {
@@ -859,9 +934,10 @@ impl Gcno {
             let res = match results.entry(&fun.file_name) {
                 hash_map::Entry::Occupied(r) => r.into_mut(),
                 hash_map::Entry::Vacant(p) => p.insert(CovResult {
-                    lines: BTreeMap::new(),
+                    lines: LineMap::new(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    mcdc: None,
                 }),
             };
             res.functions.insert(
@@ -871,22 +947,15 @@ impl Gcno {
                     executed: fun.executed,
                 },
             );
-            if fun.executed {
-                for (line, counter) in fun.lines.iter() {
-                    match res.lines.entry(*line) {
-                        btree_map::Entry::Occupied(c) => {
-                            *c.into_mut() += *counter;
-                        }
-                        btree_map::Entry::Vacant(p) => {
-                            p.insert(*counter);
-                        }
-                    }
-                }
-            } else {
-                for line in fun.lines.keys() {
-                    res.lines.entry(*line).or_insert(0);
-                }
-            }
+            // The lines of a function are sorted once, to be merged with the ones of the other
+            // functions of the file, instead of being inserted one by one.
+            let executed = fun.executed;
+            let lines: LineMap = fun
+                .lines
+                .iter()
+                .map(|(&line, &counter)| (line, if executed { counter } else { 0 }))
+                .collect();
+            res.lines.merge(lines, |count, counter| *count += counter);
             if branch_enabled {
                 for block in &fun.blocks {
                     let line = if block.lines.is_empty() {
@@ -963,6 +1032,7 @@ impl GcovFunction {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn look_for_circuit(
         fun_edges: &mut [GcovEdge],
         fun_blocks: &[GcovBlock],
@@ -1218,6 +1288,7 @@ mod tests {
 
     use super::*;
     use crate::defs::FunctionMap;
+    use std::fs;
 
     fn from_path(gcno: &mut Gcno, typ: FileType, path: &str) {
         let path = PathBuf::from(path);
@@ -1339,6 +1410,42 @@ mod tests {
         assert_eq!(output, input);
     }
 
+    #[test]
+    fn test_gcda_truncation() {
+        for entry in fs::read_dir("test")
+            .unwrap()
+            .chain(fs::read_dir("test/llvm").unwrap())
+        {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_str().unwrap();
+            if name.ends_with(".gcda")
+                && !name.ends_with("_empty.gcda")
+                && !name.ends_with("_truncated.gcda")
+            {
+                assert_eq!(
+                    gcda_truncation(&fs::read(&path).unwrap()),
+                    None,
+                    "{:?}",
+                    path
+                );
+            }
+        }
+        assert_eq!(
+            gcda_truncation(&get_input_vec("test/llvm/reader_empty.gcda")),
+            Some(0)
+        );
+        // The file is cut in the record of the counters of the arcs, after the one of the
+        // function.
+        assert_eq!(
+            gcda_truncation(&get_input_vec("test/llvm/reader_truncated.gcda")),
+            Some(36)
+        );
+        assert_eq!(
+            gcda_truncation(&get_input_vec("test/llvm/reader.gcno")),
+            None
+        );
+    }
+
     #[test]
     fn test_reader_gcno_counter() {
         let mut gcno = Gcno::new();
@@ -1402,7 +1509,7 @@ mod tests {
         gcno.stop();
         let result = gcno.finalize(true);
 
-        let mut lines: BTreeMap<u32, u64> = BTreeMap::new();
+        let mut lines = LineMap::new();
         lines.insert(2, 1);
         let mut functions: FunctionMap = FxHashMap::default();
         functions.insert(
@@ -1419,6 +1526,7 @@ mod tests {
                 lines,
                 branches,
                 functions,
+                mcdc: None,
             },
         )];
 
@@ -1433,7 +1541,7 @@ mod tests {
         gcno.stop();
         let result = gcno.finalize(true);
 
-        let mut lines: BTreeMap<u32, u64> = BTreeMap::new();
+        let mut lines = LineMap::new();
         [
             (2, 2),
             (3, 1),
@@ -1506,6 +1614,7 @@ mod tests {
                 lines,
                 branches,
                 functions,
+                mcdc: None,
             },
         )];
 
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::defs::*;
+
+/// Sums the line coverage of the files up the directory tree of their relative paths, so that
+/// each directory has the total of all the files below it, e.g. `src` includes `src/b/c.rs`.
+/// The values are the numbers of instrumented lines and of covered lines.
+pub fn directory_rollup(results: &[ResultTuple]) -> BTreeMap<PathBuf, (usize, usize)> {
+    let mut rollup = BTreeMap::new();
+    for (_, rel_path, result) in results {
+        let lines_found = result.lines.len();
+        let lines_hit = result.lines.values().filter(|&&count| count > 0).count();
+        for dir in rel_path.ancestors().skip(1) {
+            if dir.as_os_str().is_empty() {
+                continue;
+            }
+            let (found, hit) = rollup.entry(dir.to_path_buf()).or_insert((0, 0));
+            *found += lines_found;
+            *hit += lines_hit;
+        }
+    }
+    rollup
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(lines: &[(u32, u64)]) -> CovResult {
+        CovResult {
+            lines: lines.iter().cloned().collect(),
+            branches: BTreeMap::new(),
+            functions: Default::default(),
+            mcdc: None,
+        }
+    }
+
+    #[test]
+    fn test_directory_rollup() {
+        let results = vec![
+            (
+                PathBuf::from("/work/src/a.rs"),
+                PathBuf::from("src/a.rs"),
+                result(&[(1, 1), (2, 0), (3, 4)]),
+            ),
+            (
+                PathBuf::from("/work/src/b/c.rs"),
+                PathBuf::from("src/b/c.rs"),
+                result(&[(1, 0), (2, 2)]),
+            ),
+            (
+                PathBuf::from("/work/build.rs"),
+                PathBuf::from("build.rs"),
+                result(&[(1, 1)]),
+            ),
+        ];
+        let rollup = directory_rollup(&results);
+        assert_eq!(rollup[&PathBuf::from("src")], (5, 3));
+        assert_eq!(rollup[&PathBuf::from("src/b")], (2, 1));
+        assert_eq!(rollup.len(), 2);
+    }
+}
@@ -0,0 +1,231 @@
+use log::error;
+use std::fs::{self, File};
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// The address the HTML report is served on when --serve is given without one.
+pub const DEFAULT_SERVE_ADDRESS: &str = "127.0.0.1:8000";
+
+// How often the stop flag is checked while waiting for requests.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns the content type of a file served from the HTML report.
+pub fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("txt" | "md" | "info") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(std::str::from_utf8(hex).unwrap(), 16).unwrap());
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Maps the URL of a request to a file of the root directory, or to its index.html file for a
+/// directory. Returns None when the file doesn't exist or isn't in the root directory (e.g. with
+/// '..' components, or through a symbolic link).
+pub fn resolve_request_path(root: &Path, url: &str) -> Option<PathBuf> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let path = percent_decode(path)?;
+
+    let mut resolved = root.to_path_buf();
+    for component in Path::new(&path).components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    if resolved.is_dir() {
+        resolved.push("index.html");
+    }
+
+    let resolved = fs::canonicalize(resolved).ok()?;
+    if resolved.is_file() && resolved.starts_with(fs::canonicalize(root).ok()?) {
+        Some(resolved)
+    } else {
+        None
+    }
+}
+
+/// A static file server for the HTML report.
+pub struct ReportServer {
+    server: Server,
+    root: PathBuf,
+}
+
+impl ReportServer {
+    pub fn new(address: &str, root: &Path) -> Result<Self, String> {
+        let server =
+            Server::http(address).map_err(|e| format!("Unable to listen on {}: {}", address, e))?;
+        Ok(ReportServer {
+            server,
+            root: root.to_path_buf(),
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.server.server_addr().to_ip().unwrap()
+    }
+
+    /// Serves the files of the root directory, for GET and HEAD requests, until `stop` is set.
+    pub fn run(&self, stop: &AtomicBool) {
+        while !stop.load(Ordering::SeqCst) {
+            match self.server.recv_timeout(STOP_POLL_INTERVAL) {
+                Ok(Some(request)) => self.respond(request),
+                Ok(None) => {}
+                Err(e) => error!("Unable to receive a request: {}", e),
+            }
+        }
+    }
+
+    fn respond(&self, request: Request) {
+        let file = match request.method() {
+            Method::Get | Method::Head => resolve_request_path(&self.root, request.url())
+                .and_then(|path| File::open(&path).ok().map(|file| (path, file))),
+            _ => {
+                let allow = Header::from_bytes("Allow", "GET, HEAD").unwrap();
+                let response = Response::from_string("Method Not Allowed")
+                    .with_status_code(405)
+                    .with_header(allow);
+                let _ = request.respond(response);
+                return;
+            }
+        };
+        // The client can close the connection before the end of the response, so the errors are
+        // ignored. tiny_http doesn't send the body of the responses to HEAD requests.
+        let _ = match file {
+            Some((path, file)) => {
+                let content_type = Header::from_bytes("Content-Type", content_type(&path)).unwrap();
+                request.respond(Response::from_file(file).with_header(content_type))
+            }
+            None => request.respond(Response::from_string("Not Found").with_status_code(404)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(
+            content_type(Path::new("index.html")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(content_type(Path::new("badges/flat.svg")), "image/svg+xml");
+        assert_eq!(content_type(Path::new("coverage.json")), "application/json");
+        assert_eq!(
+            content_type(Path::new("archive")),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn test_resolve_request_path() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let root = tmp_dir.path().join("html");
+        fs::create_dir_all(root.join("src dir")).unwrap();
+        fs::write(root.join("index.html"), "index").unwrap();
+        fs::write(root.join("src dir").join("main.rs.html"), "main").unwrap();
+        fs::write(tmp_dir.path().join("secret"), "secret").unwrap();
+        let root = fs::canonicalize(root).unwrap();
+
+        assert_eq!(
+            resolve_request_path(&root, "/"),
+            Some(root.join("index.html"))
+        );
+        assert_eq!(
+            resolve_request_path(&root, "/src%20dir/main.rs.html?line=3#l3"),
+            Some(root.join("src dir").join("main.rs.html"))
+        );
+        assert_eq!(resolve_request_path(&root, "/missing.html"), None);
+        assert_eq!(resolve_request_path(&root, "/../secret"), None);
+        assert_eq!(resolve_request_path(&root, "/%2e%2e/secret"), None);
+        assert_eq!(resolve_request_path(&root, "/src%2"), None);
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(tmp_dir.path().join("secret"), root.join("link")).unwrap();
+            assert_eq!(resolve_request_path(&root, "/link"), None);
+        }
+    }
+
+    fn request(address: SocketAddr, method: &str, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            method, path
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_report_server() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let root = tmp_dir.path().join("html");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("index.html"), "<html></html>").unwrap();
+        fs::write(tmp_dir.path().join("secret"), "secret").unwrap();
+
+        let server = ReportServer::new("127.0.0.1:0", &root).unwrap();
+        let address = server.address();
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || server.run(&stop))
+        };
+
+        let response = request(address, "GET", "/");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Type: text/html; charset=utf-8"));
+        assert!(response.ends_with("<html></html>"));
+
+        let response = request(address, "HEAD", "/index.html");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Length: 13"));
+        assert!(!response.contains("<html>"));
+
+        assert!(request(address, "GET", "/../secret").starts_with("HTTP/1.1 404"));
+        assert!(request(address, "POST", "/").starts_with("HTTP/1.1 405"));
+
+        stop.store(true, Ordering::SeqCst);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+use quick_xml::{
+    events::{BytesEnd, BytesStart, Event},
+    Writer,
+};
+use std::collections::BTreeSet;
+use std::io::{BufWriter, Cursor, Write};
+use std::path::Path;
+
+use crate::defs::*;
+use crate::output::get_target_output_writable;
+
+/// Writes the results in the generic test coverage format of SonarQube, with a `<lineToCover>`
+/// element for each line of each file, and its branches when there are some. The files are given
+/// by their paths relative to the source directory, with `/` separators.
+pub fn results_to_sonar(results: &[ResultTuple]) -> String {
+    let mut writer = Writer::new_with_indent(Cursor::new(vec![]), b' ', 2);
+
+    let mut coverage = BytesStart::new("coverage");
+    coverage.push_attribute(("version", "1"));
+    writer.write_event(Event::Start(coverage)).unwrap();
+
+    for (_, rel_path, result) in results {
+        let mut file = BytesStart::new("file");
+        file.push_attribute((
+            "path",
+            rel_path.to_string_lossy().replace('\\', "/").as_ref(),
+        ));
+        writer.write_event(Event::Start(file)).unwrap();
+
+        // The lines with branches are lines to cover too, even without a count of their own.
+        let lines: BTreeSet<u32> = result
+            .lines
+            .keys()
+            .chain(result.branches.keys())
+            .cloned()
+            .collect();
+        for line in lines {
+            let branches = result.branches.get(&line);
+            let covered_branches = branches.map_or(0, |branches| {
+                branches.iter().filter(|&&taken| taken).count()
+            });
+            let covered = match result.lines.get(&line) {
+                Some(&count) => count > 0,
+                None => covered_branches > 0,
+            };
+
+            let mut line_to_cover = BytesStart::new("lineToCover");
+            line_to_cover.push_attribute(("lineNumber", line.to_string().as_ref()));
+            line_to_cover.push_attribute(("covered", covered.to_string().as_ref()));
+            if let Some(branches) = branches {
+                line_to_cover
+                    .push_attribute(("branchesToCover", branches.len().to_string().as_ref()));
+                line_to_cover
+                    .push_attribute(("coveredBranches", covered_branches.to_string().as_ref()));
+            }
+            writer.write_event(Event::Empty(line_to_cover)).unwrap();
+        }
+
+        writer
+            .write_event(Event::End(BytesEnd::new("file")))
+            .unwrap();
+    }
+
+    writer
+        .write_event(Event::End(BytesEnd::new("coverage")))
+        .unwrap();
+
+    let mut xml = String::from_utf8(writer.into_inner().into_inner()).unwrap();
+    xml.push('\n');
+    xml
+}
+
+pub fn output_sonar(results: &[ResultTuple], output_file: Option<&Path>) {
+    let mut writer = BufWriter::new(get_target_output_writable(output_file));
+    writer
+        .write_all(results_to_sonar(results).as_bytes())
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_lcov;
+    use quick_xml::Reader;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_results_to_sonar() {
+        let mut results: Vec<ResultTuple> = parse_lcov(&fs::read("test/prova.info").unwrap(), true)
+            .unwrap()
+            .into_iter()
+            .map(|(path, result)| {
+                let rel_path = PathBuf::from(path.trim_start_matches('/'));
+                (PathBuf::from(path), rel_path, result)
+            })
+            .collect();
+        results.push((
+            PathBuf::from("/work/src/lib.rs"),
+            PathBuf::from("src/lib.rs"),
+            CovResult {
+                lines: [(1, 1), (3, 0)].iter().cloned().collect(),
+                branches: [(3, vec![true, false]), (4, vec![false, false])]
+                    .iter()
+                    .cloned()
+                    .collect(),
+                functions: FunctionMap::default(),
+                mcdc: None,
+            },
+        ));
+        let xml = results_to_sonar(&results);
+
+        // The elements of the format, with their attributes, nested as SonarQube expects them.
+        let mut reader = Reader::from_str(&xml);
+        reader.trim_text(true);
+        let mut stack: Vec<String> = Vec::new();
+        let mut lines_by_file: Vec<(String, usize)> = Vec::new();
+        loop {
+            let event = reader.read_event().unwrap();
+            let (element, is_empty) = match &event {
+                Event::Start(element) => (element, false),
+                Event::Empty(element) => (element, true),
+                Event::End(_) => {
+                    stack.pop();
+                    continue;
+                }
+                Event::Eof => break,
+                event => panic!("Unexpected event {:?}", event),
+            };
+            let name = String::from_utf8(element.name().as_ref().to_vec()).unwrap();
+            let attributes: BTreeMap<String, String> = element
+                .attributes()
+                .map(|attribute| {
+                    let attribute = attribute.unwrap();
+                    (
+                        String::from_utf8(attribute.key.as_ref().to_vec()).unwrap(),
+                        attribute.unescape_value().unwrap().into_owned(),
+                    )
+                })
+                .collect();
+            match (stack.last().map(String::as_str), name.as_str()) {
+                (None, "coverage") => assert_eq!(attributes["version"], "1"),
+                (Some("coverage"), "file") => {
+                    assert!(!attributes["path"].starts_with('/'));
+                    lines_by_file.push((attributes["path"].clone(), 0));
+                }
+                (Some("file"), "lineToCover") => {
+                    assert!(is_empty);
+                    assert!(attributes["lineNumber"].parse::<u32>().is_ok());
+                    assert!(["true", "false"].contains(&attributes["covered"].as_str()));
+                    assert_eq!(
+                        attributes.contains_key("branchesToCover"),
+                        attributes.contains_key("coveredBranches")
+                    );
+                    lines_by_file.last_mut().unwrap().1 += 1;
+                }
+                (parent, name) => panic!("Unexpected element {} in {:?}", name, parent),
+            }
+            if !is_empty {
+                stack.push(name);
+            }
+        }
+        assert_eq!(lines_by_file.len(), results.len());
+        for ((path, lines), (_, rel_path, result)) in lines_by_file.iter().zip(&results) {
+            assert_eq!(path, &rel_path.to_string_lossy());
+            let mut expected: BTreeSet<&u32> = result.lines.keys().collect();
+            expected.extend(result.branches.keys());
+            assert_eq!(*lines, expected.len());
+        }
+
+        assert!(xml.contains(r#"<file path="src/lib.rs">"#));
+        assert!(xml.contains(r#"<lineToCover lineNumber="1" covered="true"/>"#));
+        assert!(xml.contains(
+            r#"<lineToCover lineNumber="3" covered="false" branchesToCover="2" coveredBranches="1"/>"#
+        ));
+        assert!(xml.contains(
+            r#"<lineToCover lineNumber="4" covered="false" branchesToCover="2" coveredBranches="0"/>"#
+        ));
+    }
+}
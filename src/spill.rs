@@ -0,0 +1,623 @@
+//! The bounded memory mode of --max-memory: the results are written to temporary files once they
+//! exceed the memory budget, while the coverage files are merged and while the outputs are
+//! written, and read back in the order of their paths.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::iter::Peekable;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::vec;
+
+use crate::defs::*;
+use crate::merge_mode::{merge_mode, MergeRule};
+use crate::merge_results_with_mode;
+
+// The approximate sizes in memory of the entries of the maps of a result, with the overhead of
+// the maps.
+const LINE_SIZE: usize = 32;
+const BRANCH_SIZE: usize = 48;
+const FUNCTION_SIZE: usize = 64;
+
+// Estimates the memory used by the maps of a result.
+fn coverage_size(result: &CovResult) -> usize {
+    result.lines.len() * LINE_SIZE
+        + result
+            .branches
+            .values()
+            .map(|branches| BRANCH_SIZE + branches.len())
+            .sum::<usize>()
+        + result
+            .functions
+            .keys()
+            .map(|name| FUNCTION_SIZE + name.len())
+            .sum::<usize>()
+}
+
+/// Estimates the memory used by a result, its paths included.
+pub fn estimated_size(result: &ResultTuple) -> usize {
+    let (abs_path, rel_path, result) = result;
+    mem::size_of::<ResultTuple>()
+        + abs_path.as_os_str().len()
+        + rel_path.as_os_str().len()
+        + coverage_size(result)
+}
+
+/// Estimates the memory used by a result of the merge map, its path included.
+pub fn estimated_entry_size(path: &str, result: &CovResult) -> usize {
+    mem::size_of::<(String, CovResult)>() + path.len() + coverage_size(result)
+}
+
+/// Estimates the memory used by the entries of the maps of a result from their numbers only, in
+/// constant time, to account for what merging another result into it adds.
+pub fn entries_size(result: &CovResult) -> usize {
+    result.lines.len() * LINE_SIZE
+        + result.branches.len() * BRANCH_SIZE
+        + result.functions.len() * FUNCTION_SIZE
+}
+
+// The coverage of a result as written to the temporary files.
+#[derive(Serialize, Deserialize)]
+struct SpilledCoverage {
+    lines: Vec<(u32, u64)>,
+    branches: Vec<(u32, Vec<bool>)>,
+    functions: Vec<(String, u32, bool)>,
+    mcdc: Option<Vec<(u32, Vec<bool>)>>,
+}
+
+impl SpilledCoverage {
+    fn new(result: CovResult) -> Self {
+        SpilledCoverage {
+            lines: result.lines.into_iter().collect(),
+            branches: result.branches.into_iter().collect(),
+            functions: result
+                .functions
+                .into_iter()
+                .map(|(name, function)| (name, function.start, function.executed))
+                .collect(),
+            mcdc: result.mcdc.map(|mcdc| {
+                mcdc.into_iter()
+                    .map(|record| (record.line, record.conditions))
+                    .collect()
+            }),
+        }
+    }
+
+    fn into_result(self) -> CovResult {
+        CovResult {
+            lines: self.lines.into_iter().collect(),
+            branches: self.branches.into_iter().collect(),
+            functions: self
+                .functions
+                .into_iter()
+                .map(|(name, start, executed)| (name, Function { start, executed }))
+                .collect(),
+            mcdc: self.mcdc.map(|mcdc| {
+                mcdc.into_iter()
+                    .map(|(line, conditions)| McdcRecord { line, conditions })
+                    .collect()
+            }),
+        }
+    }
+}
+
+// The results written to the temporary files, one per line in JSON, each file sorted by their
+// keys.
+trait Spilled: Sized {
+    type Key: Ord + Clone;
+
+    fn key(&self) -> &Self::Key;
+    fn write_json<W: Write>(self, writer: W) -> serde_json::Result<()>;
+    fn from_json(line: &str) -> serde_json::Result<Self>;
+}
+
+// The results to write, by relative path.
+impl Spilled for ResultTuple {
+    type Key = PathBuf;
+
+    fn key(&self) -> &PathBuf {
+        &self.1
+    }
+
+    fn write_json<W: Write>(self, writer: W) -> serde_json::Result<()> {
+        let (abs_path, rel_path, result) = self;
+        serde_json::to_writer(writer, &(abs_path, rel_path, SpilledCoverage::new(result)))
+    }
+
+    fn from_json(line: &str) -> serde_json::Result<Self> {
+        let (abs_path, rel_path, coverage): (PathBuf, PathBuf, SpilledCoverage) =
+            serde_json::from_str(line)?;
+        Ok((abs_path, rel_path, coverage.into_result()))
+    }
+}
+
+// The results of the merge map, by path.
+impl Spilled for (String, CovResult) {
+    type Key = String;
+
+    fn key(&self) -> &String {
+        &self.0
+    }
+
+    fn write_json<W: Write>(self, writer: W) -> serde_json::Result<()> {
+        let (path, result) = self;
+        serde_json::to_writer(writer, &(path, SpilledCoverage::new(result)))
+    }
+
+    fn from_json(line: &str) -> serde_json::Result<Self> {
+        let (path, coverage): (String, SpilledCoverage) = serde_json::from_str(line)?;
+        Ok((path, coverage.into_result()))
+    }
+}
+
+// Writes results to a new temporary file of `dir`, sorted by their keys.
+fn write_run<T: Spilled>(
+    dir: &Path,
+    runs: &mut Vec<PathBuf>,
+    mut results: Vec<T>,
+) -> io::Result<()> {
+    results.sort_by(|a, b| a.key().cmp(b.key()));
+
+    let path = dir.join(format!("{}.jsonl", runs.len()));
+    let mut writer = BufWriter::new(File::create(&path)?);
+    for result in results {
+        result.write_json(&mut writer)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    runs.push(path);
+    Ok(())
+}
+
+// The temporary files of results, read back together in the order of their keys.
+struct Runs<T: Spilled> {
+    readers: Vec<Lines<BufReader<File>>>,
+    // The next result of each file.
+    heads: Vec<Option<T>>,
+    // The keys of the next results of the files, with their file.
+    heap: BinaryHeap<Reverse<(T::Key, usize)>>,
+}
+
+impl<T: Spilled> Runs<T> {
+    fn open(paths: &[PathBuf]) -> io::Result<Self> {
+        let readers = paths
+            .iter()
+            .map(|path| Ok(BufReader::new(File::open(path)?).lines()))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut runs = Runs {
+            heads: readers.iter().map(|_| None).collect(),
+            readers,
+            heap: BinaryHeap::new(),
+        };
+        for run in 0..runs.readers.len() {
+            runs.advance(run);
+        }
+        Ok(runs)
+    }
+
+    fn advance(&mut self, run: usize) {
+        let result = self.readers[run].next().map(|line| {
+            let line = line.expect("Failed to read the spilled results");
+            T::from_json(&line).expect("Invalid spilled results")
+        });
+        if let Some(result) = &result {
+            self.heap.push(Reverse((result.key().clone(), run)));
+        }
+        self.heads[run] = result;
+    }
+
+    fn peek_key(&self) -> Option<&T::Key> {
+        self.heap.peek().map(|Reverse((key, _))| key)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let Reverse((_, run)) = self.heap.pop()?;
+        let result = self.heads[run].take();
+        self.advance(run);
+        result
+    }
+}
+
+/// The merge map of the consumers with --max-memory: once its results exceed the budget, they are
+/// all written to a temporary file, and merged with the ones of the other files when read back.
+pub struct MergeSpill {
+    budget: usize,
+    dir: tempfile::TempDir,
+    // The estimated memory used by the results of the map, and the temporary files.
+    state: Mutex<(usize, Vec<PathBuf>)>,
+}
+
+impl MergeSpill {
+    pub fn new(budget: usize) -> io::Result<Self> {
+        Ok(MergeSpill {
+            budget,
+            dir: tempfile::tempdir()?,
+            state: Mutex::new((0, Vec::new())),
+        })
+    }
+
+    /// Accounts for the memory added to the map by merging results into it, as estimated by
+    /// add_results, and empties the map into a temporary file once its results exceed the budget.
+    pub fn add(&self, result_map: &SyncCovResultMap, added: usize) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let (size, runs) = &mut *state;
+        *size += added;
+        if *size <= self.budget {
+            return Ok(());
+        }
+        let results: Vec<(String, CovResult)> = result_map.lock().unwrap().drain().collect();
+        *size = 0;
+        write_run(self.dir.path(), runs, results)
+    }
+
+    /// Returns whether some of the results were written to temporary files.
+    pub fn is_spilled(&self) -> bool {
+        !self.state.lock().unwrap().1.is_empty()
+    }
+
+    /// Reads the results back with the ones left in the map, merged by path, in chunks taking
+    /// about the budget, in the order of their paths.
+    pub fn into_chunks(
+        self,
+        result_map: CovResultMap,
+        merge_rules: &[MergeRule],
+    ) -> io::Result<MergedChunks<'_>> {
+        let (_, runs) = self.state.into_inner().unwrap();
+        let mut pending: Vec<(String, CovResult)> = result_map.into_iter().collect();
+        pending.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(MergedChunks {
+            budget: self.budget,
+            merge_rules,
+            pending: pending.into_iter().peekable(),
+            runs: Runs::open(&runs)?,
+            _dir: self.dir,
+        })
+    }
+}
+
+/// The merged results of a MergeSpill, as maps of results of about the budget.
+pub struct MergedChunks<'a> {
+    budget: usize,
+    merge_rules: &'a [MergeRule],
+    pending: Peekable<vec::IntoIter<(String, CovResult)>>,
+    runs: Runs<(String, CovResult)>,
+    _dir: tempfile::TempDir,
+}
+
+impl MergedChunks<'_> {
+    fn peek_path(&mut self) -> Option<&String> {
+        match (self.runs.peek_key(), self.pending.peek()) {
+            (Some(run_path), Some((pending_path, _))) => Some(run_path.min(pending_path)),
+            (Some(run_path), None) => Some(run_path),
+            (None, pending) => pending.map(|(path, _)| path),
+        }
+    }
+
+    fn pop(&mut self) -> Option<(String, CovResult)> {
+        let from_run = match (self.runs.peek_key(), self.pending.peek()) {
+            (Some(run_path), Some((pending_path, _))) => run_path < pending_path,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if from_run {
+            self.runs.pop()
+        } else {
+            self.pending.next()
+        }
+    }
+
+    // The next result, merged with the ones of the same path of the other temporary files.
+    fn next_result(&mut self) -> Option<(String, CovResult)> {
+        let (path, mut result) = self.pop()?;
+        let mode = merge_mode(self.merge_rules, &path);
+        let mut warn_overflow = false;
+        while self.peek_path() == Some(&path) {
+            let (_, other) = self.pop().unwrap();
+            warn_overflow |= merge_results_with_mode(&mut result, other, mode);
+        }
+        if warn_overflow {
+            warn!("Execution count overflow detected.");
+        }
+        Some((path, result))
+    }
+}
+
+impl Iterator for MergedChunks<'_> {
+    type Item = CovResultMap;
+
+    fn next(&mut self) -> Option<CovResultMap> {
+        let mut chunk = CovResultMap::default();
+        let mut size = 0;
+        while size < self.budget {
+            let (path, result) = match self.next_result() {
+                Some(result) => result,
+                None => break,
+            };
+            size += estimated_entry_size(&path, &result);
+            chunk.insert(path, result);
+        }
+        Some(chunk).filter(|chunk| !chunk.is_empty())
+    }
+}
+
+/// Results stored in temporary files once they exceed a memory budget.
+pub struct ResultStore {
+    budget: usize,
+    pending: Vec<ResultTuple>,
+    pending_size: usize,
+    dir: tempfile::TempDir,
+    // The temporary files, each one sorted by the relative paths of its results.
+    runs: Vec<PathBuf>,
+}
+
+impl ResultStore {
+    pub fn new(budget: usize) -> io::Result<Self> {
+        Ok(ResultStore {
+            budget,
+            pending: Vec::new(),
+            pending_size: 0,
+            dir: tempfile::tempdir()?,
+            runs: Vec::new(),
+        })
+    }
+
+    /// Adds a result, and writes the results kept in memory to a temporary file once they exceed
+    /// the budget.
+    pub fn push(&mut self, result: ResultTuple) -> io::Result<()> {
+        self.pending_size += estimated_size(&result);
+        self.pending.push(result);
+        if self.pending_size > self.budget {
+            self.pending_size = 0;
+            write_run(
+                self.dir.path(),
+                &mut self.runs,
+                mem::take(&mut self.pending),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether some of the results were written to temporary files.
+    pub fn is_spilled(&self) -> bool {
+        !self.runs.is_empty()
+    }
+
+    /// Reads all the results back, sorted by their relative paths.
+    pub fn iter(&self) -> io::Result<StoreIter<'_>> {
+        let mut pending: Vec<&ResultTuple> = self.pending.iter().collect();
+        pending.sort_by(|(_, a, _), (_, b, _)| a.cmp(b));
+        Ok(StoreIter {
+            pending: pending.into_iter().peekable(),
+            runs: Runs::open(&self.runs)?,
+        })
+    }
+}
+
+/// The results of a ResultStore, merged from the temporary files and the ones in memory.
+pub struct StoreIter<'a> {
+    pending: Peekable<vec::IntoIter<&'a ResultTuple>>,
+    runs: Runs<ResultTuple>,
+}
+
+impl<'a> Iterator for StoreIter<'a> {
+    type Item = Cow<'a, ResultTuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let from_run = match (self.runs.peek_key(), self.pending.peek()) {
+            (Some(run_path), Some((_, pending_path, _))) => run_path < pending_path,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if from_run {
+            self.runs.pop().map(Cow::Owned)
+        } else {
+            self.pending.next().map(Cow::Borrowed)
+        }
+    }
+}
+
+/// The results of a run, in memory, or in a ResultStore with --max-memory.
+pub enum StoredResults {
+    InMemory(Vec<ResultTuple>),
+    Spilled(ResultStore),
+}
+
+impl StoredResults {
+    /// Stores results in memory without a budget.
+    pub fn new(budget: Option<usize>) -> io::Result<Self> {
+        Ok(match budget {
+            Some(budget) => StoredResults::Spilled(ResultStore::new(budget)?),
+            None => StoredResults::InMemory(Vec::new()),
+        })
+    }
+
+    pub fn extend(&mut self, results: Vec<ResultTuple>) -> io::Result<()> {
+        match self {
+            StoredResults::InMemory(stored) => stored.extend(results),
+            StoredResults::Spilled(store) => {
+                for result in results {
+                    store.push(result)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sorts the results by their relative paths, as they are read back from a ResultStore.
+    pub fn sort(&mut self) {
+        if let StoredResults::InMemory(results) = self {
+            results.sort_by(|a, b| a.1.cmp(&b.1));
+        }
+    }
+
+    /// Iterates over the results, for the outputs which are written file by file.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Cow<'_, ResultTuple>> + '_> {
+        match self {
+            StoredResults::InMemory(results) => Box::new(results.iter().map(Cow::Borrowed)),
+            StoredResults::Spilled(store) => {
+                Box::new(store.iter().expect("Failed to read the spilled results"))
+            }
+        }
+    }
+
+    /// Returns all the results at once, for the outputs which need them all, which --max-memory
+    /// doesn't allow.
+    pub fn as_slice(&self) -> &[ResultTuple] {
+        match self {
+            StoredResults::InMemory(results) => results,
+            StoredResults::Spilled(_) => {
+                panic!("The results stored with --max-memory can only be read file by file")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output_lcov;
+    use std::fs;
+    use std::sync::Mutex;
+
+    fn results() -> Vec<ResultTuple> {
+        (0..20)
+            .rev()
+            .map(|i| {
+                let mut functions = FunctionMap::default();
+                functions.insert(
+                    format!("f{}", i),
+                    Function {
+                        start: 1,
+                        executed: i % 2 == 0,
+                    },
+                );
+                (
+                    PathBuf::from(format!("/work/src/file{:02}.rs", i)),
+                    PathBuf::from(format!("src/file{:02}.rs", i)),
+                    CovResult {
+                        lines: (1..=i).map(|line| (line, u64::from(line % 3))).collect(),
+                        branches: [(1, vec![true, i % 3 == 0])].iter().cloned().collect(),
+                        functions,
+                        mcdc: Some(vec![McdcRecord {
+                            line: 1,
+                            conditions: vec![i % 4 == 0, true],
+                        }])
+                        .filter(|_| i % 2 == 0),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_result_store() {
+        let mut sorted = results();
+        sorted.sort_by(|(_, a, _), (_, b, _)| a.cmp(b));
+
+        // A budget of a few results, so that most of them are spilled, in several files.
+        let budget = 3 * estimated_size(&sorted[10]);
+        let mut stored = StoredResults::new(Some(budget)).unwrap();
+        stored.extend(results()).unwrap();
+        match &stored {
+            StoredResults::Spilled(store) => {
+                assert!(store.is_spilled());
+                assert!(store.runs.len() > 2);
+                assert!(!store.pending.is_empty());
+            }
+            StoredResults::InMemory(_) => panic!("The results should be spilled"),
+        }
+        let read: Vec<ResultTuple> = stored.iter().map(Cow::into_owned).collect();
+        assert_eq!(read, sorted);
+        // The results can be read again, for another output.
+        let read: Vec<ResultTuple> = stored.iter().map(Cow::into_owned).collect();
+        assert_eq!(read, sorted);
+
+        // The writers produce the same outputs from the spilled results.
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let spilled_lcov = tmp_dir.path().join("spilled.info");
+        let lcov = tmp_dir.path().join("lcov.info");
+        output_lcov(stored.iter(), Some(&spilled_lcov), false);
+        output_lcov(&sorted, Some(&lcov), false);
+        assert_eq!(
+            fs::read_to_string(spilled_lcov).unwrap(),
+            fs::read_to_string(lcov).unwrap()
+        );
+
+        let mut stored = StoredResults::new(None).unwrap();
+        stored.extend(results()).unwrap();
+        assert!(matches!(stored, StoredResults::InMemory(_)));
+        assert_eq!(stored.as_slice().len(), 20);
+        stored.sort();
+        assert_eq!(stored.as_slice(), &sorted[..]);
+    }
+
+    #[test]
+    fn test_merge_spill() {
+        // The same files are merged three times, with the counts of the lines doubled each time.
+        let inputs: Vec<Vec<(String, CovResult)>> = (0..3)
+            .map(|_| {
+                results()
+                    .into_iter()
+                    .map(|(_, rel_path, result)| (rel_path.to_string_lossy().into_owned(), result))
+                    .collect()
+            })
+            .collect();
+        let result_map: SyncCovResultMap = Mutex::new(CovResultMap::default());
+        let expected: SyncCovResultMap = Mutex::new(CovResultMap::default());
+        let budget = 3 * estimated_size(&results()[10]);
+        let merge_spill = MergeSpill::new(budget).unwrap();
+        let mut peak = 0;
+        for input in inputs {
+            for result in input {
+                crate::add_results(vec![result.clone()], &expected, None, &[]);
+                let added = crate::add_results(vec![result], &result_map, None, &[]);
+                merge_spill.add(&result_map, added).unwrap();
+                let size: usize = result_map
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(path, result)| estimated_entry_size(path, result))
+                    .sum();
+                peak = peak.max(size);
+            }
+        }
+        assert!(merge_spill.is_spilled());
+        // The map never holds much more than the budget, the results of one file aside.
+        assert!(
+            peak <= budget + estimated_size(&results()[0]),
+            "{} > {}",
+            peak,
+            budget
+        );
+
+        let chunks: Vec<CovResultMap> = merge_spill
+            .into_chunks(result_map.into_inner().unwrap(), &[])
+            .unwrap()
+            .collect();
+        assert!(chunks.len() > 2);
+        let mut paths = Vec::new();
+        for chunk in &chunks {
+            let size: usize = chunk
+                .iter()
+                .map(|(path, result)| estimated_entry_size(path, result))
+                .sum();
+            assert!(size <= budget + estimated_size(&results()[0]));
+            let mut chunk_paths: Vec<&String> = chunk.keys().collect();
+            chunk_paths.sort();
+            paths.extend(chunk_paths);
+        }
+        // The chunks follow the order of the paths, each file in one of them, merged.
+        let mut sorted_paths = paths.clone();
+        sorted_paths.sort();
+        sorted_paths.dedup();
+        assert_eq!(paths, sorted_paths);
+        let merged: CovResultMap = chunks.into_iter().flatten().collect();
+        assert_eq!(merged, expected.into_inner().unwrap());
+    }
+}
@@ -0,0 +1,326 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::defs::*;
+use crate::html;
+use crate::threshold::{ThresholdCheck, ThresholdKind};
+
+/// The inputs of a run which didn't make it to the results, counted by the consumers.
+#[derive(Debug, Default)]
+pub struct InputCounts {
+    skipped: AtomicUsize,
+    duplicates: AtomicUsize,
+    parse_errors: AtomicUsize,
+    mismatched_profiles: AtomicUsize,
+}
+
+impl InputCounts {
+    /// Counts inputs left out on purpose, e.g. the binaries without any profile data.
+    pub fn add_skipped(&self, count: usize) {
+        self.skipped.fetch_add(count, Ordering::SeqCst);
+    }
+
+    /// Counts an input skipped by --dedup-inputs, as it has the same content as another one.
+    pub fn add_duplicate(&self) {
+        self.duplicates.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Counts an input which couldn't be parsed, or whose coverage couldn't be exported.
+    pub fn add_parse_error(&self) {
+        self.parse_errors.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Counts profiles which record none of the binaries, with --fail-on-binary-mismatch.
+    pub fn add_mismatched_profile(&self) {
+        self.mismatched_profiles.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.skipped.load(Ordering::SeqCst)
+    }
+
+    pub fn duplicates(&self) -> usize {
+        self.duplicates.load(Ordering::SeqCst)
+    }
+
+    pub fn parse_errors(&self) -> usize {
+        self.parse_errors.load(Ordering::SeqCst)
+    }
+
+    pub fn mismatched_profiles(&self) -> usize {
+        self.mismatched_profiles.load(Ordering::SeqCst)
+    }
+}
+
+/// The ratio of `found` items (lines, branches or functions) which were `hit`, between 0 and 1,
+/// with `empty_ratio` when there aren't any, instead of the NaN of a division by zero.
+pub fn coverage_ratio_or(hit: u64, found: u64, empty_ratio: f64) -> f64 {
+    if found == 0 {
+        empty_ratio
+    } else {
+        hit as f64 / found as f64
+    }
+}
+
+/// The percentage of `found` items which were `hit`, with `empty_pct` when there aren't any.
+pub fn coverage_pct_or(hit: u64, found: u64, empty_pct: f64) -> f64 {
+    coverage_ratio_or(hit, found, empty_pct / 100.0) * 100.0
+}
+
+/// The percentage of `found` items which were `hit`, 100% when there aren't any: a file without
+/// executable lines has nothing left uncovered.
+pub fn coverage_pct(hit: u64, found: u64) -> f64 {
+    coverage_pct_or(hit, found, 100.0)
+}
+
+/// Adds up the coverage of all the results.
+pub fn total_stats(results: impl IntoIterator<Item = impl Borrow<ResultTuple>>) -> HtmlStats {
+    let mut stats = HtmlStats::default();
+    for item in results {
+        stats.add(&html::get_stats(&item.borrow().2));
+    }
+    stats
+}
+
+/// The coverage totals of a run, after all the filters, printed once the outputs are written.
+#[derive(Debug, Clone)]
+pub struct CoverageSummary {
+    pub stats: HtmlStats,
+    pub files: usize,
+    pub skipped: usize,
+    pub duplicates: usize,
+    pub parse_errors: usize,
+    pub precision: usize,
+}
+
+impl CoverageSummary {
+    pub fn new(
+        results: impl IntoIterator<Item = impl Borrow<ResultTuple>>,
+        input_counts: &InputCounts,
+        precision: usize,
+    ) -> Self {
+        let mut files = 0;
+        let stats = total_stats(results.into_iter().inspect(|_| files += 1));
+        CoverageSummary {
+            stats,
+            files,
+            skipped: input_counts.skipped(),
+            duplicates: input_counts.duplicates(),
+            parse_errors: input_counts.parse_errors(),
+            precision,
+        }
+    }
+
+    /// The covered and total counts of each metric, for --print-summary, e.g.
+    /// `Lines: 1234/5678 (21.7%) Branches: 45/100 (45.0%) Functions: 12/30 (40.0%)`. The metrics
+    /// with a threshold are followed by ✓ when it is met, and ✗ when it isn't.
+    pub fn totals(&self, checks: &[ThresholdCheck]) -> String {
+        vec![
+            (
+                ThresholdKind::Lines,
+                "Lines",
+                self.stats.covered_lines,
+                self.stats.total_lines,
+            ),
+            (
+                ThresholdKind::Branches,
+                "Branches",
+                self.stats.covered_branches,
+                self.stats.total_branches,
+            ),
+            (
+                ThresholdKind::Functions,
+                "Functions",
+                self.stats.covered_funs,
+                self.stats.total_funs,
+            ),
+        ]
+        .into_iter()
+        .map(|(kind, name, covered, total)| {
+            let mut metric = format!(
+                "{}: {}/{} ({:.*}%)",
+                name,
+                covered,
+                total,
+                self.precision,
+                coverage_pct(covered as u64, total as u64)
+            );
+            if let Some(check) = checks.iter().find(|check| check.kind == kind) {
+                metric.push_str(if check.passed { " ✓" } else { " ✗" });
+            }
+            metric
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
+}
+
+pub(crate) fn plural(count: usize, singular: &str, plural: &str) -> String {
+    format!("{} {}", count, if count == 1 { singular } else { plural })
+}
+
+impl fmt::Display for CoverageSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percentage = |covered, total| {
+            format!(
+                "{:.*}%",
+                self.precision,
+                html::get_percentage_of_covered_lines(covered, total)
+            )
+        };
+        write!(
+            f,
+            "lines: {} ({}/{}), branches: {}, functions: {} — {}, {} skipped, ",
+            percentage(self.stats.covered_lines, self.stats.total_lines),
+            self.stats.covered_lines,
+            self.stats.total_lines,
+            percentage(self.stats.covered_branches, self.stats.total_branches),
+            percentage(self.stats.covered_funs, self.stats.total_funs),
+            plural(self.files, "file", "files"),
+            self.skipped
+        )?;
+        // The duplicates are only skipped with --dedup-inputs, and rare.
+        if self.duplicates > 0 {
+            write!(
+                f,
+                "{}, ",
+                plural(self.duplicates, "duplicate", "duplicates")
+            )?;
+        }
+        write!(
+            f,
+            "{}",
+            plural(self.parse_errors, "parse error", "parse errors")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Function;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_coverage_pct() {
+        assert_eq!(coverage_pct(1, 4), 25.0);
+        assert_eq!(coverage_pct(3, 3), 100.0);
+        assert_eq!(coverage_pct(0, 0), 100.0);
+        assert!(!coverage_pct(0, 0).is_nan());
+        assert_eq!(coverage_pct_or(0, 0, 0.0), 0.0);
+        assert_eq!(coverage_pct_or(1, 2, 0.0), 50.0);
+        assert_eq!(coverage_ratio_or(0, 0, 1.0), 1.0);
+        assert_eq!(coverage_ratio_or(1, 3, 1.0), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn test_coverage_summary_totals() {
+        let summary = CoverageSummary {
+            stats: HtmlStats {
+                total_lines: 5678,
+                covered_lines: 1234,
+                total_funs: 30,
+                covered_funs: 12,
+                total_branches: 100,
+                covered_branches: 45,
+            },
+            files: 3,
+            skipped: 0,
+            duplicates: 0,
+            parse_errors: 0,
+            precision: 1,
+        };
+        assert_eq!(
+            summary.totals(&[]),
+            "Lines: 1234/5678 (21.7%) Branches: 45/100 (45.0%) Functions: 12/30 (40.0%)"
+        );
+
+        let check = |kind, passed| ThresholdCheck {
+            kind,
+            covered: 0,
+            total: 0,
+            percentage: 0.0,
+            threshold: 40.0,
+            passed,
+        };
+        assert_eq!(
+            summary.totals(&[
+                check(ThresholdKind::Lines, false),
+                check(ThresholdKind::Functions, true)
+            ]),
+            "Lines: 1234/5678 (21.7%) ✗ Branches: 45/100 (45.0%) Functions: 12/30 (40.0%) ✓"
+        );
+    }
+
+    #[test]
+    fn test_coverage_summary() {
+        let mut functions = FunctionMap::default();
+        functions.insert(
+            "f".to_string(),
+            Function {
+                start: 1,
+                executed: true,
+            },
+        );
+        functions.insert(
+            "g".to_string(),
+            Function {
+                start: 3,
+                executed: false,
+            },
+        );
+        let results = vec![
+            (
+                PathBuf::from("/work/src/lib.rs"),
+                PathBuf::from("src/lib.rs"),
+                CovResult {
+                    lines: [(1, 1), (2, 1), (3, 0)].iter().cloned().collect(),
+                    branches: [(2, vec![true, false, false, false])]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                    functions,
+                    mcdc: None,
+                },
+            ),
+            (
+                PathBuf::from("/work/src/main.rs"),
+                PathBuf::from("src/main.rs"),
+                CovResult {
+                    lines: [(1, 0)].iter().cloned().collect(),
+                    branches: BTreeMap::new(),
+                    functions: FunctionMap::default(),
+                    mcdc: None,
+                },
+            ),
+        ];
+
+        let input_counts = InputCounts::default();
+        input_counts.add_skipped(3);
+        input_counts.add_skipped(1);
+        input_counts.add_parse_error();
+        let summary = CoverageSummary::new(&results, &input_counts, 1);
+        assert_eq!(
+            summary.to_string(),
+            "lines: 50.0% (2/4), branches: 25.0%, functions: 50.0% — 2 files, 4 skipped, \
+             1 parse error"
+        );
+
+        input_counts.add_duplicate();
+        let summary = CoverageSummary::new(&results, &input_counts, 1);
+        assert_eq!(
+            summary.to_string(),
+            "lines: 50.0% (2/4), branches: 25.0%, functions: 50.0% — 2 files, 4 skipped, \
+             1 duplicate, 1 parse error"
+        );
+
+        let summary = CoverageSummary::new(&results[1..], &InputCounts::default(), 2);
+        assert_eq!(
+            summary.to_string(),
+            "lines: 0.00% (0/1), branches: 100.00%, functions: 100.00% — 1 file, 0 skipped, \
+             0 parse errors"
+        );
+    }
+}
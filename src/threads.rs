@@ -0,0 +1,121 @@
+use std::fs;
+
+use crate::depfile::input_files;
+
+// The files of the CPU quota of the cgroup of the process, in cgroup v2 and v1.
+const CGROUP_V2_CPU_MAX: &str = "/sys/fs/cgroup/cpu.max";
+const CGROUP_V1_CFS_QUOTA: &str = "/sys/fs/cgroup/cpu/cpu.cfs_quota_us";
+const CGROUP_V1_CFS_PERIOD: &str = "/sys/fs/cgroup/cpu/cpu.cfs_period_us";
+
+// Returns the number of CPUs allowed by a quota of `quota` microseconds per `period`, rounded up.
+fn quota_cpus(quota: i64, period: i64) -> Option<usize> {
+    if quota <= 0 || period <= 0 {
+        return None;
+    }
+    Some(((quota + period - 1) / period) as usize)
+}
+
+/// Parses the CPU quota of cgroup v2 from `cpu.max`, e.g. "200000 100000" for 2 CPUs, or None
+/// when there is no limit ("max 100000").
+pub fn parse_cgroup_v2_cpu_max(cpu_max: &str) -> Option<usize> {
+    let mut fields = cpu_max.split_whitespace();
+    let quota = fields.next()?.parse().ok()?;
+    let period = fields.next()?.parse().ok()?;
+    quota_cpus(quota, period)
+}
+
+/// Parses the CPU quota of cgroup v1 from `cpu.cfs_quota_us` and `cpu.cfs_period_us`, or None
+/// when there is no limit (a quota of -1).
+pub fn parse_cgroup_v1_cpu_quota(cfs_quota: &str, cfs_period: &str) -> Option<usize> {
+    quota_cpus(
+        cfs_quota.trim().parse().ok()?,
+        cfs_period.trim().parse().ok()?,
+    )
+}
+
+/// Returns the number of CPUs the cgroup of the process is allowed to use, if it is limited.
+pub fn cgroup_cpu_quota() -> Option<usize> {
+    if let Ok(cpu_max) = fs::read_to_string(CGROUP_V2_CPU_MAX) {
+        return parse_cgroup_v2_cpu_max(&cpu_max);
+    }
+    let cfs_quota = fs::read_to_string(CGROUP_V1_CFS_QUOTA).ok()?;
+    let cfs_period = fs::read_to_string(CGROUP_V1_CFS_PERIOD).ok()?;
+    parse_cgroup_v1_cpu_quota(&cfs_quota, &cfs_period)
+}
+
+/// Returns the default number of threads for the given numbers of CPUs and of CPUs allowed by
+/// the cgroup: one less than the available CPUs, which are left to the producer.
+pub fn auto_thread_count(cpus: usize, cpu_quota: Option<usize>) -> usize {
+    let cpus = cpu_quota.map_or(cpus, |quota| quota.min(cpus));
+    1.max(cpus.saturating_sub(1))
+}
+
+/// Returns the number of threads to use: the one given with --threads, or the default one when
+/// it is not given or 0.
+pub fn thread_count(threads: Option<usize>, cpus: usize, cpu_quota: Option<usize>) -> usize {
+    match threads {
+        Some(threads) if threads > 0 => threads,
+        _ => auto_thread_count(cpus, cpu_quota),
+    }
+}
+
+/// Returns the number of consumers parsing the coverage files, which is never more than the
+/// number of input files when it is known. The entries of the archives aren't counted, so there
+/// is no limit with archives.
+pub fn consumer_count(threads: usize, input_files: Option<usize>) -> usize {
+    let consumers = input_files.map_or(threads, |input_files| input_files.min(threads));
+    consumers.max(1)
+}
+
+/// Counts the input files of the paths, up to `limit`, or returns None when there are archives.
+pub fn count_input_files(paths: &[String], limit: usize) -> Option<usize> {
+    if paths.iter().any(|path| path.ends_with(".zip")) {
+        return None;
+    }
+    Some(input_files(paths).take(limit).count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroup_cpu_quota() {
+        assert_eq!(parse_cgroup_v2_cpu_max("200000 100000\n"), Some(2));
+        assert_eq!(parse_cgroup_v2_cpu_max("150000 100000\n"), Some(2));
+        assert_eq!(parse_cgroup_v2_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cgroup_v1_cpu_quota("400000\n", "100000\n"), Some(4));
+        assert_eq!(parse_cgroup_v1_cpu_quota("-1\n", "100000\n"), None);
+    }
+
+    #[test]
+    fn test_thread_count() {
+        // A large CI host limited to 4 CPUs by its cgroup.
+        assert_eq!(thread_count(None, 256, Some(4)), 3);
+        assert_eq!(thread_count(Some(0), 256, Some(4)), 3);
+        // No limit.
+        assert_eq!(thread_count(None, 8, None), 7);
+        // A quota higher than the number of CPUs.
+        assert_eq!(thread_count(None, 8, Some(16)), 7);
+        // A single CPU.
+        assert_eq!(thread_count(None, 1, Some(1)), 1);
+        // The number of threads is given.
+        assert_eq!(thread_count(Some(32), 8, Some(2)), 32);
+    }
+
+    #[test]
+    fn test_consumer_count() {
+        assert_eq!(consumer_count(7, Some(3)), 3);
+        assert_eq!(consumer_count(7, Some(100)), 7);
+        assert_eq!(consumer_count(7, Some(0)), 1);
+        assert_eq!(consumer_count(7, None), 7);
+    }
+
+    #[test]
+    fn test_count_input_files() {
+        let paths = vec!["test/prova.info".to_string(), "test/sub".to_string()];
+        assert_eq!(count_input_files(&paths, 1), Some(1));
+        assert!(count_input_files(&paths, 100).unwrap() > 1);
+        assert_eq!(count_input_files(&["test/gcno.zip".to_string()], 100), None);
+    }
+}
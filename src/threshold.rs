@@ -0,0 +1,530 @@
+use globset::{Glob, GlobMatcher};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::borrow::Borrow;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::defs::*;
+use crate::html;
+use crate::summary::total_stats;
+
+/// The exit code used when the coverage is below one of the thresholds.
+pub const THRESHOLD_FAILURE_EXIT_CODE: i32 = 1;
+/// The exit code used when grcov fails, so that it can be told apart from a threshold failure.
+pub const ERROR_EXIT_CODE: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThresholdKind {
+    Lines,
+    Branches,
+    Functions,
+}
+
+impl fmt::Display for ThresholdKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ThresholdKind::Lines => "lines",
+            ThresholdKind::Branches => "branches",
+            ThresholdKind::Functions => "functions",
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ThresholdConfig {
+    pub lines: Option<f64>,
+    pub branches: Option<f64>,
+    pub functions: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ThresholdCheck {
+    pub kind: ThresholdKind,
+    pub covered: usize,
+    pub total: usize,
+    /// The coverage percentage, rounded like in the HTML report.
+    pub percentage: f64,
+    pub threshold: f64,
+    pub passed: bool,
+}
+
+impl fmt::Display for ThresholdCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}% ({}/{}), minimum {}%: {}",
+            self.kind,
+            self.percentage,
+            self.covered,
+            self.total,
+            self.threshold,
+            if self.passed { "passed" } else { "FAILED" }
+        )
+    }
+}
+
+/// Checks that a percentage is between 0 and 100.
+pub fn check_percentage(p: f64) -> Result<f64, String> {
+    if (0.0..=100.0).contains(&p) {
+        Ok(p)
+    } else {
+        Err(format!("{} is not a percentage between 0 and 100", p))
+    }
+}
+
+/// Parses a percentage between 0 and 100.
+pub fn parse_percentage(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .map_err(|_| format!("{} is not a percentage between 0 and 100", s))
+        .and_then(check_percentage)
+}
+
+// Rounded to the given number of decimals, like the round filter of the HTML templates.
+fn round_percentage(percentage: f64, precision: usize) -> f64 {
+    let multiplier = 10f64.powi(precision as i32);
+    (percentage * multiplier).round() / multiplier
+}
+
+/// Checks the overall coverage of the results against the thresholds.
+pub fn check_thresholds(
+    results: impl IntoIterator<Item = impl Borrow<ResultTuple>>,
+    thresholds: &ThresholdConfig,
+    precision: usize,
+) -> Vec<ThresholdCheck> {
+    let stats = total_stats(results);
+
+    [
+        (
+            ThresholdKind::Lines,
+            thresholds.lines,
+            stats.covered_lines,
+            stats.total_lines,
+        ),
+        (
+            ThresholdKind::Branches,
+            thresholds.branches,
+            stats.covered_branches,
+            stats.total_branches,
+        ),
+        (
+            ThresholdKind::Functions,
+            thresholds.functions,
+            stats.covered_funs,
+            stats.total_funs,
+        ),
+    ]
+    .iter()
+    .filter_map(|&(kind, threshold, covered, total)| {
+        let threshold = threshold?;
+        let percentage = round_percentage(
+            html::get_percentage_of_covered_lines(covered, total),
+            precision,
+        );
+        // Without any instrumented line, the coverage data is most likely broken.
+        let passed = percentage >= threshold && (kind != ThresholdKind::Lines || total != 0);
+        Some(ThresholdCheck {
+            kind,
+            covered,
+            total,
+            percentage,
+            threshold,
+            passed,
+        })
+    })
+    .collect()
+}
+
+/// A minimum percentage of covered lines for the files matching a glob, written `GLOB=PERCENTAGE`.
+#[derive(Debug, Clone)]
+pub struct FileThreshold {
+    pub glob: String,
+    matcher: GlobMatcher,
+    pub threshold: f64,
+}
+
+impl FromStr for FileThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (glob, threshold) = match s.rfind('=') {
+            Some(pos) => (&s[..pos], &s[pos + 1..]),
+            None => {
+                return Err(format!(
+                    "Invalid threshold '{}', expected GLOB=PERCENTAGE",
+                    s
+                ))
+            }
+        };
+        let matcher = Glob::new(glob)
+            .map_err(|e| format!("Invalid glob in threshold '{}': {}", s, e))?
+            .compile_matcher();
+        Ok(FileThreshold {
+            glob: glob.to_string(),
+            matcher,
+            threshold: parse_percentage(threshold)?,
+        })
+    }
+}
+
+impl FileThreshold {
+    // The longest glob is considered as the most specific one.
+    fn specificity(&self) -> usize {
+        self.glob.len()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileThresholdViolation {
+    pub path: PathBuf,
+    /// The glob of the rule which applies to the file.
+    pub glob: String,
+    pub covered: usize,
+    pub total: usize,
+    pub percentage: f64,
+    pub threshold: f64,
+}
+
+impl fmt::Display for FileThresholdViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}% ({}/{}), minimum {}% ({})",
+            self.path.display(),
+            self.percentage,
+            self.covered,
+            self.total,
+            self.threshold,
+            self.glob
+        )
+    }
+}
+
+/// Checks the coverage of each file against the most specific rule matching its path. When
+/// several rules are equally specific, the last one wins. The files without any instrumented
+/// line pass unless `fail_empty_files` is set.
+pub fn check_file_thresholds(
+    results: impl IntoIterator<Item = impl Borrow<ResultTuple>>,
+    rules: &[FileThreshold],
+    fail_empty_files: bool,
+    precision: usize,
+) -> Vec<FileThresholdViolation> {
+    if rules.is_empty() {
+        return Vec::new();
+    }
+
+    results
+        .into_iter()
+        .filter_map(|item| {
+            let (_, rel_path, result) = item.borrow();
+            let rule = rules
+                .iter()
+                .filter(|rule| rule.matcher.is_match(rel_path))
+                .max_by_key(|rule| rule.specificity())?;
+            let stats = html::get_stats(result);
+            let percentage = round_percentage(
+                html::get_percentage_of_covered_lines(stats.covered_lines, stats.total_lines),
+                precision,
+            );
+            let passed = if stats.total_lines == 0 {
+                !fail_empty_files
+            } else {
+                percentage >= rule.threshold
+            };
+            if passed {
+                return None;
+            }
+            Some(FileThresholdViolation {
+                path: rel_path.clone(),
+                glob: rule.glob.clone(),
+                covered: stats.covered_lines,
+                total: stats.total_lines,
+                percentage,
+                threshold: rule.threshold,
+            })
+        })
+        .collect()
+}
+
+/// Returns a message reporting all the thresholds which aren't met, if any.
+pub fn threshold_violations(
+    checks: &[ThresholdCheck],
+    file_violations: &[FileThresholdViolation],
+) -> Option<String> {
+    let violations: Vec<String> = checks
+        .iter()
+        .filter(|check| !check.passed)
+        .map(|check| {
+            format!(
+                "{} coverage is {}% ({}/{}), minimum {}%",
+                check.kind, check.percentage, check.covered, check.total, check.threshold
+            )
+        })
+        .collect();
+    let mut message = Vec::new();
+    if !violations.is_empty() {
+        message.push(format!(
+            "Coverage is below the thresholds: {}",
+            violations.join("; ")
+        ));
+    }
+    if !file_violations.is_empty() {
+        message.push(format!(
+            "{} file(s) below their coverage threshold:",
+            file_violations.len()
+        ));
+        for violation in file_violations {
+            message.push(format!("    {}", violation));
+        }
+    }
+    if message.is_empty() {
+        None
+    } else {
+        Some(message.join("\n"))
+    }
+}
+
+/// Returns the results of the threshold checks as JSON.
+pub fn threshold_report_json(
+    checks: &[ThresholdCheck],
+    file_violations: &[FileThresholdViolation],
+) -> Value {
+    json!({
+        "passed": checks.iter().all(|check| check.passed) && file_violations.is_empty(),
+        "thresholds": checks,
+        "file_violations": file_violations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn results() -> Vec<ResultTuple> {
+        let mut functions = FunctionMap::default();
+        functions.insert(
+            "f".to_string(),
+            Function {
+                start: 1,
+                executed: true,
+            },
+        );
+        let result = CovResult {
+            lines: [(1, 1), (2, 1), (3, 0)].iter().cloned().collect(),
+            branches: [(2, vec![true, false, false, false])]
+                .iter()
+                .cloned()
+                .collect(),
+            functions,
+            mcdc: None,
+        };
+        vec![(PathBuf::from("a.rs"), PathBuf::from("a.rs"), result)]
+    }
+
+    #[test]
+    fn test_parse_percentage() {
+        assert_eq!(parse_percentage("80"), Ok(80.0));
+        assert_eq!(parse_percentage("66.67"), Ok(66.67));
+        assert!(parse_percentage("101").is_err());
+        assert!(parse_percentage("-1").is_err());
+        assert!(parse_percentage("high").is_err());
+    }
+
+    #[test]
+    fn test_check_thresholds() {
+        let checks = check_thresholds(
+            results(),
+            &ThresholdConfig {
+                lines: Some(66.67),
+                branches: Some(30.0),
+                functions: None,
+            },
+            2,
+        );
+        assert_eq!(
+            checks,
+            vec![
+                ThresholdCheck {
+                    kind: ThresholdKind::Lines,
+                    covered: 2,
+                    total: 3,
+                    percentage: 66.67,
+                    threshold: 66.67,
+                    passed: true,
+                },
+                ThresholdCheck {
+                    kind: ThresholdKind::Branches,
+                    covered: 1,
+                    total: 4,
+                    percentage: 25.0,
+                    threshold: 30.0,
+                    passed: false,
+                },
+            ]
+        );
+        assert_eq!(
+            checks[1].to_string(),
+            "branches: 25% (1/4), minimum 30%: FAILED"
+        );
+    }
+
+    #[test]
+    fn test_threshold_violations() {
+        let thresholds = ThresholdConfig {
+            lines: Some(70.0),
+            branches: Some(30.0),
+            functions: Some(100.0),
+        };
+        let checks = check_thresholds(results(), &thresholds, 2);
+        assert_eq!(
+            threshold_violations(&checks, &[]).unwrap(),
+            "Coverage is below the thresholds: lines coverage is 66.67% (2/3), minimum 70%; branches coverage is 25% (1/4), minimum 30%"
+        );
+
+        let thresholds = ThresholdConfig {
+            functions: Some(100.0),
+            ..Default::default()
+        };
+        let checks = check_thresholds(results(), &thresholds, 2);
+        assert_eq!(threshold_violations(&checks, &[]), None);
+    }
+
+    fn file_results() -> Vec<ResultTuple> {
+        [
+            ("src/main.rs", vec![(1, 1), (2, 0)]),
+            ("src/parser/lcov.rs", vec![(1, 1), (2, 1), (3, 1), (4, 0)]),
+            ("src/parser/gcov.rs", vec![(1, 1), (2, 1), (3, 1), (4, 1)]),
+            ("src/empty.rs", vec![]),
+            ("build.rs", vec![(1, 0)]),
+        ]
+        .iter()
+        .map(|(path, lines)| {
+            let result = CovResult {
+                lines: lines.iter().cloned().collect(),
+                branches: Default::default(),
+                functions: Default::default(),
+                mcdc: None,
+            };
+            (PathBuf::from(path), PathBuf::from(path), result)
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_parse_file_threshold() {
+        let rule: FileThreshold = "src/parser/**=90".parse().unwrap();
+        assert_eq!(rule.glob, "src/parser/**");
+        assert_eq!(rule.threshold, 90.0);
+        let rule: FileThreshold = "a=b.rs=50".parse().unwrap();
+        assert_eq!(rule.glob, "a=b.rs");
+        assert!("src/**".parse::<FileThreshold>().is_err());
+        assert!("src/**=high".parse::<FileThreshold>().is_err());
+        assert!("src/[**=50".parse::<FileThreshold>().is_err());
+    }
+
+    #[test]
+    fn test_check_file_thresholds() {
+        let rules: Vec<FileThreshold> = ["src/**=60", "src/parser/**=90"]
+            .iter()
+            .map(|rule| rule.parse().unwrap())
+            .collect();
+
+        let violations = check_file_thresholds(file_results(), &rules, false, 2);
+        assert_eq!(
+            violations,
+            vec![
+                FileThresholdViolation {
+                    path: PathBuf::from("src/main.rs"),
+                    glob: "src/**".to_string(),
+                    covered: 1,
+                    total: 2,
+                    percentage: 50.0,
+                    threshold: 60.0,
+                },
+                FileThresholdViolation {
+                    path: PathBuf::from("src/parser/lcov.rs"),
+                    glob: "src/parser/**".to_string(),
+                    covered: 3,
+                    total: 4,
+                    percentage: 75.0,
+                    threshold: 90.0,
+                },
+            ]
+        );
+        assert_eq!(
+            violations[1].to_string(),
+            "src/parser/lcov.rs: 75% (3/4), minimum 90% (src/parser/**)"
+        );
+
+        let violations = check_file_thresholds(file_results(), &rules, true, 2);
+        assert_eq!(violations.len(), 3);
+        assert_eq!(violations[2].path, PathBuf::from("src/empty.rs"));
+
+        assert!(check_file_thresholds(file_results(), &[], true, 2).is_empty());
+    }
+
+    #[test]
+    fn test_threshold_report() {
+        let rules = vec!["src/**=60".parse().unwrap()];
+        let file_violations = check_file_thresholds(file_results(), &rules, false, 2);
+        let thresholds = ThresholdConfig {
+            lines: Some(50.0),
+            ..Default::default()
+        };
+        let checks = check_thresholds(file_results(), &thresholds, 2);
+
+        assert_eq!(
+            threshold_violations(&checks, &file_violations).unwrap(),
+            "1 file(s) below their coverage threshold:\n    src/main.rs: 50% (1/2), minimum 60% (src/**)"
+        );
+        assert_eq!(
+            threshold_report_json(&checks, &file_violations),
+            json!({
+                "passed": false,
+                "thresholds": [{
+                    "kind": "lines",
+                    "covered": 8,
+                    "total": 11,
+                    "percentage": 72.73,
+                    "threshold": 50.0,
+                    "passed": true,
+                }],
+                "file_violations": [{
+                    "path": "src/main.rs",
+                    "glob": "src/**",
+                    "covered": 1,
+                    "total": 2,
+                    "percentage": 50.0,
+                    "threshold": 60.0,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_thresholds_rounding() {
+        let thresholds = ThresholdConfig {
+            lines: Some(66.7),
+            ..Default::default()
+        };
+        assert!(check_thresholds(results(), &thresholds, 1)[0].passed);
+        assert!(!check_thresholds(results(), &thresholds, 2)[0].passed);
+    }
+
+    #[test]
+    fn test_check_thresholds_no_lines() {
+        let thresholds = ThresholdConfig {
+            lines: Some(0.0),
+            branches: Some(0.0),
+            functions: Some(0.0),
+        };
+        let checks = check_thresholds(&[], &thresholds, 2);
+        assert_eq!(checks.len(), 3);
+        assert!(!checks[0].passed);
+        assert!(checks[1].passed);
+        assert!(checks[2].passed);
+    }
+}
@@ -0,0 +1,291 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::defs::*;
+use crate::html;
+
+/// The change of the line coverage per run, in percentage points, under which a trend is
+/// considered as stable.
+pub const STABLE_TREND_SLOPE: f64 = 0.1;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct TrendCoverage {
+    pub covered_lines: usize,
+    pub total_lines: usize,
+    pub covered_branches: usize,
+    pub total_branches: usize,
+    pub covered_functions: usize,
+    pub total_functions: usize,
+    pub line_percentage: f64,
+}
+
+impl From<&HtmlStats> for TrendCoverage {
+    fn from(stats: &HtmlStats) -> Self {
+        TrendCoverage {
+            covered_lines: stats.covered_lines,
+            total_lines: stats.total_lines,
+            covered_branches: stats.covered_branches,
+            total_branches: stats.total_branches,
+            covered_functions: stats.covered_funs,
+            total_functions: stats.total_funs,
+            line_percentage: html::get_percentage_of_covered_lines(
+                stats.covered_lines,
+                stats.total_lines,
+            ),
+        }
+    }
+}
+
+/// The coverage of one run, as stored in the trend file.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TrendRecord {
+    /// The time of the run, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub commit: String,
+    pub files: BTreeMap<String, TrendCoverage>,
+    pub aggregate: TrendCoverage,
+}
+
+impl TrendRecord {
+    pub fn new(
+        results: impl IntoIterator<Item = impl Borrow<ResultTuple>>,
+        commit: &str,
+        timestamp: u64,
+    ) -> Self {
+        let mut files = BTreeMap::new();
+        let mut aggregate = HtmlStats::default();
+        for item in results {
+            let (_, rel_path, result) = item.borrow();
+            let stats = html::get_stats(result);
+            aggregate.add(&stats);
+            files.insert(rel_path.to_string_lossy().into_owned(), (&stats).into());
+        }
+        TrendRecord {
+            timestamp,
+            commit: commit.to_string(),
+            files,
+            aggregate: (&aggregate).into(),
+        }
+    }
+}
+
+/// Reads the records of a trend file. A missing file has no records.
+pub fn read_trend_file(path: &Path) -> Result<Vec<TrendRecord>, String> {
+    match fs::read(path) {
+        Ok(buffer) => serde_json::from_slice(&buffer)
+            .map_err(|e| format!("Invalid trend file {}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!(
+            "Unable to read the trend file {}: {}",
+            path.display(),
+            e
+        )),
+    }
+}
+
+/// Appends the record of a run to a trend file, creating it if needed.
+pub fn append_trend_record(path: &Path, record: TrendRecord) -> Result<(), String> {
+    let mut records = read_trend_file(path)?;
+    records.push(record);
+    let buffer = serde_json::to_vec(&records).unwrap();
+    fs::write(path, buffer)
+        .map_err(|e| format!("Unable to write the trend file {}: {}", path.display(), e))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trend {
+    Increasing,
+    Decreasing,
+    Stable,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrendStats {
+    /// The number of runs the statistics are computed from.
+    pub runs: usize,
+    pub latest: f64,
+    /// The average of the line coverage percentages of the runs.
+    pub moving_average: f64,
+    /// The change of the line coverage per run, in percentage points (the slope of the linear
+    /// regression of the percentages).
+    pub slope: f64,
+    pub trend: Trend,
+}
+
+impl TrendStats {
+    fn new(percentages: &[f64]) -> Self {
+        let n = percentages.len() as f64;
+        let mean_x = (n - 1.0) / 2.0;
+        let mean_y = percentages.iter().sum::<f64>() / n;
+        let (mut covariance, mut variance) = (0.0, 0.0);
+        for (x, y) in percentages.iter().enumerate() {
+            let dx = x as f64 - mean_x;
+            covariance += dx * (y - mean_y);
+            variance += dx * dx;
+        }
+        let slope = if variance > 0.0 {
+            covariance / variance
+        } else {
+            0.0
+        };
+        let trend = if slope > STABLE_TREND_SLOPE {
+            Trend::Increasing
+        } else if slope < -STABLE_TREND_SLOPE {
+            Trend::Decreasing
+        } else {
+            Trend::Stable
+        };
+        TrendStats {
+            runs: percentages.len(),
+            latest: *percentages.last().unwrap(),
+            moving_average: mean_y,
+            slope,
+            trend,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrendReport {
+    pub aggregate: Option<TrendStats>,
+    pub files: BTreeMap<String, TrendStats>,
+}
+
+/// Computes the trends of the line coverage over the last `last_n` runs, overall and for each
+/// file. The files are only considered in the runs which include them.
+pub fn trend_stats(records: &[TrendRecord], last_n: usize) -> TrendReport {
+    let records = &records[records.len().saturating_sub(last_n)..];
+
+    let mut percentages: BTreeMap<&str, Vec<f64>> = BTreeMap::new();
+    for record in records {
+        for (path, coverage) in &record.files {
+            percentages
+                .entry(path)
+                .or_default()
+                .push(coverage.line_percentage);
+        }
+    }
+
+    let aggregate: Vec<f64> = records
+        .iter()
+        .map(|record| record.aggregate.line_percentage)
+        .collect();
+    TrendReport {
+        aggregate: if aggregate.is_empty() {
+            None
+        } else {
+            Some(TrendStats::new(&aggregate))
+        },
+        files: percentages
+            .into_iter()
+            .map(|(path, percentages)| (path.to_string(), TrendStats::new(&percentages)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn record(timestamp: u64, files: &[(&str, usize, usize)]) -> TrendRecord {
+        let results: Vec<ResultTuple> = files
+            .iter()
+            .map(|&(path, covered, total)| {
+                let result = CovResult {
+                    lines: (0..total as u32)
+                        .map(|line| (line + 1, u64::from((line as usize) < covered)))
+                        .collect(),
+                    branches: Default::default(),
+                    functions: Default::default(),
+                    mcdc: None,
+                };
+                (PathBuf::from(path), PathBuf::from(path), result)
+            })
+            .collect();
+        TrendRecord::new(&results, "abc123", timestamp)
+    }
+
+    #[test]
+    fn test_trend_record() {
+        let record = record(1000, &[("src/a.rs", 1, 4), ("src/b.rs", 3, 4)]);
+        assert_eq!(record.timestamp, 1000);
+        assert_eq!(record.commit, "abc123");
+        assert_eq!(record.files.len(), 2);
+        assert_eq!(record.files["src/a.rs"].line_percentage, 25.0);
+        assert_eq!(record.aggregate.covered_lines, 4);
+        assert_eq!(record.aggregate.total_lines, 8);
+        assert_eq!(record.aggregate.line_percentage, 50.0);
+    }
+
+    #[test]
+    fn test_append_trend_record() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let path = tmp_dir.path().join("trend.json");
+
+        append_trend_record(&path, record(1, &[("src/a.rs", 1, 2)])).unwrap();
+        append_trend_record(&path, record(2, &[("src/a.rs", 2, 2)])).unwrap();
+
+        let records = read_trend_file(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp, 1);
+        assert_eq!(records[1].files["src/a.rs"].covered_lines, 2);
+
+        let value: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert!(value.is_array());
+        assert_eq!(value[1]["aggregate"]["line_percentage"], 100.0);
+
+        fs::write(&path, "{}").unwrap();
+        assert!(read_trend_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_trend_stats() {
+        let records = vec![
+            record(1, &[("src/a.rs", 0, 10), ("src/b.rs", 9, 10)]),
+            record(2, &[("src/a.rs", 2, 10), ("src/b.rs", 8, 10)]),
+            record(
+                3,
+                &[
+                    ("src/a.rs", 4, 10),
+                    ("src/b.rs", 7, 10),
+                    ("src/c.rs", 5, 10),
+                ],
+            ),
+            record(
+                4,
+                &[
+                    ("src/a.rs", 6, 10),
+                    ("src/b.rs", 6, 10),
+                    ("src/c.rs", 5, 10),
+                ],
+            ),
+        ];
+
+        let report = trend_stats(&records, 3);
+        let a = &report.files["src/a.rs"];
+        assert_eq!(a.runs, 3);
+        assert_eq!(a.latest, 60.0);
+        assert_eq!(a.moving_average, 40.0);
+        assert_eq!(a.slope, 20.0);
+        assert_eq!(a.trend, Trend::Increasing);
+        assert_eq!(report.files["src/b.rs"].trend, Trend::Decreasing);
+        let c = &report.files["src/c.rs"];
+        assert_eq!(c.runs, 2);
+        assert_eq!(c.trend, Trend::Stable);
+
+        let aggregate = report.aggregate.unwrap();
+        assert_eq!(aggregate.runs, 3);
+        assert!((aggregate.moving_average - 160.0 / 3.0).abs() < 1e-9);
+        assert_eq!(aggregate.trend, Trend::Increasing);
+
+        let report = trend_stats(&[], 3);
+        assert_eq!(report.aggregate, None);
+        assert!(report.files.is_empty());
+    }
+}
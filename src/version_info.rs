@@ -0,0 +1,125 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::llvm_tools::{get_cov_path, get_profdata_path};
+
+/// Returns the line with the version from the output of `<command> --version`, e.g.
+/// "LLVM version 17.0.6" for the llvm tools or "rustc 1.75.0 (82e1608df 2023-12-21)".
+pub fn command_version(command: impl AsRef<OsStr>) -> Option<String> {
+    let output = Command::new(command).arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut lines = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    let first = lines.clone().next()?;
+    Some(
+        lines
+            .find(|line| line.contains("version"))
+            .unwrap_or(first)
+            .to_string(),
+    )
+}
+
+// The rustc of the environment, as cargo sets it for the commands it runs.
+pub(crate) fn rustc_command() -> OsString {
+    env::var_os("RUSTC").unwrap_or_else(|| "rustc".into())
+}
+
+// Returns the target triple of the host from the output of `rustc -vV`, e.g.
+// "x86_64-unknown-linux-gnu".
+fn parse_rustc_host(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("host:"))
+        .map(|host| host.trim().to_string())
+}
+
+fn rustc_host(rustc: impl AsRef<OsStr>) -> Option<String> {
+    let output = Command::new(rustc).arg("-vV").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_rustc_host(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The versions of grcov and of the tools of a run, written with --version-file to explain the
+/// differences between the reports of two runs. The versions which can't be found (e.g. without
+/// a Rust toolchain for the gcov inputs) are null.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VersionInfo {
+    pub grcov_version: String,
+    pub llvm_cov_version: Option<String>,
+    pub llvm_profdata_version: Option<String>,
+    pub rustc_version: Option<String>,
+    /// The target triple of the rustc host.
+    pub target: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl VersionInfo {
+    pub fn collect() -> Self {
+        let rustc = rustc_command();
+        VersionInfo {
+            grcov_version: env!("CARGO_PKG_VERSION").to_string(),
+            llvm_cov_version: get_cov_path().ok().and_then(command_version),
+            llvm_profdata_version: get_profdata_path().ok().and_then(command_version),
+            rustc_version: command_version(&rustc),
+            target: rustc_host(&rustc),
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).unwrap();
+        fs::write(path, json)
+            .map_err(|e| format!("Unable to write the versions to {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_parse_rustc_host() {
+        let output = "rustc 1.75.0 (82e1608df 2023-12-21)\nbinary: rustc\n\
+                      commit-hash: 82e1608dfa6e0b5569232559e3d385fea5a93112\n\
+                      host: x86_64-unknown-linux-gnu\nrelease: 1.75.0\nLLVM version: 17.0.6\n";
+        assert_eq!(
+            parse_rustc_host(output),
+            Some("x86_64-unknown-linux-gnu".to_string())
+        );
+        assert_eq!(parse_rustc_host("rustc 1.75.0\n"), None);
+    }
+
+    #[test]
+    fn test_version_info() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let info = VersionInfo::collect();
+        assert_eq!(info.grcov_version, env!("CARGO_PKG_VERSION"));
+        assert!(info.rustc_version.as_ref().unwrap().starts_with("rustc "));
+        assert!(info.target.is_some());
+
+        let path = tmp_dir.path().join("versions.json");
+        info.write(&path).unwrap();
+        let written: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["grcov_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(written["target"], info.target.clone().unwrap());
+        let timestamp: DateTime<Utc> = written["timestamp"].as_str().unwrap().parse().unwrap();
+        assert_eq!(timestamp, info.timestamp);
+
+        assert!(info
+            .write(&tmp_dir.path().join("missing/versions.json"))
+            .is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! The WebAssembly modules built with source-based coverage, e.g. for `wasm32-unknown-unknown`.
+//! Their coverage mapping is stored in custom sections with the same names as the ELF sections.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use crate::raw_coverage::COVMAP_SECTIONS;
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+// The size of the magic number and of the version of a module, before its sections.
+const WASM_HEADER_SIZE: usize = 8;
+const CUSTOM_SECTION_ID: u8 = 0;
+
+/// The first major version of LLVM whose llvm-cov reads the coverage mapping of WebAssembly
+/// modules.
+pub const WASM_MIN_LLVM_MAJOR: u32 = 19;
+
+/// The architecture given to `llvm-cov export` for the WebAssembly modules.
+pub const WASM_EXPORT_ARCH: &str = "wasm32";
+
+/// Returns whether a buffer is a WebAssembly module.
+pub fn is_wasm_module(binary: &[u8]) -> bool {
+    binary.starts_with(WASM_MAGIC)
+}
+
+/// Returns whether a file is a WebAssembly module, from its magic number.
+pub fn is_wasm(path: impl AsRef<Path>) -> bool {
+    let mut magic = [0; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && is_wasm_module(&magic)
+}
+
+fn read_leb128(data: &[u8], pos: &mut usize) -> Result<usize, String> {
+    let mut result = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *data
+            .get(*pos)
+            .ok_or_else(|| "Unexpected end of the WebAssembly module".to_string())?;
+        *pos += 1;
+        if shift >= 32 {
+            return Err("LEB128 value too large in the WebAssembly module".to_string());
+        }
+        result |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+}
+
+/// Lists the names and contents of the custom sections of a WebAssembly module.
+pub fn wasm_custom_sections(binary: &[u8]) -> Result<Vec<(&str, &[u8])>, String> {
+    if !is_wasm_module(binary) || binary.len() < WASM_HEADER_SIZE {
+        return Err("Not a WebAssembly module".to_string());
+    }
+    let truncated = || "Unexpected end of the WebAssembly module".to_string();
+
+    let mut sections = Vec::new();
+    let mut pos = WASM_HEADER_SIZE;
+    while pos < binary.len() {
+        let id = binary[pos];
+        pos += 1;
+        let size = read_leb128(binary, &mut pos)?;
+        let end = pos.checked_add(size).filter(|&end| end <= binary.len());
+        let end = end.ok_or_else(truncated)?;
+        if id == CUSTOM_SECTION_ID {
+            let mut name_pos = pos;
+            let name_size = read_leb128(binary, &mut name_pos)?;
+            let name_end = name_pos
+                .checked_add(name_size)
+                .filter(|&name_end| name_end <= end)
+                .ok_or_else(truncated)?;
+            let name = std::str::from_utf8(&binary[name_pos..name_end])
+                .map_err(|e| format!("Invalid custom section name: {}", e))?;
+            sections.push((name, &binary[name_end..end]));
+        }
+        pos = end;
+    }
+    Ok(sections)
+}
+
+/// Checks that the coverage of a WebAssembly module can be exported by the llvm-cov of the given
+/// major version, if it is known.
+pub fn check_wasm_support(binary_path: &Path, llvm_major: Option<u32>) -> Result<(), String> {
+    if let Some(llvm_major) = llvm_major.filter(|&major| major < WASM_MIN_LLVM_MAJOR) {
+        return Err(format!(
+            "llvm-cov {} can't read the coverage mapping of the WebAssembly module {}, LLVM {} or \
+             newer is needed. Use the llvm-tools of a recent Rust toolchain, or --llvm-path.",
+            llvm_major,
+            binary_path.display(),
+            WASM_MIN_LLVM_MAJOR
+        ));
+    }
+    let binary = fs::read(binary_path)
+        .map_err(|e| format!("Unable to read {}: {}", binary_path.display(), e))?;
+    let sections = wasm_custom_sections(&binary).map_err(|e| {
+        format!(
+            "Invalid WebAssembly module {}: {}",
+            binary_path.display(),
+            e
+        )
+    })?;
+    if !sections
+        .iter()
+        .any(|(name, _)| COVMAP_SECTIONS.contains(name))
+    {
+        return Err(format!(
+            "The WebAssembly module {} has no coverage mapping. It must be built with \
+             -Cinstrument-coverage, and the custom sections must be kept by the tools run after \
+             the build (e.g. wasm-bindgen --keep-debug, or wasm-opt without --strip).",
+            binary_path.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Builds a module with the given custom sections, after a type section.
+    pub(crate) fn wasm_module(custom_sections: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        // A type section with no types.
+        module.extend([1, 1, 0]);
+        for (name, data) in custom_sections {
+            let size = 1 + name.len() + data.len();
+            assert!(size < 0x80 * 0x80);
+            module.push(CUSTOM_SECTION_ID);
+            if size < 0x80 {
+                module.push(size as u8);
+            } else {
+                module.extend([(size & 0x7f) as u8 | 0x80, (size >> 7) as u8]);
+            }
+            module.push(name.len() as u8);
+            module.extend(name.as_bytes());
+            module.extend(*data);
+        }
+        module
+    }
+
+    #[test]
+    fn test_wasm_custom_sections() {
+        let covmap = [7u8; 200];
+        let module = wasm_module(&[("__llvm_covmap", &covmap), ("name", b"\0")]);
+        assert!(is_wasm_module(&module));
+        assert_eq!(
+            wasm_custom_sections(&module).unwrap(),
+            vec![("__llvm_covmap", &covmap[..]), ("name", &b"\0"[..])]
+        );
+
+        assert!(wasm_custom_sections(b"\x7fELF").is_err());
+        assert!(wasm_custom_sections(&module[..module.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_check_wasm_support() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let instrumented = tmp_dir.path().join("instrumented.wasm");
+        fs::write(&instrumented, wasm_module(&[("__llvm_covmap", &[0; 16])])).unwrap();
+        let stripped = tmp_dir.path().join("stripped.wasm");
+        fs::write(&stripped, wasm_module(&[])).unwrap();
+
+        assert!(is_wasm(&instrumented));
+        assert!(!is_wasm("test/raw_coverage/sample.o"));
+        assert!(check_wasm_support(&instrumented, Some(19)).is_ok());
+        assert!(check_wasm_support(&instrumented, None).is_ok());
+        let err = check_wasm_support(&instrumented, Some(18)).unwrap_err();
+        assert!(err.contains("LLVM 19 or newer is needed"));
+        let err = check_wasm_support(&stripped, Some(19)).unwrap_err();
+        assert!(err.contains("has no coverage mapping"));
+    }
+}
@@ -0,0 +1,281 @@
+use log::warn;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::discovery::discover_binaries_and_profraws;
+use crate::llvm_tools::{profraws_to_lcov, ExportOptions, LlvmToolError};
+
+// How often the stop flag is checked while waiting for changes.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Checks whether a change of the given file can change the coverage report.
+pub fn is_coverage_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("profraw" | "profdata" | "gcda" | "gcno" | "info")
+    )
+}
+
+// The paths which don't exist yet (e.g. after a cargo clean) are watched through their closest
+// existing ancestor.
+fn watch_root(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .find(|ancestor| ancestor.exists())
+        .or_else(|| Some(Path::new(".")).filter(|_| path.is_relative()))
+}
+
+/// Watches the input paths for changes of the coverage files.
+pub struct CoverageWatcher {
+    paths: Vec<PathBuf>,
+    // The directories or files actually watched for each input path.
+    roots: Vec<PathBuf>,
+    receiver: Receiver<notify::Result<Event>>,
+    watcher: RecommendedWatcher,
+}
+
+impl CoverageWatcher {
+    pub fn new(paths: &[PathBuf]) -> Result<Self, String> {
+        let (sender, receiver) = channel();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })
+        .map_err(|e| format!("Failed to watch the input paths: {}", e))?;
+        let mut watcher = CoverageWatcher {
+            paths: paths.to_vec(),
+            roots: Vec::new(),
+            receiver,
+            watcher,
+        };
+        watcher.watch_roots()?;
+        Ok(watcher)
+    }
+
+    // Watches the closest existing ancestors of the input paths, as they can be removed and
+    // created again while being watched.
+    fn watch_roots(&mut self) -> Result<(), String> {
+        let roots = self
+            .paths
+            .iter()
+            .map(|path| {
+                watch_root(path)
+                    .map(Path::to_path_buf)
+                    .ok_or_else(|| format!("Failed to watch {}: no such path", path.display()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if roots == self.roots {
+            return Ok(());
+        }
+        for root in &self.roots {
+            let _ = self.watcher.unwatch(root);
+        }
+        for root in &roots {
+            self.watcher
+                .watch(root, RecursiveMode::Recursive)
+                .map_err(|e| format!("Failed to watch {}: {}", root.display(), e))?;
+        }
+        self.roots = roots;
+        Ok(())
+    }
+
+    /// Waits for changes of coverage files, created, modified or removed, and returns them once
+    /// no other change happened for `debounce`. Returns None once `stop` is set.
+    pub fn wait_for_changes(
+        &mut self,
+        debounce: Duration,
+        stop: &AtomicBool,
+    ) -> Option<Vec<PathBuf>> {
+        let mut changed: Vec<PathBuf> = Vec::new();
+        let mut last_change = Instant::now();
+        loop {
+            if stop.load(Ordering::SeqCst) {
+                return None;
+            }
+            let timeout = if changed.is_empty() {
+                STOP_POLL_INTERVAL
+            } else {
+                let elapsed = last_change.elapsed();
+                if elapsed >= debounce {
+                    if let Err(e) = self.watch_roots() {
+                        warn!("{}", e);
+                    }
+                    changed.sort();
+                    changed.dedup();
+                    return Some(changed);
+                }
+                STOP_POLL_INTERVAL.min(debounce - elapsed)
+            };
+            match self.receiver.recv_timeout(timeout) {
+                Ok(Ok(event)) => {
+                    if matches!(event.kind, EventKind::Access(_)) {
+                        continue;
+                    }
+                    let paths: Vec<PathBuf> = event
+                        .paths
+                        .into_iter()
+                        .filter(|path| is_coverage_file(path))
+                        .collect();
+                    if !paths.is_empty() {
+                        changed.extend(paths);
+                        last_change = Instant::now();
+                    }
+                }
+                // The errors are about a single event (e.g. a file removed while it was
+                // being watched), so keep waiting for the next ones.
+                Ok(Err(_)) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+}
+
+fn is_profraw(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "profraw")
+}
+
+/// Watches a directory, and exports the coverage of the binaries in `binary_path` for all the
+/// profraw files of the directory each time they change, once no other change happened for
+/// `debounce`. The lcov outputs, or the error of the export, are passed to `on_update`. Returns
+/// once `stop` is set.
+pub fn watch_and_export(
+    dir: &Path,
+    binary_path: &Path,
+    working_dir: &Path,
+    debounce: Duration,
+    stop: &AtomicBool,
+    mut on_update: impl FnMut(Result<Vec<Vec<u8>>, LlvmToolError>),
+) -> Result<(), String> {
+    let mut watcher = CoverageWatcher::new(&[dir.to_path_buf()])?;
+    while let Some(changed) = watcher.wait_for_changes(debounce, stop) {
+        if !changed.iter().any(|path| is_profraw(path)) {
+            continue;
+        }
+        let (binaries, profraws) = discover_binaries_and_profraws(binary_path, dir);
+        on_update(profraws_to_lcov(
+            &profraws,
+            working_dir,
+            &ExportOptions {
+                executables: &binaries,
+                sort: true,
+                ..Default::default()
+            },
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_is_coverage_file() {
+        assert!(is_coverage_file(Path::new("target/default_1.profraw")));
+        assert!(is_coverage_file(Path::new("obj/main.gcda")));
+        assert!(is_coverage_file(Path::new("lcov.info")));
+        assert!(!is_coverage_file(Path::new("src/main.rs")));
+        assert!(!is_coverage_file(Path::new("target/html/index.html")));
+    }
+
+    #[test]
+    fn test_watch_root() {
+        assert_eq!(
+            watch_root(Path::new("src/lib.rs")),
+            Some(Path::new("src/lib.rs"))
+        );
+        assert_eq!(
+            watch_root(Path::new("src/missing/dir")),
+            Some(Path::new("src"))
+        );
+        assert_eq!(watch_root(Path::new("missing")), Some(Path::new(".")));
+    }
+
+    #[test]
+    fn test_wait_for_changes() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let dir = tmp_dir.path().join("target");
+        fs::create_dir(&dir).unwrap();
+        let mut watcher = CoverageWatcher::new(std::slice::from_ref(&dir)).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        fs::write(dir.join("a.profraw"), "a").unwrap();
+        fs::write(dir.join("b.gcda"), "b").unwrap();
+        let changed = watcher
+            .wait_for_changes(Duration::from_millis(200), &stop)
+            .unwrap();
+        assert_eq!(changed, vec![dir.join("a.profraw"), dir.join("b.gcda")]);
+
+        // Removing the directory, as cargo clean does, is a change too.
+        fs::remove_dir_all(&dir).unwrap();
+        let changed = watcher
+            .wait_for_changes(Duration::from_millis(200), &stop)
+            .unwrap();
+        assert!(changed.contains(&dir.join("a.profraw")));
+
+        // The directory is still watched once created again.
+        fs::create_dir(&dir).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        fs::write(dir.join("c.profraw"), "c").unwrap();
+        let changed = watcher
+            .wait_for_changes(Duration::from_millis(200), &stop)
+            .unwrap();
+        assert_eq!(changed, vec![dir.join("c.profraw")]);
+
+        let stopper = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                stop.store(true, Ordering::SeqCst);
+            })
+        };
+        assert_eq!(
+            watcher.wait_for_changes(Duration::from_millis(200), &stop),
+            None
+        );
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn test_watch_and_export() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let dir = tmp_dir.path().join("target");
+        fs::create_dir(&dir).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        {
+            let dir = dir.clone();
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                fs::write(dir.join("notes.txt"), "ignored").unwrap();
+                fs::write(dir.join("default.profraw"), "not a real profraw").unwrap();
+                // Give up if the update never comes.
+                thread::sleep(Duration::from_secs(10));
+                stop.store(true, Ordering::SeqCst);
+            });
+        }
+
+        let mut updates = Vec::new();
+        watch_and_export(
+            &dir,
+            tmp_dir.path(),
+            tmp_dir.path(),
+            Duration::from_millis(100),
+            &stop,
+            |result| {
+                updates.push(result);
+                stop.store(true, Ordering::SeqCst);
+            },
+        )
+        .unwrap();
+        // The profraw isn't valid, so the export itself fails, or the llvm tools are missing.
+        assert_eq!(updates.len(), 1);
+        assert!(updates[0].is_err());
+    }
+}
@@ -0,0 +1,56 @@
+#!/usr/bin/env bash
+# Regenerates the LCOV fixtures used by tests/offline.rs.
+#
+# This requires a Rust toolchain with the llvm-tools component installed
+# (`rustup component add llvm-tools-preview`). The generated files are
+# committed, so the offline tests don't need any of this to run.
+
+set -euo pipefail
+
+FIXTURES_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+SAMPLE_DIR="$FIXTURES_DIR/sample"
+# The absolute sample path is replaced by this prefix, so that the fixtures
+# don't depend on where the repository was checked out.
+BUILD_PREFIX="/builds/sample"
+
+SYSROOT="$(rustc --print sysroot)"
+HOST="$(rustc -vV | sed -n 's/^host: //p')"
+LLVM_BIN="${LLVM_BIN:-$SYSROOT/lib/rustlib/$HOST/bin}"
+
+WORK_DIR="$(mktemp -d)"
+trap 'rm -rf "$WORK_DIR"' EXIT
+
+cd "$SAMPLE_DIR"
+RUSTFLAGS="-Cinstrument-coverage" CARGO_TARGET_DIR="$WORK_DIR/target" cargo build --quiet
+
+BINARY="$WORK_DIR/target/debug/grcov-fixture-sample"
+
+for op in add mul sub; do
+    LLVM_PROFILE_FILE="$WORK_DIR/$op.profraw" "$BINARY" "$op" > /dev/null
+    "$LLVM_BIN/llvm-profdata" merge -sparse "$WORK_DIR/$op.profraw" -o "$WORK_DIR/$op.profdata"
+    "$LLVM_BIN/llvm-cov" export "$BINARY" \
+        --instr-profile "$WORK_DIR/$op.profdata" \
+        --format lcov \
+        --ignore-filename-regex '/rustc/|\.cargo/registry' \
+        | sed "s|$SAMPLE_DIR|$BUILD_PREFIX|g" > "$FIXTURES_DIR/$op.info"
+done
+
+# The header fixtures: the inline functions of header/stack.h are compiled in the two objects of
+# header/a.cpp and header/b.cpp, and each object is read on its own, as the records of the same
+# file from several compilation units. This requires gcc.
+HEADER_DIR="$FIXTURES_DIR/header"
+mkdir "$WORK_DIR/header"
+cp "$HEADER_DIR"/*.h "$HEADER_DIR"/*.cpp "$WORK_DIR/header"
+(
+    cd "$WORK_DIR/header"
+    g++ --coverage -O0 -c a.cpp b.cpp
+    g++ --coverage a.o b.o -o main
+    ./main || true
+)
+for object in a b; do
+    mkdir "$WORK_DIR/header_$object"
+    cp "$WORK_DIR/header/$object".gc* "$WORK_DIR/header"/*.h "$WORK_DIR/header/$object.cpp" \
+        "$WORK_DIR/header_$object"
+    cargo run --quiet --manifest-path "$FIXTURES_DIR/../../Cargo.toml" -- \
+        "$WORK_DIR/header_$object" -t lcov > "$FIXTURES_DIR/header_$object.info"
+done
@@ -0,0 +1,5 @@
+#include "stack.h"
+
+int a(int x) {
+    return twice(x) + thrice(x);
+}
@@ -0,0 +1,7 @@
+#include "stack.h"
+
+int a(int x);
+
+int main() {
+    return a(1) + twice(2) + thrice(3) - 20;
+}
@@ -0,0 +1,7 @@
+inline int twice(int x) {
+    return x * 2;
+}
+
+static inline int thrice(int x) {
+    return x * 3;
+}
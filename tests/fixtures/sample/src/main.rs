@@ -0,0 +1,23 @@
+mod math;
+
+fn describe(value: i64) -> &'static str {
+    if value < 0 {
+        "negative"
+    } else if value == 0 {
+        "zero"
+    } else {
+        "positive"
+    }
+}
+
+fn main() {
+    let op = std::env::args().nth(1).unwrap_or_default();
+
+    let value = match op.as_str() {
+        "add" => math::add(2, 3),
+        "mul" => math::mul(2, 3),
+        _ => math::sub(2, 3),
+    };
+
+    println!("{} is {}", value, describe(value));
+}
@@ -0,0 +1,15 @@
+pub fn add(a: i64, b: i64) -> i64 {
+    a + b
+}
+
+pub fn sub(a: i64, b: i64) -> i64 {
+    a - b
+}
+
+pub fn mul(a: i64, b: i64) -> i64 {
+    let mut result = 0;
+    for _ in 0..b {
+        result = add(result, a);
+    }
+    result
+}
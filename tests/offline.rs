@@ -0,0 +1,244 @@
+//! Tests for the pure-Rust parts of grcov, using the LCOV fixtures in tests/fixtures.
+//!
+//! Unlike tests/test.rs, these don't need a compiler or the llvm tools to be installed.
+//! The fixtures can be regenerated with tests/fixtures/generate-fixtures.sh.
+
+use grcov::*;
+use rstest::rstest;
+use rustc_hash::FxHashMap;
+use serde_json::{json, Value};
+use std::collections::hash_map;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const BUILD_PREFIX: &str = "/builds/sample";
+
+fn read_fixture(name: &str) -> Vec<u8> {
+    let path = Path::new("tests/fixtures").join(name);
+    fs::read(&path).unwrap_or_else(|_| panic!("Failed to read fixture {:?}", path))
+}
+
+fn parse_fixture(name: &str) -> CovResultMap {
+    parse_lcov(&read_fixture(name), false)
+        .unwrap_or_else(|e| panic!("Failed to parse fixture {}: {}", name, e))
+        .into_iter()
+        .collect()
+}
+
+fn merge_fixtures(names: &[&str]) -> CovResultMap {
+    let mut merged: CovResultMap = FxHashMap::default();
+    for name in names {
+        for (path, result) in parse_fixture(name) {
+            match merged.entry(path) {
+                hash_map::Entry::Occupied(obj) => {
+                    merge_results(obj.into_mut(), result);
+                }
+                hash_map::Entry::Vacant(v) => {
+                    v.insert(result);
+                }
+            };
+        }
+    }
+    merged
+}
+
+fn source_path(rel_path: &str) -> String {
+    format!("{}/{}", BUILD_PREFIX, rel_path)
+}
+
+fn covered_lines(result: &CovResult) -> usize {
+    result.lines.values().filter(|&&count| count > 0).count()
+}
+
+fn rewrite(
+    result_map: CovResultMap,
+    path_mapping: Option<Value>,
+    to_ignore_dirs: &[&str],
+    to_keep_dirs: &[&str],
+) -> Vec<ResultTuple> {
+    let mut results = rewrite_paths(
+        result_map,
+        RewriteOptions {
+            path_mapping,
+            prefix_dir: Some(Path::new(BUILD_PREFIX)),
+            to_ignore_dirs,
+            to_keep_dirs,
+            ..Default::default()
+        },
+    );
+    results.sort_by(|a, b| a.1.cmp(&b.1));
+    results
+}
+
+#[rstest]
+#[case("add.info", 11, 3, 1)]
+#[case("mul.info", 12, 10, 2)]
+#[case("sub.info", 12, 3, 1)]
+fn test_parse_fixture(
+    #[case] fixture: &str,
+    #[case] main_covered: usize,
+    #[case] math_covered: usize,
+    #[case] math_functions_executed: usize,
+) {
+    let results = parse_fixture(fixture);
+    assert_eq!(results.len(), 2);
+
+    let main = &results[&source_path("src/main.rs")];
+    assert_eq!(main.lines.len(), 15);
+    assert_eq!(covered_lines(main), main_covered);
+    assert_eq!(main.functions.len(), 2);
+    assert!(main.functions.values().all(|f| f.executed));
+
+    let math = &results[&source_path("src/math.rs")];
+    assert_eq!(math.lines.len(), 13);
+    assert_eq!(covered_lines(math), math_covered);
+    assert_eq!(math.functions.len(), 3);
+    assert_eq!(
+        math.functions.values().filter(|f| f.executed).count(),
+        math_functions_executed
+    );
+}
+
+#[rstest]
+#[case(&["add.info"], 3)]
+#[case(&["add.info", "mul.info"], 10)]
+#[case(&["add.info", "sub.info"], 6)]
+#[case(&["add.info", "mul.info", "sub.info"], 13)]
+fn test_merge_fixtures(#[case] fixtures: &[&str], #[case] math_covered: usize) {
+    let merged = merge_fixtures(fixtures);
+    assert_eq!(merged.len(), 2);
+
+    let math = &merged[&source_path("src/math.rs")];
+    assert_eq!(math.lines.len(), 13);
+    assert_eq!(covered_lines(math), math_covered);
+
+    // The execution counts are summed up.
+    let expected_hits: u64 = fixtures
+        .iter()
+        .map(|fixture| parse_fixture(fixture)[&source_path("src/math.rs")].lines[&2])
+        .sum();
+    assert_eq!(math.lines[&2], expected_hits);
+}
+
+// The inline functions of header/stack.h are compiled in both objects: twice() is run twice from
+// the copy of a.cpp, which the linker kept, and the static thrice() once from each copy.
+#[rstest]
+#[case(&[], &[(1, 2), (2, 2), (5, 2), (6, 2)])]
+#[case(&["max"], &[(1, 2), (2, 2), (5, 1), (6, 1)])]
+#[case(&["**/*.h=max"], &[(1, 2), (2, 2), (5, 1), (6, 1)])]
+#[case(&["**/*.cpp=max"], &[(1, 2), (2, 2), (5, 2), (6, 2)])]
+fn test_merge_mode_header_fixtures(
+    #[case] merge_mode: &[&str],
+    #[case] header_lines: &[(u32, u64)],
+) {
+    let merge_rules: Vec<MergeRule> = merge_mode
+        .iter()
+        .map(|rule| rule.parse().unwrap())
+        .collect();
+    let result_map: SyncCovResultMap = Mutex::new(FxHashMap::default());
+    for fixture in &["header_a.info", "header_b.info"] {
+        let results = parse_lcov(&read_fixture(fixture), false).unwrap();
+        add_results(results, &result_map, None, &merge_rules);
+    }
+    let result_map = result_map.into_inner().unwrap();
+    assert_eq!(result_map.len(), 3);
+
+    let header = &result_map["stack.h"];
+    assert_eq!(header.lines, header_lines.iter().cloned().collect());
+    assert!(header.functions.values().all(|f| f.executed));
+    assert_eq!(
+        result_map["a.cpp"].lines,
+        [(3, 1), (4, 1)].iter().cloned().collect()
+    );
+}
+
+#[rstest]
+#[case("add.info")]
+#[case("mul.info")]
+#[case("sub.info")]
+fn test_remove_prefix(#[case] fixture: &str) {
+    let results = rewrite(parse_fixture(fixture), None, &[], &[]);
+    let rel_paths: Vec<&PathBuf> = results.iter().map(|(_, rel_path, _)| rel_path).collect();
+    assert_eq!(
+        rel_paths,
+        vec![&PathBuf::from("src/main.rs"), &PathBuf::from("src/math.rs")]
+    );
+}
+
+#[rstest]
+#[case(&["src/math.rs"], &[], "src/main.rs")]
+#[case(&[], &["src/math.rs"], "src/math.rs")]
+#[case(&["src/main.rs"], &["src/*"], "src/math.rs")]
+fn test_filter_fixture(
+    #[case] to_ignore_dirs: &[&str],
+    #[case] to_keep_dirs: &[&str],
+    #[case] expected: &str,
+) {
+    let results = rewrite(
+        parse_fixture("add.info"),
+        None,
+        to_ignore_dirs,
+        to_keep_dirs,
+    );
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].1, PathBuf::from(expected));
+}
+
+#[test]
+fn test_path_mapping() {
+    let mapping = json!({
+        source_path("src/main.rs"): "renamed/main.rs",
+    });
+    let results = rewrite(parse_fixture("add.info"), Some(mapping), &[], &[]);
+    let rel_paths: Vec<&PathBuf> = results.iter().map(|(_, rel_path, _)| rel_path).collect();
+    assert_eq!(
+        rel_paths,
+        vec![
+            &PathBuf::from("renamed/main.rs"),
+            &PathBuf::from("src/math.rs")
+        ]
+    );
+}
+
+#[rstest]
+#[case("add.info")]
+#[case("mul.info")]
+#[case("sub.info")]
+fn test_lcov_output_roundtrip(#[case] fixture: &str) {
+    let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+    let output_path = tmp_dir.path().join("output.info");
+
+    let results = rewrite(parse_fixture(fixture), None, &[], &[]);
+    output_lcov(&results, Some(&output_path), false);
+
+    let written: CovResultMap = parse_lcov(&fs::read(&output_path).unwrap(), false)
+        .unwrap()
+        .into_iter()
+        .collect();
+    assert_eq!(written.len(), results.len());
+    for (_, rel_path, result) in &results {
+        assert_eq!(&written[rel_path.to_str().unwrap()], result);
+    }
+}
+
+#[rstest]
+#[case(&["add.info"], 14)]
+#[case(&["mul.info"], 22)]
+#[case(&["add.info", "mul.info", "sub.info"], 27)]
+fn test_covdir_output(#[case] fixtures: &[&str], #[case] lines_covered: u64) {
+    let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+    let output_path = tmp_dir.path().join("covdir.json");
+
+    let results = rewrite(merge_fixtures(fixtures), None, &[], &[]);
+    output_covdir(&results, Some(&output_path), 2);
+
+    let covdir: Value = serde_json::from_slice(&fs::read(&output_path).unwrap()).unwrap();
+    assert_eq!(covdir["linesTotal"], 28);
+    assert_eq!(covdir["linesCovered"], lines_covered);
+    assert_eq!(covdir["children"]["src"]["linesTotal"], 28);
+    assert_eq!(
+        covdir["children"]["src"]["children"]["math.rs"]["linesTotal"],
+        13
+    );
+}
@@ -463,6 +463,7 @@ fn test_integration() {
         if path.starts_with("tests/basic_zip_zip")
             || path.starts_with("tests/basic_zip_dir")
             || path.starts_with("tests/rust")
+            || path.starts_with("tests/fixtures")
         {
             continue;
         }
@@ -739,3 +740,120 @@ fn test_coveralls_service_job_id_is_not_sufficient() {
         assert!(!status.success());
     }
 }
+
+#[test]
+fn test_sort_lcov_stable_output() {
+    let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+    let tmp_path = tmp_dir.path();
+    let record = |path: &str, functions: &[(&str, u32)]| {
+        let mut record = format!("SF:{}\n", path);
+        for (name, line) in functions {
+            record.push_str(&format!("FN:{},{}\n", line, name));
+        }
+        for (name, _) in functions {
+            record.push_str(&format!("FNDA:1,{}\n", name));
+        }
+        record.push_str("DA:1,1\nDA:2,0\nend_of_record\n");
+        record
+    };
+    fs::write(
+        tmp_path.join("first.info"),
+        record("src/z.rs", &[("z2", 2), ("z1", 1)]) + &record("src/a.rs", &[("a", 1)]),
+    )
+    .unwrap();
+    fs::write(
+        tmp_path.join("second.info"),
+        record("src/m.rs", &[("m1", 1), ("m2", 2)]) + &record("src/b.rs", &[("b", 1)]),
+    )
+    .unwrap();
+
+    // The same inputs, given in another order.
+    let report = |inputs: &[&str]| {
+        let output = Command::new(get_cmd_path())
+            .args(inputs.iter().map(|input| tmp_path.join(input)))
+            .args(["-t", "lcov", "--sort-lcov", "--no-path-warning"])
+            .stderr(Stdio::null())
+            .output()
+            .expect("Failed to run grcov");
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let lcov = report(&["first.info", "second.info"]);
+    assert_eq!(lcov, report(&["second.info", "first.info"]));
+
+    let source_files: Vec<&str> = lcov.lines().filter(|l| l.starts_with("SF:")).collect();
+    assert_eq!(
+        source_files,
+        vec!["SF:src/a.rs", "SF:src/b.rs", "SF:src/m.rs", "SF:src/z.rs"]
+    );
+    assert!(lcov.contains("SF:src/z.rs\nFN:1,z1\nFN:2,z2\n"));
+}
+
+// Runs grcov, and returns the peak of its resident memory, in KB. The process is waited on with
+// wait4, for its resource usage.
+#[cfg(target_os = "linux")]
+#[allow(clippy::zombie_processes)]
+fn grcov_peak_memory(args: &[&std::ffi::OsStr]) -> i64 {
+    let child = Command::new(get_cmd_path())
+        .args(args)
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to run grcov");
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let pid = unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) };
+    assert_eq!(pid, child.id() as libc::pid_t);
+    assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+    usage.ru_maxrss
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_max_memory_peak() {
+    let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+    let tmp_path = tmp_dir.path();
+    let reports = tmp_path.join("reports");
+    fs::create_dir(&reports).unwrap();
+    // 1000 source files of 2000 lines, each one in two of the 100 reports.
+    for report in 0..100 {
+        let mut lcov = String::new();
+        for file in report * 10..report * 10 + 20 {
+            lcov.push_str(&format!("SF:src/file{}.rs\n", file % 1000));
+            for line in 1..=2000 {
+                lcov.push_str(&format!("DA:{},{}\n", line, line % 3));
+            }
+            lcov.push_str("end_of_record\n");
+        }
+        fs::write(reports.join(format!("{}.info", report)), lcov).unwrap();
+    }
+
+    let peak = |output: &str, max_memory: Option<&str>| {
+        let mut args = vec![reports.as_os_str()];
+        args.extend(
+            ["-t", "lcov", "--sort-lcov", "--no-path-warning", "-o"]
+                .iter()
+                .map(std::ffi::OsStr::new),
+        );
+        let output = tmp_path.join(output);
+        args.push(output.as_os_str());
+        if let Some(max_memory) = max_memory {
+            args.push("--max-memory".as_ref());
+            args.push(max_memory.as_ref());
+        }
+        grcov_peak_memory(&args)
+    };
+    let in_memory = peak("in_memory.info", None);
+    let spilled = peak("spilled.info", Some("4"));
+
+    // The results are spilled while they are merged, and the report is the same.
+    assert!(
+        spilled * 2 < in_memory,
+        "{} KB with --max-memory, {} KB without",
+        spilled,
+        in_memory
+    );
+    assert_eq!(
+        read_file(&tmp_path.join("spilled.info")),
+        read_file(&tmp_path.join("in_memory.info"))
+    );
+}